@@ -0,0 +1,433 @@
+//! WinZip AES encryption, see http://www.winzip.com/aes_info.htm
+//!
+//! The cryptography itself needs the `aes` cargo feature; without it, only the
+//! header plumbing is available.
+
+use std::io::{Reader, Writer};
+use std::io::{IoError, InvalidInput};
+use error;
+use error::ZipResult;
+
+/// Compression method recorded in the headers of AES-encrypted entries.
+pub static AES_COMPRESSION_METHOD: u16 = 99;
+/// Header ID of the AES extra field.
+pub static AES_EXTRA_FIELD_ID: u16 = 0x9901;
+pub static PASSWORD_VERIFIER_SIZE: uint = 2;
+pub static AUTHENTICATION_CODE_SIZE: uint = 10;
+
+#[deriving(PartialEq, Show, Clone)]
+pub enum AesStrength {
+    Aes128 = 1,
+    Aes192 = 2,
+    Aes256 = 3,
+}
+
+impl AesStrength {
+    pub fn from_u8(x: u8) -> Option<AesStrength> {
+        match x {
+            1 => Some(Aes128),
+            2 => Some(Aes192),
+            3 => Some(Aes256),
+            _ => None,
+        }
+    }
+
+    pub fn key_size(&self) -> uint {
+        match *self {
+            Aes128 => 16,
+            Aes192 => 24,
+            Aes256 => 32,
+        }
+    }
+
+    pub fn salt_size(&self) -> uint {
+        self.key_size() / 2
+    }
+}
+
+/// The AES extra field (0x9901), which records the real compression method of the entry.
+#[deriving(Clone)]
+pub struct AesExtraField {
+    /// 1 for AE-1, 2 for AE-2 (where the CRC32 is not stored)
+    pub vendor_version: u16,
+    pub strength: AesStrength,
+    pub compression_method: u16,
+}
+
+impl AesExtraField {
+    // reads the data part of the record, after the header ID and data size
+    pub fn read<T:Reader>(r: &mut T) -> ZipResult<AesExtraField> {
+        let vendor_version = try_io!(r.read_le_u16());
+        let vendor_id = try_io!(r.read_exact(2));
+        let strength = try_io!(r.read_u8());
+        let compression_method = try_io!(r.read_le_u16());
+        if vendor_id.as_slice() != b"AE" {
+            return Err(error::SomeIoError(IoError {
+                kind: InvalidInput,
+                desc: "unknown AES extra field vendor",
+                detail: None,
+            }));
+        }
+        match AesStrength::from_u8(strength) {
+            Some(strength) => Ok(AesExtraField {
+                vendor_version: vendor_version,
+                strength: strength,
+                compression_method: compression_method,
+            }),
+            None => Err(error::SomeIoError(IoError {
+                kind: InvalidInput,
+                desc: "unknown AES strength",
+                detail: Some(format!("{}", strength)),
+            }))
+        }
+    }
+
+    // writes the whole record, including the header ID and data size
+    pub fn write<T:Writer>(&self, w: &mut T) -> ZipResult<()> {
+        try_io!(w.write_le_u16(AES_EXTRA_FIELD_ID));
+        try_io!(w.write_le_u16(7));
+        try_io!(w.write_le_u16(self.vendor_version));
+        try_io!(w.write(b"AE"));
+        try_io!(w.write_u8(self.strength as u8));
+        try_io!(w.write_le_u16(self.compression_method));
+        Ok(())
+    }
+}
+
+#[cfg(feature = "aes")]
+mod imp {
+    use std::cmp;
+    use std::io::{Reader, IoResult, IoError, InvalidInput, EndOfFile};
+    use std::io;
+    use std::rand::{task_rng, Rng};
+    use crypto::aessafe::{AesSafe128Encryptor, AesSafe192Encryptor, AesSafe256Encryptor};
+    use crypto::symmetriccipher::BlockEncryptor;
+    use crypto::hmac::Hmac;
+    use crypto::mac::Mac;
+    use crypto::pbkdf2::pbkdf2;
+    use crypto::sha1::Sha1;
+    use crypto::util::fixed_time_eq;
+    use error;
+    use error::ZipResult;
+    use super::{AesStrength, Aes128, Aes192, Aes256, AUTHENTICATION_CODE_SIZE, PASSWORD_VERIFIER_SIZE};
+
+    pub static SUPPORTED: bool = true;
+
+    struct DerivedKeys {
+        encryption_key: Vec<u8>,
+        authentication_key: Vec<u8>,
+        password_verifier: Vec<u8>,
+    }
+
+    fn derive_keys(password: &[u8], salt: &[u8], strength: AesStrength) -> DerivedKeys {
+        let key_size = strength.key_size();
+        let mut mac = Hmac::new(Sha1::new(), password);
+        let mut derived = Vec::from_elem(2 * key_size + PASSWORD_VERIFIER_SIZE, 0u8);
+        pbkdf2(&mut mac, salt, 1000, derived.as_mut_slice());
+        DerivedKeys {
+            encryption_key: derived.slice(0, key_size).to_vec(),
+            authentication_key: derived.slice(key_size, 2 * key_size).to_vec(),
+            password_verifier: derived.slice_from(2 * key_size).to_vec(),
+        }
+    }
+
+    // AES in counter mode as WinZip does it: the counter is little-endian and starts at 1.
+    // Encryption and decryption are the same operation.
+    struct AesCtr {
+        cipher: Box<BlockEncryptor + 'static>,
+        counter: u64,
+        keystream: [u8, ..16],
+        // bytes of `keystream` already used
+        used: uint,
+    }
+
+    impl AesCtr {
+        fn new(key: &[u8], strength: AesStrength) -> AesCtr {
+            let cipher = match strength {
+                Aes128 => box AesSafe128Encryptor::new(key) as Box<BlockEncryptor>,
+                Aes192 => box AesSafe192Encryptor::new(key) as Box<BlockEncryptor>,
+                Aes256 => box AesSafe256Encryptor::new(key) as Box<BlockEncryptor>,
+            };
+            AesCtr { cipher: cipher, counter: 0, keystream: [0u8, ..16], used: 16 }
+        }
+
+        fn apply(&mut self, data: &mut [u8]) {
+            for byte in data.iter_mut() {
+                if self.used == 16 {
+                    self.counter += 1;
+                    let mut block = [0u8, ..16];
+                    for i in range(0u, 8) {
+                        block[i] = (self.counter >> (8 * i)) as u8;
+                    }
+                    self.cipher.encrypt_block(block.as_slice(), self.keystream.as_mut_slice());
+                    self.used = 0;
+                }
+                *byte ^= self.keystream[self.used];
+                self.used += 1;
+            }
+        }
+    }
+
+    /// Encrypts the (already compressed) data of an entry a chunk at a time.
+    pub struct AesEncryptor {
+        ctr: AesCtr,
+        mac: Hmac<Sha1>,
+    }
+
+    impl AesEncryptor {
+        /// Returns the encryptor and the salt and password verifier that start the entry data.
+        pub fn new(password: &[u8], strength: AesStrength) -> ZipResult<(AesEncryptor, Vec<u8>)> {
+            let mut salt = Vec::from_elem(strength.salt_size(), 0u8);
+            task_rng().fill_bytes(salt.as_mut_slice());
+            let keys = derive_keys(password, salt.as_slice(), strength);
+            let encryptor = AesEncryptor {
+                ctr: AesCtr::new(keys.encryption_key.as_slice(), strength),
+                mac: Hmac::new(Sha1::new(), keys.authentication_key.as_slice()),
+            };
+            let mut prefix = salt;
+            prefix.push_all(keys.password_verifier.as_slice());
+            Ok((encryptor, prefix))
+        }
+
+        /// Encrypts the next chunk of data in place.
+        pub fn encrypt(&mut self, data: &mut [u8]) {
+            self.ctr.apply(data);
+            self.mac.input(data);
+        }
+
+        /// Returns the authentication code that ends the entry data.
+        pub fn finish(&mut self) -> Vec<u8> {
+            self.mac.result().code().slice_to(AUTHENTICATION_CODE_SIZE).to_vec()
+        }
+    }
+
+    /// Encrypts the (already compressed) data of an entry. The result is the salt, the password
+    /// verifier, the encrypted data and the authentication code, in that order.
+    pub fn encrypt_entry(password: &[u8], strength: AesStrength, data: &[u8]) -> ZipResult<Vec<u8>> {
+        let (mut encryptor, mut result) = try!(AesEncryptor::new(password, strength));
+        let start = result.len();
+        result.push_all(data);
+        encryptor.encrypt(result.slice_from_mut(start));
+        result.push_all(encryptor.finish().as_slice());
+        Ok(result)
+    }
+
+    /// A Reader decrypting the data of an AES-encrypted entry. The authentication code is
+    /// checked as soon as the last byte of encrypted data has been read.
+    pub struct AesReader<R> {
+        inner: R,
+        ctr: AesCtr,
+        mac: Hmac<Sha1>,
+        // encrypted bytes left before the authentication code
+        remaining: u64,
+    }
+
+    impl<R:Reader> AesReader<R> {
+        /// Reads the salt and password verifier from `inner`, which is positioned at the start
+        /// of the entry data, `data_size` bytes long. Fails with InvalidPassword if the password
+        /// verifier doesn't match.
+        pub fn new(mut inner: R, password: &[u8], strength: AesStrength, data_size: u64)
+                   -> ZipResult<AesReader<R>> {
+            let overhead = strength.salt_size() + PASSWORD_VERIFIER_SIZE + AUTHENTICATION_CODE_SIZE;
+            if data_size < overhead as u64 {
+                return Err(error::SomeIoError(IoError {
+                    kind: InvalidInput,
+                    desc: "AES-encrypted entry is too short",
+                    detail: None,
+                }));
+            }
+            let salt = try_io!(inner.read_exact(strength.salt_size()));
+            let verifier = try_io!(inner.read_exact(PASSWORD_VERIFIER_SIZE));
+            let keys = derive_keys(password, salt.as_slice(), strength);
+            if verifier != keys.password_verifier {
+                return Err(error::InvalidPassword);
+            }
+            Ok(AesReader {
+                inner: inner,
+                ctr: AesCtr::new(keys.encryption_key.as_slice(), strength),
+                mac: Hmac::new(Sha1::new(), keys.authentication_key.as_slice()),
+                remaining: data_size - overhead as u64,
+            })
+        }
+    }
+
+    impl<R:Reader> Reader for AesReader<R> {
+        fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+            if self.remaining == 0 {
+                return Err(io::standard_error(EndOfFile));
+            }
+            let len = cmp::min(buf.len() as u64, self.remaining) as uint;
+            let n = try!(self.inner.read(buf.slice_to_mut(len)));
+            self.mac.input(buf.slice_to(n));
+            self.ctr.apply(buf.slice_to_mut(n));
+            self.remaining -= n as u64;
+
+            if self.remaining == 0 {
+                let code = try!(self.inner.read_exact(AUTHENTICATION_CODE_SIZE));
+                let expected = self.mac.result();
+                if !fixed_time_eq(code.as_slice(), expected.code().slice_to(AUTHENTICATION_CODE_SIZE)) {
+                    return Err(IoError {
+                        kind: InvalidInput,
+                        desc: "AES authentication code mismatch",
+                        detail: None,
+                    });
+                }
+            }
+            Ok(n)
+        }
+    }
+}
+
+#[cfg(not(feature = "aes"))]
+mod imp {
+    use std::io::{Reader, IoResult, IoError, InvalidInput};
+    use error;
+    use error::ZipResult;
+    use super::AesStrength;
+
+    pub static SUPPORTED: bool = false;
+
+    fn not_enabled<T>() -> ZipResult<T> {
+        Err(error::SomeIoError(IoError {
+            kind: InvalidInput,
+            desc: "AES support is not enabled (see the `aes` feature)",
+            detail: None,
+        }))
+    }
+
+    pub fn encrypt_entry(_password: &[u8], _strength: AesStrength, _data: &[u8]) -> ZipResult<Vec<u8>> {
+        not_enabled()
+    }
+
+    // never built, since SUPPORTED is false
+    pub struct AesEncryptor;
+
+    impl AesEncryptor {
+        pub fn new(_password: &[u8], _strength: AesStrength) -> ZipResult<(AesEncryptor, Vec<u8>)> {
+            not_enabled()
+        }
+
+        pub fn encrypt(&mut self, _data: &mut [u8]) {
+        }
+
+        pub fn finish(&mut self) -> Vec<u8> {
+            Vec::new()
+        }
+    }
+
+    // never built, since `new` always fails
+    pub struct AesReader<R> {
+        inner: R,
+    }
+
+    impl<R:Reader> AesReader<R> {
+        pub fn new(_inner: R, _password: &[u8], _strength: AesStrength, _data_size: u64)
+                   -> ZipResult<AesReader<R>> {
+            not_enabled()
+        }
+    }
+
+    impl<R:Reader> Reader for AesReader<R> {
+        fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+            self.inner.read(buf)
+        }
+    }
+}
+
+pub use self::imp::{SUPPORTED, encrypt_entry, AesEncryptor, AesReader};
+
+#[cfg(test)]
+mod test {
+    use std::io::{MemWriter, BufReader};
+    use super::{AesExtraField, AesStrength, Aes192, AES_EXTRA_FIELD_ID};
+
+    // "Hello, WinZip AES! More than one block." encrypted with AES-128 and the password
+    // "password", salt 01 02 ... 10; computed outside this crate from the AE-2 description, so
+    // that it pins the key derivation and the counter (little-endian, starting at 1)
+    #[cfg(feature = "aes")]
+    static AE2_VECTOR: &'static [u8] = &[
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f, 0x10,
+        0x24, 0x06, 0xee, 0x80, 0x5d, 0x30, 0x9c, 0xad, 0xb9, 0x2d, 0x55, 0xc1, 0x08, 0x62, 0xf0, 0x90,
+        0x06, 0xfd, 0x78, 0xa7, 0x7c, 0x7c, 0x98, 0x7e, 0xb7, 0xcf, 0x60, 0x48, 0xfa, 0x47, 0x56, 0x51,
+        0xdd, 0x45, 0x3a, 0x69, 0x68, 0xbb, 0x8b, 0x9d, 0x0e, 0x6a, 0x98, 0x81, 0xe1, 0xb1, 0x53, 0x0b,
+        0x30, 0x9e, 0x89,
+    ];
+
+    #[test]
+    fn extra_field() {
+        let field = AesExtraField { vendor_version: 2, strength: Aes192, compression_method: 8 };
+        let mut w = MemWriter::new();
+        field.write(&mut w).unwrap();
+        let bytes = w.unwrap();
+        assert!(bytes.slice_to(4) == [(AES_EXTRA_FIELD_ID & 0xff) as u8, (AES_EXTRA_FIELD_ID >> 8) as u8, 7, 0].as_slice());
+        let read = AesExtraField::read(&mut BufReader::new(bytes.slice_from(4))).unwrap();
+        assert_eq!(read.vendor_version, 2);
+        assert_eq!(read.strength, Aes192);
+        assert_eq!(read.compression_method, 8);
+
+        let mut unknown_strength = bytes.clone();
+        unknown_strength.as_mut_slice()[8] = 4;
+        assert!(AesExtraField::read(&mut BufReader::new(unknown_strength.slice_from(4))).is_err());
+        assert!(AesStrength::from_u8(0).is_none());
+    }
+
+    #[cfg(feature = "aes")]
+    #[test]
+    fn fixed_vector() {
+        use super::{AesReader, Aes128};
+
+        let mut reader = AesReader::new(BufReader::new(AE2_VECTOR), b"password", Aes128, AE2_VECTOR.len() as u64).unwrap();
+        assert!(reader.read_to_end().unwrap().as_slice() == b"Hello, WinZip AES! More than one block.");
+    }
+
+    #[cfg(feature = "aes")]
+    #[test]
+    fn wrong_password() {
+        use error;
+        use super::{AesReader, Aes128};
+
+        let e = AesReader::new(BufReader::new(AE2_VECTOR), b"wrong", Aes128, AE2_VECTOR.len() as u64).err().unwrap();
+        assert_eq!(e, error::InvalidPassword);
+    }
+
+    #[cfg(feature = "aes")]
+    #[test]
+    fn tampered_data() {
+        use super::{AesReader, Aes128};
+
+        // a flipped bit in the encrypted data, then in the authentication code
+        for &i in [20u, AE2_VECTOR.len() - 1].iter() {
+            let mut data = AE2_VECTOR.to_vec();
+            data.as_mut_slice()[i] ^= 1;
+            let mut reader = AesReader::new(BufReader::new(data.as_slice()), b"password", Aes128, data.len() as u64).unwrap();
+            assert_eq!(reader.read_to_end().unwrap_err().desc, "AES authentication code mismatch");
+        }
+    }
+
+    #[cfg(feature = "aes")]
+    #[test]
+    fn too_short() {
+        use super::{AesReader, Aes128};
+
+        // the salt, the password verifier and the authentication code, less one byte
+        let size = (16 / 2 + 2 + 10 - 1) as u64;
+        let e = AesReader::new(BufReader::new(AE2_VECTOR), b"password", Aes128, size).err().unwrap();
+        assert_eq!(e.io_error().unwrap().desc, "AES-encrypted entry is too short");
+    }
+
+    #[cfg(feature = "aes")]
+    #[test]
+    fn round_trip() {
+        use reader::ZipReader;
+        use writer::{ZipWriter, FileOptions};
+        use super::Aes256;
+
+        let data = Vec::from_fn(100000, |i| (i % 251) as u8);
+        let mut w = ZipWriter::new(MemWriter::new());
+        w.add_file("a.bin", &FileOptions::new().password(Some(b"pass")).aes_strength(Some(Aes256)), data.as_slice()).unwrap();
+        let mut zip = ZipReader::from_bytes(w.finish().unwrap().unwrap()).unwrap();
+        let f = zip.info("a.bin").unwrap();
+        assert!(f.is_encrypted);
+        assert!(zip.read_encrypted(&f, b"pass").unwrap() == data);
+    }
+}
@@ -0,0 +1,56 @@
+//! bzip2 decompression (compression method 12), which needs the `bzip2` cargo feature.
+
+#[cfg(feature = "bzip2")]
+mod imp {
+    use std::io::{Reader, IoResult};
+    use bzip2::reader::BzDecompressor;
+
+    pub static SUPPORTED: bool = true;
+
+    /// A Reader yielding the decompressed contents of a bzip2 stream read from `R`.
+    pub struct Bunzip2Reader<R> {
+        inner: BzDecompressor<R>,
+    }
+
+    impl<R:Reader> Bunzip2Reader<R> {
+        pub fn new(inner: R) -> Bunzip2Reader<R> {
+            Bunzip2Reader { inner: BzDecompressor::new(inner) }
+        }
+    }
+
+    impl<R:Reader> Reader for Bunzip2Reader<R> {
+        fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+            self.inner.read(buf)
+        }
+    }
+}
+
+#[cfg(not(feature = "bzip2"))]
+mod imp {
+    use std::io::{Reader, IoResult, IoError, InvalidInput};
+
+    pub static SUPPORTED: bool = false;
+
+    // never built, since SUPPORTED is false
+    pub struct Bunzip2Reader<R> {
+        _inner: R,
+    }
+
+    impl<R:Reader> Bunzip2Reader<R> {
+        pub fn new(inner: R) -> Bunzip2Reader<R> {
+            Bunzip2Reader { _inner: inner }
+        }
+    }
+
+    impl<R:Reader> Reader for Bunzip2Reader<R> {
+        fn read(&mut self, _buf: &mut [u8]) -> IoResult<uint> {
+            Err(IoError {
+                kind: InvalidInput,
+                desc: "bzip2 support is not enabled (see the `bzip2` feature)",
+                detail: None,
+            })
+        }
+    }
+}
+
+pub use self::imp::{SUPPORTED, Bunzip2Reader};
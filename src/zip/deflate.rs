@@ -0,0 +1,306 @@
+//! An incremental encoder for raw deflate streams (RFC 1951).
+//!
+//! `flate` only deflates whole buffers at once; this encoder takes the data a chunk at a time
+//! and keeps nothing but the 32 KiB window and the block being compressed, so entries of any
+//! size can be compressed in bounded memory. Matches are found with hash chains and written
+//! as fixed Huffman blocks, or as stored blocks where those would be smaller, so incompressible
+//! data grows by a few bytes per block at most.
+
+use std::cmp;
+use inflate::{WINDOW_SIZE, LENGTH_BASE, LENGTH_EXTRA, DISTANCE_BASE, DISTANCE_EXTRA};
+
+// input is compressed a block at a time, which also fits in a stored block
+static BLOCK_SIZE: uint = 32768;
+static MIN_MATCH: uint = 3;
+static MAX_MATCH: uint = 258;
+static HASH_SIZE: uint = 1 << 15;
+// how many earlier positions with the same hash are tried for each match
+static MAX_CHAIN: uint = 64;
+static END_OF_BLOCK: uint = 256;
+
+enum Token {
+    Literal(u8),
+    // length and distance of a back-reference
+    Match(uint, uint),
+}
+
+// the code of a literal/length symbol in the fixed Huffman code, and its length
+fn fixed_literal_code(symbol: uint) -> (u32, uint) {
+    match symbol {
+        0...143 => (0x30 + symbol as u32, 8),
+        144...255 => (0x190 + (symbol - 144) as u32, 9),
+        256...279 => ((symbol - 256) as u32, 7),
+        _ => (0xc0 + (symbol - 280) as u32, 8),
+    }
+}
+
+// the index of the last entry of `bases` that is at most `value`
+fn base_index(bases: &[u16], value: uint) -> uint {
+    let mut i = bases.len() - 1;
+    while bases[i] as uint > value {
+        i -= 1;
+    }
+    i
+}
+
+// Huffman codes are written starting from their most significant bit
+fn reverse_bits(code: u32, len: uint) -> u32 {
+    let mut reversed = 0;
+    for i in range(0, len) {
+        reversed = (reversed << 1) | ((code >> i) & 1);
+    }
+    reversed
+}
+
+// the number of bits a token takes in a fixed Huffman block
+fn token_bits(token: &Token) -> uint {
+    match *token {
+        Literal(byte) => {
+            let (_, bits) = fixed_literal_code(byte as uint);
+            bits
+        }
+        Match(length, distance) => {
+            let l = base_index(LENGTH_BASE, length);
+            let d = base_index(DISTANCE_BASE, distance);
+            let (_, bits) = fixed_literal_code(257 + l);
+            bits + LENGTH_EXTRA[l] as uint + 5 + DISTANCE_EXTRA[d] as uint
+        }
+    }
+}
+
+/// Compresses data given a chunk at a time into a raw deflate stream.
+pub struct Deflater {
+    // the window of data already compressed, followed by the data of the next block
+    buffer: Vec<u8>,
+    // where the data of the next block starts in `buffer`
+    pos: uint,
+    // hash of 3 bytes => last position in `buffer` they start at, or -1
+    head: Vec<int>,
+    // position in `buffer` => previous position with the same hash, or -1
+    prev: Vec<int>,
+    bit_buffer: u32,
+    bit_count: uint,
+}
+
+impl Deflater {
+    pub fn new() -> Deflater {
+        Deflater {
+            buffer: Vec::new(),
+            pos: 0,
+            head: Vec::from_elem(HASH_SIZE, -1),
+            prev: Vec::new(),
+            bit_buffer: 0,
+            bit_count: 0,
+        }
+    }
+
+    /// Compresses `data`, appending the blocks completed so far to `out`.
+    pub fn write(&mut self, data: &[u8], out: &mut Vec<u8>) {
+        let mut data = data;
+        while !data.is_empty() {
+            let n = cmp::min(data.len(), BLOCK_SIZE - (self.buffer.len() - self.pos));
+            self.buffer.push_all(data.slice_to(n));
+            data = data.slice_from(n);
+            if self.buffer.len() - self.pos == BLOCK_SIZE {
+                self.write_block(false, out);
+            }
+        }
+    }
+
+    /// Compresses what is left and appends the end of the stream to `out`.
+    pub fn finish(&mut self, out: &mut Vec<u8>) {
+        self.write_block(true, out);
+    }
+
+    fn hash(&self, p: uint) -> uint {
+        let b = self.buffer.slice(p, p + MIN_MATCH);
+        ((b[0] as uint << 10) ^ (b[1] as uint << 5) ^ b[2] as uint) & (HASH_SIZE - 1)
+    }
+
+    fn insert(&mut self, p: uint, end: uint) {
+        if p + MIN_MATCH <= end {
+            let h = self.hash(p);
+            self.prev.as_mut_slice()[p] = self.head[h];
+            self.head.as_mut_slice()[h] = p as int;
+        }
+    }
+
+    // the longest earlier match for the data at `p`, as (length, distance)
+    fn longest_match(&self, p: uint, end: uint) -> (uint, uint) {
+        let (mut best_length, mut best_distance) = (0, 0);
+        let max_length = cmp::min(MAX_MATCH, end - p);
+        let mut candidate = self.head[self.hash(p)];
+        let mut chain = 0;
+        while candidate >= 0 && chain < MAX_CHAIN {
+            let c = candidate as uint;
+            if p - c > WINDOW_SIZE {
+                break;
+            }
+            let mut length = 0;
+            while length < max_length && self.buffer[c + length] == self.buffer[p + length] {
+                length += 1;
+            }
+            if length > best_length {
+                best_length = length;
+                best_distance = p - c;
+                if length == max_length {
+                    break;
+                }
+            }
+            candidate = self.prev[c];
+            chain += 1;
+        }
+        (best_length, best_distance)
+    }
+
+    fn tokens(&mut self, end: uint) -> Vec<Token> {
+        let mut tokens = Vec::new();
+        let mut p = self.pos;
+        while p < end {
+            let (length, distance) = if p + MIN_MATCH <= end { self.longest_match(p, end) } else { (0, 0) };
+            if length >= MIN_MATCH {
+                tokens.push(Match(length, distance));
+                for q in range(p, p + length) {
+                    self.insert(q, end);
+                }
+                p += length;
+            } else {
+                tokens.push(Literal(self.buffer[p]));
+                self.insert(p, end);
+                p += 1;
+            }
+        }
+        tokens
+    }
+
+    fn put_bits(&mut self, value: u32, count: uint, out: &mut Vec<u8>) {
+        self.bit_buffer |= value << self.bit_count;
+        self.bit_count += count;
+        while self.bit_count >= 8 {
+            out.push(self.bit_buffer as u8);
+            self.bit_buffer >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
+    fn put_code(&mut self, (code, len): (u32, uint), out: &mut Vec<u8>) {
+        self.put_bits(reverse_bits(code, len), len, out);
+    }
+
+    // pads the current byte with zero bits
+    fn align(&mut self, out: &mut Vec<u8>) {
+        if self.bit_count > 0 {
+            out.push(self.bit_buffer as u8);
+            self.bit_buffer = 0;
+            self.bit_count = 0;
+        }
+    }
+
+    fn write_block(&mut self, last: bool, out: &mut Vec<u8>) {
+        let end = self.buffer.len();
+        let len = end - self.pos;
+        let missing = end - self.prev.len();
+        self.prev.grow(missing, -1);
+        let tokens = self.tokens(end);
+
+        // a stored block is byte-aligned and starts with its length
+        let fixed_bits = tokens.iter().fold(3 + 7, |bits, t| bits + token_bits(t));
+        let stored_bits = 3 + 7 + 32 + 8 * len;
+        self.put_bits(if last { 1 } else { 0 }, 1, out);
+        if fixed_bits < stored_bits {
+            self.put_bits(1, 2, out);
+            for token in tokens.iter() {
+                match *token {
+                    Literal(byte) => self.put_code(fixed_literal_code(byte as uint), out),
+                    Match(length, distance) => {
+                        let l = base_index(LENGTH_BASE, length);
+                        self.put_code(fixed_literal_code(257 + l), out);
+                        self.put_bits((length - LENGTH_BASE[l] as uint) as u32, LENGTH_EXTRA[l] as uint, out);
+                        let d = base_index(DISTANCE_BASE, distance);
+                        self.put_code((d as u32, 5), out);
+                        self.put_bits((distance - DISTANCE_BASE[d] as uint) as u32, DISTANCE_EXTRA[d] as uint, out);
+                    }
+                }
+            }
+            self.put_code(fixed_literal_code(END_OF_BLOCK), out);
+        } else {
+            self.put_bits(0, 2, out);
+            self.align(out);
+            out.push(len as u8);
+            out.push((len >> 8) as u8);
+            out.push(!len as u8);
+            out.push((!len >> 8) as u8);
+            out.push_all(self.buffer.slice(self.pos, end));
+        }
+        if last {
+            self.align(out);
+        }
+        self.pos = end;
+        self.slide();
+    }
+
+    // drops what is now out of reach of back-references
+    fn slide(&mut self) {
+        if self.pos <= WINDOW_SIZE {
+            return;
+        }
+        let shift = self.pos - WINDOW_SIZE;
+        let rebase = |p: int| if p >= shift as int { p - shift as int } else { -1 };
+        self.buffer = self.buffer.slice_from(shift).to_vec();
+        self.prev = self.prev.slice_from(shift).iter().map(|&p| rebase(p)).collect();
+        for p in self.head.iter_mut() {
+            *p = rebase(*p);
+        }
+        self.pos -= shift;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::BufReader;
+    use flate;
+    use inflate::InflateReader;
+    use super::Deflater;
+
+    // deflates `data` written `chunk` bytes at a time
+    fn deflate(data: &[u8], chunk: uint) -> Vec<u8> {
+        let mut deflater = Deflater::new();
+        let mut out = Vec::new();
+        for part in data.chunks(chunk) {
+            deflater.write(part, &mut out);
+        }
+        deflater.finish(&mut out);
+        out
+    }
+
+    fn round_trip(data: &[u8]) {
+        for &chunk in [1u, 1000, 100000].iter() {
+            let compressed = deflate(data, chunk);
+            let inflated = InflateReader::new(BufReader::new(compressed.as_slice())).read_to_end().unwrap();
+            assert!(inflated.as_slice() == data);
+            assert!(flate::inflate_bytes(compressed.as_slice()).unwrap().as_slice() == data);
+        }
+    }
+
+    #[test]
+    fn empty() {
+        round_trip(b"");
+    }
+
+    #[test]
+    fn repetitive() {
+        let data = Vec::from_fn(200000, |i| b"abcabcabd"[i % 9]);
+        round_trip(data.as_slice());
+        assert!(deflate(data.as_slice(), 4096).len() < data.len() / 20);
+    }
+
+    #[test]
+    fn incompressible() {
+        // a linear congruential generator, so the test doesn't depend on the system RNG
+        let mut x = 12345u32;
+        let data = Vec::from_fn(100000, |_| { x = x * 1103515245 + 12345; (x >> 16) as u8 });
+        round_trip(data.as_slice());
+        // stored blocks cost 5 bytes for every 32 KiB
+        assert!(deflate(data.as_slice(), 4096).len() <= data.len() + 5 * 4);
+    }
+}
@@ -0,0 +1,160 @@
+//! Removing and replacing entries of an existing archive.
+
+use std::io::{Reader, Writer, Seek};
+use std::path::BytesContainer;
+use error::ZipResult;
+use maybe_utf8::MaybeUTF8;
+use reader::ZipReader;
+use writer::{ZipWriter, FileOptions};
+
+/// Produces a new archive from an existing one, leaving out or replacing some of its entries.
+///
+/// Untouched entries are copied without being recompressed, so their compression method,
+/// timestamps, extra fields and comments are preserved, and so is the archive comment.
+pub struct ZipEditor<'a, R:'a> {
+    source: &'a mut ZipReader<R>,
+    removed: Vec<MaybeUTF8>,
+    replacements: Vec<(MaybeUTF8, FileOptions, Vec<u8>)>,
+}
+
+fn to_maybe_utf8<T:BytesContainer>(name: T) -> MaybeUTF8 {
+    match name.container_as_str() {
+        Some(s) => MaybeUTF8::from_str(s.to_string()),
+        None => MaybeUTF8::from_bytes(name.container_as_bytes().to_vec()),
+    }
+}
+
+impl<'a, R:Reader+Seek> ZipEditor<'a, R> {
+    pub fn new(source: &'a mut ZipReader<R>) -> ZipEditor<'a, R> {
+        ZipEditor {
+            source: source,
+            removed: Vec::new(),
+            replacements: Vec::new(),
+        }
+    }
+
+    /// Leaves out every entry with the given name.
+    pub fn remove<T:BytesContainer>(&mut self, name: T) {
+        self.removed.push(to_maybe_utf8(name));
+    }
+
+    /// Leaves out every entry whose name matches `predicate`.
+    pub fn remove_matching(&mut self, predicate: |&MaybeUTF8| -> bool) -> ZipResult<()> {
+        let headers = try!(self.source.central_directory());
+        for h in headers.into_iter() {
+            if predicate(&h.file_name) {
+                self.removed.push(h.file_name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the contents of the entry with the given name, keeping its position in the
+    /// archive. If there is no such entry, the new one is added at the end. If there are
+    /// several, the new one takes the place of the first and the others are left out. Replacing
+    /// the same name again overrides the earlier replacement.
+    pub fn replace<T:BytesContainer>(&mut self, name: T, options: &FileOptions, data: Vec<u8>) {
+        let name = to_maybe_utf8(name);
+        self.replacements.retain(|&(ref n, _, _)| *n != name);
+        self.replacements.push((name, options.clone(), data));
+    }
+
+    /// Writes the edited archive to `writer`, and returns it.
+    pub fn write<W:Writer+Seek>(self, writer: W) -> ZipResult<W> {
+        let ZipEditor { source, removed, mut replacements } = self;
+
+        let headers = try!(source.central_directory());
+        let mut out = ZipWriter::new(writer);
+        try!(out.set_comment(source.comment().as_bytes()));
+
+        // names already replaced, whose other entries are left out
+        let mut replaced = Vec::new();
+        for h in headers.iter() {
+            if removed.iter().chain(replaced.iter()).any(|name| *name == h.file_name) {
+                continue;
+            }
+            match replacements.iter().position(|&(ref name, _, _)| *name == h.file_name) {
+                Some(i) => {
+                    let (name, options, data) = replacements.remove(i).unwrap();
+                    try!(out.add_file(name.clone(), &options, data.as_slice()));
+                    replaced.push(name);
+                }
+                None => try!(out.copy_raw_entry(source, h)),
+            }
+        }
+
+        // replacements that didn't match any entry are new files
+        for (name, options, data) in replacements.into_iter() {
+            try!(out.add_file(name, &options, data.as_slice()));
+        }
+
+        out.finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{MemWriter, MemReader};
+    use reader::ZipReader;
+    use writer::{ZipWriter, FileOptions};
+    use fileinfo;
+    use super::ZipEditor;
+
+    // an archive with the given entries, each holding its name and then `suffix`
+    fn archive(names: &[&str], suffix: &str) -> ZipReader<MemReader> {
+        let mut w = ZipWriter::new(MemWriter::new());
+        for name in names.iter() {
+            let data = format!("{}{}", name, suffix);
+            w.add_file(*name, &FileOptions::new(), data.as_bytes()).unwrap();
+        }
+        ZipReader::from_bytes(w.finish().unwrap().unwrap()).unwrap()
+    }
+
+    fn contents(zip: &mut ZipReader<MemReader>) -> Vec<(String, String)> {
+        let files = zip.files().map(|f| f.clone()).collect::<Vec<_>>();
+        files.iter().map(|f| {
+            let data = zip.read(f).unwrap();
+            (f.name.as_str().unwrap().to_string(), String::from_utf8(data).unwrap())
+        }).collect()
+    }
+
+    fn edit(source: &mut ZipReader<MemReader>, f: |&mut ZipEditor<MemReader>|)
+            -> ZipReader<MemReader> {
+        let mut editor = ZipEditor::new(source);
+        f(&mut editor);
+        ZipReader::from_bytes(editor.write(MemWriter::new()).unwrap().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn remove_and_replace() {
+        let mut source = archive(&["a", "b", "c", "d"], " has some text in it, some text in it, some text in it");
+        let stored = FileOptions { compression_method: fileinfo::Store, ..FileOptions::new() };
+        let mut edited = edit(&mut source, |e| {
+            e.remove("b");
+            e.remove_matching(|name| name.as_bytes() == b"d").unwrap();
+            e.replace("a", &stored, b"new a".to_vec());
+            e.replace("e", &stored, b"new e".to_vec());
+        });
+        assert!(contents(&mut edited) == vec![("a".to_string(), "new a".to_string()),
+                                             ("c".to_string(), "c has some text in it, some text in it, some text in it".to_string()),
+                                             ("e".to_string(), "new e".to_string())]);
+
+        // untouched entries are copied as they were compressed
+        let (f, g) = (source.info("c").unwrap(), edited.info("c").unwrap());
+        assert_eq!(g.compression_method, fileinfo::Deflate);
+        assert!(source.read_raw(&f).unwrap().data == edited.read_raw(&g).unwrap().data);
+    }
+
+    #[test]
+    fn replace_duplicates() {
+        let mut source = archive(&["a", "b", "a"], "");
+        assert!(source.has_duplicates());
+        let mut edited = edit(&mut source, |e| {
+            e.replace("a", &FileOptions::new(), b"first".to_vec());
+            e.replace("a", &FileOptions::new(), b"second".to_vec());
+        });
+        // the last replacement, in place of the first entry, and no other "a"
+        assert!(contents(&mut edited) == vec![("a".to_string(), "second".to_string()),
+                                             ("b".to_string(), "b".to_string())]);
+    }
+}
@@ -3,7 +3,10 @@
 #![macro_escape]
 
 use std::fmt;
-use std::io::IoError;
+use std::error::FromError;
+use std::io::{IoError, IoErrorKind, InvalidInput, FileNotFound};
+use maybe_utf8::MaybeUTF8;
+use format::Irregularity;
 
 /// A list of possible errors. This is a supetset of `std::Io::IoError`.
 #[deriving(PartialEq,Clone)]
@@ -12,9 +15,130 @@ pub enum ZipError {
     NotAZipFile,
     CrcError,
     FileNotFoundInArchive,
-    InvalidSignature(u32),
+    // the signature that was expected, the bytes found instead, and the offset they are at
+    InvalidSignature(u32, u32, u64),
     NonUTF8Field,
     TooLongField,
+    UnsupportedCompressionMethod(u16),
+    EncryptedFileUnsupported,
+    InvalidPassword,
+    FeatureNotSupported(&'static str),
+    // the named size, offset or count doesn't fit its header field, and ZIP64 isn't written
+    Zip64Required(&'static str),
+    UnsafeFileName(MaybeUTF8),
+    // the named field of a local file header disagrees with the central directory
+    HeaderMismatch(&'static str),
+    // the archive is split into this many parts, but a different number was given
+    MissingParts(u32),
+    // the header of the named entry violates the specification, in strict mode
+    IrregularHeader(MaybeUTF8, Irregularity),
+    // an error while reading or extracting the named entry, whose local file header is at
+    // this offset
+    EntryError(MaybeUTF8, u64, Box<ZipError>),
+    // an I/O error, and what was being done when it happened
+    IoErrorWhile(IoError, &'static str),
+}
+
+impl ZipError {
+    /// Says what was being done when an I/O error happened: SomeIoError becomes IoErrorWhile,
+    /// other errors are returned as they are (an error with a context keeps the innermost one).
+    pub fn during(self, context: &'static str) -> ZipError {
+        match self {
+            SomeIoError(e) => IoErrorWhile(e, context),
+            EntryError(name, offset, e) => EntryError(name, offset, box e.during(context)),
+            e => e,
+        }
+    }
+
+    /// The error an EntryError wraps (through any nesting), or this error itself otherwise:
+    /// what to match on to tell, say, InvalidPassword from CrcError, whichever entry it is about.
+    pub fn innermost<'a>(&'a self) -> &'a ZipError {
+        match *self {
+            EntryError(_, _, ref e) => e.innermost(),
+            ref e => e,
+        }
+    }
+
+    /// The underlying I/O error, if this is one.
+    pub fn io_error<'a>(&'a self) -> Option<&'a IoError> {
+        match *self {
+            SomeIoError(ref e) | IoErrorWhile(ref e, _) => Some(e),
+            EntryError(_, _, ref e) => e.io_error(),
+            _ => None,
+        }
+    }
+
+    /// Converts to an IoError, for code that returns IoResult. SomeIoError gives back the
+    /// original error; the other errors have a short `desc`, and their full message as `detail`.
+    pub fn to_io_error(self) -> IoError {
+        match self {
+            SomeIoError(e) => e,
+            e => IoError { kind: e.io_error_kind(), desc: e.io_error_desc(), detail: Some(format!("{}", e)) },
+        }
+    }
+
+    fn io_error_kind(&self) -> IoErrorKind {
+        match *self {
+            SomeIoError(ref e) | IoErrorWhile(ref e, _) => e.kind,
+            EntryError(_, _, ref e) => e.io_error_kind(),
+            FileNotFoundInArchive => FileNotFound,
+            _ => InvalidInput,
+        }
+    }
+
+    fn io_error_desc(&self) -> &'static str {
+        match *self {
+            NotAZipFile => "not a ZIP file",
+            CrcError => "CRC mismatch",
+            FileNotFoundInArchive => "file not found in archive",
+            InvalidSignature(..) => "invalid ZIP signature",
+            NonUTF8Field => "invalid UTF-8 in a file name or comment",
+            TooLongField => "file name, comment or extra field is too long",
+            UnsupportedCompressionMethod(_) => "unsupported compression method",
+            EncryptedFileUnsupported => "the file is encrypted",
+            InvalidPassword => "invalid password",
+            FeatureNotSupported(_) => "unsupported ZIP feature",
+            Zip64Required(_) => "the archive would need ZIP64",
+            UnsafeFileName(_) => "unsafe file name",
+            HeaderMismatch(_) => "local file header doesn't match the central directory",
+            MissingParts(_) => "missing parts of a split archive",
+            IrregularHeader(..) => "irregular header",
+            SomeIoError(ref e) | IoErrorWhile(ref e, _) => e.desc,
+            EntryError(_, _, ref e) => e.io_error_desc(),
+        }
+    }
+}
+
+impl FromError<ZipError> for IoError {
+    fn from_error(err: ZipError) -> IoError {
+        err.to_io_error()
+    }
+}
+
+// the names of the compression methods of section 4.4.5 of APPNOTE.TXT, to tell what an
+// archive needs
+fn compression_method_name(method: u16) -> Option<&'static str> {
+    match method {
+        0 => Some("stored"),
+        1 => Some("shrunk"),
+        2...5 => Some("reduced"),
+        6 => Some("imploded"),
+        8 => Some("deflated"),
+        9 => Some("Deflate64"),
+        10 => Some("PKWARE DCL imploded"),
+        12 => Some("bzip2"),
+        14 => Some("LZMA"),
+        18 => Some("IBM TERSE"),
+        19 => Some("IBM LZ77"),
+        93 => Some("Zstandard"),
+        94 => Some("MP3"),
+        95 => Some("XZ"),
+        96 => Some("JPEG"),
+        97 => Some("WavPack"),
+        98 => Some("PPMd"),
+        99 => Some("AES-encrypted"),
+        _ => None,
+    }
 }
 
 impl fmt::Show for ZipError {
@@ -24,9 +148,24 @@ impl fmt::Show for ZipError {
             NotAZipFile => "not a ZIP file".fmt(f),
             CrcError => "CRC mismatch".fmt(f),
             FileNotFoundInArchive => "file not found in archive".fmt(f),
-            InvalidSignature(magic) => write!(f, "invalid ZIP signature {:#08x}", magic),
+            InvalidSignature(expected, found, offset) =>
+                write!(f, "invalid ZIP signature: expected {:#010x}, found {:#010x} at offset {}", expected, found, offset),
             NonUTF8Field => "file name or comment is set to UTF-8 encoded but it isn't".fmt(f),
             TooLongField => "file name, comment or extra field is too long (> 64KB)".fmt(f),
+            UnsupportedCompressionMethod(method) => match compression_method_name(method) {
+                Some(name) => write!(f, "unsupported compression method {} ({})", method, name),
+                None => write!(f, "unsupported compression method {}", method),
+            },
+            EncryptedFileUnsupported => "the file is encrypted, and no password was given".fmt(f),
+            InvalidPassword => "invalid password".fmt(f),
+            FeatureNotSupported(feature) => write!(f, "the file uses {}, which is not supported", feature),
+            Zip64Required(field) => write!(f, "the {} is too large for its header field, and would need ZIP64", field),
+            UnsafeFileName(ref name) => write!(f, "refusing to extract {}, which could point outside of the destination", name.display_escaped()),
+            HeaderMismatch(field) => write!(f, "the {} in the local file header doesn't match the central directory", field),
+            MissingParts(count) => write!(f, "the archive is split into {} parts, which were not all given", count),
+            IrregularHeader(ref name, ref irregularity) => write!(f, "irregular header for {}: {}", name.display_escaped(), irregularity.description()),
+            EntryError(ref name, offset, ref e) => write!(f, "\"{}\" at offset {}: {}", name.display_escaped(), offset, e),
+            IoErrorWhile(ref e, context) => write!(f, "error while {}: {}", context, e),
         }
     }
 }
@@ -34,6 +173,84 @@ impl fmt::Show for ZipError {
 pub type ZipResult<T> = Result<T, ZipError>;
 
 macro_rules! try_io(
-    ($e:expr) => (try!($e.map_err(::error::SomeIoError)))
+    ($e:expr) => (try!($e.map_err(::error::SomeIoError)));
+    // with what is being done, for the message
+    ($e:expr, $context:expr) => (try!($e.map_err(|e| ::error::IoErrorWhile(e, $context))))
 )
 
+
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use std::io::{IoError, IoResult};
+    use maybe_utf8::MaybeUTF8;
+    use super::{ZipError, SomeIoError, NotAZipFile, CrcError, FileNotFoundInArchive, InvalidSignature,
+                NonUTF8Field, TooLongField, UnsupportedCompressionMethod, EncryptedFileUnsupported,
+                InvalidPassword, FeatureNotSupported, Zip64Required, UnsafeFileName, HeaderMismatch,
+                MissingParts, IrregularHeader, EntryError, IoErrorWhile};
+
+    // a match over every variant, as callers write them: adding one must be a conscious change
+    fn is_unsupported(e: &ZipError) -> bool {
+        match *e {
+            UnsupportedCompressionMethod(_) | EncryptedFileUnsupported | FeatureNotSupported(_) |
+                Zip64Required(_) => true,
+            SomeIoError(_) | NotAZipFile | CrcError | FileNotFoundInArchive | InvalidSignature(..) |
+                NonUTF8Field | TooLongField | InvalidPassword | UnsafeFileName(_) | HeaderMismatch(_) |
+                MissingParts(_) | IrregularHeader(..) | IoErrorWhile(..) => false,
+            EntryError(_, _, ref e) => is_unsupported(&**e),
+        }
+    }
+
+    #[test]
+    fn unsupported_features() {
+        let messages = [
+            (UnsupportedCompressionMethod(14), "unsupported compression method 14 (LZMA)"),
+            (UnsupportedCompressionMethod(3), "unsupported compression method 3 (reduced)"),
+            (UnsupportedCompressionMethod(200), "unsupported compression method 200"),
+            (EncryptedFileUnsupported, "the file is encrypted, and no password was given"),
+            (FeatureNotSupported("strong encryption"), "the file uses strong encryption, which is not supported"),
+            (Zip64Required("compressed size"), "the compressed size is too large for its header field, and would need ZIP64"),
+        ];
+        for &(ref e, message) in messages.iter() {
+            assert_eq!(format!("{}", e).as_slice(), message);
+            assert!(is_unsupported(e));
+        }
+        let nested = EntryError(MaybeUTF8::from_str("a.xz".to_string()), 0, box UnsupportedCompressionMethod(95));
+        assert_eq!(format!("{}", nested).as_slice(), "\"a.xz\" at offset 0: unsupported compression method 95 (XZ)");
+        assert!(is_unsupported(&nested));
+        assert!(!is_unsupported(&CrcError));
+        assert!(!is_unsupported(&SomeIoError(io::standard_error(io::EndOfFile))));
+    }
+
+    // a function returning IoResult, using try! on a ZIP operation
+    fn checked(result: Result<uint, ZipError>) -> IoResult<uint> {
+        Ok(try!(result) + 1)
+    }
+
+    #[test]
+    fn into_io_errors() {
+        // an IoError wrapped and converted back is the same error
+        let original = IoError { kind: io::PermissionDenied, desc: "permission denied", detail: Some("a.zip".to_string()) };
+        assert_eq!(SomeIoError(original.clone()).to_io_error(), original);
+        assert_eq!(checked(Err(SomeIoError(original.clone()))).unwrap_err(), original);
+        assert_eq!(checked(Ok(1)), Ok(2));
+
+        // the others are InvalidInput, with the message as detail
+        let e = CrcError.to_io_error();
+        assert_eq!((e.kind, e.desc), (io::InvalidInput, "CRC mismatch"));
+        assert_eq!(e.detail, Some("CRC mismatch".to_string()));
+        let e = checked(Err(InvalidSignature(0x02014b50, 0, 100))).unwrap_err();
+        assert_eq!((e.kind, e.desc), (io::InvalidInput, "invalid ZIP signature"));
+        assert_eq!(e.detail, Some("invalid ZIP signature: expected 0x02014b50, found 0x00000000 at offset 100".to_string()));
+        assert_eq!(NotAZipFile.to_io_error().kind, io::InvalidInput);
+        assert_eq!(FileNotFoundInArchive.to_io_error().kind, io::FileNotFound);
+
+        // an I/O error with context, or about an entry, keeps the kind and desc of the original
+        let e = IoErrorWhile(original.clone(), "reading the central directory").to_io_error();
+        assert_eq!((e.kind, e.desc), (io::PermissionDenied, "permission denied"));
+        assert_eq!(e.detail, Some("error while reading the central directory: permission denied (a.zip)".to_string()));
+        let e = EntryError(MaybeUTF8::from_str("x".to_string()), 0, box SomeIoError(original.clone())).to_io_error();
+        assert_eq!((e.kind, e.desc), (io::PermissionDenied, "permission denied"));
+    }
+}
@@ -1,10 +1,17 @@
 use format;
+use error::ZipResult;
+use format::extra;
+use format::extra::{ExtraRecord, UnicodePath, ExtendedTimestamp, InfoZipUnix, UnixOwner, NtfsTimestamps};
+use maybe_utf8;
 use maybe_utf8::MaybeUTF8;
+use crc32;
+use aes;
 
 #[deriving(PartialEq, Show, Clone)]
 pub enum CompressionMethod {
     Store=0,
     Deflate=8,
+    Bzip2=12,
     Unknown
 }
 
@@ -13,41 +20,392 @@ impl CompressionMethod {
         let u = x as uint;
         if      u == (Store   as uint) { Store }
         else if u == (Deflate as uint) { Deflate }
+        else if u == (Bzip2   as uint) { Bzip2 }
         else                           { Unknown }
     }
 }
 
 #[deriving(Clone)]
 pub struct FileInfo {
+    // from the Unicode path extra field when there is a valid one, else the same as raw_name
     pub name:               MaybeUTF8,
+    // as stored in the header: raw bytes unless the entry has the UTF-8 flag set, so non-UTF-8
+    // names never fail
+    pub raw_name:           MaybeUTF8,
+    // decoded like the name
+    pub comment:            MaybeUTF8,
     pub compression_method: CompressionMethod,
+    // the compression method as stored in the header, for when compression_method is Unknown
+    pub raw_compression_method: u16,
+    // how a deflated entry was compressed, as its creator recorded it; None for other methods
+    pub deflate_option: Option<format::DeflateOption>,
     // (year, month, day, hour, minute, second)
     pub last_modified_datetime: (uint, uint, uint, uint, uint, uint),
     pub crc32:              u32,
-    pub compressed_size:    u32,
-    pub uncompressed_size:  u32,
+    pub compressed_size:    u64,
+    pub uncompressed_size:  u64,
     pub is_encrypted:       bool,
+    // the byte that ends the decrypted encryption header of entries with the traditional
+    // encryption, which a password must reproduce; None for other entries
+    pub encryption_check_byte: Option<u8>,
+    // as stored in the central directory header
+    pub extra_field:        Vec<u8>,
+    // the upper byte is the host system of the creator, see section 4.4.2 of APPNOTE.TXT
+    pub version_made_by:    u16,
+    // bit 0 marks the entry as apparently text; the other bits are reserved
+    pub internal_file_attributes: u16,
+    // host-dependent: MS-DOS attributes in the low byte, or the Unix mode in the upper 16 bits
+    pub external_file_attributes: u32,
 
-    pub local_file_header_offset: u32,
+    pub local_file_header_offset: u64,
+    // rebuilt from the local file header by `ZipReader::open_with_recovery`, without the
+    // central directory: the comment, attributes and version made by are then missing
+    pub recovered:          bool,
+}
+
+// the check byte of entries with the traditional encryption, which have a 12-byte encryption
+// header before their data; AES and strong encryption work differently
+fn encryption_check_byte(flags: format::GpFlags, compression_method: u16, crc32: u32,
+                         datetime: &format::MsdosDateTime) -> Option<u8> {
+    if !flags.encrypted() || flags.strong_encryption() || compression_method == aes::AES_COMPRESSION_METHOD {
+        return None;
+    }
+    Some(format::encryption_check_byte(flags.data_descriptor(), crc32, datetime))
+}
+
+fn deflate_option(flags: &format::GpFlags, compression_method: u16) -> Option<format::DeflateOption> {
+    match CompressionMethod::from_u16(compression_method) {
+        Deflate => Some(flags.deflate_option()),
+        _ => None,
+    }
 }
 
 impl FileInfo {
-    // fills a FileInfo struct with the file properties, for users of the external API to see
-    pub fn from_cdh(h: &format::CentralDirectoryHeader) -> FileInfo {
-        let method : CompressionMethod =
-            if h.compression_method == 0 { Store }
-            else if h.compression_method == 8 { Deflate }
-            else { panic!() };
-        FileInfo {
+    // fills a FileInfo struct with the file properties, for users of the external API to see.
+    // Fails if the header says the sizes or offset are in a ZIP64 extra field that isn't there.
+    pub fn from_cdh(h: &format::CentralDirectoryHeader) -> ZipResult<FileInfo> {
+        let zip64 = try!(h.zip64_extended_information());
+        let mut info = FileInfo {
             name:               h.file_name.clone(),
-            compression_method: method,
+            raw_name:           h.file_name.clone(),
+            comment:            h.file_comment.clone(),
+            compression_method: CompressionMethod::from_u16(h.compression_method),
+            raw_compression_method: h.compression_method,
+            deflate_option:     deflate_option(&h.general_purpose_bit_flag, h.compression_method),
             last_modified_datetime: h.last_modified_datetime.to_tuple(),
             crc32:              h.crc32,
-            compressed_size:    h.compressed_size,
-            uncompressed_size:  h.uncompressed_size,
-            local_file_header_offset: h.relative_offset_of_local_header,
+            compressed_size:    zip64.compressed_size,
+            uncompressed_size:  zip64.uncompressed_size,
+            local_file_header_offset: zip64.relative_offset_of_local_header,
             is_encrypted:       h.is_encrypted(),
+            encryption_check_byte: encryption_check_byte(h.general_purpose_bit_flag.clone(),
+                                                         h.compression_method, h.crc32,
+                                                         &h.last_modified_datetime),
+            extra_field:        h.extra_field.clone(),
+            version_made_by:    h.version_made_by,
+            internal_file_attributes: h.internal_file_attributes,
+            external_file_attributes: h.external_file_attributes,
+            recovered:          false,
+        };
+        match info.unicode_path_name() {
+            Some(name) => info.name = MaybeUTF8::from_str(name),
+            None => {}
+        }
+        Ok(info)
+    }
+
+    // fills a FileInfo struct from a local file header found at `offset`, when the central
+    // directory can't be used. The CRC32 and sizes are passed in, since they may come from a
+    // data descriptor or ZIP64 extra field instead of the header.
+    pub fn from_lfh(h: &format::LocalFileHeader, offset: u64, crc32: u32,
+                    compressed_size: u64, uncompressed_size: u64) -> FileInfo {
+        let mut info = FileInfo {
+            name:               h.file_name.clone(),
+            raw_name:           h.file_name.clone(),
+            comment:            MaybeUTF8::new(),
+            compression_method: CompressionMethod::from_u16(h.compression_method),
+            raw_compression_method: h.compression_method,
+            deflate_option:     deflate_option(&h.general_purpose_bit_flag, h.compression_method),
+            last_modified_datetime: h.last_modified_datetime.to_tuple(),
+            crc32:              crc32,
+            compressed_size:    compressed_size,
+            uncompressed_size:  uncompressed_size,
+            local_file_header_offset: offset,
+            is_encrypted:       h.is_encrypted(),
+            encryption_check_byte: encryption_check_byte(h.general_purpose_bit_flag.clone(),
+                                                         h.compression_method, crc32,
+                                                         &h.last_modified_datetime),
+            extra_field:        h.extra_field.clone(),
+            version_made_by:    0,
+            internal_file_attributes: 0,
+            external_file_attributes: 0,
+            recovered:          true,
+        };
+        match info.unicode_path_name() {
+            Some(name) => info.name = MaybeUTF8::from_str(name),
+            None => {}
+        }
+        info
+    }
+
+    /// The name in the Info-ZIP Unicode path extra field, which tools that write names in the
+    /// local code page add with the UTF-8 name. It is ignored if the header name was changed
+    /// without updating it, that is if the CRC32 of the header name doesn't match.
+    pub fn unicode_path_name(&self) -> Option<String> {
+        let data = match self.extra_record(extra::UNICODE_PATH_ID) {
+            Some(data) => data,
+            None => return None,
+        };
+        match UnicodePath::parse(data) {
+            Ok(field) => {
+                if field.name_crc32 == crc32::crc32(self.raw_name.as_bytes()) { Some(field.name) } else { None }
+            }
+            Err(_) => None,
+        }
+    }
+
+    /// The name with backslashes turned into slashes if the entry was made on MS-DOS or
+    /// Windows, where some tools write them as separators. Elsewhere a backslash is a valid
+    /// character of a file name, so names from other systems are left alone.
+    pub fn normalized_name(&self) -> MaybeUTF8 {
+        match self.host_system() {
+            format::MsDos | format::WindowsNtfs | format::Vfat | format::Os2 => {}
+            _ => return self.name.clone(),
+        }
+        let bytes: Vec<u8> = self.name.as_bytes().iter().map(|&b| if b == b'\\' { b'/' } else { b }).collect();
+        match self.name {
+            // replacing an ASCII character keeps it UTF-8
+            maybe_utf8::UTF8(_) => MaybeUTF8::from_str(String::from_utf8(bytes).unwrap()),
+            maybe_utf8::Bytes(_) => MaybeUTF8::from_bytes(bytes),
+        }
+    }
+
+    /// The name as text, for display: used as is if it is valid UTF-8 (many tools write UTF-8
+    /// without setting the flag), else decoded as CP437.
+    pub fn display_name(&self) -> String {
+        match self.name.as_str() {
+            Some(s) => s.to_string(),
+            None => self.name.to_string_cp437(),
+        }
+    }
+
+    /// The Unix user ID of the owner, from the Info-ZIP Unix owner extra field, or else the old
+    /// Info-ZIP Unix extra field (whose owner is only in the local header).
+    pub fn uid(&self) -> Option<u64> {
+        self.unix_owner().map(|(uid, _)| uid)
+    }
+
+    /// The Unix group ID of the owner, found like `uid`.
+    pub fn gid(&self) -> Option<u64> {
+        self.unix_owner().map(|(_, gid)| gid)
+    }
+
+    fn unix_owner(&self) -> Option<(u64, u64)> {
+        match self.extra_record(extra::UNIX_OWNER_ID).map(UnixOwner::parse) {
+            Some(Ok(owner)) => return Some((owner.uid, owner.gid)),
+            _ => {}
+        }
+        match self.extra_record(extra::INFOZIP_UNIX_ID).map(InfoZipUnix::parse) {
+            Some(Ok(InfoZipUnix { uid: Some(uid), gid: Some(gid), .. })) => Some((uid as u64, gid as u64)),
+            _ => None,
+        }
+    }
+
+    /// Seconds and nanoseconds since the Unix epoch at which the entry was last modified, from
+    /// the most precise source there is: the NTFS extra field (100 ns), the extended timestamp
+    /// or old Info-ZIP Unix extra field (1 s), or else the MS-DOS date and time, which only have
+    /// a 2-second precision and are taken as local time, as the tools that write them do.
+    pub fn last_modified(&self) -> (i64, u32) {
+        match self.extra_record(extra::NTFS_ID).map(NtfsTimestamps::parse) {
+            Some(Ok(times)) => return extra::filetime_to_unix(times.modified),
+            _ => {}
+        }
+        match self.extra_record(extra::EXTENDED_TIMESTAMP_ID).map(ExtendedTimestamp::parse) {
+            Some(Ok(ExtendedTimestamp { modified: Some(modified), .. })) => return (modified as i64, 0),
+            _ => {}
+        }
+        match self.extra_record(extra::INFOZIP_UNIX_ID).map(InfoZipUnix::parse) {
+            Some(Ok(field)) => return (field.modified as i64, 0),
+            _ => {}
+        }
+        let (year, month, day, hour, minute, second) = self.last_modified_datetime;
+        (format::MsdosDateTime::new(year, month, day, hour, minute, second).to_epoch_seconds(), 0)
+    }
+
+    /// The seconds of `last_modified`.
+    pub fn modified_unix_time(&self) -> i64 {
+        let (seconds, _) = self.last_modified();
+        seconds
+    }
+
+    // the data of the first record with the given header ID; these accessors can't report a
+    // malformed extra field, so they make do without the records it may hide
+    fn extra_record<'a>(&'a self, id: u16) -> Option<&'a [u8]> {
+        match extra::find(self.extra_field.as_slice(), id) {
+            Ok(data) => data,
+            Err(_) => None,
+        }
+    }
+
+    /// Decodes the records of the extra field (as stored in the central directory).
+    pub fn extra_records<'a>(&'a self) -> ZipResult<Vec<ExtraRecord<'a>>> {
+        extra::parse_records(self.extra_field.as_slice())
+    }
+
+    /// Whether the entry is a directory: its name ends with a separator, or its attributes say so
+    /// (some tools, like Windows Explorer, only set the attributes).
+    pub fn is_dir(&self) -> bool {
+        let name = self.name.as_bytes();
+        if !name.is_empty() && (name[name.len() - 1] == b'/' || name[name.len() - 1] == b'\\') {
+            return true;
+        }
+        if self.msdos_attributes() & format::MSDOS_DIRECTORY != 0 {
+            return true;
+        }
+        match self.unix_mode() {
+            Some(mode) => mode & 0o170000 == 0o040000, // S_IFDIR
+            None => false,
+        }
+    }
+
+    pub fn is_file(&self) -> bool {
+        !self.is_dir()
+    }
+
+    /// Whether the entry is metadata added by the macOS Finder: under `__MACOSX/` (resource
+    /// forks), a `.DS_Store` file, or an AppleDouble file, whose name starts with `._`.
+    pub fn is_macos_metadata(&self) -> bool {
+        let name = self.name.as_bytes();
+        if name.starts_with(b"__MACOSX/") || name.starts_with(b"__MACOSX\\") || name == b"__MACOSX" {
+            return true;
         }
+        let trimmed = match name.last() {
+            Some(&b'/') | Some(&b'\\') => name.slice_to(name.len() - 1),
+            _ => name,
+        };
+        let basename = match trimmed.iter().rposition(|&b| b == b'/' || b == b'\\') {
+            Some(i) => trimmed.slice_from(i + 1),
+            None => trimmed,
+        };
+        basename == b".DS_Store" || basename.starts_with(b"._")
+    }
+
+    /// Whether the entry is a symlink, whose data is the link target. Only entries made on Unix
+    /// can be symlinks.
+    pub fn is_symlink(&self) -> bool {
+        match self.unix_mode() {
+            Some(mode) => mode & 0o170000 == 0o120000, // S_IFLNK
+            None => false,
+        }
+    }
+
+    /// The system the entry was made on, which tells how to interpret its attributes.
+    pub fn host_system(&self) -> format::HostSystem {
+        format::HostSystem::from_u8((self.version_made_by >> 8) as u8)
+    }
+
+    /// The version of the specification supported by the creator, as major * 10 + minor.
+    pub fn creator_spec_version(&self) -> u8 {
+        self.version_made_by as u8
+    }
+
+    /// The Unix mode (file type and permission bits) of the entry, if it was made on Unix.
+    pub fn unix_mode(&self) -> Option<u32> {
+        format::unix_mode_of(self.version_made_by, self.external_file_attributes)
+    }
+
+    /// The MS-DOS attributes, a combination of the `format::MSDOS_*` constants. Most tools set
+    /// them whatever the host system.
+    pub fn msdos_attributes(&self) -> u8 {
+        self.external_file_attributes as u8
+    }
+
+    /// Whether the entry has a Unix mode without write permission, or else the MS-DOS
+    /// read-only attribute.
+    pub fn is_readonly(&self) -> bool {
+        match self.unix_mode() {
+            Some(mode) => mode & 0o222 == 0,
+            None => self.msdos_attributes() & format::MSDOS_READONLY != 0,
+        }
+    }
+
+    /// Whether the entry is marked as apparently text, which some tools use to convert line
+    /// endings.
+    pub fn is_text(&self) -> bool {
+        self.internal_file_attributes & 1 != 0
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        self.msdos_attributes() & format::MSDOS_HIDDEN != 0
     }
 }
 
+
+#[cfg(test)]
+mod test {
+    use std::io::{MemWriter, BufReader};
+    use format;
+    use format::CentralDirectoryHeader;
+    use maybe_utf8::MaybeUTF8;
+    use super::FileInfo;
+
+    fn named(name: MaybeUTF8) -> FileInfo {
+        let mut h = CentralDirectoryHeader::new();
+        h.file_name = name;
+        FileInfo::from_cdh(&h).unwrap()
+    }
+
+    #[test]
+    fn display_name() {
+        // without the UTF-8 flag, the bytes are kept, and decoded as CP437 for display
+        let f = named(MaybeUTF8::from_bytes(vec![b'c', b'a', b'f', 0x82, 0x80, 0xff]));
+        assert!(f.name.as_bytes() == [b'c', b'a', b'f', 0x82, 0x80, 0xff].as_slice());
+        assert_eq!(f.display_name().as_slice(), "caf\u00e9\u00c7\u00a0");
+        // unless they are valid UTF-8, which many tools write without the flag
+        let f = named(MaybeUTF8::from_bytes("café".as_bytes().to_vec()));
+        assert_eq!(f.display_name().as_slice(), "café");
+        let f = named(MaybeUTF8::from_str("café".to_string()));
+        assert_eq!(f.display_name().as_slice(), "café");
+    }
+
+    #[test]
+    fn comments() {
+        // only some entries have a comment, decoded as UTF-8 only with the flag
+        let mut w = MemWriter::new();
+        for &(name, comment, utf8) in [("a.txt", b"".as_slice(), false),
+                                       ("b.txt", "première".as_bytes(), true),
+                                       ("c.txt", b"\x82t\x82".as_slice(), false)].iter() {
+            let mut h = CentralDirectoryHeader::new();
+            h.general_purpose_bit_flag.set_utf8_names(utf8);
+            h.file_name = MaybeUTF8::from_str(name.to_string());
+            h.file_comment = MaybeUTF8::from_bytes(comment.to_vec());
+            h.write(&mut w).unwrap();
+        }
+        let bytes = w.unwrap();
+        let mut r = BufReader::new(bytes.as_slice());
+        let files: Vec<FileInfo> = range(0u, 3).map(|_| {
+            FileInfo::from_cdh(&CentralDirectoryHeader::read(&mut r).unwrap()).unwrap()
+        }).collect();
+        assert!(files[0].comment.as_bytes().is_empty());
+        assert_eq!(files[1].comment.as_str(), Some("première"));
+        assert!(files[2].comment.as_bytes() == b"\x82t\x82");
+        assert!(files[2].comment.as_str().is_none());
+        assert_eq!(files[2].comment.to_string_cp437().as_slice(), "\u00e9t\u00e9");
+    }
+
+    #[test]
+    fn deflate_options() {
+        for &(bits, option) in [(0u16, format::Normal), (2, format::Maximum),
+                                (4, format::Fast), (6, format::SuperFast)].iter() {
+            let mut h = CentralDirectoryHeader::new();
+            h.compression_method = 8;
+            h.general_purpose_bit_flag = format::GpFlags(bits | 0x0800);
+            assert_eq!(FileInfo::from_cdh(&h).unwrap().deflate_option, Some(option));
+            // meaningless for other methods, even with the bits set
+            h.compression_method = 0;
+            assert_eq!(FileInfo::from_cdh(&h).unwrap().deflate_option, None);
+            h.compression_method = 12;
+            assert_eq!(FileInfo::from_cdh(&h).unwrap().deflate_option, None);
+        }
+    }
+}
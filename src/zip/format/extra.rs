@@ -0,0 +1,619 @@
+//! Parsing and building of extra fields, see section 4.5 of APPNOTE.TXT.
+//!
+//! An extra field is a sequence of records, each made of a 2-byte header ID, a 2-byte data size
+//! and the data. Some of the records written by common tools can be decoded into ExtraRecord.
+
+use std::io::{BufReader, MemWriter, IoError, InvalidInput};
+use error;
+use error::ZipResult;
+
+pub static ZIP64_ID: u16 = 0x0001;
+pub static NTFS_ID: u16 = 0x000a;
+pub static EXTENDED_TIMESTAMP_ID: u16 = 0x5455;
+pub static INFOZIP_UNIX_ID: u16 = 0x5855;
+pub static UNICODE_PATH_ID: u16 = 0x7075;
+pub static UNIX_OWNER_ID: u16 = 0x7875;
+
+fn malformed<T>(desc: &'static str) -> ZipResult<T> {
+    Err(error::SomeIoError(IoError { kind: InvalidInput, desc: desc, detail: None }))
+}
+
+/// Iterates over the (header ID, data) records of an extra field. A record running past the
+/// end of the field yields an error, and ends the iteration.
+pub struct ExtraFieldIter<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> ExtraFieldIter<'a> {
+    pub fn new(extra_field: &'a [u8]) -> ExtraFieldIter<'a> {
+        ExtraFieldIter { rest: extra_field }
+    }
+}
+
+impl<'a> Iterator<ZipResult<(u16, &'a [u8])>> for ExtraFieldIter<'a> {
+    fn next(&mut self) -> Option<ZipResult<(u16, &'a [u8])>> {
+        if self.rest.is_empty() {
+            return None;
+        }
+        let rest = self.rest;
+        // nothing is left to read after an error
+        self.rest = rest.slice_from(rest.len());
+        if rest.len() < 4 {
+            return Some(malformed("truncated extra field record header"));
+        }
+        let id = (rest[0] as u16) | (rest[1] as u16 << 8);
+        let size = (rest[2] as uint) | (rest[3] as uint << 8);
+        if rest.len() < 4 + size {
+            return Some(malformed("extra field record runs past the end of the field"));
+        }
+        self.rest = rest.slice_from(4 + size);
+        Some(Ok((id, rest.slice(4, 4 + size))))
+    }
+}
+
+/// Splits an extra field into its (header ID, data) records, in order.
+pub fn parse(extra_field: &[u8]) -> ZipResult<Vec<(u16, Vec<u8>)>> {
+    let mut records = Vec::new();
+    for record in ExtraFieldIter::new(extra_field) {
+        let (id, data) = try!(record);
+        records.push((id, data.to_vec()));
+    }
+    Ok(records)
+}
+
+/// Builds an extra field from (header ID, data) records. Fails with TooLongField if a record
+/// has more data than its 16-bit size can tell.
+pub fn serialize(records: &[(u16, &[u8])]) -> ZipResult<Vec<u8>> {
+    let mut w = MemWriter::new();
+    for &(id, data) in records.iter() {
+        let size = match data.len().to_u16() {
+            Some(size) => size,
+            None => return Err(error::TooLongField),
+        };
+        // writing to memory can't fail
+        w.write_le_u16(id).unwrap();
+        w.write_le_u16(size).unwrap();
+        w.write(data).unwrap();
+    }
+    Ok(w.unwrap())
+}
+
+/// Returns the data of the first record with the given header ID, failing if a record before it
+/// is malformed.
+pub fn find<'a>(extra_field: &'a [u8], id: u16) -> ZipResult<Option<&'a [u8]>> {
+    for record in ExtraFieldIter::new(extra_field) {
+        let (header_id, data) = try!(record);
+        if header_id == id {
+            return Ok(Some(data));
+        }
+    }
+    Ok(None)
+}
+
+/// Returns a copy of an extra field where the first record with the given header ID has `data`
+/// (appended if there was none) and any other one with that ID is left out. Other records are
+/// kept as they are.
+pub fn replace(extra_field: &[u8], id: u16, data: &[u8]) -> ZipResult<Vec<u8>> {
+    let mut records = Vec::new();
+    let mut replaced = false;
+    for record in ExtraFieldIter::new(extra_field) {
+        let (header_id, record_data) = try!(record);
+        if header_id != id {
+            records.push((header_id, record_data));
+        } else if !replaced {
+            records.push((id, data));
+            replaced = true;
+        }
+    }
+    if !replaced {
+        records.push((id, data));
+    }
+    serialize(records.as_slice())
+}
+
+/// Returns a copy of an extra field without the records with the given header ID.
+pub fn remove(extra_field: &[u8], id: u16) -> ZipResult<Vec<u8>> {
+    let mut records = Vec::new();
+    for record in ExtraFieldIter::new(extra_field) {
+        let (header_id, data) = try!(record);
+        if header_id != id {
+            records.push((header_id, data));
+        }
+    }
+    serialize(records.as_slice())
+}
+
+/// The ZIP64 extended information record (0x0001): 64-bit values for the header fields that
+/// are saturated (0xFFFFFFFF, or 0xFFFF for the disk number), in header order. Which fields
+/// are present can't be told from the record itself, only from the header it belongs to.
+#[deriving(PartialEq, Show, Clone)]
+pub struct Zip64ExtraField {
+    pub uncompressed_size: Option<u64>,
+    pub compressed_size: Option<u64>,
+    pub local_header_offset: Option<u64>,
+    pub disk_start: Option<u32>,
+}
+
+impl Zip64ExtraField {
+    /// Parses the record for a header with the given (32-bit) values: there is a field for
+    /// each saturated one. A record too short for them is an error, anything after them is
+    /// ignored.
+    pub fn parse(data: &[u8], uncompressed_size: u32, compressed_size: u32,
+                 local_header_offset: u32, disk_start: u16) -> ZipResult<Zip64ExtraField> {
+        let mut r = BufReader::new(data);
+        let mut field = Zip64ExtraField {
+            uncompressed_size: None,
+            compressed_size: None,
+            local_header_offset: None,
+            disk_start: None,
+        };
+        let needed = (if uncompressed_size == 0xFFFFFFFF { 8 } else { 0 })
+                   + (if compressed_size == 0xFFFFFFFF { 8 } else { 0 })
+                   + (if local_header_offset == 0xFFFFFFFF { 8 } else { 0 })
+                   + (if disk_start == 0xFFFF { 4 } else { 0 });
+        if data.len() < needed {
+            return malformed("ZIP64 extra field is too short for its header");
+        }
+        if uncompressed_size == 0xFFFFFFFF {
+            field.uncompressed_size = Some(try_io!(r.read_le_u64()));
+        }
+        if compressed_size == 0xFFFFFFFF {
+            field.compressed_size = Some(try_io!(r.read_le_u64()));
+        }
+        if local_header_offset == 0xFFFFFFFF {
+            field.local_header_offset = Some(try_io!(r.read_le_u64()));
+        }
+        if disk_start == 0xFFFF {
+            field.disk_start = Some(try_io!(r.read_le_u32()));
+        }
+        Ok(field)
+    }
+
+    /// The data of the record (without its header ID and size), the present fields in order.
+    /// The header it goes with must have exactly those fields saturated.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut w = MemWriter::new();
+        // writing to memory can't fail
+        for &value in [self.uncompressed_size, self.compressed_size, self.local_header_offset].iter() {
+            match value {
+                Some(value) => w.write_le_u64(value).unwrap(),
+                None => {}
+            }
+        }
+        match self.disk_start {
+            Some(disk_start) => w.write_le_u32(disk_start).unwrap(),
+            None => {}
+        }
+        w.unwrap()
+    }
+}
+
+/// The extended timestamp record (0x5455), with Unix times in seconds. The copy in the central
+/// directory only ever has the modification time.
+#[deriving(PartialEq, Show, Clone)]
+pub struct ExtendedTimestamp {
+    pub modified: Option<i32>,
+    pub accessed: Option<i32>,
+    pub created: Option<i32>,
+}
+
+impl ExtendedTimestamp {
+    pub fn parse(data: &[u8]) -> ZipResult<ExtendedTimestamp> {
+        let mut r = BufReader::new(data);
+        let flags = try_io!(r.read_u8());
+        let mut times = [None, None, None];
+        for (i, time) in times.iter_mut().enumerate() {
+            // flags can announce times that are only in the local header's copy
+            if flags & (1 << i) != 0 && !r.eof() {
+                *time = Some(try_io!(r.read_le_i32()));
+            }
+        }
+        Ok(ExtendedTimestamp { modified: times[0], accessed: times[1], created: times[2] })
+    }
+}
+
+/// The NTFS record (0x000a), with times in 100-nanosecond intervals since 1601-01-01 UTC.
+#[deriving(PartialEq, Show, Clone)]
+pub struct NtfsTimestamps {
+    pub modified: u64,
+    pub accessed: u64,
+    pub created: u64,
+}
+
+impl NtfsTimestamps {
+    pub fn parse(data: &[u8]) -> ZipResult<NtfsTimestamps> {
+        // 4 reserved bytes, then attributes as (tag, size, data); tag 1 holds the timestamps
+        let mut r = BufReader::new(data);
+        try_io!(r.read_le_u32());
+        while !r.eof() {
+            let tag = try_io!(r.read_le_u16());
+            let size = try_io!(r.read_le_u16()) as uint;
+            if tag == 1 && size == 24 {
+                let modified = try_io!(r.read_le_u64());
+                let accessed = try_io!(r.read_le_u64());
+                let created = try_io!(r.read_le_u64());
+                return Ok(NtfsTimestamps { modified: modified, accessed: accessed, created: created });
+            }
+            try_io!(r.read_exact(size));
+        }
+        malformed("NTFS extra field without timestamps")
+    }
+
+    /// The data of the record: the reserved bytes and the timestamps attribute.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut w = MemWriter::new();
+        // writing to memory can't fail
+        w.write_le_u32(0).unwrap();
+        w.write_le_u16(1).unwrap();
+        w.write_le_u16(24).unwrap();
+        w.write_le_u64(self.modified).unwrap();
+        w.write_le_u64(self.accessed).unwrap();
+        w.write_le_u64(self.created).unwrap();
+        w.unwrap()
+    }
+}
+
+// 1970-01-01 UTC as a FILETIME
+static FILETIME_UNIX_EPOCH: u64 = 116444736000000000;
+
+/// Converts a FILETIME (100-nanosecond intervals since 1601-01-01 UTC) to seconds and
+/// nanoseconds since the Unix epoch. Before 1970 the seconds are rounded down, so that the
+/// nanoseconds are always positive.
+pub fn filetime_to_unix(filetime: u64) -> (i64, u32) {
+    if filetime >= FILETIME_UNIX_EPOCH {
+        let since = filetime - FILETIME_UNIX_EPOCH;
+        ((since / 10000000) as i64, (since % 10000000) as u32 * 100)
+    } else {
+        let before = FILETIME_UNIX_EPOCH - filetime;
+        let (seconds, rest) = ((before / 10000000) as i64, before % 10000000);
+        if rest == 0 { (-seconds, 0) } else { (-seconds - 1, (10000000 - rest) as u32 * 100) }
+    }
+}
+
+/// Converts seconds and nanoseconds since the Unix epoch to a FILETIME, saturating for times
+/// it can't represent.
+pub fn unix_to_filetime(seconds: i64, nanoseconds: u32) -> u64 {
+    let intervals = (nanoseconds / 100) as u64;
+    if seconds >= 0 {
+        (seconds as u64).checked_mul(&10000000)
+            .and_then(|s| s.checked_add(&intervals))
+            .and_then(|s| s.checked_add(&FILETIME_UNIX_EPOCH))
+            .unwrap_or(0xFFFFFFFFFFFFFFFF)
+    } else {
+        let before = (-(seconds + 1)) as u64 + 1;
+        match before.checked_mul(&10000000) {
+            Some(before) if before <= FILETIME_UNIX_EPOCH + intervals => FILETIME_UNIX_EPOCH + intervals - before,
+            _ => 0,
+        }
+    }
+}
+
+/// The old Info-ZIP Unix record (0x5855), with Unix times in seconds. Only the copy in the local
+/// header has the (16-bit) owner.
+#[deriving(PartialEq, Show, Clone)]
+pub struct InfoZipUnix {
+    pub accessed: i32,
+    pub modified: i32,
+    pub uid: Option<u16>,
+    pub gid: Option<u16>,
+}
+
+impl InfoZipUnix {
+    pub fn parse(data: &[u8]) -> ZipResult<InfoZipUnix> {
+        if data.len() < 8 {
+            return malformed("Info-ZIP Unix extra field is too short for its times");
+        }
+        let mut r = BufReader::new(data);
+        let accessed = try_io!(r.read_le_i32());
+        let modified = try_io!(r.read_le_i32());
+        let (uid, gid) = if data.len() >= 12 {
+            (Some(try_io!(r.read_le_u16())), Some(try_io!(r.read_le_u16())))
+        } else {
+            (None, None)
+        };
+        Ok(InfoZipUnix { accessed: accessed, modified: modified, uid: uid, gid: gid })
+    }
+}
+
+/// The Info-ZIP Unix owner record (0x7875), which supersedes the owner in 0x5855.
+#[deriving(PartialEq, Show, Clone)]
+pub struct UnixOwner {
+    pub uid: u64,
+    pub gid: u64,
+}
+
+impl UnixOwner {
+    pub fn parse(data: &[u8]) -> ZipResult<UnixOwner> {
+        let mut r = BufReader::new(data);
+        let version = try_io!(r.read_u8());
+        if version != 1 {
+            return malformed("unknown Unix owner extra field version");
+        }
+        // each ID is preceded by its size: 4 in practice, but any size up to 8 is allowed
+        let mut ids = [0u64, ..2];
+        for id in ids.iter_mut() {
+            let size = try_io!(r.read_u8()) as uint;
+            if size > 8 {
+                return malformed("Unix owner extra field ID is too large");
+            }
+            *id = if size == 0 { 0 } else { try_io!(r.read_le_uint_n(size)) };
+        }
+        Ok(UnixOwner { uid: ids[0], gid: ids[1] })
+    }
+
+    /// The data of the record, with 4-byte IDs like Info-ZIP writes, or 8-byte ones for IDs
+    /// that don't fit.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut w = MemWriter::new();
+        // writing to memory can't fail
+        w.write_u8(1).unwrap();
+        for &id in [self.uid, self.gid].iter() {
+            if id <= 0xFFFFFFFF {
+                w.write_u8(4).unwrap();
+                w.write_le_u32(id as u32).unwrap();
+            } else {
+                w.write_u8(8).unwrap();
+                w.write_le_u64(id).unwrap();
+            }
+        }
+        w.unwrap()
+    }
+}
+
+/// The Info-ZIP Unicode path record (0x7075): the UTF-8 name of an entry whose header has a
+/// name in another encoding.
+#[deriving(PartialEq, Show, Clone)]
+pub struct UnicodePath {
+    /// CRC32 of the name in the header, to tell whether it was changed without updating this
+    pub name_crc32: u32,
+    pub name: String,
+}
+
+impl UnicodePath {
+    pub fn parse(data: &[u8]) -> ZipResult<UnicodePath> {
+        let mut r = BufReader::new(data);
+        let version = try_io!(r.read_u8());
+        if version != 1 {
+            return malformed("unknown Unicode path extra field version");
+        }
+        let name_crc32 = try_io!(r.read_le_u32());
+        match String::from_utf8(try_io!(r.read_to_end())) {
+            Ok(name) => Ok(UnicodePath { name_crc32: name_crc32, name: name }),
+            Err(_) => Err(error::NonUTF8Field),
+        }
+    }
+}
+
+/// A record of an extra field, decoded when its header ID is known.
+#[deriving(PartialEq, Show, Clone)]
+pub enum ExtraRecord<'a> {
+    /// ZIP64 extended information (0x0001): 64-bit values for the header fields that are
+    /// saturated, in header order (uncompressed size, compressed size, offset, disk number).
+    /// Which ones are present depends on the header, see `Zip64ExtraField`.
+    Zip64Record(&'a [u8]),
+    TimestampRecord(ExtendedTimestamp),
+    NtfsRecord(NtfsTimestamps),
+    InfoZipUnixRecord(InfoZipUnix),
+    UnixOwnerRecord(UnixOwner),
+    UnicodePathRecord(UnicodePath),
+    UnknownRecord(u16, &'a [u8]),
+}
+
+impl<'a> ExtraRecord<'a> {
+    pub fn parse(id: u16, data: &'a [u8]) -> ZipResult<ExtraRecord<'a>> {
+        let record =
+            if id == ZIP64_ID { Zip64Record(data) }
+            else if id == NTFS_ID { NtfsRecord(try!(NtfsTimestamps::parse(data))) }
+            else if id == EXTENDED_TIMESTAMP_ID { TimestampRecord(try!(ExtendedTimestamp::parse(data))) }
+            else if id == INFOZIP_UNIX_ID { InfoZipUnixRecord(try!(InfoZipUnix::parse(data))) }
+            else if id == UNICODE_PATH_ID { UnicodePathRecord(try!(UnicodePath::parse(data))) }
+            else if id == UNIX_OWNER_ID { UnixOwnerRecord(try!(UnixOwner::parse(data))) }
+            else { UnknownRecord(id, data) };
+        Ok(record)
+    }
+}
+
+/// Decodes all the records of an extra field, failing on the first malformed one.
+pub fn parse_records<'a>(extra_field: &'a [u8]) -> ZipResult<Vec<ExtraRecord<'a>>> {
+    let mut records = Vec::new();
+    for record in ExtraFieldIter::new(extra_field) {
+        let (id, data) = try!(record);
+        records.push(try!(ExtraRecord::parse(id, data)));
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse, serialize, find, replace, remove, ZIP64_ID, UNIX_OWNER_ID};
+    use super::{parse_records, ExtendedTimestamp, TimestampRecord, Zip64Record, UnknownRecord};
+    use super::{UnicodePath, UNICODE_PATH_ID, InfoZipUnix, UnixOwner};
+    use super::{NtfsTimestamps, filetime_to_unix, unix_to_filetime};
+    use super::Zip64ExtraField;
+    use error;
+    use maybe_utf8::MaybeUTF8;
+    use fileinfo::FileInfo;
+    use format::CentralDirectoryHeader;
+
+    // a ZIP64 record, then an unknown one
+    static FIELD: &'static [u8] = &[0x01, 0x00, 0x02, 0x00, 0xaa, 0xbb,
+                                     0x34, 0x12, 0x01, 0x00, 0xcc];
+
+    #[test]
+    fn parse_and_serialize() {
+        let records = parse(FIELD).unwrap();
+        assert!(records == vec![(ZIP64_ID, vec![0xaa, 0xbb]), (0x1234, vec![0xcc])]);
+        let borrowed: Vec<(u16, &[u8])> = records.iter().map(|&(id, ref data)| (id, data.as_slice())).collect();
+        assert!(serialize(borrowed.as_slice()).unwrap().as_slice() == FIELD);
+        assert!(parse(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn malformed() {
+        // a truncated record header, and a record longer than what is left
+        assert!(parse(&[0x01, 0x00, 0x02]).is_err());
+        assert!(parse(&[0x01, 0x00, 0x03, 0x00, 0xaa, 0xbb]).is_err());
+        assert!(find(&[0x01, 0x00, 0x03, 0x00, 0xaa, 0xbb], 0x1234).is_err());
+        // the records before the malformed one are still found
+        let mut field = FIELD.to_vec();
+        field.push_all(&[0x55, 0x55, 0xff, 0xff]);
+        assert!(find(field.as_slice(), ZIP64_ID).unwrap() == Some(&[0xaa, 0xbb][]));
+        assert!(find(field.as_slice(), UNIX_OWNER_ID).is_err());
+        assert!(replace(field.as_slice(), ZIP64_ID, &[]).is_err());
+        assert!(remove(field.as_slice(), ZIP64_ID).is_err());
+    }
+
+    #[test]
+    fn too_long() {
+        let data = Vec::from_elem(0x10000, 0u8);
+        assert!(serialize(&[(ZIP64_ID, data.as_slice())]).is_err());
+    }
+
+    #[test]
+    fn find_records() {
+        assert!(find(FIELD, 0x1234).unwrap() == Some(&[0xcc][]));
+        assert!(find(FIELD, UNIX_OWNER_ID).unwrap() == None);
+    }
+
+    #[test]
+    fn replace_and_remove() {
+        let replaced = replace(FIELD, ZIP64_ID, &[1, 2, 3]).unwrap();
+        assert!(parse(replaced.as_slice()).unwrap() == vec![(ZIP64_ID, vec![1, 2, 3]), (0x1234, vec![0xcc])]);
+        let appended = replace(FIELD, UNIX_OWNER_ID, &[9]).unwrap();
+        assert!(parse(appended.as_slice()).unwrap().last() == Some(&(UNIX_OWNER_ID, vec![9])));
+        // unknown records are kept verbatim
+        assert!(remove(FIELD, ZIP64_ID).unwrap().as_slice() == FIELD.slice_from(6));
+        assert!(remove(FIELD, UNIX_OWNER_ID).unwrap().as_slice() == FIELD);
+    }
+
+    #[test]
+    fn zip64_extra_field() {
+        let data = [0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00,   // uncompressed size
+                    0x00, 0x00, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00,   // compressed size
+                    0x10, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00,   // local header offset
+                    0x03, 0x00, 0x00, 0x00];                          // disk start
+        // all four present
+        let all = Zip64ExtraField::parse(&data, 0xFFFFFFFF, 0xFFFFFFFF, 0xFFFFFFFF, 0xFFFF).unwrap();
+        assert_eq!(all, Zip64ExtraField { uncompressed_size: Some(0x1_0000_0000), compressed_size: Some(0x8000_0000),
+                                          local_header_offset: Some(0x2_0000_0010), disk_start: Some(3) });
+        assert!(all.serialize().as_slice() == data.as_slice());
+
+        // only some: the fields present are packed in order, whatever is missing before them
+        let some = Zip64ExtraField::parse(data.slice_from(8), 100, 0xFFFFFFFF, 0xFFFFFFFF, 0).unwrap();
+        assert_eq!(some, Zip64ExtraField { uncompressed_size: None, compressed_size: Some(0x8000_0000),
+                                           local_header_offset: Some(0x2_0000_0010), disk_start: None });
+        assert!(some.serialize().as_slice() == data.slice(8, 24));
+        // nothing saturated, and data left over, which is ignored
+        assert_eq!(Zip64ExtraField::parse(&data, 1, 2, 3, 4).unwrap().serialize(), vec![]);
+
+        // too short for what the header says is there
+        assert!(Zip64ExtraField::parse(data.slice_to(27), 0xFFFFFFFF, 0xFFFFFFFF, 0xFFFFFFFF, 0xFFFF).is_err());
+        assert!(Zip64ExtraField::parse(data.slice_to(4), 0xFFFFFFFF, 0, 0, 0).is_err());
+        assert!(Zip64ExtraField::parse(&[], 0, 0, 0xFFFFFFFF, 0).is_err());
+
+        // and the 64-bit values end up in FileInfo
+        let mut h = CentralDirectoryHeader::new();
+        h.uncompressed_size = 0xFFFFFFFF;
+        h.compressed_size = 0xFFFFFFFF;
+        h.relative_offset_of_local_header = 0xFFFFFFFF;
+        h.extra_field = serialize(&[(ZIP64_ID, data.slice_to(24))]).unwrap();
+        let f = FileInfo::from_cdh(&h).unwrap();
+        assert_eq!((f.uncompressed_size, f.compressed_size), (0x1_0000_0000, 0x8000_0000));
+        assert_eq!(f.local_file_header_offset, 0x2_0000_0010);
+        h.extra_field = serialize(&[(ZIP64_ID, data.slice_to(16))]).unwrap();
+        assert!(FileInfo::from_cdh(&h).is_err());
+    }
+
+    #[test]
+    fn typed_records() {
+        // an extended timestamp with the modification and access times, a ZIP64 record and an
+        // unknown one
+        let field = [0x55, 0x54, 0x09, 0x00, 0x03, 0x00, 0xca, 0x9a, 0x3b, 0x01, 0x00, 0x00, 0x00,
+                     0x01, 0x00, 0x02, 0x00, 0xaa, 0xbb,
+                     0x34, 0x12, 0x01, 0x00, 0xcc];
+        let timestamp = ExtendedTimestamp { modified: Some(1000000000), accessed: Some(1), created: None };
+        assert!(parse_records(field.as_slice()).unwrap() ==
+                vec![TimestampRecord(timestamp), Zip64Record(&[0xaa, 0xbb][]), UnknownRecord(0x1234, &[0xcc][])]);
+
+        // the central directory copy, whose flags still announce all three times
+        let field = [0x55, 0x54, 0x05, 0x00, 0x07, 0x00, 0xca, 0x9a, 0x3b];
+        let timestamp = ExtendedTimestamp { modified: Some(1000000000), accessed: None, created: None };
+        assert!(parse_records(field.as_slice()).unwrap() == vec![TimestampRecord(timestamp)]);
+
+        // a known record that doesn't parse
+        assert!(parse_records(&[0x75, 0x78, 0x01, 0x00, 0x02]).is_err());
+    }
+
+    #[test]
+    fn unicode_path() {
+        // version 1, the CRC32 of "cafe.txt", and "café.txt" in UTF-8
+        let data = [0x01, 0x40, 0x75, 0x63, 0x3a, 0x63, 0x61, 0x66, 0xc3, 0xa9, 0x2e, 0x74, 0x78, 0x74];
+        let field = UnicodePath::parse(data.as_slice()).unwrap();
+        assert_eq!(field.name_crc32, 0x3a637540);
+        assert_eq!(field.name.as_slice(), "café.txt");
+        assert!(UnicodePath::parse(&[0x02, 0x00, 0x00, 0x00, 0x00]).is_err());
+        assert_eq!(UnicodePath::parse(&[0x01, 0x00, 0x00, 0x00, 0x00, 0xff]).unwrap_err(), error::NonUTF8Field);
+
+        // the field names the entry while its CRC32 matches the name in the header, and is
+        // ignored once the header name was changed without it
+        let mut h = CentralDirectoryHeader::new();
+        h.file_name = MaybeUTF8::from_str("cafe.txt".to_string());
+        h.extra_field = serialize(&[(UNICODE_PATH_ID, data.as_slice())]).unwrap();
+        let f = FileInfo::from_cdh(&h).unwrap();
+        assert!(f.name == "café.txt");
+        assert!(f.raw_name == "cafe.txt");
+        h.file_name = MaybeUTF8::from_str("renamed.txt".to_string());
+        let f = FileInfo::from_cdh(&h).unwrap();
+        assert!(f.name == "renamed.txt");
+        assert!(f.unicode_path_name().is_none());
+    }
+
+    #[test]
+    fn info_zip_unix() {
+        // the access and modification times, then a 16-bit uid and gid, as in a local header
+        let data = [0x01, 0x00, 0x00, 0x00, 0x00, 0xca, 0x9a, 0x3b, 0xe8, 0x03, 0x64, 0x00];
+        assert_eq!(InfoZipUnix::parse(data.as_slice()).unwrap(),
+                   InfoZipUnix { accessed: 1, modified: 1000000000, uid: Some(1000), gid: Some(100) });
+        // the central directory copy only has the times
+        assert_eq!(InfoZipUnix::parse(data.slice_to(8)).unwrap(),
+                   InfoZipUnix { accessed: 1, modified: 1000000000, uid: None, gid: None });
+        assert!(InfoZipUnix::parse(data.slice_to(7)).is_err());
+    }
+
+    #[test]
+    fn unix_owner() {
+        // version 1, then the uid and gid, each after its size: 4 bytes like Info-ZIP writes, then 2
+        let data = [0x01, 0x04, 0xe8, 0x03, 0x00, 0x00, 0x02, 0x64, 0x00];
+        assert_eq!(UnixOwner::parse(data.as_slice()).unwrap(), UnixOwner { uid: 1000, gid: 100 });
+        assert!(UnixOwner::parse(&[0x02, 0x00, 0x00]).is_err());
+        assert!(UnixOwner::parse(&[0x01, 0x09, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x00]).is_err());
+
+        let owner = UnixOwner { uid: 1000, gid: 100 };
+        assert!(owner.serialize().as_slice() == [0x01, 0x04, 0xe8, 0x03, 0x00, 0x00, 0x04, 0x64, 0x00, 0x00, 0x00].as_slice());
+        let large = UnixOwner { uid: 1 << 40, gid: 100 };
+        assert_eq!(UnixOwner::parse(large.serialize().as_slice()).unwrap(), large);
+    }
+
+    #[test]
+    fn ntfs_timestamps() {
+        // the reserved bytes, an unknown attribute, then attribute 1 with the modification,
+        // access and creation times; 2001-09-09 01:46:40.1234567 UTC is 0x01c138d145125687
+        let data = [0x00, 0x00, 0x00, 0x00,
+                    0x02, 0x00, 0x02, 0x00, 0xaa, 0xbb,
+                    0x01, 0x00, 0x18, 0x00,
+                    0x87, 0x56, 0x12, 0x45, 0xd1, 0x38, 0xc1, 0x01,
+                    0x88, 0x56, 0x12, 0x45, 0xd1, 0x38, 0xc1, 0x01,
+                    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        let times = NtfsTimestamps::parse(data.as_slice()).unwrap();
+        assert_eq!(times, NtfsTimestamps { modified: 0x01c138d145125687, accessed: 0x01c138d145125688, created: 0 });
+        assert_eq!(NtfsTimestamps::parse(times.serialize().as_slice()).unwrap(), times);
+        assert!(NtfsTimestamps::parse(data.slice_to(10)).is_err());
+
+        assert_eq!(filetime_to_unix(times.modified), (1000000000, 123456700));
+        assert_eq!(unix_to_filetime(1000000000, 123456700), times.modified);
+        // 1601-01-01, and the 100 ns before the Unix epoch
+        assert_eq!(filetime_to_unix(0), (-11644473600, 0));
+        assert_eq!(filetime_to_unix(116444736000000000 - 1), (-1, 999999900));
+        assert_eq!(unix_to_filetime(-1, 999999900), 116444736000000000 - 1);
+    }
+}
@@ -0,0 +1,1990 @@
+//! Internal format stuffs.
+
+use std::cmp;
+use std::fmt;
+use std::io::{IoResult, IoError, InvalidInput, Seek, SeekSet};
+use std::i64;
+use time;
+use error;
+use error::ZipResult;
+use maybe_utf8;
+use maybe_utf8::MaybeUTF8;
+use fileinfo::FileInfo;
+
+pub mod extra;
+
+// Names and comments are only interpreted as UTF-8 when the UTF-8 flag (bit 11) is set, in which
+// case invalid UTF-8 is an error. Otherwise they are kept as raw bytes (usually CP437, or whatever
+// the local code page of the creator was), and never fail to parse.
+fn read_maybe_utf8<T:Reader>(r: &mut T, should_be_utf8: bool, len: uint) -> ZipResult<MaybeUTF8> {
+    let v = try_io!(r.read_exact(len));
+    if should_be_utf8 {
+        match String::from_utf8(v) {
+            Ok(s) => Ok(MaybeUTF8::from_str(s)),
+            Err(_) => Err(error::NonUTF8Field),
+        }
+    } else {
+        Ok(MaybeUTF8::from_bytes(v))
+    }
+}
+
+fn write_maybe_utf8<T:Writer>(w: &mut T, should_be_utf8: bool, s: &MaybeUTF8) -> ZipResult<()> {
+    if should_be_utf8 {
+        match s.as_str() {
+            Some(s) => try_io!(w.write(s.as_bytes())),
+            None => return Err(error::NonUTF8Field),
+        }
+    } else {
+        try_io!(w.write(s.as_bytes()));
+    }
+    Ok(())
+}
+
+// Positions in an archive are u64, while Seek takes an i64: fails instead of wrapping around.
+pub fn seek_to<S:Seek>(s: &mut S, pos: u64) -> IoResult<()> {
+    if pos > i64::MAX as u64 {
+        return Err(IoError { kind: InvalidInput, desc: "offset too large to seek to", detail: Some(format!("{}", pos)) });
+    }
+    s.seek(pos as i64, SeekSet)
+}
+
+// `start + len`, failing instead of wrapping around when a corrupt size or offset is huge
+pub fn offset_after(start: u64, len: u64) -> IoResult<u64> {
+    match start.checked_add(&len) {
+        Some(end) => Ok(end),
+        None => Err(IoError { kind: InvalidInput, desc: "offset overflows 64 bits", detail: None }),
+    }
+}
+
+// Reads the 4-byte signature a record starts with, failing with InvalidSignature, the bytes
+// actually found and where, if it isn't `expected`.
+pub fn expect_signature<T:Reader+Seek>(r: &mut T, expected: u32) -> ZipResult<()> {
+    let offset = try_io!(r.tell());
+    let found = try_io!(r.read_le_u32());
+    if found != expected {
+        return Err(error::InvalidSignature(expected, found, offset));
+    }
+    Ok(())
+}
+
+// Headers don't store the lengths of their variable-size fields: they are computed from the
+// fields themselves when writing, so they can't disagree, and a field that is too long fails
+// instead of being truncated. The lengths are checked before anything is written, so that a
+// failed write doesn't leave half a header behind.
+fn ensure_u16_field_length(len: uint) -> ZipResult<u16> {
+    match len.to_u16() {
+        Some(v) => Ok(v),
+        None => Err(error::TooLongField),
+    }
+}
+
+// days between 1970-01-01 and the given date of the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+// the date of the proleptic Gregorian calendar the given number of days after 1970-01-01
+fn civil_from_days(days: i64) -> (i64, uint, uint) {
+    let days = days + 719468;
+    let era = (if days >= 0 { days } else { days - 146096 }) / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153; // starting from March
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month as uint, day as uint)
+}
+
+// the offset of local time from UTC at the given time, in seconds
+fn local_offset(epoch_seconds: i64) -> i64 {
+    time::at(time::Timespec::new(epoch_seconds, 0)).tm_gmtoff as i64
+}
+
+// the range of MS-DOS timestamps, in seconds since the Unix epoch, taken as UTC
+static MIN_EPOCH_SECONDS: i64 = 315532800;   // 1980-01-01 00:00:00
+static MAX_EPOCH_SECONDS: i64 = 4354819198;  // 2107-12-31 23:59:58
+
+fn days_in_month(year: uint, month: uint) -> uint {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// whether the fields fit an MS-DOS timestamp: years 1980 to 2107, and a real date and time
+fn is_valid_datetime(year: uint, month: uint, day: uint, hour: uint, minute: uint, second: uint) -> bool {
+    year >= 1980 && year <= 2107 &&
+        month >= 1 && month <= 12 &&
+        day >= 1 && day <= days_in_month(year, month) &&
+        hour <= 23 && minute <= 59 && second <= 59
+}
+
+/// An MS-DOS date and time format.
+/// This is not very accurate (2-second granularity), nor guaranteed to be valid.
+#[deriving(PartialEq, Eq, Clone)]
+pub struct MsdosDateTime {
+    time: u16,
+    date: u16,
+}
+
+impl MsdosDateTime {
+    /// Fields out of range are masked, which gives a corrupt timestamp; see `new_checked`.
+    pub fn new(year: uint, month: uint, day: uint,
+               hour: uint, minute: uint, second: uint) -> MsdosDateTime {
+        let year = year - 1980;
+        MsdosDateTime {
+            time: (((hour & 0b11111) << 11) |
+                   ((minute & 0b111111) << 5) |
+                   ((second / 2) & 0b11111)) as u16,
+            date: (((year & 0b1111111) << 9) |
+                   ((month & 0b1111) << 5) |
+                   (day & 0b11111)) as u16,
+        }
+    }
+
+    /// Like `new`, but fails on dates before 1980 or after 2107, and on dates and times that
+    /// don't exist. Seconds are stored with a 2-second granularity, so odd ones are rounded
+    /// down.
+    pub fn new_checked(year: uint, month: uint, day: uint,
+                       hour: uint, minute: uint, second: uint) -> ZipResult<MsdosDateTime> {
+        if !is_valid_datetime(year, month, day, hour, minute, second) {
+            return Err(error::SomeIoError(IoError {
+                kind: InvalidInput,
+                desc: "invalid MS-DOS date and time",
+                detail: Some(format!("{}-{:02}-{:02} {:02}:{:02}:{:02}", year, month, day, hour, minute, second)),
+            }));
+        }
+        Ok(MsdosDateTime::new(year, month, day, hour, minute, second))
+    }
+
+    /// Whether the timestamp is a real date and time; those read from archives may not be
+    /// (zero() isn't either).
+    pub fn is_valid(&self) -> bool {
+        let (year, month, day, hour, minute, second) = self.to_tuple();
+        is_valid_datetime(year, month, day, hour, minute, second)
+    }
+
+    /// The timestamp of a time in seconds since the Unix epoch, in local time as MS-DOS (and
+    /// most tools) did. Times before 1980 or after 2107 are clamped to the ends of the range;
+    /// see `from_epoch_seconds_checked` to detect that.
+    pub fn from_epoch_seconds(epoch_seconds: i64) -> MsdosDateTime {
+        MsdosDateTime::from_epoch_seconds_utc(epoch_seconds + local_offset(epoch_seconds))
+    }
+
+    /// Like `from_epoch_seconds`, but keeps the time in UTC.
+    pub fn from_epoch_seconds_utc(epoch_seconds: i64) -> MsdosDateTime {
+        let epoch_seconds = if epoch_seconds < MIN_EPOCH_SECONDS {
+            MIN_EPOCH_SECONDS
+        } else if epoch_seconds > MAX_EPOCH_SECONDS {
+            MAX_EPOCH_SECONDS
+        } else {
+            epoch_seconds
+        };
+        let days = epoch_seconds / 86400;
+        let seconds = (epoch_seconds % 86400) as uint;
+        let (year, month, day) = civil_from_days(days);
+        MsdosDateTime::new(year as uint, month, day, seconds / 3600, seconds / 60 % 60, seconds % 60)
+    }
+
+    /// Like `from_epoch_seconds`, but returns None instead of clamping.
+    pub fn from_epoch_seconds_checked(epoch_seconds: i64) -> Option<MsdosDateTime> {
+        let local = epoch_seconds + local_offset(epoch_seconds);
+        if local < MIN_EPOCH_SECONDS || local > MAX_EPOCH_SECONDS + 1 {
+            None
+        } else {
+            Some(MsdosDateTime::from_epoch_seconds_utc(local))
+        }
+    }
+
+    /// The current time, in local time.
+    pub fn now() -> MsdosDateTime {
+        MsdosDateTime::from_epoch_seconds(time::get_time().sec)
+    }
+
+    /// The time in seconds since the Unix epoch, taking the timestamp as local time. Times
+    /// skipped or repeated by daylight saving time changes are resolved with the offset in
+    /// effect right before the change.
+    pub fn to_epoch_seconds(&self) -> i64 {
+        let naive = self.to_epoch_seconds_utc();
+        let guess = naive - local_offset(naive);
+        naive - local_offset(guess)
+    }
+
+    /// Like `to_epoch_seconds`, taking the timestamp as UTC.
+    pub fn to_epoch_seconds_utc(&self) -> i64 {
+        let days = days_from_civil(self.year() as i64, cmp::max(self.month(), 1) as i64,
+                                   cmp::max(self.day(), 1) as i64);
+        days * 86400 + (self.hour() * 3600 + self.minute() * 60 + self.second()) as i64
+    }
+
+    pub fn zero() -> MsdosDateTime {
+        MsdosDateTime { time: 0, date: 0 }
+    }
+
+    pub fn year  (&self) -> uint { ((self.date >>  9) & 0b1111111) as uint + 1980 }
+    pub fn month (&self) -> uint { ((self.date >>  5) &    0b1111) as uint }
+    pub fn day   (&self) -> uint { ( self.date        &   0b11111) as uint }
+    pub fn hour  (&self) -> uint { ((self.time >> 11) &   0b11111) as uint }
+    pub fn minute(&self) -> uint { ((self.time >>  5) &  0b111111) as uint }
+    // stored in 2-second units
+    pub fn second(&self) -> uint { ( self.time        &   0b11111) as uint * 2 }
+
+    // the raw time and date words, as stored in the headers
+    pub fn dos_time(&self) -> u16 { self.time }
+    pub fn dos_date(&self) -> u16 { self.date }
+
+    pub fn to_tuple(&self) -> (uint, uint, uint, uint, uint, uint) {
+        (self.year(), self.month(), self.day(), self.hour(), self.minute(), self.second())
+    }
+
+    pub fn read<T:Reader>(r: &mut T) -> IoResult<MsdosDateTime> {
+        let time = try!(r.read_le_u16());
+        let date = try!(r.read_le_u16());
+        Ok(MsdosDateTime { time: time, date: date })
+    }
+
+    pub fn write<T:Writer>(&self, w: &mut T) -> IoResult<()> {
+        try!(w.write_le_u16(self.time));
+        try!(w.write_le_u16(self.date));
+        Ok(())
+    }
+}
+
+impl fmt::Show for MsdosDateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{:02}-{:02} {:02}:{:02}:{:02}",
+               self.year(), self.month(), self.day(), self.hour(), self.minute(), self.second())
+    }
+}
+
+//  http://www.pkware.com/documents/casestudies/APPNOTE.TXT
+//
+//  4.3.6 Overall .ZIP file format:
+//
+//  [local file header 1]
+//  [encryption header 1]
+//  [file data 1]
+//  [data descriptor 1]
+//  .
+//  .
+//  .
+//  [local file header n]
+//  [encryption header n]
+//  [file data n]
+//  [data descriptor n]
+//  [archive decryption header]
+//  [archive extra data record]
+//  [central directory header 1]
+//  .
+//  .
+//  .
+//  [central directory header n]
+//  [zip64 end of central directory record]
+//  [zip64 end of central directory locator]
+//  [end of central directory record]
+
+
+// A 32-bit size or offset (or 16-bit count) that is saturated to this value has its real value
+// in the ZIP64 structures.
+pub static ZIP64_SENTINEL_U32: u32 = 0xFFFFFFFF;
+pub static ZIP64_SENTINEL_U16: u16 = 0xFFFF;
+
+// the 32-bit header field for a value, saturated if the value is in the ZIP64 extra field
+fn u32_or_sentinel(value: Option<u64>, fallback: u64) -> u32 {
+    match value {
+        Some(_) => ZIP64_SENTINEL_U32,
+        None => fallback as u32,
+    }
+}
+
+// ==== GENERAL PURPOSE BIT FLAG ====
+
+/// How a deflated entry was compressed, from bits 1 and 2 of the general purpose bit flag.
+#[deriving(PartialEq, Eq, Show, Clone)]
+pub enum DeflateOption {
+    Normal,
+    Maximum,
+    Fast,
+    SuperFast,
+}
+
+impl DeflateOption {
+    /// Decodes bits 1 and 2 of a general purpose bit flag.
+    pub fn from_flags(flags: u16) -> DeflateOption {
+        match (flags >> 1) & 0b11 {
+            0 => Normal,
+            1 => Maximum,
+            2 => Fast,
+            _ => SuperFast,
+        }
+    }
+}
+
+/// The general purpose bit flag of a header, see section 4.4.4 of APPNOTE.TXT. Bits without
+/// a name here are kept as they are.
+#[deriving(PartialEq, Eq, Clone)]
+pub struct GpFlags(pub u16);
+
+// (bit, name) of the flags with one, in bit order
+static GP_FLAG_NAMES: [(u16, &'static str), ..6] = [
+    (1, "encrypted"),
+    (8, "data_descriptor"),
+    (32, "compressed_patched_data"),
+    (64, "strong_encryption"),
+    (2048, "utf8_names"),
+    (8192, "masked"),
+];
+
+impl GpFlags {
+    pub fn bits(&self) -> u16 {
+        let GpFlags(bits) = *self;
+        bits
+    }
+
+    fn get(&self, bit: u16) -> bool {
+        self.bits() & bit != 0
+    }
+
+    fn set(&mut self, bit: u16, on: bool) {
+        let bits = if on { self.bits() | bit } else { self.bits() & !bit };
+        *self = GpFlags(bits);
+    }
+
+    pub fn encrypted(&self) -> bool               { self.get(1) }
+    pub fn data_descriptor(&self) -> bool         { self.get(8) }
+    pub fn compressed_patched_data(&self) -> bool { self.get(32) }
+    pub fn strong_encryption(&self) -> bool       { self.get(64) }
+    pub fn utf8_names(&self) -> bool              { self.get(2048) }
+    pub fn masked(&self) -> bool                  { self.get(8192) }
+
+    pub fn set_encrypted(&mut self, on: bool)         { self.set(1, on) }
+    pub fn set_data_descriptor(&mut self, on: bool)   { self.set(8, on) }
+    pub fn set_strong_encryption(&mut self, on: bool) { self.set(64, on) }
+    pub fn set_utf8_names(&mut self, on: bool)        { self.set(2048, on) }
+    pub fn set_masked(&mut self, on: bool)            { self.set(8192, on) }
+
+    /// Bits 1 and 2, which only mean this for deflated entries (and are also used by
+    /// implode and LZMA, differently).
+    pub fn deflate_option(&self) -> DeflateOption {
+        DeflateOption::from_flags(self.bits())
+    }
+
+    pub fn set_deflate_option(&mut self, option: DeflateOption) {
+        let value = match option {
+            Normal => 0,
+            Maximum => 1,
+            Fast => 2,
+            SuperFast => 3,
+        };
+        *self = GpFlags((self.bits() & !0b110) | (value << 1));
+    }
+}
+
+impl fmt::Show for GpFlags {
+    /// Lists the named flags that are set, then any other bits in hexadecimal, like
+    /// `encrypted | utf8_names | 0x0002`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut rest = self.bits();
+        let mut first = true;
+        for &(bit, name) in GP_FLAG_NAMES.iter() {
+            if rest & bit != 0 {
+                try!(write!(f, "{}{}", if first { "" } else { " | " }, name));
+                rest &= !bit;
+                first = false;
+            }
+        }
+        if rest != 0 || first {
+            try!(write!(f, "{}{:#06x}", if first { "" } else { " | " }, rest));
+        }
+        Ok(())
+    }
+}
+
+// ==== VERSION MADE BY ====
+
+/// The host system in the upper byte of "version made by", see section 4.4.2 of APPNOTE.TXT.
+/// It tells how to interpret the external file attributes.
+#[deriving(PartialEq, Eq, Show, Clone)]
+pub enum HostSystem {
+    MsDos,
+    Amiga,
+    OpenVms,
+    Unix,
+    VmCms,
+    AtariSt,
+    Os2,
+    Macintosh,
+    ZSystem,
+    CpM,
+    WindowsNtfs,
+    Mvs,
+    Vse,
+    AcornRisc,
+    Vfat,
+    AlternateMvs,
+    BeOs,
+    Tandem,
+    Os400,
+    OsX,
+    // kept as is, so it round-trips
+    UnknownHost(u8),
+}
+
+// in order of their values
+static HOST_SYSTEMS: [HostSystem, ..20] = [
+    MsDos, Amiga, OpenVms, Unix, VmCms, AtariSt, Os2, Macintosh, ZSystem, CpM,
+    WindowsNtfs, Mvs, Vse, AcornRisc, Vfat, AlternateMvs, BeOs, Tandem, Os400, OsX,
+];
+
+impl HostSystem {
+    pub fn from_u8(x: u8) -> HostSystem {
+        match HOST_SYSTEMS.as_slice().get(x as uint) {
+            Some(host) => host.clone(),
+            None => UnknownHost(x),
+        }
+    }
+
+    pub fn to_u8(&self) -> u8 {
+        match *self {
+            UnknownHost(x) => x,
+            ref host => HOST_SYSTEMS.iter().position(|h| h == host).unwrap() as u8,
+        }
+    }
+}
+
+/// Composes "version made by" from the host system and the version of the specification
+/// supported by the creator (major * 10 + minor, e.g. 20 for 2.0).
+pub fn version_made_by(host: HostSystem, spec_version: u8) -> u16 {
+    (host.to_u8() as u16 << 8) | spec_version as u16
+}
+
+// ==== EXTERNAL FILE ATTRIBUTES ====
+
+// The external file attributes depend on the host system: MS-DOS attributes in the low byte,
+// which most tools set whatever the host, plus the Unix mode in the upper 16 bits for Unix.
+pub static MSDOS_READONLY: u8 = 0x01;
+pub static MSDOS_HIDDEN: u8 = 0x02;
+pub static MSDOS_SYSTEM: u8 = 0x04;
+pub static MSDOS_DIRECTORY: u8 = 0x10;
+pub static MSDOS_ARCHIVE: u8 = 0x20;
+
+// the Unix mode in external file attributes, if the host system is Unix and it was set
+pub fn unix_mode_of(version_made_by: u16, external_file_attributes: u32) -> Option<u32> {
+    let mode = external_file_attributes >> 16;
+    if HostSystem::from_u8((version_made_by >> 8) as u8) == Unix && mode != 0 { Some(mode) } else { None }
+}
+
+/// External file attributes for an entry made on Unix (to be used with the Unix host system):
+/// the mode, and the matching MS-DOS attributes for readers that only know those.
+pub fn unix_external_attributes(mode: u32) -> u32 {
+    let mut msdos = 0;
+    if mode & 0o222 == 0 {
+        msdos |= MSDOS_READONLY;
+    }
+    if mode & 0o170000 == 0o040000 { // S_IFDIR
+        msdos |= MSDOS_DIRECTORY;
+    }
+    (mode << 16) | msdos as u32
+}
+
+/// External file attributes with only MS-DOS attributes (a combination of the MSDOS_*
+/// constants).
+pub fn msdos_external_attributes(attributes: u8) -> u32 {
+    attributes as u32
+}
+
+// -- what headers built from a FileInfo have in common
+
+// see section 4.4.3 of APPNOTE.TXT
+fn version_needed_for(f: &FileInfo, zip64: bool) -> u16 {
+    let version = match f.raw_compression_method {
+        0 => 10,
+        12 => 46,
+        // AES, whose real method is in its extra field
+        99 => 51,
+        _ => 20,
+    };
+    if zip64 { cmp::max(version, 45) } else { version }
+}
+
+fn general_purpose_bit_flag_for(f: &FileInfo) -> GpFlags {
+    let mut flags = GpFlags(0);
+    flags.set_encrypted(f.is_encrypted);
+    match f.deflate_option {
+        Some(ref option) => flags.set_deflate_option(option.clone()),
+        None => {}
+    }
+    // the traditional encryption header ends with the high byte of the DOS time rather than of
+    // the CRC32 when there is a data descriptor, so the flag has to stay with it
+    match f.encryption_check_byte {
+        Some(byte) if byte != (f.crc32 >> 24) as u8 => flags.set_data_descriptor(true),
+        _ => {}
+    }
+    // the name is only marked as UTF-8 if it was given as text; the flag covers the comment too
+    match f.raw_name {
+        maybe_utf8::UTF8(_) if f.comment.as_str().is_some() => flags.set_utf8_names(true),
+        _ => {}
+    }
+    flags
+}
+
+fn datetime_for(f: &FileInfo) -> MsdosDateTime {
+    let (year, month, day, hour, minute, second) = f.last_modified_datetime;
+    MsdosDateTime::new(year, month, day, hour, minute, second)
+}
+
+// ==== SPEC VIOLATIONS ====
+
+/// How header readers deal with values the specification doesn't allow, but that are common
+/// enough in real archives to be readable anyway.
+#[deriving(PartialEq, Eq, Show, Clone)]
+pub enum ParseMode {
+    /// Every irregularity is an error (IrregularHeader).
+    Strict,
+    /// Irregularities are accepted, and recorded as ParseWarning.
+    Lenient,
+}
+
+/// A way a header violates the specification.
+#[deriving(PartialEq, Eq, Show, Clone)]
+pub enum Irregularity {
+    /// The version needed to extract is 0.
+    ZeroVersionNeeded,
+    /// The last modification date and time are all zeros.
+    ZeroDateTime,
+    /// The last modification date or time has an out of range field (like month 13).
+    InvalidDateTime,
+    /// The records of the extra field don't fill it exactly, as when its length runs into
+    /// the next header.
+    MalformedExtraField,
+    /// A name, comment or extra field is longer than its 16-bit length can tell.
+    FieldTooLong,
+    /// Strong encryption is flagged, but not encryption itself (bit 0).
+    StrongEncryptionWithoutEncryption,
+    /// The entry is stored (not compressed) but its sizes differ.
+    StoredSizeMismatch,
+    /// The end of central directory record counts more entries on this disk than in total.
+    InconsistentEntryCounts,
+    /// No data descriptor with a signature follows the data of an entry that has one, so
+    /// where its data ends can't be told (only when recovering entries from local headers).
+    UndelimitedData,
+}
+
+impl Irregularity {
+    pub fn description(&self) -> &'static str {
+        match *self {
+            ZeroVersionNeeded => "the version needed to extract is 0",
+            ZeroDateTime => "the modification date and time are zero",
+            InvalidDateTime => "the modification date or time is invalid",
+            MalformedExtraField => "the extra field is malformed",
+            FieldTooLong => "a name, comment or extra field is too long",
+            StrongEncryptionWithoutEncryption => "strong encryption is flagged without encryption",
+            StoredSizeMismatch => "the entry is stored but its sizes differ",
+            InconsistentEntryCounts => "there are more entries on this disk than in total",
+            UndelimitedData => "the end of the data can't be found without a data descriptor signature",
+        }
+    }
+}
+
+/// An irregularity found in the header of an entry in lenient mode.
+#[deriving(PartialEq, Clone)]
+pub struct ParseWarning {
+    pub entry: MaybeUTF8,
+    pub irregularity: Irregularity,
+}
+
+impl fmt::Show for ParseWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.entry.display_escaped(), self.irregularity.description())
+    }
+}
+
+// the irregularities of the fields local file headers and central directory headers have in
+// common, in field order
+fn header_irregularities(version_needed_to_extract: u16, flags: &GpFlags, compression_method: u16,
+                         datetime: &MsdosDateTime, compressed_size: u32, uncompressed_size: u32,
+                         field_lengths: &[uint], extra_field: &[u8]) -> Vec<Irregularity> {
+    let mut found = Vec::new();
+    if version_needed_to_extract == 0 {
+        found.push(ZeroVersionNeeded);
+    }
+    if flags.strong_encryption() && !flags.encrypted() {
+        found.push(StrongEncryptionWithoutEncryption);
+    }
+    if datetime.dos_date() == 0 && datetime.dos_time() == 0 {
+        found.push(ZeroDateTime);
+    } else if !datetime.is_valid() {
+        found.push(InvalidDateTime);
+    }
+    // the encryption header makes encrypted entries bigger
+    if compression_method == 0 && !flags.encrypted() && compressed_size != uncompressed_size {
+        found.push(StoredSizeMismatch);
+    }
+    if field_lengths.iter().any(|&len| len > 0xFFFF) {
+        found.push(FieldTooLong);
+    }
+    if extra::parse(extra_field).is_err() {
+        found.push(MalformedExtraField);
+    }
+    found
+}
+
+// fails on the first irregularity of a header in strict mode, and adds them all to `warnings`
+// in lenient mode
+fn check_irregularities(file_name: &MaybeUTF8, found: Vec<Irregularity>, mode: ParseMode,
+                        warnings: &mut Vec<ParseWarning>) -> ZipResult<()> {
+    for irregularity in found.into_iter() {
+        if mode == Strict {
+            return Err(error::IrregularHeader(file_name.clone(), irregularity));
+        }
+        warnings.push(ParseWarning { entry: file_name.clone(), irregularity: irregularity });
+    }
+    Ok(())
+}
+
+// ==== LOCAL FILE HEADER ====
+
+pub static LFH_SIGNATURE: u32 = 0x04034b50;
+
+#[deriving(PartialEq, Clone)]
+pub struct LocalFileHeader {
+    pub version_needed_to_extract: u16,
+    pub general_purpose_bit_flag:  GpFlags,
+    pub compression_method:        u16,
+    pub last_modified_datetime:    MsdosDateTime,
+    pub crc32:                     u32,
+    pub compressed_size:           u32,
+    pub uncompressed_size:         u32,
+    pub file_name:                 MaybeUTF8,
+    pub extra_field:               Vec<u8>
+}
+
+impl LocalFileHeader {
+    // -- header property getters
+
+    // see section 4.4.4 of APPNOTE.TXT for more info about these flags
+    pub fn is_encrypted(&self) -> bool               { self.general_purpose_bit_flag.encrypted() }
+    pub fn has_data_descriptor(&self) -> bool        { self.general_purpose_bit_flag.data_descriptor() }
+    pub fn is_compressed_patched_data(&self) -> bool { self.general_purpose_bit_flag.compressed_patched_data() }
+    pub fn uses_strong_encryption(&self) -> bool     { self.general_purpose_bit_flag.strong_encryption() }
+    pub fn has_utf8_name(&self) -> bool              { self.general_purpose_bit_flag.utf8_names() }
+    pub fn uses_masking(&self) -> bool               { self.general_purpose_bit_flag.masked() }
+
+    // the sizes are then in the ZIP64 extra field, as well as in the central directory
+    pub fn has_zip64_extra_field(&self) -> bool {
+        match extra::find(self.extra_field.as_slice(), ZIP64_EXTRA_FIELD_ID) {
+            Ok(Some(_)) => true,
+            _ => false,
+        }
+    }
+
+    pub fn total_size(&self) -> u64 {
+        let local_file_header_fixed_size = 30;
+        local_file_header_fixed_size + self.file_name.len() as u64 + self.extra_field.len() as u64
+    }
+
+    // -- constructors
+    pub fn new() -> LocalFileHeader {
+        LocalFileHeader{
+            version_needed_to_extract: 0,
+            general_purpose_bit_flag: GpFlags(0),
+            compression_method: 0,
+            last_modified_datetime: MsdosDateTime::zero(),
+            crc32: 0,
+            compressed_size: 0,
+            uncompressed_size: 0,
+            file_name: MaybeUTF8::new(),
+            extra_field: Vec::new()
+        }
+    }
+
+    /// The local file header of an entry, with the CRC32 and sizes of `f` (saturated, with a
+    /// ZIP64 extra field holding both, if either doesn't fit) and its extra field otherwise
+    /// unchanged. Fails if the extra field is malformed.
+    ///
+    /// The header has the data descriptor flag only for entries with the traditional
+    /// encryption whose check byte needs it; a data descriptor must follow their data then.
+    pub fn from_info(f: &FileInfo) -> ZipResult<LocalFileHeader> {
+        let zip64 = f.uncompressed_size >= ZIP64_SENTINEL_U32 as u64 ||
+                    f.compressed_size >= ZIP64_SENTINEL_U32 as u64;
+        let extra_field = if zip64 {
+            let field = extra::Zip64ExtraField {
+                uncompressed_size: Some(f.uncompressed_size),
+                compressed_size: Some(f.compressed_size),
+                local_header_offset: None,
+                disk_start: None,
+            };
+            try!(extra::replace(f.extra_field.as_slice(), ZIP64_EXTRA_FIELD_ID, field.serialize().as_slice()))
+        } else {
+            try!(extra::remove(f.extra_field.as_slice(), ZIP64_EXTRA_FIELD_ID))
+        };
+        Ok(LocalFileHeader {
+            version_needed_to_extract: version_needed_for(f, zip64),
+            general_purpose_bit_flag: general_purpose_bit_flag_for(f),
+            compression_method: f.raw_compression_method,
+            last_modified_datetime: datetime_for(f),
+            crc32: f.crc32,
+            compressed_size: if zip64 { ZIP64_SENTINEL_U32 } else { f.compressed_size as u32 },
+            uncompressed_size: if zip64 { ZIP64_SENTINEL_U32 } else { f.uncompressed_size as u32 },
+            file_name: f.raw_name.clone(),
+            extra_field: extra_field,
+        })
+    }
+
+    // reads a LocalFileHeader from the current position of the reader r, leniently
+    pub fn read<T:Reader+Seek>(r: &mut T) -> ZipResult<LocalFileHeader> {
+        try!(expect_signature(r, LFH_SIGNATURE));
+        LocalFileHeader::read_after_signature(r)
+    }
+
+    /// Like `read`, dealing with irregularities as `mode` says; in lenient mode, they are
+    /// added to `warnings`.
+    pub fn read_with_mode<T:Reader+Seek>(r: &mut T, mode: ParseMode, warnings: &mut Vec<ParseWarning>)
+                                    -> ZipResult<LocalFileHeader> {
+        let h = try!(LocalFileHeader::read(r));
+        try!(check_irregularities(&h.file_name, h.validate(), mode, warnings));
+        Ok(h)
+    }
+
+    // like `read`, when the signature has already been read
+    pub fn read_after_signature<T:Reader>(r: &mut T) -> ZipResult<LocalFileHeader> {
+        let mut h = LocalFileHeader::new();
+
+        h.version_needed_to_extract = try_io!(r.read_le_u16());
+        h.general_purpose_bit_flag = GpFlags(try_io!(r.read_le_u16()));
+        h.compression_method = try_io!(r.read_le_u16());
+        h.last_modified_datetime = try_io!(MsdosDateTime::read(r));
+        h.crc32 = try_io!(r.read_le_u32());
+        h.compressed_size = try_io!(r.read_le_u32());
+        h.uncompressed_size = try_io!(r.read_le_u32());
+        let file_name_length = try_io!(r.read_le_u16()) as uint;
+        let extra_field_length = try_io!(r.read_le_u16()) as uint;
+        h.file_name = try!(read_maybe_utf8(r, h.has_utf8_name(), file_name_length));
+        h.extra_field = try_io!(r.read_exact(extra_field_length));
+
+        // features we don't support are refused when reading the data, see `check_supported`
+        Ok(h)
+    }
+
+    // fails if reading the data of the entry needs something we don't support (yet?)
+    // Encryption is left to the caller, since it is supported when a password is given.
+    pub fn check_supported(&self) -> ZipResult<()> {
+        if self.uses_strong_encryption() {
+            return Err(error::FeatureNotSupported("strong encryption (general purpose flag bit 6)"));
+        }
+        if self.is_compressed_patched_data() {
+            return Err(error::FeatureNotSupported("compressed patched data (general purpose flag bit 5)"));
+        }
+        if self.uses_masking() {
+            return Err(error::FeatureNotSupported("masked local headers (general purpose flag bit 13)"));
+        }
+        Ok(())
+    }
+
+    pub fn write<T:Writer>(&self, w: &mut T) -> ZipResult<()> {
+        let file_name_length = try!(ensure_u16_field_length(self.file_name.len()));
+        let extra_field_length = try!(ensure_u16_field_length(self.extra_field.len()));
+        try_io!(w.write_le_u32(LFH_SIGNATURE));
+        try_io!(w.write_le_u16(self.version_needed_to_extract));
+        try_io!(w.write_le_u16(self.general_purpose_bit_flag.bits()));
+        try_io!(w.write_le_u16(self.compression_method));
+        try_io!(self.last_modified_datetime.write(w));
+        try_io!(w.write_le_u32(self.crc32));
+        try_io!(w.write_le_u32(self.compressed_size));
+        try_io!(w.write_le_u32(self.uncompressed_size));
+        try_io!(w.write_le_u16(file_name_length));
+        try_io!(w.write_le_u16(extra_field_length));
+        try!(write_maybe_utf8(w, self.has_utf8_name(), &self.file_name));
+        try_io!(w.write(self.extra_field.as_slice()));
+        Ok(())
+    }
+
+    /// The ways the header violates the specification, in field order; empty if it doesn't.
+    pub fn validate(&self) -> Vec<Irregularity> {
+        header_irregularities(self.version_needed_to_extract, &self.general_purpose_bit_flag,
+                              self.compression_method, &self.last_modified_datetime,
+                              self.compressed_size, self.uncompressed_size,
+                              &[self.file_name.len(), self.extra_field.len()], self.extra_field.as_slice())
+    }
+}
+
+impl fmt::Show for LocalFileHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "LocalFileHeader {{ version_needed_to_extract: {}, general_purpose_bit_flag: {}, \
+                   compression_method: {}, last_modified_datetime: {}, crc32: {:#010x}, \
+                   compressed_size: {}, uncompressed_size: {}, file_name: {}, extra_field: {} }}",
+               self.version_needed_to_extract, self.general_purpose_bit_flag, self.compression_method,
+               self.last_modified_datetime, self.crc32, self.compressed_size, self.uncompressed_size,
+               self.file_name, self.extra_field)
+    }
+}
+
+// ==== ENCRYPTION HEADER ====
+
+pub static ENCRYPTION_HEADER_SIZE: uint = 12;
+
+// Precedes the data of entries encrypted with the traditional PKWARE encryption, and counts in
+// their compressed size. Its bytes are random except for the last one (once decrypted), which is
+// the check byte used to verify the password, see `encryption_check_byte`.
+#[deriving(PartialEq, Clone)]
+pub struct EncryptionHeader(pub [u8, ..ENCRYPTION_HEADER_SIZE]);
+
+impl EncryptionHeader {
+    pub fn as_bytes<'a>(&'a self) -> &'a [u8] {
+        let EncryptionHeader(ref bytes) = *self;
+        bytes.as_slice()
+    }
+
+    pub fn read<T:Reader>(r: &mut T) -> ZipResult<EncryptionHeader> {
+        let mut bytes = [0u8, ..ENCRYPTION_HEADER_SIZE];
+        try_io!(r.read_at_least(ENCRYPTION_HEADER_SIZE, bytes.as_mut_slice()));
+        Ok(EncryptionHeader(bytes))
+    }
+
+    pub fn write<T:Writer>(&self, w: &mut T) -> ZipResult<()> {
+        try_io!(w.write(self.as_bytes()));
+        Ok(())
+    }
+}
+
+// The last byte of the decrypted encryption header: the high byte of the CRC32, or of the DOS
+// time when the entry has a data descriptor, since the CRC32 isn't known yet when the header is
+// written then.
+pub fn encryption_check_byte(has_data_descriptor: bool, crc32: u32, datetime: &MsdosDateTime) -> u8 {
+    if has_data_descriptor {
+        (datetime.dos_time() >> 8) as u8
+    } else {
+        (crc32 >> 24) as u8
+    }
+}
+
+// Data descriptor section after the file contents, used when the zip file writer doesn't know the
+// file size beforehand (because it's receiving a stream of data or something). The local file
+// header then has zeroes in place of the CRC32 and sizes.
+
+pub static DD_SIGNATURE: u32 = 0x08074b50;
+
+pub struct DataDescriptor {
+    pub signature_present: bool, // not standard but sometimes present
+    pub zip64: bool, // sizes are 8 bytes long, when the local file header has a ZIP64 extra field
+    pub crc32: u32,
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+}
+
+impl DataDescriptor {
+    pub fn total_size(&self) -> u64 {
+        let sizes = if self.zip64 { 16 } else { 8 };
+        if self.signature_present { 8 + sizes } else { 4 + sizes }
+    }
+
+    // reads a DataDescriptor from the current position of the reader r, with or without signature
+    pub fn read<T:Reader>(r: &mut T, zip64: bool) -> ZipResult<DataDescriptor> {
+        // a CRC32 equal to the signature can't be told apart from it; every reader guesses this way
+        let first = try_io!(r.read_le_u32());
+        let signature_present = first == DD_SIGNATURE;
+        let crc32 = if signature_present { try_io!(r.read_le_u32()) } else { first };
+        let (compressed_size, uncompressed_size) = if zip64 {
+            (try_io!(r.read_le_u64()), try_io!(r.read_le_u64()))
+        } else {
+            (try_io!(r.read_le_u32()) as u64, try_io!(r.read_le_u32()) as u64)
+        };
+        Ok(DataDescriptor {
+            signature_present: signature_present,
+            zip64: zip64,
+            crc32: crc32,
+            compressed_size: compressed_size,
+            uncompressed_size: uncompressed_size,
+        })
+    }
+
+    // Reads the DataDescriptor right after the data of an entry, checking it against the CRC32
+    // (of the uncompressed data) and compressed size of the data that was actually read. Knowing
+    // the CRC32 resolves the case where it is equal to the signature.
+    pub fn read_after_data<T:Reader>(r: &mut T, zip64: bool, crc32: u32, compressed_size: u64)
+                                     -> ZipResult<DataDescriptor> {
+        let first = try_io!(r.read_le_u32());
+        let second = try_io!(r.read_le_u32());
+        // with the signature, the CRC32 comes second; without, the (low half of the) compressed size
+        let signature_present = first == DD_SIGNATURE && (second == crc32 || first != crc32);
+        let (stored_crc32, size_start) = if signature_present {
+            (second, None)
+        } else {
+            (first, Some(second))
+        };
+        let stored_compressed_size = match (size_start, zip64) {
+            (Some(low), true) => (low as u64) | (try_io!(r.read_le_u32()) as u64 << 32),
+            (Some(low), false) => low as u64,
+            (None, true) => try_io!(r.read_le_u64()),
+            (None, false) => try_io!(r.read_le_u32()) as u64,
+        };
+        let uncompressed_size = if zip64 { try_io!(r.read_le_u64()) } else { try_io!(r.read_le_u32()) as u64 };
+        if stored_compressed_size != compressed_size {
+            return Err(error::SomeIoError(IoError {
+                kind: InvalidInput,
+                desc: "data descriptor doesn't match the entry data",
+                detail: None,
+            }));
+        }
+        if stored_crc32 != crc32 {
+            return Err(error::CrcError);
+        }
+        Ok(DataDescriptor {
+            signature_present: signature_present,
+            zip64: zip64,
+            crc32: stored_crc32,
+            compressed_size: stored_compressed_size,
+            uncompressed_size: uncompressed_size,
+        })
+    }
+
+    pub fn write<T:Writer>(&self, w: &mut T) -> ZipResult<()> {
+        if self.signature_present {
+            try_io!(w.write_le_u32(DD_SIGNATURE));
+        }
+        try_io!(w.write_le_u32(self.crc32));
+        if self.zip64 {
+            try_io!(w.write_le_u64(self.compressed_size));
+            try_io!(w.write_le_u64(self.uncompressed_size));
+        } else {
+            try_io!(w.write_le_u32(self.compressed_size as u32));
+            try_io!(w.write_le_u32(self.uncompressed_size as u32));
+        }
+        Ok(())
+    }
+}
+
+// ==== ARCHIVE EXTRA DATA RECORD ====
+
+pub static AEDR_SIGNATURE: u32 = 0x08064b50;
+
+// right before the central directory, after the data of the last entry; used by central
+// directory encryption, its data is opaque to us
+#[deriving(Clone)]
+pub struct ArchiveExtraDataRecord {
+    pub data: Vec<u8>,
+}
+
+impl ArchiveExtraDataRecord {
+    pub fn new(data: Vec<u8>) -> ArchiveExtraDataRecord {
+        ArchiveExtraDataRecord { data: data }
+    }
+
+    pub fn total_size(&self) -> u64 {
+        8 + self.data.len() as u64
+    }
+
+    // `size` is the room between the data of the last entry and the central directory, which
+    // the record has to fill exactly; nothing else tells whether it is really there
+    pub fn read<T:Reader+Seek>(r: &mut T, size: u64) -> ZipResult<ArchiveExtraDataRecord> {
+        try!(expect_signature(r, AEDR_SIGNATURE));
+        let data_length = try_io!(r.read_le_u32());
+        if 8 + data_length as u64 != size {
+            return Err(error::SomeIoError(IoError {
+                kind: InvalidInput,
+                desc: "archive extra data record doesn't end at the central directory",
+                detail: Some(format!("{}", data_length)),
+            }));
+        }
+        let data = try_io!(r.read_exact(data_length as uint));
+        Ok(ArchiveExtraDataRecord { data: data })
+    }
+
+    pub fn write<T:Writer>(&self, w: &mut T) -> ZipResult<()> {
+        let data_length = match self.data.len().to_u32() {
+            Some(len) => len,
+            None => return Err(error::TooLongField),
+        };
+        try_io!(w.write_le_u32(AEDR_SIGNATURE));
+        try_io!(w.write_le_u32(data_length));
+        try_io!(w.write(self.data.as_slice()));
+        Ok(())
+    }
+}
+
+// ==== CENTRAL DIRECTORY HEADER ====
+
+pub static CDH_SIGNATURE: u32 = 0x02014b50;
+// the size of a header without its name, extra field and comment
+pub static CDH_FIXED_SIZE: u64 = 46;
+
+#[deriving(PartialEq, Clone)]
+pub struct CentralDirectoryHeader {
+    pub version_made_by: u16,
+    pub version_needed_to_extract: u16,
+    pub general_purpose_bit_flag: GpFlags,
+    pub compression_method: u16,
+    pub last_modified_datetime: MsdosDateTime,
+    pub crc32: u32,
+    pub compressed_size: u32,
+    pub uncompressed_size: u32,
+    pub disk_number_start: u16,
+    pub internal_file_attributes: u16,
+    pub external_file_attributes: u32,
+    pub relative_offset_of_local_header: u32,
+    pub file_name: MaybeUTF8,
+    pub extra_field: Vec<u8>,
+    pub file_comment: MaybeUTF8,
+}
+
+impl CentralDirectoryHeader {
+    pub fn is_encrypted(&self) -> bool               { self.general_purpose_bit_flag.encrypted() }
+    pub fn has_data_descriptor(&self) -> bool        { self.general_purpose_bit_flag.data_descriptor() }
+    pub fn is_compressed_patched_data(&self) -> bool { self.general_purpose_bit_flag.compressed_patched_data() }
+    pub fn uses_strong_encryption(&self) -> bool     { self.general_purpose_bit_flag.strong_encryption() }
+    pub fn has_utf8_name(&self) -> bool              { self.general_purpose_bit_flag.utf8_names() }
+    pub fn uses_masking(&self) -> bool               { self.general_purpose_bit_flag.masked() }
+
+    pub fn host_system(&self) -> HostSystem {
+        HostSystem::from_u8((self.version_made_by >> 8) as u8)
+    }
+
+    /// The version of the specification supported by the creator, as major * 10 + minor.
+    pub fn creator_spec_version(&self) -> u8 {
+        self.version_made_by as u8
+    }
+
+    /// The Unix mode (file type and permission bits) of the entry, if it was made on Unix.
+    pub fn unix_mode(&self) -> Option<u32> {
+        unix_mode_of(self.version_made_by, self.external_file_attributes)
+    }
+
+    /// The MS-DOS attributes, a combination of the MSDOS_* constants.
+    pub fn msdos_attributes(&self) -> u8 {
+        self.external_file_attributes as u8
+    }
+
+    /// Whether the entry is marked as apparently text (bit 0 of the internal file attributes).
+    pub fn is_text(&self) -> bool { self.internal_file_attributes & 1 != 0 }
+
+    /// Whether the entry has a Unix mode without write permission, or else the MS-DOS
+    /// read-only attribute, like `FileInfo::is_readonly`.
+    pub fn is_readonly(&self) -> bool {
+        match self.unix_mode() {
+            Some(mode) => mode & 0o222 == 0,
+            None => self.msdos_attributes() & MSDOS_READONLY != 0,
+        }
+    }
+
+    pub fn is_hidden(&self) -> bool { self.msdos_attributes() & MSDOS_HIDDEN != 0 }
+
+    // the sizes, offset and disk number of the entry; those that are saturated in the header
+    // come from the ZIP64 extra field, which only holds these, in this order
+    pub fn zip64_extended_information(&self) -> ZipResult<Zip64ExtendedInformation> {
+        let mut info = Zip64ExtendedInformation {
+            uncompressed_size: self.uncompressed_size as u64,
+            compressed_size: self.compressed_size as u64,
+            relative_offset_of_local_header: self.relative_offset_of_local_header as u64,
+            disk_number_start: self.disk_number_start as u32,
+        };
+        if self.uncompressed_size != ZIP64_SENTINEL_U32 &&
+           self.compressed_size != ZIP64_SENTINEL_U32 &&
+           self.relative_offset_of_local_header != ZIP64_SENTINEL_U32 &&
+           self.disk_number_start != ZIP64_SENTINEL_U16 {
+            return Ok(info);
+        }
+
+        let data = match try!(extra::find(self.extra_field.as_slice(), ZIP64_EXTRA_FIELD_ID)) {
+            Some(data) => data,
+            None => return Err(error::SomeIoError(IoError {
+                kind: InvalidInput,
+                desc: "missing ZIP64 extra field",
+                detail: Some(format!("{}", self.file_name.display_escaped())),
+            })),
+        };
+        let field = try!(extra::Zip64ExtraField::parse(data, self.uncompressed_size, self.compressed_size,
+                                                       self.relative_offset_of_local_header,
+                                                       self.disk_number_start));
+        info.uncompressed_size = field.uncompressed_size.unwrap_or(info.uncompressed_size);
+        info.compressed_size = field.compressed_size.unwrap_or(info.compressed_size);
+        info.relative_offset_of_local_header =
+            field.local_header_offset.unwrap_or(info.relative_offset_of_local_header);
+        info.disk_number_start = field.disk_start.unwrap_or(info.disk_number_start);
+        Ok(info)
+    }
+
+    pub fn total_size(&self) -> u64 {
+        CDH_FIXED_SIZE
+            + self.file_name.len() as u64
+            + self.extra_field.len() as u64
+            + self.file_comment.len() as u64
+    }
+
+
+    pub fn new() -> CentralDirectoryHeader {
+        CentralDirectoryHeader {
+            version_made_by: 0,
+            version_needed_to_extract: 0,
+            general_purpose_bit_flag: GpFlags(0),
+            compression_method: 0,
+            last_modified_datetime: MsdosDateTime::zero(),
+            crc32: 0,
+            compressed_size: 0,
+            uncompressed_size: 0,
+            disk_number_start: 0,
+            internal_file_attributes: 0,
+            external_file_attributes: 0,
+            relative_offset_of_local_header: 0,
+            file_name: MaybeUTF8::new(),
+            extra_field: Vec::new(),
+            file_comment: MaybeUTF8::new(),
+        }
+    }
+
+    /// The central directory header of an entry whose local file header is at
+    /// `local_header_offset` on disk `disk_number_start`, the counterpart of
+    /// `FileInfo::from_cdh`. Sizes and the offset that don't fit are saturated and put in a
+    /// ZIP64 extra field; the extra field is otherwise unchanged. Fails if it is malformed.
+    pub fn from_info(f: &FileInfo, disk_number_start: u16,
+                     local_header_offset: u64) -> ZipResult<CentralDirectoryHeader> {
+        let too_large = |value: u64| if value >= ZIP64_SENTINEL_U32 as u64 { Some(value) } else { None };
+        let field = extra::Zip64ExtraField {
+            uncompressed_size: too_large(f.uncompressed_size),
+            compressed_size: too_large(f.compressed_size),
+            local_header_offset: too_large(local_header_offset),
+            disk_start: None,
+        };
+        let zip64 = field.uncompressed_size.is_some() || field.compressed_size.is_some() ||
+                    field.local_header_offset.is_some();
+        let extra_field = if zip64 {
+            try!(extra::replace(f.extra_field.as_slice(), ZIP64_EXTRA_FIELD_ID, field.serialize().as_slice()))
+        } else {
+            try!(extra::remove(f.extra_field.as_slice(), ZIP64_EXTRA_FIELD_ID))
+        };
+        Ok(CentralDirectoryHeader {
+            version_made_by: f.version_made_by,
+            version_needed_to_extract: version_needed_for(f, zip64),
+            general_purpose_bit_flag: general_purpose_bit_flag_for(f),
+            compression_method: f.raw_compression_method,
+            last_modified_datetime: datetime_for(f),
+            crc32: f.crc32,
+            compressed_size: u32_or_sentinel(field.compressed_size, f.compressed_size),
+            uncompressed_size: u32_or_sentinel(field.uncompressed_size, f.uncompressed_size),
+            disk_number_start: disk_number_start,
+            internal_file_attributes: f.internal_file_attributes,
+            external_file_attributes: f.external_file_attributes,
+            relative_offset_of_local_header: u32_or_sentinel(field.local_header_offset, local_header_offset),
+            file_name: f.raw_name.clone(),
+            extra_field: extra_field,
+            file_comment: f.comment.clone(),
+        })
+    }
+
+    // reads a CentralDirectoryHeader from the current position of the reader r, leniently
+    pub fn read<T:Reader+Seek>(r: &mut T) -> ZipResult<CentralDirectoryHeader> {
+        let mut h = CentralDirectoryHeader::new();
+
+        try!(expect_signature(r, CDH_SIGNATURE));
+
+        h.version_made_by = try_io!(r.read_le_u16());
+        h.version_needed_to_extract = try_io!(r.read_le_u16());
+        h.general_purpose_bit_flag = GpFlags(try_io!(r.read_le_u16()));
+        h.compression_method = try_io!(r.read_le_u16());
+        h.last_modified_datetime = try_io!(MsdosDateTime::read(r));
+        h.crc32 = try_io!(r.read_le_u32());
+        h.compressed_size = try_io!(r.read_le_u32());
+        h.uncompressed_size = try_io!(r.read_le_u32());
+        let file_name_length = try_io!(r.read_le_u16()) as uint;
+        let extra_field_length = try_io!(r.read_le_u16()) as uint;
+        let file_comment_length = try_io!(r.read_le_u16()) as uint;
+        h.disk_number_start = try_io!(r.read_le_u16());
+        h.internal_file_attributes = try_io!(r.read_le_u16());
+        h.external_file_attributes = try_io!(r.read_le_u32());
+        h.relative_offset_of_local_header = try_io!(r.read_le_u32());
+        h.file_name = try!(read_maybe_utf8(r, h.has_utf8_name(), file_name_length));
+        h.extra_field = try_io!(r.read_exact(extra_field_length));
+        h.file_comment = try!(read_maybe_utf8(r, h.has_utf8_name(), file_comment_length));
+
+        // check for some things we don't support (yet?)
+        // TODO
+
+        Ok(h)
+    }
+
+    /// Like `read`, dealing with irregularities as `mode` says; in lenient mode, they are
+    /// added to `warnings`.
+    pub fn read_with_mode<T:Reader+Seek>(r: &mut T, mode: ParseMode, warnings: &mut Vec<ParseWarning>)
+                                    -> ZipResult<CentralDirectoryHeader> {
+        let h = try!(CentralDirectoryHeader::read(r));
+        try!(check_irregularities(&h.file_name, h.validate(), mode, warnings));
+        Ok(h)
+    }
+
+    pub fn write<T:Writer>(&self, w: &mut T) -> ZipResult<()> {
+        let file_name_length = try!(ensure_u16_field_length(self.file_name.len()));
+        let extra_field_length = try!(ensure_u16_field_length(self.extra_field.len()));
+        let file_comment_length = try!(ensure_u16_field_length(self.file_comment.len()));
+        try_io!(w.write_le_u32(CDH_SIGNATURE));
+        try_io!(w.write_le_u16(self.version_made_by));
+        try_io!(w.write_le_u16(self.version_needed_to_extract));
+        try_io!(w.write_le_u16(self.general_purpose_bit_flag.bits()));
+        try_io!(w.write_le_u16(self.compression_method));
+        try_io!(self.last_modified_datetime.write(w));
+        try_io!(w.write_le_u32(self.crc32));
+        try_io!(w.write_le_u32(self.compressed_size));
+        try_io!(w.write_le_u32(self.uncompressed_size));
+        try_io!(w.write_le_u16(file_name_length));
+        try_io!(w.write_le_u16(extra_field_length));
+        try_io!(w.write_le_u16(file_comment_length));
+        try_io!(w.write_le_u16(self.disk_number_start));
+        try_io!(w.write_le_u16(self.internal_file_attributes));
+        try_io!(w.write_le_u32(self.external_file_attributes));
+        try_io!(w.write_le_u32(self.relative_offset_of_local_header));
+        try!(write_maybe_utf8(w, self.has_utf8_name(), &self.file_name));
+        try_io!(w.write(self.extra_field.as_slice()));
+        try!(write_maybe_utf8(w, self.has_utf8_name(), &self.file_comment));
+        Ok(())
+    }
+
+    /// The ways the header violates the specification, in field order; empty if it doesn't.
+    pub fn validate(&self) -> Vec<Irregularity> {
+        header_irregularities(self.version_needed_to_extract, &self.general_purpose_bit_flag,
+                              self.compression_method, &self.last_modified_datetime,
+                              self.compressed_size, self.uncompressed_size,
+                              &[self.file_name.len(), self.extra_field.len(), self.file_comment.len()],
+                              self.extra_field.as_slice())
+    }
+}
+
+impl fmt::Show for CentralDirectoryHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CentralDirectoryHeader {{ version_made_by: {:#06x}, version_needed_to_extract: {}, \
+                   general_purpose_bit_flag: {}, compression_method: {}, last_modified_datetime: {}, \
+                   crc32: {:#010x}, compressed_size: {}, uncompressed_size: {}, disk_number_start: {}, \
+                   internal_file_attributes: {:#06x}, external_file_attributes: {:#010x}, \
+                   relative_offset_of_local_header: {}, file_name: {}, extra_field: {}, file_comment: {} }}",
+               self.version_made_by, self.version_needed_to_extract, self.general_purpose_bit_flag,
+               self.compression_method, self.last_modified_datetime, self.crc32, self.compressed_size,
+               self.uncompressed_size, self.disk_number_start, self.internal_file_attributes,
+               self.external_file_attributes, self.relative_offset_of_local_header, self.file_name,
+               self.extra_field, self.file_comment)
+    }
+}
+
+// ==== ZIP64 EXTENDED INFORMATION EXTRA FIELD ====
+
+pub static ZIP64_EXTRA_FIELD_ID: u16 = extra::ZIP64_ID;
+
+pub struct Zip64ExtendedInformation {
+    pub uncompressed_size: u64,
+    pub compressed_size: u64,
+    pub relative_offset_of_local_header: u64,
+    pub disk_number_start: u32,
+}
+
+// ==== CENTRAL DIRECTORY DIGITAL SIGNATURE ====
+
+pub static CDDS_SIGNATURE: u32 = 0x05054b50;
+
+// right after the last central directory header; its data is opaque to us
+#[deriving(Clone)]
+pub struct CentralDirectoryDigitalSignature {
+    pub data: Vec<u8>
+}
+
+impl CentralDirectoryDigitalSignature {
+    pub fn new(data: Vec<u8>) -> CentralDirectoryDigitalSignature {
+        CentralDirectoryDigitalSignature { data: data }
+    }
+
+    pub fn total_size(&self) -> u64 {
+        6 + self.data.len() as u64
+    }
+
+    pub fn read<T:Reader+Seek>(r: &mut T) -> ZipResult<CentralDirectoryDigitalSignature> {
+        try!(expect_signature(r, CDDS_SIGNATURE));
+        let data_size = try_io!(r.read_le_u16());
+        let data = try_io!(r.read_exact(data_size as uint));
+        Ok(CentralDirectoryDigitalSignature { data: data })
+    }
+
+    pub fn write<T:Writer>(&self, w: &mut T) -> ZipResult<()> {
+        let data_size = try!(ensure_u16_field_length(self.data.len()));
+        try_io!(w.write_le_u32(CDDS_SIGNATURE));
+        try_io!(w.write_le_u16(data_size));
+        try_io!(w.write(self.data.as_slice()));
+        Ok(())
+    }
+}
+
+
+// ==== END OF CENTRAL DIRECTORY RECORD ====
+
+pub static EOCDR_SIGNATURE: u32 = 0x06054b50;
+
+#[deriving(PartialEq, Show, Clone)]
+pub struct EndOfCentralDirectoryRecord {
+    pub disk_number: u16,
+    pub disk_number_with_start_of_central_directory: u16,
+    pub entry_count_this_disk: u16,
+    pub total_entry_count: u16,
+    pub central_directory_size: u32,
+    pub central_directory_offset: u32,
+    pub comment: MaybeUTF8, // no encoding provision, so always raw bytes
+}
+
+impl EndOfCentralDirectoryRecord {
+    pub fn new() -> EndOfCentralDirectoryRecord {
+        EndOfCentralDirectoryRecord {
+            disk_number: 0,
+            disk_number_with_start_of_central_directory: 0,
+            entry_count_this_disk: 0,
+            total_entry_count: 0,
+            central_directory_size: 0,
+            central_directory_offset: 0,
+            comment: MaybeUTF8::new()
+        }
+    }
+
+    pub fn read<T:Reader+Seek>(r: &mut T) -> ZipResult<EndOfCentralDirectoryRecord> {
+        let mut h = EndOfCentralDirectoryRecord::new();
+
+        try!(expect_signature(r, EOCDR_SIGNATURE));
+
+        h.disk_number = try_io!(r.read_le_u16());
+        h.disk_number_with_start_of_central_directory = try_io!(r.read_le_u16());
+        h.entry_count_this_disk = try_io!(r.read_le_u16());
+        h.total_entry_count = try_io!(r.read_le_u16());
+        h.central_directory_size = try_io!(r.read_le_u32());
+        h.central_directory_offset = try_io!(r.read_le_u32());
+        let comment_length = try_io!(r.read_le_u16()) as uint;
+        h.comment = try!(read_maybe_utf8(r, false, comment_length));
+
+        // check for some things we don't support (yet?)
+        // TODO
+
+        Ok(h)
+    }
+
+    pub fn write<T:Writer>(&self, w: &mut T) -> ZipResult<()> {
+        let comment_length = try!(ensure_u16_field_length(self.comment.len()));
+        try_io!(w.write_le_u32(EOCDR_SIGNATURE));
+        try_io!(w.write_le_u16(self.disk_number));
+        try_io!(w.write_le_u16(self.disk_number_with_start_of_central_directory));
+        try_io!(w.write_le_u16(self.entry_count_this_disk));
+        try_io!(w.write_le_u16(self.total_entry_count));
+        try_io!(w.write_le_u32(self.central_directory_size));
+        try_io!(w.write_le_u32(self.central_directory_offset));
+        try_io!(w.write_le_u16(comment_length));
+        try!(write_maybe_utf8(w, false, &self.comment));
+        Ok(())
+    }
+
+    /// The ways the record violates the specification; empty if it doesn't.
+    pub fn validate(&self) -> Vec<Irregularity> {
+        let mut found = Vec::new();
+        if self.entry_count_this_disk > self.total_entry_count {
+            found.push(InconsistentEntryCounts);
+        }
+        if self.comment.len() > 0xFFFF {
+            found.push(FieldTooLong);
+        }
+        found
+    }
+}
+
+// ==== ZIP64 END OF CENTRAL DIRECTORY RECORD ====
+
+pub static ZIP64_EOCDR_SIGNATURE: u32 = 0x06064b50;
+
+#[deriving(Clone)]
+pub struct Zip64EndOfCentralDirectoryRecord {
+    pub version_made_by: u16,
+    pub version_needed_to_extract: u16,
+    pub disk_number: u32,
+    pub disk_number_with_start_of_central_directory: u32,
+    pub entry_count_this_disk: u64,
+    pub total_entry_count: u64,
+    pub central_directory_size: u64,
+    pub central_directory_offset: u64,
+    pub extensible_data: Vec<u8>,
+}
+
+impl Zip64EndOfCentralDirectoryRecord {
+    pub fn new() -> Zip64EndOfCentralDirectoryRecord {
+        Zip64EndOfCentralDirectoryRecord {
+            version_made_by: 0,
+            version_needed_to_extract: 0,
+            disk_number: 0,
+            disk_number_with_start_of_central_directory: 0,
+            entry_count_this_disk: 0,
+            total_entry_count: 0,
+            central_directory_size: 0,
+            central_directory_offset: 0,
+            extensible_data: Vec::new(),
+        }
+    }
+
+    // `available` is the room there is for the record, up to the locator; its size comes from
+    // the archive, so it is checked before anything is read for the extensible data
+    pub fn read<T:Reader+Seek>(r: &mut T, available: u64) -> ZipResult<Zip64EndOfCentralDirectoryRecord> {
+        let mut h = Zip64EndOfCentralDirectoryRecord::new();
+
+        try!(expect_signature(r, ZIP64_EOCDR_SIGNATURE));
+
+        // size of the rest of the record, which has 44 fixed bytes
+        let record_size = try_io!(r.read_le_u64());
+        if record_size < 44 || available < 12 || record_size > available - 12 {
+            return Err(error::SomeIoError(IoError {
+                kind: InvalidInput,
+                desc: "invalid ZIP64 end of central directory record size",
+                detail: Some(format!("{}", record_size)),
+            }));
+        }
+        h.version_made_by = try_io!(r.read_le_u16());
+        h.version_needed_to_extract = try_io!(r.read_le_u16());
+        h.disk_number = try_io!(r.read_le_u32());
+        h.disk_number_with_start_of_central_directory = try_io!(r.read_le_u32());
+        h.entry_count_this_disk = try_io!(r.read_le_u64());
+        h.total_entry_count = try_io!(r.read_le_u64());
+        h.central_directory_size = try_io!(r.read_le_u64());
+        h.central_directory_offset = try_io!(r.read_le_u64());
+        h.extensible_data = try_io!(r.read_exact((record_size - 44) as uint));
+
+        Ok(h)
+    }
+
+    pub fn total_size(&self) -> u64 {
+        56 + self.extensible_data.len() as u64
+    }
+
+    pub fn write<T:Writer>(&self, w: &mut T) -> ZipResult<()> {
+        try_io!(w.write_le_u32(ZIP64_EOCDR_SIGNATURE));
+        // the size doesn't count the signature and itself
+        try_io!(w.write_le_u64(self.total_size() - 12));
+        try_io!(w.write_le_u16(self.version_made_by));
+        try_io!(w.write_le_u16(self.version_needed_to_extract));
+        try_io!(w.write_le_u32(self.disk_number));
+        try_io!(w.write_le_u32(self.disk_number_with_start_of_central_directory));
+        try_io!(w.write_le_u64(self.entry_count_this_disk));
+        try_io!(w.write_le_u64(self.total_entry_count));
+        try_io!(w.write_le_u64(self.central_directory_size));
+        try_io!(w.write_le_u64(self.central_directory_offset));
+        try_io!(w.write(self.extensible_data.as_slice()));
+        Ok(())
+    }
+}
+
+// ==== ZIP64 END OF CENTRAL DIRECTORY LOCATOR ====
+
+pub static ZIP64_EOCDL_SIGNATURE: u32 = 0x07064b50;
+pub static ZIP64_EOCDL_SIZE: uint = 20;
+
+// right before the end of central directory record
+pub struct Zip64EndOfCentralDirectoryLocator {
+    pub disk_number_with_zip64_end_record: u32,
+    pub zip64_end_record_offset: u64,
+    pub total_disk_count: u32,
+}
+
+impl Zip64EndOfCentralDirectoryLocator {
+    pub fn read<T:Reader+Seek>(r: &mut T) -> ZipResult<Zip64EndOfCentralDirectoryLocator> {
+        try!(expect_signature(r, ZIP64_EOCDL_SIGNATURE));
+
+        let disk_number_with_zip64_end_record = try_io!(r.read_le_u32());
+        let zip64_end_record_offset = try_io!(r.read_le_u64());
+        let total_disk_count = try_io!(r.read_le_u32());
+        Ok(Zip64EndOfCentralDirectoryLocator {
+            disk_number_with_zip64_end_record: disk_number_with_zip64_end_record,
+            zip64_end_record_offset: zip64_end_record_offset,
+            total_disk_count: total_disk_count,
+        })
+    }
+
+    pub fn write<T:Writer>(&self, w: &mut T) -> ZipResult<()> {
+        try_io!(w.write_le_u32(ZIP64_EOCDL_SIGNATURE));
+        try_io!(w.write_le_u32(self.disk_number_with_zip64_end_record));
+        try_io!(w.write_le_u64(self.zip64_end_record_offset));
+        try_io!(w.write_le_u32(self.total_disk_count));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{MemWriter, BufReader, SeekSet};
+    use maybe_utf8::MaybeUTF8;
+    use error;
+    use fileinfo::FileInfo;
+    use super::{CentralDirectoryHeader, LocalFileHeader, MsdosDateTime, Maximum};
+    use super::{DataDescriptor, DD_SIGNATURE};
+    use super::{Zip64EndOfCentralDirectoryRecord, Zip64EndOfCentralDirectoryLocator};
+    use super::CentralDirectoryDigitalSignature;
+    use super::{HostSystem, MsDos, Unix, WindowsNtfs, OsX, UnknownHost};
+    use super::{GpFlags, Normal, Fast, SuperFast};
+
+    fn header() -> CentralDirectoryHeader {
+        let mut h = CentralDirectoryHeader::new();
+        h.version_made_by = 0x031e;
+        h.version_needed_to_extract = 20;
+        h.general_purpose_bit_flag.set_utf8_names(true);
+        h.general_purpose_bit_flag.set_deflate_option(Maximum);
+        h.compression_method = 8;
+        h.last_modified_datetime = MsdosDateTime::new(2014, 10, 3, 12, 30, 44);
+        h.crc32 = 0x12345678;
+        h.compressed_size = 100;
+        h.uncompressed_size = 300;
+        h.external_file_attributes = 0o100644 << 16;
+        h.relative_offset_of_local_header = 1234;
+        h.file_name = MaybeUTF8::from_str("dir/é.txt".to_string());
+        h.file_comment = MaybeUTF8::from_str("comment".to_string());
+        h
+    }
+
+    fn assert_same(a: &FileInfo, b: &FileInfo) {
+        assert!(a.raw_name == b.raw_name && a.comment == b.comment);
+        assert_eq!(a.raw_compression_method, b.raw_compression_method);
+        assert_eq!(a.deflate_option, b.deflate_option);
+        assert_eq!(a.last_modified_datetime, b.last_modified_datetime);
+        assert_eq!((a.crc32, a.compressed_size, a.uncompressed_size), (b.crc32, b.compressed_size, b.uncompressed_size));
+        assert_eq!((a.is_encrypted, a.encryption_check_byte), (b.is_encrypted, b.encryption_check_byte));
+        assert_eq!(a.version_made_by, b.version_made_by);
+        assert_eq!(a.external_file_attributes, b.external_file_attributes);
+        assert_eq!(a.local_file_header_offset, b.local_file_header_offset);
+    }
+
+    // FileInfo -> headers, written and read back -> FileInfo
+    fn round_trip(f: &FileInfo) -> (LocalFileHeader, CentralDirectoryHeader) {
+        let mut w = MemWriter::new();
+        LocalFileHeader::from_info(f).unwrap().write(&mut w).unwrap();
+        CentralDirectoryHeader::from_info(f, 0, f.local_file_header_offset).unwrap().write(&mut w).unwrap();
+        let bytes = w.unwrap();
+        let mut r = BufReader::new(bytes.as_slice());
+        let lfh = LocalFileHeader::read(&mut r).unwrap();
+        let cdh = CentralDirectoryHeader::read(&mut r).unwrap();
+        assert_same(f, &FileInfo::from_cdh(&cdh).unwrap());
+        (lfh, cdh)
+    }
+
+    #[test]
+    fn headers_from_info() {
+        let f = FileInfo::from_cdh(&header()).unwrap();
+        let (lfh, cdh) = round_trip(&f);
+        assert!(lfh.general_purpose_bit_flag.bits() == cdh.general_purpose_bit_flag.bits());
+        assert!(!cdh.has_data_descriptor());
+    }
+
+    #[test]
+    fn headers_from_info_zip64() {
+        let mut f = FileInfo::from_cdh(&header()).unwrap();
+        f.uncompressed_size = 0x1_0000_0000;
+        f.local_file_header_offset = 0x2_0000_0000;
+        let (lfh, cdh) = round_trip(&f);
+        assert_eq!(lfh.uncompressed_size, 0xFFFFFFFF);
+        assert_eq!(cdh.relative_offset_of_local_header, 0xFFFFFFFF);
+        assert!(cdh.version_needed_to_extract >= 45);
+    }
+
+    #[test]
+    fn headers_from_info_encrypted_with_data_descriptor() {
+        // the check byte comes from the DOS time, which the data descriptor flag says
+        let mut h = header();
+        h.general_purpose_bit_flag.set_encrypted(true);
+        h.general_purpose_bit_flag.set_data_descriptor(true);
+        let f = FileInfo::from_cdh(&h).unwrap();
+        let (_, cdh) = round_trip(&f);
+        assert!(cdh.has_data_descriptor());
+    }
+
+    #[test]
+    fn non_utf8_names() {
+        // "café.txt" in CP437, without bit 11: kept as raw bytes
+        let mut h = header();
+        h.general_purpose_bit_flag.set_utf8_names(false);
+        h.file_name = MaybeUTF8::from_bytes(b"caf\x82.txt".to_vec());
+        let f = FileInfo::from_cdh(&h).unwrap();
+        let mut w = MemWriter::new();
+        LocalFileHeader::from_info(&f).unwrap().write(&mut w).unwrap();
+        let cd = w.get_ref().len();
+        h.write(&mut w).unwrap();
+        let mut bytes = w.unwrap();
+        {
+            let mut r = BufReader::new(bytes.as_slice());
+            let lfh = LocalFileHeader::read(&mut r).unwrap();
+            let cdh = CentralDirectoryHeader::read(&mut r).unwrap();
+            assert!(lfh.file_name.as_bytes() == b"caf\x82.txt");
+            assert!(cdh.file_name.as_bytes() == b"caf\x82.txt");
+            assert!(cdh.file_name.as_str().is_none());
+        }
+
+        // with bit 11 set, the same bytes are an error rather than a panic
+        bytes.as_mut_slice()[7] |= 0x08;
+        bytes.as_mut_slice()[cd + 9] |= 0x08;
+        let mut r = BufReader::new(bytes.as_slice());
+        assert_eq!(LocalFileHeader::read(&mut r).err().unwrap(), error::NonUTF8Field);
+        r.seek(cd as i64, SeekSet).unwrap();
+        assert_eq!(CentralDirectoryHeader::read(&mut r).err().unwrap(), error::NonUTF8Field);
+    }
+
+    #[test]
+    fn field_lengths() {
+        // the lengths written are those of the fields, however they were changed
+        let mut h = header();
+        h.file_name.push_str("/longer");
+        h.extra_field = vec![0x34, 0x12, 0x02, 0x00, 0xaa, 0xbb];
+        h.file_comment = MaybeUTF8::new();
+        let mut w = MemWriter::new();
+        h.write(&mut w).unwrap();
+        assert_eq!(w.get_ref().len() as u64, h.total_size());
+        let bytes = w.unwrap();
+        let read = CentralDirectoryHeader::read(&mut BufReader::new(bytes.as_slice())).unwrap();
+        assert!(read.file_name == "dir/é.txt/longer");
+        assert!(read.extra_field == h.extra_field);
+        assert!(read.file_comment.is_empty());
+
+        // too long for their 16-bit length, rather than truncated
+        let long = Vec::from_elem(0x10000, b'a');
+        let mut h = header();
+        h.file_name = MaybeUTF8::from_bytes(long.clone());
+        assert_eq!(h.write(&mut MemWriter::new()).err().unwrap(), error::TooLongField);
+        let mut h = header();
+        h.file_comment = MaybeUTF8::from_bytes(long.clone());
+        assert_eq!(h.write(&mut MemWriter::new()).err().unwrap(), error::TooLongField);
+        let mut lfh = LocalFileHeader::from_info(&FileInfo::from_cdh(&header()).unwrap()).unwrap();
+        lfh.extra_field = long.clone();
+        assert_eq!(lfh.write(&mut MemWriter::new()).err().unwrap(), error::TooLongField);
+        let signature = CentralDirectoryDigitalSignature::new(long.clone());
+        assert_eq!(signature.write(&mut MemWriter::new()).err().unwrap(), error::TooLongField);
+        // one byte less fits
+        let signature = CentralDirectoryDigitalSignature::new(long.slice_from(1).to_vec());
+        assert!(signature.write(&mut MemWriter::new()).is_ok());
+    }
+
+    #[test]
+    fn validate_and_compare_headers() {
+        let h = header();
+        assert!(h.validate().is_empty());
+        let lfh = LocalFileHeader::from_info(&FileInfo::from_cdh(&h).unwrap()).unwrap();
+        assert!(lfh.validate().is_empty());
+
+        // read back, the headers are equal to what was written
+        let mut w = MemWriter::new();
+        lfh.write(&mut w).unwrap();
+        h.write(&mut w).unwrap();
+        let bytes = w.unwrap();
+        let mut r = BufReader::new(bytes.as_slice());
+        assert!(LocalFileHeader::read(&mut r).unwrap() == lfh);
+        let read = CentralDirectoryHeader::read(&mut r).unwrap();
+        assert!(read == h);
+        let mut other = read.clone();
+        other.crc32 += 1;
+        assert!(other != h);
+        let shown = format!("{}", h);
+        assert!(shown.as_slice().starts_with("CentralDirectoryHeader { version_made_by: 0x031e, "));
+        assert!(shown.as_slice().contains("crc32: 0x12345678"));
+        assert!(format!("{}", lfh).as_slice().contains("last_modified_datetime: 2014-10-03 12:30:44"));
+
+        // every irregularity there is, in field order
+        let mut h = header();
+        h.version_needed_to_extract = 0;
+        h.general_purpose_bit_flag.set_strong_encryption(true);
+        h.last_modified_datetime = MsdosDateTime::new(2014, 13, 3, 12, 30, 44);
+        h.compression_method = 0;
+        h.extra_field = vec![0x01, 0x00, 0x05, 0x00, 0xaa];
+        assert_eq!(h.validate(), vec![super::ZeroVersionNeeded, super::StrongEncryptionWithoutEncryption,
+                                      super::InvalidDateTime, super::StoredSizeMismatch,
+                                      super::MalformedExtraField]);
+        h.last_modified_datetime = MsdosDateTime::zero();
+        h.file_comment = MaybeUTF8::from_bytes(Vec::from_elem(0x10000, b'a'));
+        assert!(h.validate().contains(&super::ZeroDateTime));
+        assert!(h.validate().contains(&super::FieldTooLong));
+
+        let mut e = super::EndOfCentralDirectoryRecord::new();
+        assert!(e.validate().is_empty());
+        e.entry_count_this_disk = 3;
+        e.total_entry_count = 2;
+        assert_eq!(e.validate(), vec![super::InconsistentEntryCounts]);
+    }
+
+    #[test]
+    fn gp_flags() {
+        // each named bit, set and cleared alone
+        let mut flags = GpFlags(0);
+        flags.set_encrypted(true);
+        assert!(flags.encrypted() && flags.bits() == 1);
+        flags.set_encrypted(false);
+        flags.set_data_descriptor(true);
+        assert!(flags.data_descriptor() && flags.bits() == 8);
+        flags.set_data_descriptor(false);
+        flags.set_strong_encryption(true);
+        assert!(flags.strong_encryption() && flags.bits() == 64);
+        flags.set_strong_encryption(false);
+        flags.set_utf8_names(true);
+        assert!(flags.utf8_names() && flags.bits() == 2048);
+        flags.set_utf8_names(false);
+        flags.set_masked(true);
+        assert!(flags.masked() && flags.bits() == 8192);
+        flags.set_masked(false);
+        assert_eq!(flags.bits(), 0);
+        assert!(!flags.encrypted() && !flags.data_descriptor() && !flags.strong_encryption());
+        assert!(!flags.utf8_names() && !flags.masked() && !flags.compressed_patched_data());
+        assert!(GpFlags(32).compressed_patched_data());
+
+        for &(option, bits) in [(Normal, 0u16), (Maximum, 2), (Fast, 4), (SuperFast, 6)].iter() {
+            let mut flags = GpFlags(0xFFFF);
+            flags.set_deflate_option(option);
+            assert_eq!(flags.bits(), 0xFFF9 | bits);
+            assert_eq!(flags.deflate_option(), option);
+        }
+
+        // reserved bits are kept, and shown as they are
+        let mut flags = GpFlags(0x8000 | 0x0010 | 1);
+        flags.set_utf8_names(true);
+        assert_eq!(flags.bits(), 0x8811);
+        assert_eq!(format!("{}", flags).as_slice(), "encrypted | utf8_names | 0x8010");
+        assert_eq!(format!("{}", GpFlags(8 | 2)).as_slice(), "data_descriptor | 0x0002");
+        assert_eq!(format!("{}", GpFlags(0)).as_slice(), "0x0000");
+    }
+
+    #[test]
+    fn host_systems() {
+        // Windows' built-in zip folders, Info-ZIP on Linux, macOS Archive Utility
+        for &(version_made_by, host, spec_version) in [(0x0014u16, MsDos, 20u8),
+                                                        (0x031e, Unix, 30),
+                                                        (0x0315, Unix, 21),
+                                                        (0x0a3f, WindowsNtfs, 63),
+                                                        (0x133f, OsX, 63),
+                                                        (0x4a14, UnknownHost(74), 20)].iter() {
+            let mut h = header();
+            h.version_made_by = version_made_by;
+            assert_eq!(h.host_system(), host);
+            assert_eq!(h.creator_spec_version(), spec_version);
+            let f = FileInfo::from_cdh(&h).unwrap();
+            assert_eq!((f.host_system(), f.creator_spec_version()), (host, spec_version));
+            assert_eq!(super::version_made_by(host, spec_version), version_made_by);
+        }
+        // every value round-trips, known or not
+        for x in range(0u, 256) {
+            assert_eq!(HostSystem::from_u8(x as u8).to_u8(), x as u8);
+        }
+        assert_eq!(HostSystem::from_u8(19), OsX);
+        assert_eq!(HostSystem::from_u8(20), UnknownHost(20));
+    }
+
+    #[test]
+    fn readonly_follows_the_host_system() {
+        // a Unix entry with a writable mode, whatever its MS-DOS attributes say
+        let mut h = header();
+        h.external_file_attributes |= super::MSDOS_READONLY as u32;
+        assert!(!h.is_readonly());
+        h.external_file_attributes = (0o100444 << 16) as u32;
+        assert!(h.is_readonly());
+        h.version_made_by = 0x0014;
+        h.external_file_attributes = super::MSDOS_READONLY as u32;
+        assert!(h.is_readonly());
+        assert_eq!(h.is_readonly(), FileInfo::from_cdh(&h).unwrap().is_readonly());
+    }
+
+    #[test]
+    fn headers_from_info_disk() {
+        let f = FileInfo::from_cdh(&header()).unwrap();
+        assert_eq!(CentralDirectoryHeader::from_info(&f, 3, 10).unwrap().disk_number_start, 3);
+    }
+
+    #[test]
+    fn invalid_signature_says_where() {
+        let f = FileInfo::from_cdh(&header()).unwrap();
+        let mut w = MemWriter::new();
+        w.write([0u8, ..7].as_slice()).unwrap();
+        LocalFileHeader::from_info(&f).unwrap().write(&mut w).unwrap();
+        let bytes = w.unwrap();
+        let mut r = BufReader::new(bytes.as_slice());
+        r.seek(7, SeekSet).unwrap();
+        let e = CentralDirectoryHeader::read(&mut r).unwrap_err();
+        assert_eq!(e, error::InvalidSignature(super::CDH_SIGNATURE, super::LFH_SIGNATURE, 7));
+        assert_eq!(format!("{}", e).as_slice(),
+                   "invalid ZIP signature: expected 0x02014b50, found 0x04034b50 at offset 7");
+    }
+
+    fn descriptor(signature_present: bool, zip64: bool, crc32: u32) -> Vec<u8> {
+        let dd = DataDescriptor {
+            signature_present: signature_present,
+            zip64: zip64,
+            crc32: crc32,
+            compressed_size: 100,
+            uncompressed_size: 300,
+        };
+        let mut w = MemWriter::new();
+        dd.write(&mut w).unwrap();
+        assert_eq!(w.get_ref().len() as u64, dd.total_size());
+        w.unwrap()
+    }
+
+    #[test]
+    fn data_descriptor_round_trip() {
+        for &(signature_present, zip64) in [(true, false), (false, false), (true, true), (false, true)].iter() {
+            let bytes = descriptor(signature_present, zip64, 0x12345678);
+            for dd in [DataDescriptor::read(&mut BufReader::new(bytes.as_slice()), zip64).unwrap(),
+                       DataDescriptor::read_after_data(&mut BufReader::new(bytes.as_slice()), zip64, 0x12345678, 100).unwrap()].iter() {
+                assert_eq!(dd.signature_present, signature_present);
+                assert_eq!(dd.crc32, 0x12345678);
+                assert_eq!((dd.compressed_size, dd.uncompressed_size), (100, 300));
+            }
+        }
+    }
+
+    #[test]
+    fn data_descriptor_crc_equal_to_the_signature() {
+        // without the signature, `read` takes the CRC32 for it; knowing the CRC32 tells them apart
+        for &signature_present in [true, false].iter() {
+            let bytes = descriptor(signature_present, false, DD_SIGNATURE);
+            let dd = DataDescriptor::read_after_data(&mut BufReader::new(bytes.as_slice()), false, DD_SIGNATURE, 100).unwrap();
+            assert_eq!(dd.signature_present, signature_present);
+            assert_eq!(dd.crc32, DD_SIGNATURE);
+            assert_eq!((dd.compressed_size, dd.uncompressed_size), (100, 300));
+        }
+        // followed by the next local header, say
+        let mut bytes = descriptor(false, false, DD_SIGNATURE);
+        bytes.push_all(&[0x50, 0x4b, 0x03, 0x04]);
+        assert!(DataDescriptor::read(&mut BufReader::new(bytes.as_slice()), false).unwrap().signature_present);
+    }
+
+    #[test]
+    fn data_descriptor_mismatch() {
+        let bytes = descriptor(true, false, 0x12345678);
+        assert_eq!(DataDescriptor::read_after_data(&mut BufReader::new(bytes.as_slice()), false, 0x12345679, 100).err().unwrap(),
+                   error::CrcError);
+        let e = DataDescriptor::read_after_data(&mut BufReader::new(bytes.as_slice()), false, 0x12345678, 101).err().unwrap();
+        assert_eq!(e.io_error().unwrap().desc, "data descriptor doesn't match the entry data");
+    }
+
+    // the ZIP64 end of central directory record and locator of a one-entry archive written by
+    // `zip -fz` (Info-ZIP 3.0), from standard input
+    static ZIP64_END_RECORDS: &'static [u8] = &[
+        0x50, 0x4b, 0x06, 0x06, 0x2c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1e, 0x03, 0x2d, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x3b, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x39, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x50, 0x4b, 0x06, 0x07, 0x00, 0x00, 0x00, 0x00,
+        0x74, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn zip64_end_records() {
+        let mut r = BufReader::new(ZIP64_END_RECORDS);
+        let e64 = Zip64EndOfCentralDirectoryRecord::read(&mut r, ZIP64_END_RECORDS.len() as u64).unwrap();
+        assert_eq!((e64.version_made_by, e64.version_needed_to_extract), (0x031e, 45));
+        assert_eq!((e64.disk_number, e64.disk_number_with_start_of_central_directory), (0, 0));
+        assert_eq!((e64.entry_count_this_disk, e64.total_entry_count), (1, 1));
+        assert_eq!((e64.central_directory_size, e64.central_directory_offset), (0x3b, 0x39));
+        assert!(e64.extensible_data.is_empty());
+        let locator = Zip64EndOfCentralDirectoryLocator::read(&mut r).unwrap();
+        assert_eq!(locator.disk_number_with_zip64_end_record, 0);
+        assert_eq!(locator.zip64_end_record_offset, 0x74);
+        assert_eq!(locator.total_disk_count, 1);
+
+        let mut w = MemWriter::new();
+        e64.write(&mut w).unwrap();
+        assert_eq!(w.get_ref().len() as u64, e64.total_size());
+        locator.write(&mut w).unwrap();
+        assert!(w.get_ref().as_slice() == ZIP64_END_RECORDS);
+
+        // with an extensible data sector, which must fit before the locator
+        let mut e64 = e64.clone();
+        e64.extensible_data = vec![1, 2, 3, 4, 5];
+        let mut w = MemWriter::new();
+        e64.write(&mut w).unwrap();
+        let bytes = w.unwrap();
+        assert_eq!(bytes.len(), 61);
+        let read = Zip64EndOfCentralDirectoryRecord::read(&mut BufReader::new(bytes.as_slice()), 61).unwrap();
+        assert!(read.extensible_data == vec![1, 2, 3, 4, 5]);
+        assert_eq!(read.central_directory_offset, 0x39);
+        assert!(Zip64EndOfCentralDirectoryRecord::read(&mut BufReader::new(bytes.as_slice()), 60).is_err());
+    }
+
+    #[test]
+    fn msdos_time_round_trip() {
+        // every time of day, with fields that would clobber each other if shifted wrong
+        for hour in range(0u, 24) {
+            for minute in range(0u, 60) {
+                for second in range(0u, 60) {
+                    let t = MsdosDateTime::new(2014, 10, 3, hour, minute, second);
+                    // odd seconds are rounded down
+                    assert_eq!(t.to_tuple(), (2014, 10, 3, hour, minute, second / 2 * 2));
+                }
+            }
+        }
+        // every day of the range
+        for year in range(1980u, 2108) {
+            for month in range(1u, 13) {
+                for day in range(1u, super::days_in_month(year, month) + 1) {
+                    let t = MsdosDateTime::new_checked(year, month, day, 23, 59, 58).unwrap();
+                    assert_eq!(t.to_tuple(), (year, month, day, 23, 59, 58));
+                    assert!(t.is_valid());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn msdos_time_invalid() {
+        assert!(MsdosDateTime::new_checked(1979, 12, 31, 23, 59, 58).is_err());
+        assert!(MsdosDateTime::new_checked(2108, 1, 1, 0, 0, 0).is_err());
+        assert!(MsdosDateTime::new_checked(2014, 2, 29, 0, 0, 0).is_err());
+        assert!(MsdosDateTime::new_checked(2000, 2, 29, 0, 0, 0).is_ok());
+        assert!(MsdosDateTime::new_checked(2014, 10, 3, 24, 0, 0).is_err());
+        assert!(!MsdosDateTime::zero().is_valid());
+    }
+
+    #[test]
+    fn epoch_seconds_utc() {
+        assert_eq!(MsdosDateTime::from_epoch_seconds_utc(super::MIN_EPOCH_SECONDS).to_tuple(), (1980, 1, 1, 0, 0, 0));
+        assert_eq!(MsdosDateTime::from_epoch_seconds_utc(super::MAX_EPOCH_SECONDS).to_tuple(), (2107, 12, 31, 23, 59, 58));
+        assert_eq!(MsdosDateTime::new(2014, 10, 3, 12, 30, 44).to_epoch_seconds_utc(), 1412339444);
+        // a day apart and a little over, across leap days, round down to even seconds
+        let mut t = super::MIN_EPOCH_SECONDS;
+        while t <= super::MAX_EPOCH_SECONDS {
+            assert_eq!(MsdosDateTime::from_epoch_seconds_utc(t).to_epoch_seconds_utc(), t - t % 2);
+            t += 86400 + 3601;
+        }
+    }
+
+    #[test]
+    fn epoch_seconds_clamped() {
+        // the ends of the range in local time, whatever the time zone
+        let first = MsdosDateTime::new(1980, 1, 1, 0, 0, 0);
+        let last = MsdosDateTime::new(2107, 12, 31, 23, 59, 58);
+        assert!(MsdosDateTime::from_epoch_seconds(0) == first);
+        assert!(MsdosDateTime::from_epoch_seconds(first.to_epoch_seconds() - 2) == first);
+        assert!(MsdosDateTime::from_epoch_seconds(first.to_epoch_seconds()) == first);
+        assert!(MsdosDateTime::from_epoch_seconds(last.to_epoch_seconds()) == last);
+        assert!(MsdosDateTime::from_epoch_seconds(last.to_epoch_seconds() + 2) == last);
+        assert!(MsdosDateTime::from_epoch_seconds_checked(0).is_none());
+        assert!(MsdosDateTime::from_epoch_seconds_checked(first.to_epoch_seconds() - 2).is_none());
+        assert!(MsdosDateTime::from_epoch_seconds_checked(first.to_epoch_seconds()) == Some(first));
+        assert!(MsdosDateTime::from_epoch_seconds_checked(last.to_epoch_seconds()) == Some(last));
+        assert!(MsdosDateTime::from_epoch_seconds_checked(last.to_epoch_seconds() + 2).is_none());
+    }
+
+    #[test]
+    fn epoch_seconds_local() {
+        // every half hour of a year, which crosses daylight saving time changes in time zones
+        // that have them: instants round trip, except in the hour that is repeated when the
+        // clocks go back, which gives the other instant with the same local time
+        let start = MsdosDateTime::new(2014, 1, 1, 0, 0, 0).to_epoch_seconds();
+        let mut t = start;
+        while t < start + 366 * 86400 {
+            let local = MsdosDateTime::from_epoch_seconds(t);
+            let back = local.to_epoch_seconds();
+            assert!(back == t || MsdosDateTime::from_epoch_seconds(back) == local);
+            t += 1800;
+        }
+        // local times skipped when the clocks go forward still give an instant within the hour
+        let start = MsdosDateTime::new(2014, 1, 1, 0, 0, 0).to_epoch_seconds_utc();
+        let mut t = start;
+        while t < start + 366 * 86400 {
+            let local = MsdosDateTime::from_epoch_seconds_utc(t);
+            let again = MsdosDateTime::from_epoch_seconds(local.to_epoch_seconds()).to_epoch_seconds_utc();
+            assert!((again - t).abs() <= 3600);
+            t += 1800;
+        }
+    }
+}
@@ -0,0 +1,415 @@
+//! An incremental decoder for raw deflate streams (RFC 1951).
+//!
+//! `flate` only inflates whole buffers at once; this decoder pulls compressed bytes from a
+//! Reader as needed and keeps nothing but the 32 KiB window, so entries of any size can be
+//! decompressed in bounded memory.
+
+use std::io::{Reader, IoResult, IoError, InvalidInput, EndOfFile};
+use std::io;
+
+pub static WINDOW_SIZE: uint = 32768;
+static MAX_BITS: uint = 15;
+
+pub static LENGTH_BASE: [u16, ..29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+pub static LENGTH_EXTRA: [u8, ..29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+pub static DISTANCE_BASE: [u16, ..30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+    8193, 12289, 16385, 24577];
+pub static DISTANCE_EXTRA: [u8, ..30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+// the order in which code length code lengths are stored in a dynamic block header
+static CODE_LENGTH_ORDER: [uint, ..19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+fn invalid(desc: &'static str) -> IoError {
+    IoError { kind: InvalidInput, desc: desc, detail: None }
+}
+
+// canonical Huffman code, decoded one bit at a time
+struct Huffman {
+    // number of codes of each length
+    counts: [u16, ..16],
+    // symbols ordered by code
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> IoResult<Huffman> {
+        let mut counts = [0u16, ..16];
+        for &len in lengths.iter() {
+            counts[len as uint] += 1;
+        }
+
+        // reject over-subscribed codes; incomplete ones are fine as long as unused codes never show up
+        let mut left = 1i32;
+        for len in range(1u, MAX_BITS + 1) {
+            left <<= 1;
+            left -= counts[len] as i32;
+            if left < 0 {
+                return Err(invalid("invalid deflate stream (over-subscribed Huffman code)"));
+            }
+        }
+
+        let mut offsets = [0u16, ..16];
+        for len in range(1u, MAX_BITS) {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = Vec::from_elem(lengths.len(), 0u16);
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols.as_mut_slice()[offsets[len as uint] as uint] = symbol as u16;
+                offsets[len as uint] += 1;
+            }
+        }
+
+        counts[0] = 0;
+        Ok(Huffman { counts: counts, symbols: symbols })
+    }
+
+    fn fixed_literals() -> Huffman {
+        let mut lengths = [0u8, ..288];
+        for i in range(0u, 144) { lengths[i] = 8; }
+        for i in range(144u, 256) { lengths[i] = 9; }
+        for i in range(256u, 280) { lengths[i] = 7; }
+        for i in range(280u, 288) { lengths[i] = 8; }
+        Huffman::new(lengths.as_slice()).unwrap()
+    }
+
+    fn fixed_distances() -> Huffman {
+        Huffman::new([5u8, ..30].as_slice()).unwrap()
+    }
+}
+
+struct BitReader<R> {
+    inner: R,
+    bit_buffer: u32,
+    bit_count: uint,
+}
+
+impl<R:Reader> BitReader<R> {
+    fn bits(&mut self, need: uint) -> IoResult<u32> {
+        while self.bit_count < need {
+            let byte = match self.inner.read_byte() {
+                Ok(byte) => byte,
+                Err(ref e) if e.kind == EndOfFile => return Err(invalid("unexpected end of deflate stream")),
+                Err(e) => return Err(e),
+            };
+            self.bit_buffer |= (byte as u32) << self.bit_count;
+            self.bit_count += 8;
+        }
+        let value = self.bit_buffer & ((1u32 << need) - 1);
+        self.bit_buffer >>= need;
+        self.bit_count -= need;
+        Ok(value)
+    }
+
+    // drops the bits left in the current byte
+    fn align(&mut self) {
+        let extra = self.bit_count % 8;
+        self.bit_buffer >>= extra;
+        self.bit_count -= extra;
+    }
+
+    fn decode(&mut self, h: &Huffman) -> IoResult<u16> {
+        let mut code = 0i32;  // bits read so far
+        let mut first = 0i32; // first code of the current length
+        let mut index = 0i32; // index of the first code of the current length in h.symbols
+        for len in range(1u, MAX_BITS + 1) {
+            code |= try!(self.bits(1)) as i32;
+            let count = h.counts[len] as i32;
+            if code - count < first {
+                return Ok(h.symbols[(index + (code - first)) as uint]);
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+        Err(invalid("invalid deflate stream (unused Huffman code)"))
+    }
+}
+
+enum State {
+    BlockHeader,
+    // uncompressed block, with the number of bytes left
+    StoredBlock(uint),
+    // block compressed with the current literal/length and distance codes
+    CompressedBlock,
+    Finished,
+}
+
+/// A Reader yielding the decompressed contents of a raw deflate stream read from `R`.
+pub struct InflateReader<R> {
+    input: BitReader<R>,
+    state: State,
+    last_block: bool,
+    literals: Huffman,
+    distances: Huffman,
+    window: Vec<u8>,
+    window_pos: uint,
+    // bytes output so far, which back-references can't reach past
+    produced: u64,
+    // pending back-reference
+    copy_length: uint,
+    copy_distance: uint,
+}
+
+impl<R:Reader> InflateReader<R> {
+    pub fn new(inner: R) -> InflateReader<R> {
+        InflateReader {
+            input: BitReader { inner: inner, bit_buffer: 0, bit_count: 0 },
+            state: BlockHeader,
+            last_block: false,
+            literals: Huffman { counts: [0u16, ..16], symbols: Vec::new() },
+            distances: Huffman { counts: [0u16, ..16], symbols: Vec::new() },
+            window: Vec::from_elem(WINDOW_SIZE, 0u8),
+            window_pos: 0,
+            produced: 0,
+            copy_length: 0,
+            copy_distance: 0,
+        }
+    }
+
+    /// Returns the underlying Reader. Some bytes past the end of the deflate stream may have
+    /// been consumed from it.
+    pub fn unwrap(self) -> R {
+        self.input.inner
+    }
+
+    /// The underlying Reader, to check what follows the deflate stream once it has ended.
+    pub fn get_mut<'a>(&'a mut self) -> &'a mut R {
+        &mut self.input.inner
+    }
+
+    fn output(&mut self, byte: u8) -> u8 {
+        self.window.as_mut_slice()[self.window_pos] = byte;
+        self.window_pos = (self.window_pos + 1) % WINDOW_SIZE;
+        self.produced += 1;
+        byte
+    }
+
+    fn read_block_header(&mut self) -> IoResult<()> {
+        if self.last_block {
+            self.state = Finished;
+            return Ok(());
+        }
+        self.last_block = try!(self.input.bits(1)) == 1;
+        match try!(self.input.bits(2)) {
+            0 => {
+                self.input.align();
+                let len = try!(self.input.bits(16));
+                let nlen = try!(self.input.bits(16));
+                if len != !nlen & 0xffff {
+                    return Err(invalid("invalid deflate stream (stored block length mismatch)"));
+                }
+                self.state = StoredBlock(len as uint);
+            }
+            1 => {
+                self.literals = Huffman::fixed_literals();
+                self.distances = Huffman::fixed_distances();
+                self.state = CompressedBlock;
+            }
+            2 => {
+                try!(self.read_dynamic_codes());
+                self.state = CompressedBlock;
+            }
+            _ => return Err(invalid("invalid deflate stream (reserved block type)")),
+        }
+        Ok(())
+    }
+
+    fn read_dynamic_codes(&mut self) -> IoResult<()> {
+        let nlen = try!(self.input.bits(5)) as uint + 257;
+        let ndist = try!(self.input.bits(5)) as uint + 1;
+        let ncode = try!(self.input.bits(4)) as uint + 4;
+        if nlen > 286 || ndist > 30 {
+            return Err(invalid("invalid deflate stream (bad code counts)"));
+        }
+
+        let mut code_lengths = [0u8, ..19];
+        for i in range(0, ncode) {
+            code_lengths[CODE_LENGTH_ORDER[i]] = try!(self.input.bits(3)) as u8;
+        }
+        let length_code = try!(Huffman::new(code_lengths.as_slice()));
+
+        let mut lengths = Vec::from_elem(nlen + ndist, 0u8);
+        let mut index = 0u;
+        while index < nlen + ndist {
+            let symbol = try!(self.input.decode(&length_code));
+            let (value, repeat) = match symbol {
+                0...15 => (symbol as u8, 1u),
+                16 => {
+                    if index == 0 {
+                        return Err(invalid("invalid deflate stream (repeat with no first length)"));
+                    }
+                    (lengths[index - 1], 3 + try!(self.input.bits(2)) as uint)
+                }
+                17 => (0, 3 + try!(self.input.bits(3)) as uint),
+                _ => (0, 11 + try!(self.input.bits(7)) as uint),
+            };
+            if index + repeat > nlen + ndist {
+                return Err(invalid("invalid deflate stream (too many lengths)"));
+            }
+            for _ in range(0, repeat) {
+                lengths.as_mut_slice()[index] = value;
+                index += 1;
+            }
+        }
+        if lengths[256] == 0 {
+            return Err(invalid("invalid deflate stream (no end-of-block code)"));
+        }
+
+        self.literals = try!(Huffman::new(lengths.slice_to(nlen)));
+        self.distances = try!(Huffman::new(lengths.slice_from(nlen)));
+        Ok(())
+    }
+
+    // decodes the next byte of output, or returns None at the end of the stream
+    fn next_byte(&mut self) -> IoResult<Option<u8>> {
+        loop {
+            if self.copy_length > 0 {
+                let pos = (self.window_pos + WINDOW_SIZE - self.copy_distance) % WINDOW_SIZE;
+                let byte = self.window[pos];
+                self.copy_length -= 1;
+                return Ok(Some(self.output(byte)));
+            }
+
+            let state = self.state;
+            match state {
+                BlockHeader => try!(self.read_block_header()),
+                StoredBlock(0) => self.state = BlockHeader,
+                StoredBlock(left) => {
+                    let byte = try!(self.input.bits(8)) as u8;
+                    self.state = StoredBlock(left - 1);
+                    return Ok(Some(self.output(byte)));
+                }
+                CompressedBlock => {
+                    let symbol = try!(self.input.decode(&self.literals)) as uint;
+                    if symbol < 256 {
+                        return Ok(Some(self.output(symbol as u8)));
+                    } else if symbol == 256 {
+                        self.state = BlockHeader;
+                    } else {
+                        let symbol = symbol - 257;
+                        if symbol >= 29 {
+                            return Err(invalid("invalid deflate stream (bad length symbol)"));
+                        }
+                        let length = LENGTH_BASE[symbol] as uint +
+                                     try!(self.input.bits(LENGTH_EXTRA[symbol] as uint)) as uint;
+                        let symbol = try!(self.input.decode(&self.distances)) as uint;
+                        if symbol >= 30 {
+                            return Err(invalid("invalid deflate stream (bad distance symbol)"));
+                        }
+                        let distance = DISTANCE_BASE[symbol] as uint +
+                                       try!(self.input.bits(DISTANCE_EXTRA[symbol] as uint)) as uint;
+                        if distance as u64 > self.produced {
+                            return Err(invalid("invalid deflate stream (distance too far back)"));
+                        }
+                        self.copy_length = length;
+                        self.copy_distance = distance;
+                    }
+                }
+                Finished => return Ok(None),
+            }
+        }
+    }
+}
+
+impl<R:Reader> Reader for InflateReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        let mut n = 0;
+        while n < buf.len() {
+            match try!(self.next_byte()) {
+                Some(byte) => { buf[n] = byte; n += 1; }
+                None => break,
+            }
+        }
+        if n == 0 && buf.len() > 0 {
+            Err(io::standard_error(EndOfFile))
+        } else {
+            Ok(n)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufReader, IoResult};
+    use flate;
+    use super::InflateReader;
+
+    fn inflate(compressed: &[u8]) -> IoResult<Vec<u8>> {
+        InflateReader::new(BufReader::new(compressed)).read_to_end()
+    }
+
+    fn error(compressed: &[u8]) -> &'static str {
+        inflate(compressed).unwrap_err().desc
+    }
+
+    // pseudo-random bytes, from a linear congruential generator
+    fn noise(len: uint) -> Vec<u8> {
+        let mut x = 1u32;
+        Vec::from_fn(len, |_| { x = x * 1103515245 + 12345; (x >> 16) as u8 })
+    }
+
+    #[test]
+    fn vectors() {
+        // a fixed Huffman block with only the end-of-block code
+        assert_eq!(inflate([0x03, 0x00].as_slice()).unwrap(), vec![]);
+        // "a" in a fixed Huffman block, as zlib compresses it
+        assert!(inflate([0x4b, 0x04, 0x00].as_slice()).unwrap().as_slice() == b"a");
+        // a stored block
+        assert!(inflate(b"\x01\x05\x00\xfa\xffhello").unwrap().as_slice() == b"hello");
+        // a stored block that isn't the last, then an empty fixed one
+        assert!(inflate(b"\x00\x02\x00\xfd\xffhi\x03\x00").unwrap().as_slice() == b"hi");
+    }
+
+    #[test]
+    fn invalid_streams() {
+        assert_eq!(error([0x07].as_slice()), "invalid deflate stream (reserved block type)");
+        assert_eq!(error(b"\x01\x05\x00\x00\x00hello"), "invalid deflate stream (stored block length mismatch)");
+        // a back-reference at the very start, before anything was output
+        assert_eq!(error([0x03, 0x02].as_slice()), "invalid deflate stream (distance too far back)");
+        assert_eq!(error([0x4b].as_slice()), "unexpected end of deflate stream");
+        assert_eq!(error(b"\x01\x05\x00\xfa\xffhel"), "unexpected end of deflate stream");
+    }
+
+    #[test]
+    fn zlib_streams() {
+        let mut text = Vec::new();
+        for i in range(0u, 5000) {
+            text.push_all(format!("line {} of some text, ", i % 97).as_bytes());
+        }
+        // back-references as far as the window reaches
+        let mut far = noise(70000);
+        for i in range(32768u, far.len()) {
+            far.as_mut_slice()[i] = far[i - 32768];
+        }
+        for data in [Vec::new(), b"a".to_vec(), text, noise(100000), far].iter() {
+            let compressed = flate::deflate_bytes(data.as_slice()).unwrap();
+            assert!(inflate(compressed.as_slice()).unwrap() == *data);
+        }
+    }
+
+    #[test]
+    fn small_reads() {
+        let data = noise(1000);
+        let compressed = flate::deflate_bytes(data.as_slice()).unwrap();
+        let mut r = InflateReader::new(BufReader::new(compressed.as_slice()));
+        let mut out = Vec::new();
+        let mut buf = [0u8, ..7];
+        loop {
+            match r.read(buf.as_mut_slice()) {
+                Ok(n) => out.push_all(buf.slice_to(n)),
+                Err(_) => break,
+            }
+        }
+        assert!(out == data);
+    }
+}
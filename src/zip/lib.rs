@@ -1,17 +1,37 @@
 #![desc = "A simple rust library for reading and writing ZIP files"]
 #![license = "MIT"]
 
-#![feature(macro_rules)]
+#![feature(macro_rules, unsafe_destructor)]
 
 extern crate flate;
+extern crate time;
+#[cfg(feature = "aes")]
+extern crate "rust-crypto" as crypto;
+#[cfg(feature = "bzip2")]
+extern crate bzip2;
 
 pub use self::fileinfo::{CompressionMethod, Deflate, Unknown, FileInfo};
-pub use self::reader::ZipReader;
+pub use self::reader::{ZipReader, ZipEntryReader, RawEntry, SymlinkPolicy, VerifyReport, ExtractReport};
+pub use self::writer::{ZipWriter, EntryWriter, SplitZipWriter, FileOptions};
+pub use self::editor::ZipEditor;
+pub use self::split::SplitReader;
+pub use self::stream::ZipStreamReader;
+pub use self::listing::{EntryStat, ArchiveStats};
 
-mod crc32;
+mod zipcrypto;
+mod inflate;
+mod deflate;
+mod bunzip2;
+pub mod crc32;
 pub mod maybe_utf8;
 pub mod error;
 pub mod format;
 pub mod fileinfo;
 pub mod reader;
+pub mod writer;
+pub mod aes;
+pub mod editor;
+pub mod split;
+pub mod stream;
+pub mod listing;
 
@@ -0,0 +1,142 @@
+//! Per-entry and archive-wide statistics, as listed by `unzip -v`.
+
+use std::fmt;
+use maybe_utf8::MaybeUTF8;
+use fileinfo;
+use fileinfo::{CompressionMethod, FileInfo};
+
+// space saved by compression, in percent of the uncompressed size; negative when compression
+// made things bigger
+fn savings(compressed_size: u64, uncompressed_size: u64) -> i64 {
+    if uncompressed_size == 0 {
+        return 0;
+    }
+    (uncompressed_size as i64 - compressed_size as i64) * 100 / uncompressed_size as i64
+}
+
+/// One line of `ZipReader::list`.
+#[deriving(PartialEq, Clone)]
+pub struct EntryStat {
+    pub name: MaybeUTF8,
+    pub compression_method: CompressionMethod,
+    pub raw_compression_method: u16,
+    pub uncompressed_size: u64,
+    pub compressed_size: u64,
+    // (year, month, day, hour, minute, second)
+    pub last_modified_datetime: (uint, uint, uint, uint, uint, uint),
+    pub crc32: u32,
+}
+
+impl EntryStat {
+    pub fn from_info(f: &FileInfo) -> EntryStat {
+        EntryStat {
+            name: f.name.clone(),
+            compression_method: f.compression_method,
+            raw_compression_method: f.raw_compression_method,
+            uncompressed_size: f.uncompressed_size,
+            compressed_size: f.compressed_size,
+            last_modified_datetime: f.last_modified_datetime,
+            crc32: f.crc32,
+        }
+    }
+
+    /// The space saved by compression, in percent of the uncompressed size (0 for empty
+    /// entries, negative if compression made the entry bigger).
+    pub fn ratio(&self) -> i64 {
+        savings(self.compressed_size, self.uncompressed_size)
+    }
+}
+
+impl fmt::Show for EntryStat {
+    /// Formats the entry as `unzip -v` does: size, method, compressed size, ratio, date, time,
+    /// CRC32 and name.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let method = match self.compression_method {
+            fileinfo::Store => "Stored".to_string(),
+            fileinfo::Deflate => "Defl".to_string(),
+            fileinfo::Bzip2 => "BZip2".to_string(),
+            fileinfo::Unknown => format!("Unk:{:03}", self.raw_compression_method),
+        };
+        let (year, month, day, hour, minute, _) = self.last_modified_datetime;
+        write!(f, "{:>8}  {:<7} {:>8} {:>3}% {:04}-{:02}-{:02} {:02}:{:02} {:08x}  {}",
+               self.uncompressed_size, method, self.compressed_size, self.ratio(),
+               year, month, day, hour, minute, self.crc32, self.name.display_escaped())
+    }
+}
+
+/// The totals of `ZipReader::stats`.
+#[deriving(PartialEq, Clone)]
+pub struct ArchiveStats {
+    pub entry_count: uint,
+    pub uncompressed_size: u64,
+    pub compressed_size: u64,
+}
+
+impl ArchiveStats {
+    pub fn from_entries<'a, I:Iterator<&'a FileInfo>>(entries: I) -> ArchiveStats {
+        let mut entries = entries;
+        let mut stats = ArchiveStats { entry_count: 0, uncompressed_size: 0, compressed_size: 0 };
+        for f in entries {
+            stats.entry_count += 1;
+            stats.uncompressed_size += f.uncompressed_size;
+            stats.compressed_size += f.compressed_size;
+        }
+        stats
+    }
+
+    /// The space saved by compression over the whole archive, like `EntryStat::ratio`.
+    pub fn ratio(&self) -> i64 {
+        savings(self.compressed_size, self.uncompressed_size)
+    }
+}
+
+impl fmt::Show for ArchiveStats {
+    /// Formats the totals as the last line of `unzip -v`, aligned with the entries.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let files = if self.entry_count == 1 { "file" } else { "files" };
+        write!(f, "{:>8}          {:>8} {:>3}%                            {} {}",
+               self.uncompressed_size, self.compressed_size, self.ratio(), self.entry_count, files)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use format::{CentralDirectoryHeader, MsdosDateTime};
+    use maybe_utf8::MaybeUTF8;
+    use fileinfo::FileInfo;
+    use super::{EntryStat, ArchiveStats};
+
+    fn info(name: &str, method: u16, uncompressed_size: u32, compressed_size: u32, crc: u32) -> FileInfo {
+        let mut h = CentralDirectoryHeader::new();
+        h.file_name = MaybeUTF8::from_str(name.to_string());
+        h.compression_method = method;
+        h.last_modified_datetime = MsdosDateTime::new(2014, 10, 3, 12, 30, 44);
+        h.uncompressed_size = uncompressed_size;
+        h.compressed_size = compressed_size;
+        h.crc32 = crc;
+        FileInfo::from_cdh(&h).unwrap()
+    }
+
+    #[test]
+    fn unzip_v_listing() {
+        // an empty directory, and an entry that compression made bigger
+        let files = vec![info("a.txt", 8, 1000, 250, 0x12345678),
+                         info("empty/", 0, 0, 0, 0),
+                         info("b.lzma", 14, 100, 120, 0xdeadbeef)];
+        let lines: Vec<String> = files.iter().map(|f| format!("{}", EntryStat::from_info(f))).collect();
+        assert_eq!(lines, vec!["    1000  Defl         250  75% 2014-10-03 12:30 12345678  a.txt".to_string(),
+                               "       0  Stored         0   0% 2014-10-03 12:30 00000000  empty/".to_string(),
+                               "     100  Unk:014      120 -20% 2014-10-03 12:30 deadbeef  b.lzma".to_string()]);
+
+        let stats = ArchiveStats::from_entries(files.iter());
+        assert_eq!(stats.entry_count, 3);
+        assert_eq!((stats.uncompressed_size, stats.compressed_size), (1100, 370));
+        assert_eq!(format!("{}", stats),
+                   "    1100               370  66%                            3 files".to_string());
+
+        let empty = ArchiveStats::from_entries(files.slice_to(0).iter());
+        assert_eq!(empty.ratio(), 0);
+        assert_eq!(format!("{}", ArchiveStats::from_entries(files.slice_to(1).iter())),
+                   "    1000               250  75%                            1 file".to_string());
+    }
+}
@@ -1,9 +1,39 @@
 //! Byte container optionally encoded as UTF-8.
 
-use std::{str, string, fmt};
+use std::{cmp, mem, str, string, fmt, hash};
+use std::hash::Hash;
 use std::str::MaybeOwned;
 use std::default::Default;
 use std::path::BytesContainer;
+use error;
+use error::ZipResult;
+
+// the upper half of IBM code page 437, the encoding of names without the UTF-8 flag; the lower
+// half is ASCII
+static CP437_UPPER_HALF: [char, ..128] = [
+    '\u00c7', '\u00fc', '\u00e9', '\u00e2', '\u00e4', '\u00e0', '\u00e5', '\u00e7',
+    '\u00ea', '\u00eb', '\u00e8', '\u00ef', '\u00ee', '\u00ec', '\u00c4', '\u00c5',
+    '\u00c9', '\u00e6', '\u00c6', '\u00f4', '\u00f6', '\u00f2', '\u00fb', '\u00f9',
+    '\u00ff', '\u00d6', '\u00dc', '\u00a2', '\u00a3', '\u00a5', '\u20a7', '\u0192',
+    '\u00e1', '\u00ed', '\u00f3', '\u00fa', '\u00f1', '\u00d1', '\u00aa', '\u00ba',
+    '\u00bf', '\u2310', '\u00ac', '\u00bd', '\u00bc', '\u00a1', '\u00ab', '\u00bb',
+    '\u2591', '\u2592', '\u2593', '\u2502', '\u2524', '\u2561', '\u2562', '\u2556',
+    '\u2555', '\u2563', '\u2551', '\u2557', '\u255d', '\u255c', '\u255b', '\u2510',
+    '\u2514', '\u2534', '\u252c', '\u251c', '\u2500', '\u253c', '\u255e', '\u255f',
+    '\u255a', '\u2554', '\u2569', '\u2566', '\u2560', '\u2550', '\u256c', '\u2567',
+    '\u2568', '\u2564', '\u2565', '\u2559', '\u2558', '\u2552', '\u2553', '\u256b',
+    '\u256a', '\u2518', '\u250c', '\u2588', '\u2584', '\u258c', '\u2590', '\u2580',
+    '\u03b1', '\u00df', '\u0393', '\u03c0', '\u03a3', '\u03c3', '\u00b5', '\u03c4',
+    '\u03a6', '\u0398', '\u03a9', '\u03b4', '\u221e', '\u03c6', '\u03b5', '\u2229',
+    '\u2261', '\u00b1', '\u2265', '\u2264', '\u2320', '\u2321', '\u00f7', '\u2248',
+    '\u00b0', '\u2219', '\u00b7', '\u221a', '\u207f', '\u00b2', '\u25a0', '\u00a0',
+];
+
+fn decode_cp437(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| {
+        if b < 0x80 { b as char } else { CP437_UPPER_HALF[(b - 0x80) as uint] }
+    }).collect()
+}
 
 #[deriving(Clone)]
 pub enum MaybeUTF8 {
@@ -24,6 +54,19 @@ impl MaybeUTF8 {
         Bytes(v)
     }
 
+    /// Decodes `bytes` as IBM code page 437, giving the UTF-8 variant. Never fails, since every
+    /// byte is a character in CP437; the original bytes are lost though, so use `from_bytes` to
+    /// keep them.
+    ///
+    /// This is for callers only: header parsing keeps names without the UTF-8 flag as the
+    /// bytes variant, since entries are looked up, checked against their Unicode path extra
+    /// field and written back by those bytes (and many tools write UTF-8 without the flag).
+    /// `FileInfo::display_name` and `to_string_cp437` decode them for display.
+    pub fn from_cp437(bytes: &[u8]) -> MaybeUTF8 {
+        UTF8(decode_cp437(bytes))
+    }
+
+    /// The underlying bytes; `len` and `is_empty` (from `Collection`) count them too.
     pub fn as_bytes<'a>(&'a self) -> &'a [u8] {
         match *self {
             UTF8(ref s) => s.as_bytes(),
@@ -31,6 +74,7 @@ impl MaybeUTF8 {
         }
     }
 
+    /// The contents as text, if they are valid UTF-8 (whichever the variant).
     pub fn as_str<'a>(&'a self) -> Option<&'a str> {
         match *self {
             UTF8(ref s) => Some(s.as_slice()),
@@ -67,7 +111,9 @@ impl MaybeUTF8 {
         }
     }
 
-    pub fn into_str_lossy(self) -> String {
+    /// Converts to a String, replacing invalid UTF-8 sequences with U+FFFD. Valid UTF-8 is
+    /// kept as is, without copying; see `to_string_cp437` to decode the bytes as CP437 instead.
+    pub fn into_string_lossy(self) -> String {
         self.map_into_str(|v| match String::from_utf8_lossy(v.as_slice()) {
             // `v` is definitely UTF-8, so do not make a copy!
             str::Slice(_) => unsafe {string::raw::from_utf8(v)},
@@ -75,6 +121,136 @@ impl MaybeUTF8 {
         })
     }
 
+    /// The same as `into_string_lossy`.
+    pub fn into_str_lossy(self) -> String {
+        self.into_string_lossy()
+    }
+
+    /// Borrows the contents, keeping the variant.
+    pub fn as_slice<'a>(&'a self) -> MaybeUTF8Slice<'a> {
+        match *self {
+            UTF8(ref s) => UTF8Slice(s.as_slice()),
+            Bytes(ref v) => BytesSlice(v.as_slice()),
+        }
+    }
+
+    /// Whether the bytes start with those of `prefix`, which can be a `&str`, a `&[u8]`, a
+    /// String, a Path or a MaybeUTF8 of either variant.
+    pub fn starts_with<T:BytesContainer>(&self, prefix: T) -> bool {
+        self.as_bytes().starts_with(prefix.container_as_bytes())
+    }
+
+    /// Whether the bytes end with those of `suffix`, see `starts_with`.
+    pub fn ends_with<T:BytesContainer>(&self, suffix: T) -> bool {
+        self.as_bytes().ends_with(suffix.container_as_bytes())
+    }
+
+    /// Appends text; the variant doesn't change.
+    pub fn push_str(&mut self, s: &str) {
+        match *self {
+            UTF8(ref mut v) => v.push_str(s),
+            Bytes(ref mut v) => v.push_all(s.as_bytes()),
+        }
+    }
+
+    /// Appends bytes. The UTF-8 variant stays so if they are valid UTF-8, and becomes the bytes
+    /// variant otherwise.
+    pub fn push_bytes(&mut self, bytes: &[u8]) {
+        match str::from_utf8(bytes) {
+            Some(s) => return self.push_str(s),
+            None => {}
+        }
+        let mut v = mem::replace(self, MaybeUTF8::new()).into_bytes();
+        v.push_all(bytes);
+        *self = Bytes(v);
+    }
+
+    /// The rest of the name after `prefix`, or None if it doesn't start with it. The rest of a
+    /// UTF-8 name is UTF-8 unless the prefix ends in the middle of a character.
+    pub fn strip_prefix<'a, T:BytesContainer>(&'a self, prefix: T) -> Option<MaybeUTF8Slice<'a>> {
+        if !self.starts_with(prefix.container_as_bytes()) {
+            return None;
+        }
+        let rest = self.as_bytes().slice_from(prefix.container_as_bytes().len());
+        match *self {
+            UTF8(_) => Some(MaybeUTF8Slice::from_bytes(rest)),
+            Bytes(_) => Some(BytesSlice(rest)),
+        }
+    }
+
+    /// Replaces `old` at the start of the name with `new`, or returns None if the name doesn't
+    /// start with `old`. The result is UTF-8 if the name and `new` are.
+    pub fn map_prefix<T:BytesContainer, U:BytesContainer>(&self, old: T, new: U) -> Option<MaybeUTF8> {
+        let rest = match self.strip_prefix(old) {
+            Some(rest) => rest,
+            None => return None,
+        };
+        let mut result = match (self, new.container_as_str()) {
+            (&UTF8(_), Some(s)) => UTF8(s.to_string()),
+            _ => Bytes(new.container_as_bytes().to_vec()),
+        };
+        result.push_bytes(rest.as_bytes());
+        Some(result)
+    }
+
+    /// Decodes the bytes as IBM code page 437 (which APPNOTE specifies for names and comments
+    /// without the UTF-8 flag), unless this is known to be UTF-8. Never fails, since every byte
+    /// is a character in CP437.
+    pub fn to_string_cp437(&self) -> String {
+        match *self {
+            UTF8(ref s) => s.clone(),
+            Bytes(ref v) => decode_cp437(v.as_slice()),
+        }
+    }
+
+    /// Formats the contents for printing where control characters could do harm, like a
+    /// terminal or a log: valid UTF-8 is shown as text with C0 and C1 control characters (and
+    /// backslashes) escaped, and the bytes of invalid sequences as `\xNN`.
+    pub fn display_escaped<'a>(&'a self) -> EscapedDisplay<'a> {
+        EscapedDisplay { bytes: self.as_bytes() }
+    }
+
+    /// Splits an entry name into the components of a path relative to the extraction
+    /// directory, or returns None if it could point outside of it or isn't a valid path:
+    /// absolute names, drive prefixes, `..` components and NUL bytes are refused, and empty and
+    /// `.` components are dropped. Nothing is decoded, so `..%2f` is an ordinary component.
+    /// Some Windows tools write backslashes as separators; with `backslash_separates` they
+    /// are separators here too, else they are part of the components.
+    pub fn safe_components<'a>(&'a self, backslash_separates: bool) -> Option<Vec<&'a [u8]>> {
+        let name = self.as_bytes();
+        if name.is_empty() || name[0] == b'/' || (backslash_separates && name[0] == b'\\') || name.contains(&0) {
+            return None;
+        }
+        if name.len() >= 2 && name[1] == b':' && (name[0] as char).is_alphabetic() {
+            return None;
+        }
+        let mut components = Vec::new();
+        for component in name.split(|&b| b == b'/' || (backslash_separates && b == b'\\')) {
+            if component == b".." {
+                return None;
+            }
+            if !component.is_empty() && component != b"." {
+                components.push(component);
+            }
+        }
+        if components.is_empty() { None } else { Some(components) }
+    }
+
+    /// The relative path an entry name stands for, see `safe_components`. Fails with
+    /// UnsafeFileName for names it refuses.
+    pub fn to_safe_path(&self, backslash_separates: bool) -> ZipResult<Path> {
+        match self.safe_components(backslash_separates) {
+            Some(components) => {
+                let mut path = Path::new(components[0]);
+                for component in components.slice_from(1).iter() {
+                    path.push(*component);
+                }
+                Ok(path)
+            }
+            None => Err(error::UnsafeFileName(self.clone())),
+        }
+    }
+
     pub fn into_bytes(self) -> Vec<u8> {
         match self {
             UTF8(s) => s.into_bytes(),
@@ -83,6 +259,10 @@ impl MaybeUTF8 {
     }
 }
 
+// Equality, ordering and hashing are over the bytes, so the variant doesn't matter: a UTF-8 name
+// and the same bytes without the UTF-8 flag are the same name. The order is byte-wise, which for
+// UTF-8 is the order of code points, not a locale-aware one.
+
 impl PartialEq for MaybeUTF8 {
     fn eq(&self, other: &MaybeUTF8) -> bool {
         self.as_bytes().eq(&other.as_bytes())
@@ -104,6 +284,12 @@ impl Ord for MaybeUTF8 {
     }
 }
 
+impl<S:hash::Writer> Hash<S> for MaybeUTF8 {
+    fn hash(&self, state: &mut S) {
+        self.as_bytes().hash(state)
+    }
+}
+
 impl BytesContainer for MaybeUTF8 {
     fn container_as_bytes<'a>(&'a self) -> &'a [u8] {
         self.as_bytes()
@@ -118,12 +304,38 @@ impl BytesContainer for MaybeUTF8 {
     }
 }
 
+// compares with anything made of bytes, like `&str`, `&[u8]`, String or Path: `name.equiv(&"a/b")`
 impl<T:BytesContainer> Equiv<T> for MaybeUTF8 {
     fn equiv(&self, other: &T) -> bool {
         self.as_bytes() == other.container_as_bytes()
     }
 }
 
+// `name == "a/b"` and `"a/b" == name`, whatever the variant of `name`
+impl<'a> PartialEq<&'a str> for MaybeUTF8 {
+    fn eq(&self, other: &&'a str) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl<'a> PartialEq<&'a [u8]> for MaybeUTF8 {
+    fn eq(&self, other: &&'a [u8]) -> bool {
+        self.as_bytes() == *other
+    }
+}
+
+impl<'a> PartialEq<MaybeUTF8> for &'a str {
+    fn eq(&self, other: &MaybeUTF8) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl<'a> PartialEq<MaybeUTF8> for &'a [u8] {
+    fn eq(&self, other: &MaybeUTF8) -> bool {
+        *self == other.as_bytes()
+    }
+}
+
 impl Collection for MaybeUTF8 {
     fn len(&self) -> uint {
         self.as_bytes().len()
@@ -139,6 +351,101 @@ impl Mutable for MaybeUTF8 {
     }
 }
 
+/// A borrowed MaybeUTF8, to look at names without copying them. It compares and hashes like
+/// the owned form.
+#[deriving(Clone)]
+pub enum MaybeUTF8Slice<'a> {
+    UTF8Slice(&'a str),
+    BytesSlice(&'a [u8]),
+}
+
+impl<'a> MaybeUTF8Slice<'a> {
+    /// Borrows `bytes`, as the UTF-8 variant if they are valid UTF-8.
+    pub fn from_bytes(bytes: &'a [u8]) -> MaybeUTF8Slice<'a> {
+        match str::from_utf8(bytes) {
+            Some(s) => UTF8Slice(s),
+            None => BytesSlice(bytes),
+        }
+    }
+
+    pub fn as_bytes(&self) -> &'a [u8] {
+        match *self {
+            UTF8Slice(s) => s.as_bytes(),
+            BytesSlice(v) => v,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&'a str> {
+        match *self {
+            UTF8Slice(s) => Some(s),
+            BytesSlice(v) => str::from_utf8(v),
+        }
+    }
+
+    pub fn to_owned(&self) -> MaybeUTF8 {
+        match *self {
+            UTF8Slice(s) => UTF8(s.to_string()),
+            BytesSlice(v) => Bytes(v.to_vec()),
+        }
+    }
+}
+
+impl<'a> PartialEq for MaybeUTF8Slice<'a> {
+    fn eq(&self, other: &MaybeUTF8Slice<'a>) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl<'a> Eq for MaybeUTF8Slice<'a> {
+}
+
+impl<'a> PartialOrd for MaybeUTF8Slice<'a> {
+    fn partial_cmp(&self, other: &MaybeUTF8Slice<'a>) -> Option<Ordering> {
+        self.as_bytes().partial_cmp(&other.as_bytes())
+    }
+}
+
+impl<'a> Ord for MaybeUTF8Slice<'a> {
+    fn cmp(&self, other: &MaybeUTF8Slice<'a>) -> Ordering {
+        self.as_bytes().cmp(&other.as_bytes())
+    }
+}
+
+impl<'a, S:hash::Writer> Hash<S> for MaybeUTF8Slice<'a> {
+    fn hash(&self, state: &mut S) {
+        self.as_bytes().hash(state)
+    }
+}
+
+// lets a borrowed name look up a map keyed by owned names, with `find_equiv`
+impl<'a> Equiv<MaybeUTF8> for MaybeUTF8Slice<'a> {
+    fn equiv(&self, other: &MaybeUTF8) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl<'a> BytesContainer for MaybeUTF8Slice<'a> {
+    fn container_as_bytes<'b>(&'b self) -> &'b [u8] {
+        self.as_bytes()
+    }
+
+    fn container_as_str<'b>(&'b self) -> Option<&'b str> {
+        self.as_str()
+    }
+}
+
+impl<'a> Collection for MaybeUTF8Slice<'a> {
+    fn len(&self) -> uint {
+        self.as_bytes().len()
+    }
+}
+
+impl<'a> fmt::Show for MaybeUTF8Slice<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write(self.as_bytes())
+    }
+}
+
 // a workaround for multiple `FromIterator` implementations with differing type params
 trait MaybeUTF8FromIterator {
     fn maybe_utf8_from_iter<I:Iterator<Self>>(iterator: I) -> MaybeUTF8;
@@ -168,9 +475,324 @@ impl Default for MaybeUTF8 {
     }
 }
 
+/// The escaped form of a MaybeUTF8, see `MaybeUTF8::display_escaped`.
+pub struct EscapedDisplay<'a> {
+    bytes: &'a [u8],
+}
+
+// the length of the UTF-8 sequence starting with `lead`, or 1 for a byte that can't start one
+fn utf8_sequence_length(lead: u8) -> uint {
+    match lead {
+        0xc2...0xdf => 2,
+        0xe0...0xef => 3,
+        0xf0...0xf4 => 4,
+        _ => 1,
+    }
+}
+
+impl<'a> fmt::Show for EscapedDisplay<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bytes = self.bytes;
+        let mut i = 0;
+        while i < bytes.len() {
+            let end = cmp::min(i + utf8_sequence_length(bytes[i]), bytes.len());
+            match str::from_utf8(bytes.slice(i, end)) {
+                Some(s) => {
+                    let c = s.char_at(0);
+                    match c {
+                        '\\' => try!(f.write(b"\\\\")),
+                        '\n' => try!(f.write(b"\\n")),
+                        '\r' => try!(f.write(b"\\r")),
+                        '\t' => try!(f.write(b"\\t")),
+                        '\x00'...'\x1f' | '\x7f' => try!(write!(f, "\\x{:02x}", c as u32)),
+                        '\u0080'...'\u009f' => try!(write!(f, "\\u{{{:x}}}", c as u32)),
+                        _ => try!(f.write(s.as_bytes())),
+                    }
+                    i = end;
+                }
+                None => {
+                    try!(write!(f, "\\x{:02x}", bytes[i]));
+                    i += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
 impl fmt::Show for MaybeUTF8 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.write(self.as_bytes())
     }
 }
 
+
+#[cfg(test)]
+mod test {
+    use std::hash;
+    use std::collections::{HashMap, TreeMap};
+    use error;
+    use super::{MaybeUTF8, MaybeUTF8Slice, UTF8, Bytes, UTF8Slice, BytesSlice};
+
+    // the same name as both variants
+    fn names(s: &str) -> [MaybeUTF8, ..2] {
+        [MaybeUTF8::from_str(s.to_string()), MaybeUTF8::from_bytes(s.as_bytes().to_vec())]
+    }
+
+    #[test]
+    fn equality_is_symmetric() {
+        for name in names("dir/é.txt").iter() {
+            assert!(*name == "dir/é.txt");
+            assert!("dir/é.txt" == *name);
+            assert!(*name == "dir/é.txt".as_bytes());
+            assert!("dir/é.txt".as_bytes() == *name);
+            assert!(*name != "dir/e.txt");
+            assert!("dir/e.txt" != *name);
+            assert!(*name != b"dir/".as_slice());
+            assert!(b"dir/".as_slice() != *name);
+        }
+        let both = names("a");
+        assert!(both[0] == both[1]);
+    }
+
+    #[test]
+    fn equality_with_invalid_utf8() {
+        let name = MaybeUTF8::from_bytes(vec![b'a', 0xff]);
+        assert!(name == [b'a', 0xff].as_slice());
+        assert!([b'a', 0xff].as_slice() == name);
+        assert!(name != "a");
+    }
+
+    #[test]
+    fn prefixes_and_suffixes() {
+        for name in names("dir/sub/file.txt").iter() {
+            assert!(name.starts_with("dir/"));
+            assert!(name.starts_with(b"dir/sub".as_slice()));
+            assert!(name.starts_with(""));
+            assert!(!name.starts_with("sub"));
+            assert!(name.ends_with(".txt"));
+            assert!(name.ends_with(b"file.txt".as_slice()));
+            assert!(!name.ends_with("dir"));
+            assert_eq!(name.strip_prefix("dir/").unwrap().as_bytes(), b"sub/file.txt");
+            assert!(name.strip_prefix("sub/").is_none());
+        }
+    }
+
+    // the path as bytes, since Path isn't Show
+    fn safe_path(name: &str, backslash_separates: bool) -> Option<Vec<u8>> {
+        MaybeUTF8::from_str(name.to_string()).to_safe_path(backslash_separates).ok().map(|p| p.as_vec().to_vec())
+    }
+
+    #[test]
+    fn safe_paths() {
+        assert_eq!(safe_path("a/b.txt", false), Some(b"a/b.txt".to_vec()));
+        // empty and `.` components are dropped
+        assert_eq!(safe_path("a//b", false), Some(b"a/b".to_vec()));
+        assert_eq!(safe_path("./a/./b/", false), Some(b"a/b".to_vec()));
+        // nothing is decoded
+        assert_eq!(safe_path("..%2fetc%2fpasswd", false), Some(b"..%2fetc%2fpasswd".to_vec()));
+        assert_eq!(safe_path("a\\b", true), Some(b"a/b".to_vec()));
+    }
+
+    #[test]
+    fn unsafe_paths() {
+        for name in ["..", "../a", "a/../../b", "a/..", "../../etc/passwd", "/etc/passwd", "",
+                     ".", "./", "C:\\evil.txt", "C:evil.txt", "c:/evil.txt", "a\0b"].iter() {
+            assert_eq!(safe_path(*name, false), None);
+            assert_eq!(safe_path(*name, true), None);
+        }
+        for name in ["C:..\\..", "..\\a", "a\\..\\..\\b", "\\evil.txt"].iter() {
+            assert_eq!(safe_path(*name, true), None);
+        }
+        // without backslash separators, those are file names of their own
+        assert_eq!(safe_path("..\\a", false), Some(b"..\\a".to_vec()));
+        let name = MaybeUTF8::from_str("../a".to_string());
+        assert!(name.to_safe_path(false) == Err(error::UnsafeFileName(name.clone())));
+    }
+
+    #[test]
+    fn cp437() {
+        let bytes = [b'a', 0x80, 0x81, 0xe1, 0xff];
+        let name = MaybeUTF8::from_cp437(bytes.as_slice());
+        assert!(name == "aÇüß\u00a0");
+        assert_eq!(MaybeUTF8::from_bytes(bytes.to_vec()).to_string_cp437().as_slice(), name.as_str().unwrap());
+    }
+
+    #[test]
+    fn cp437_upper_half() {
+        let bytes = Vec::from_fn(128, |i| 0x80 + i as u8);
+        let decoded: Vec<char> = MaybeUTF8::from_cp437(bytes.as_slice()).as_str().unwrap().chars().collect();
+        assert_eq!(decoded.len(), 128);
+        assert_eq!(decoded[0], '\u00c7');
+        assert_eq!(decoded[0xb0 - 0x80], '\u2591');
+        assert_eq!(decoded[0x7f], '\u00a0');
+        // every byte is a different character, and none is ASCII
+        for (i, &c) in decoded.iter().enumerate() {
+            assert!(c as u32 >= 0x80);
+            assert!(!decoded.slice_from(i + 1).contains(&c));
+        }
+    }
+
+    #[test]
+    fn lossy_strings() {
+        for name in names("readme.txt").iter() {
+            assert_eq!(name.as_str(), Some("readme.txt"));
+            assert_eq!(name.clone().into_string_lossy().as_slice(), "readme.txt");
+        }
+        for name in names("r\u00e9sum\u00e9 \u65e5\u672c.txt").iter() {
+            assert_eq!(name.as_str(), Some("r\u00e9sum\u00e9 \u65e5\u672c.txt"));
+            assert_eq!(name.clone().into_string_lossy().as_slice(), "r\u00e9sum\u00e9 \u65e5\u672c.txt");
+            assert_eq!(name.len(), 19);
+        }
+        // a lone continuation byte and a truncated sequence
+        let name = MaybeUTF8::from_bytes(vec![b'a', 0x80, b'b', 0xe6, 0x97]);
+        assert_eq!(name.as_str(), None);
+        assert_eq!(name.len(), 5);
+        assert_eq!(name.into_string_lossy().as_slice(), "a\ufffdb\ufffd");
+        assert!(MaybeUTF8::new().is_empty());
+        assert!(MaybeUTF8::from_bytes(vec![]).is_empty());
+        assert!(!MaybeUTF8::from_bytes(vec![0]).is_empty());
+    }
+
+    #[test]
+    fn hash_and_order() {
+        let both = names("abc");
+        assert_eq!(hash::hash(&both[0]), hash::hash(&both[1]));
+        assert_eq!(hash::hash(&both[0]), hash::hash(&MaybeUTF8Slice::from_bytes(b"abc")));
+        assert_eq!(both[0].cmp(&both[1]), Equal);
+
+        // either variant finds the other in a map
+        let mut map = HashMap::new();
+        map.insert(both[0].clone(), 1u);
+        assert_eq!(map.find(&both[1]), Some(&1));
+        assert_eq!(map.find_equiv(&both[1].as_slice()), Some(&1));
+        map.insert(both[1].clone(), 2u);
+        assert_eq!(map.len(), 1);
+
+        // byte-wise order, whatever the variant: uppercase before lowercase, and U+00E9 (C3 A9)
+        // after 'z' and before invalid UTF-8
+        let mut sorted = vec![MaybeUTF8::from_bytes(vec![0xff]), MaybeUTF8::from_str("z".to_string()),
+                              MaybeUTF8::from_bytes(b"\xc3\xa9".to_vec()), MaybeUTF8::from_str("B".to_string()),
+                              MaybeUTF8::from_bytes(b"a".to_vec()), MaybeUTF8::from_str("ab".to_string()),
+                              MaybeUTF8::new()];
+        sorted.sort();
+        let expected: [&[u8], ..7] = [b"", b"B", b"a", b"ab", b"z", b"\xc3\xa9", b"\xff"];
+        for (name, &bytes) in sorted.iter().zip(expected.iter()) {
+            assert_eq!(name.as_bytes(), bytes);
+        }
+        let mut tree = TreeMap::new();
+        for name in sorted.iter().rev() {
+            tree.insert(name.clone(), ());
+        }
+        assert!(tree.keys().zip(sorted.iter()).all(|(a, b)| a == b));
+        assert!(MaybeUTF8::from_str("\u00e9".to_string()) > MaybeUTF8::from_bytes(b"z".to_vec()));
+    }
+
+    fn escaped(name: MaybeUTF8) -> String {
+        format!("{}", name.display_escaped())
+    }
+
+    #[test]
+    fn escaped_display() {
+        // an ANSI escape sequence clearing the screen, and a newline faking another line
+        for name in names("evil\x1b[2J.txt").iter() {
+            assert_eq!(escaped(name.clone()).as_slice(), "evil\\x1b[2J.txt");
+        }
+        for name in names("a.txt\nb.txt").iter() {
+            assert_eq!(escaped(name.clone()).as_slice(), "a.txt\\nb.txt");
+        }
+        assert_eq!(escaped(MaybeUTF8::from_str("tab\there\r\x00\x7f".to_string())).as_slice(),
+                   "tab\\there\\r\\x00\\x7f");
+        // C1 controls, while other text is kept, backslashes escaped so the output is unambiguous
+        assert_eq!(escaped(MaybeUTF8::from_str("\u009b31m d\u00e9j\u00e0\\vu".to_string())).as_slice(),
+                   "\\u{9b}31m d\u00e9j\u00e0\\\\vu");
+        // invalid UTF-8 byte by byte, including a truncated sequence at the end
+        assert_eq!(escaped(MaybeUTF8::from_bytes(vec![b'a', 0xff, 0x82, b'b', 0xe6, 0x97])).as_slice(),
+                   "a\\xff\\x82b\\xe6\\x97");
+        // the plain Show is unchanged
+        assert_eq!(format!("{}", MaybeUTF8::from_str("a\nb".to_string())).as_slice(), "a\nb");
+    }
+
+    // the part of the name after its last slash, borrowed from it
+    fn base_name<'a>(name: &'a MaybeUTF8) -> MaybeUTF8Slice<'a> {
+        let bytes = name.as_bytes();
+        match bytes.iter().rposition(|&b| b == b'/') {
+            Some(i) => MaybeUTF8Slice::from_bytes(bytes.slice_from(i + 1)),
+            None => name.as_slice(),
+        }
+    }
+
+    #[test]
+    fn borrowed_slices() {
+        let owned = names("dir/file.txt");
+        // the variant is kept
+        match owned[0].as_slice() { UTF8Slice(s) => assert_eq!(s, "dir/file.txt"), _ => panic!() }
+        match owned[1].as_slice() { BytesSlice(v) => assert_eq!(v, b"dir/file.txt"), _ => panic!() }
+        assert!(owned[0].as_slice() == owned[1].as_slice());
+        assert!(owned[1].as_slice().equiv(&owned[0]));
+        for name in owned.iter() {
+            assert!(name.as_slice().to_owned() == *name);
+            assert_eq!(name.as_slice().as_str(), Some("dir/file.txt"));
+        }
+        match owned[1].as_slice().to_owned() { Bytes(_) => {}, _ => panic!() }
+
+        // slices outlive the function that made them, as long as the names do
+        let slices: Vec<MaybeUTF8Slice> = owned.iter().map(base_name).collect();
+        assert!(slices.iter().all(|s| s.as_bytes() == b"file.txt"));
+        assert!(slices[0] == MaybeUTF8Slice::from_bytes(b"file.txt"));
+
+        // from_bytes picks the variant
+        match MaybeUTF8Slice::from_bytes(b"a\xffb") { BytesSlice(_) => {}, _ => panic!() }
+        let invalid = MaybeUTF8Slice::from_bytes(b"a\xffb");
+        assert_eq!(invalid.as_str(), None);
+        assert!(invalid.to_owned() == b"a\xffb".as_slice());
+    }
+
+    fn is_utf8(name: &MaybeUTF8) -> bool {
+        match *name { UTF8(_) => true, Bytes(_) => false }
+    }
+
+    #[test]
+    fn rewriting_names() {
+        // appending a slash to a directory keeps either variant
+        for name in names("dir").iter() {
+            let mut dir = name.clone();
+            dir.push_str("/");
+            assert!(dir == "dir/");
+            assert_eq!(is_utf8(&dir), is_utf8(name));
+            dir.push_bytes(b"sub/");
+            assert!(dir == "dir/sub/");
+            assert_eq!(is_utf8(&dir), is_utf8(name));
+        }
+
+        // invalid bytes turn a UTF-8 name into bytes, with nothing lost
+        let mut name = MaybeUTF8::from_str("caf\u00e9".to_string());
+        name.push_bytes(b"\x82.txt");
+        assert!(!is_utf8(&name));
+        assert!(name == b"caf\xc3\xa9\x82.txt".as_slice());
+
+        // merging: a prefix added in front
+        let name = MaybeUTF8::from_str("a/b.txt".to_string());
+        let merged = name.map_prefix("", "other/").unwrap();
+        assert!(merged == "other/a/b.txt");
+        assert!(is_utf8(&merged));
+        // extracting a sub-tree: a leading directory stripped
+        let rest = name.strip_prefix("a/").unwrap();
+        assert_eq!(rest.as_str(), Some("b.txt"));
+        assert!(name.map_prefix("a/", "").unwrap() == "b.txt");
+        assert!(name.map_prefix("b/", "c/").is_none());
+
+        // either side not being UTF-8 gives bytes
+        let renamed = name.map_prefix("a/", b"\xff/".as_slice()).unwrap();
+        assert!(!is_utf8(&renamed));
+        assert!(renamed == b"\xff/b.txt".as_slice());
+        let bytes = MaybeUTF8::from_bytes(b"a/b.txt".to_vec());
+        let renamed = bytes.map_prefix("a/", "c/").unwrap();
+        assert!(!is_utf8(&renamed));
+        assert!(renamed == "c/b.txt");
+        // a prefix ending inside a character leaves invalid UTF-8
+        let e = MaybeUTF8::from_str("\u00e9t\u00e9".to_string());
+        assert_eq!(e.strip_prefix(b"\xc3".as_slice()).unwrap().as_str(), None);
+        assert!(!is_utf8(&e.map_prefix(b"\xc3".as_slice(), "").unwrap()));
+    }
+}
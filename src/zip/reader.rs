@@ -1,47 +1,592 @@
-use std::io::File;
-use std::io::{Reader, Writer, Seek, SeekSet, SeekEnd};
-use std::io::{IoResult, IoError, InvalidInput};
+use std::io::{File, fs};
+use std::io::{Reader, Writer, Seek, SeekEnd, RefReader, BufferedReader, BufReader, MemReader, MemWriter};
+use std::io::{IoResult, IoError, InvalidInput, EndOfFile};
+use std::io::util::{LimitReader, NullWriter};
+use std::io;
+use std::cmp;
+use std::uint;
+use std::collections::HashMap;
+use std::hash;
+use std::sync::Arc;
+use std::comm;
+use std::task;
 use std::iter;
-use std::iter::range_inclusive;
+use std::slice;
 use std::path::BytesContainer;
 use error;
 use error::ZipError;
-use maybe_utf8::MaybeUTF8;
-use flate;
-use crc32;
+use maybe_utf8::{MaybeUTF8, MaybeUTF8Slice};
+use crc32::CrcReader;
 use format;
+use format::extra;
 use fileinfo;
 use fileinfo::{CompressionMethod, FileInfo};
+use inflate::InflateReader;
+use bunzip2;
+use bunzip2::Bunzip2Reader;
+use zipcrypto::ZipCryptoReader;
+use aes;
+use aes::{AesReader, AesExtraField};
+use split::SplitReader;
+use listing::{EntryStat, ArchiveStats};
+
+static COPY_BUFFER_SIZE: uint = 64 * 1024;
 
 pub struct ZipReader<R> {
     reader: R,
     end_record: format::EndOfCentralDirectoryRecord,
+    zip64_end_record: Option<format::Zip64EndOfCentralDirectoryRecord>,
+    // from the ZIP64 end of central directory record if there is one, else from end_record
+    entry_count: u64,
+    // where the central directory is in the reader, base_offset included
+    central_directory_offset: u64,
+    // size of the data prepended to the archive (like the stub of a self-extracting archive),
+    // which the offsets stored in the archive don't count
+    base_offset: u64,
+    // position of each part of a split archive in the reader; empty when there is only one,
+    // in which case disk numbers are ignored
+    disk_starts: Vec<u64>,
+    // shared with the ZipReaders made by `try_clone`
+    entries: Arc<Entries>,
+    // whether extracted files get the modification time of their entry
+    restore_times: bool,
+    // permission bits extracted files may keep
+    permission_mask: u32,
+    symlink_policy: SymlinkPolicy,
+    // whether local file headers must agree with the central directory
+    check_local_headers: bool,
+    // whether extract_all leaves out macOS Finder metadata
+    skip_macos_metadata: bool,
+    // bytes after the end of central directory record and its comment
+    trailing_garbage_len: u64,
+    // whether lookups and extraction use `FileInfo::normalized_name`
+    normalize_names: bool,
+    // the record after the last central directory header, if there is one
+    digital_signature: Option<format::CentralDirectoryDigitalSignature>,
+    // the record between the data of the last entry and the central directory, if there is one
+    archive_extra_data: Option<format::ArchiveExtraDataRecord>,
+    parse_mode: format::ParseMode,
+    // irregularities found in the central directory in lenient mode
+    warnings: Vec<format::ParseWarning>,
+}
+
+// the central directory, parsed once when the archive is opened, and what looks entries up
+#[deriving(Clone)]
+struct Entries {
+    files: Vec<FileInfo>,
+    // file name => indices in `files`, in archive order
+    name_index: HashMap<MaybeUTF8, Vec<uint>>,
+    // folded file name (see `fold_name`) => indices in `files`, in archive order
+    folded_name_index: HashMap<Vec<u8>, Vec<uint>>,
+}
+
+/// What extracting a symlink entry does.
+#[deriving(PartialEq, Show, Clone)]
+pub enum SymlinkPolicy {
+    SkipSymlinks,
+    /// Creates a real symlink (on Unix; elsewhere, same as MaterializeSymlinks). Links pointing
+    /// outside of the destination directory are refused.
+    CreateSymlinks,
+    /// Writes a regular file containing the link target.
+    MaterializeSymlinks,
+}
+
+// what extracting an entry produced
+enum Extracted {
+    ExtractedFile(Path),
+    ExtractedDirectory(Path),
+    ExtractedSymlink(Path),
+    SkippedSymlink(Path),
 }
 
+/// Iterates over the central directory as stored, see `ZipReader::files_raw`. Iteration ends
+/// after the first error, since the position of the next header can't be known then, and after
+/// as many headers as the rest of the central directory has room for, since the entry count
+/// comes from the archive. The length is exact unless an error ends the iteration early.
 pub struct Files<'a, R:'a> {
     zip_reader: &'a mut ZipReader<R>,
-    current_entry: u16,
+    current_entry: u64,
     current_offset: u64,
+    failed: bool,
 }
 
 impl<'a, R:Reader+Seek> Iterator<Result<FileInfo, ZipError>> for Files<'a, R> {
     fn next(&mut self) -> Option<Result<FileInfo, ZipError>> {
-        if self.current_entry < self.zip_reader.end_record.total_entry_count {
-            match self.zip_reader.reader.seek(self.current_offset as i64, SeekSet) {
-                Ok(()) => {}
-                Err(err) => { return Some(Err(error::SomeIoError(err))); }
+        if self.remaining() == 0 {
+            return None;
+        }
+        match format::seek_to(&mut self.zip_reader.reader, self.current_offset) {
+            Ok(()) => {}
+            Err(err) => {
+                self.failed = true;
+                return Some(Err(error::SomeIoError(err)));
             }
-            let h = match format::CentralDirectoryHeader::read(&mut self.zip_reader.reader) {
-                Ok(h) => h,
-                Err(err) => { return Some(Err(err)); }
-            };
-            let info = FileInfo::from_cdh(&h);
-            self.current_entry += 1;
-            self.current_offset += h.total_size() as u64;
-            Some(Ok(info))
-        } else {
-            None
         }
+        let h = match format::CentralDirectoryHeader::read(&mut self.zip_reader.reader) {
+            Ok(h) => h,
+            Err(err) => {
+                self.failed = true;
+                return Some(Err(err));
+            }
+        };
+        self.current_entry += 1;
+        self.current_offset += h.total_size();
+        let zip_reader = &*self.zip_reader;
+        let result = FileInfo::from_cdh(&h).and_then(|mut f| {
+            f.local_file_header_offset = try!(zip_reader.local_header_position(&h));
+            Ok(f)
+        });
+        // the next header can still be found, since this one was read whole
+        Some(result)
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        let remaining = self.remaining();
+        (remaining, Some(remaining))
+    }
+}
+
+impl<'a, R:Reader+Seek> iter::ExactSizeIterator<Result<FileInfo, ZipError>> for Files<'a, R> {}
+
+impl<'a, R:Reader+Seek> Files<'a, R> {
+    // the headers left, capped like in `header_capacity`
+    fn remaining(&self) -> uint {
+        if self.failed {
+            return 0;
+        }
+        let zip_reader = &*self.zip_reader;
+        let end = zip_reader.central_directory_offset + zip_reader.central_directory_size();
+        let room = if end > self.current_offset { (end - self.current_offset) / format::CDH_FIXED_SIZE } else { 0 };
+        cmp::min(zip_reader.entry_count - self.current_entry, room) as uint
+    }
+}
+
+/// An entry exactly as stored in the archive, returned by `ZipReader::read_raw`.
+pub struct RawEntry {
+    /// The compression method as stored in the header (99 for AES-encrypted entries).
+    pub compression_method: u16,
+    pub crc32: u32,
+    pub uncompressed_size: u64,
+    /// The encryption header of entries with the traditional encryption, still encrypted.
+    pub encryption_header: Option<format::EncryptionHeader>,
+    /// The compressed (and possibly encrypted) data, without the local file header or the
+    /// encryption header.
+    pub data: Vec<u8>,
+}
+
+/// The outcome of `ZipReader::verify`.
+pub struct VerifyReport {
+    /// Number of entries that passed every check.
+    pub ok: uint,
+    /// The entries that failed, in archive order, with the first problem found in each (an
+    /// EntryError, which also gives the offset of the entry).
+    pub failures: Vec<(MaybeUTF8, ZipError)>,
+}
+
+/// The outcome of `ZipReader::extract_all_parallel`.
+pub struct ExtractReport {
+    /// Number of entries extracted, skipped symlinks excluded.
+    pub extracted: uint,
+    /// The entries that failed, with their error (an EntryError, which also gives the offset of
+    /// the entry).
+    pub failures: Vec<(MaybeUTF8, ZipError)>,
+}
+
+impl VerifyReport {
+    pub fn bad(&self) -> uint {
+        self.failures.len()
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+// the compressed data of an entry, decrypted if needed
+enum EntrySource<'a, R:'a> {
+    PlainSource(LimitReader<RefReader<'a, R>>),
+    ZipCryptoSource(ZipCryptoReader<LimitReader<RefReader<'a, R>>>),
+    AesSource(AesReader<LimitReader<RefReader<'a, R>>>),
+}
+
+impl<'a, R:Reader> Reader for EntrySource<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        match *self {
+            PlainSource(ref mut r) => r.read(buf),
+            ZipCryptoSource(ref mut r) => r.read(buf),
+            AesSource(ref mut r) => r.read(buf),
+        }
+    }
+}
+
+enum EntryData<'a, R:'a> {
+    StoredData(EntrySource<'a, R>),
+    DeflatedData(InflateReader<BufferedReader<EntrySource<'a, R>>>),
+    Bzip2Data(Bunzip2Reader<EntrySource<'a, R>>),
+}
+
+impl<'a, R:Reader> EntryData<'a, R> {
+    // Called once the recorded size has been read: the decompressor must have nothing more to
+    // give, and deflate streams must end with the compressed data. Anything past that is data
+    // the headers don't account for, which other tools would read differently.
+    fn check_end(&mut self) -> IoResult<()> {
+        if !try!(at_end(self)) {
+            return Err(IoError { kind: InvalidInput, desc: "entry data is longer than its recorded size", detail: None });
+        }
+        let compressed_at_end = match *self {
+            DeflatedData(ref mut r) => try!(at_end(r.get_mut())),
+            StoredData(_) | Bzip2Data(_) => true,
+        };
+        if !compressed_at_end {
+            return Err(IoError { kind: InvalidInput, desc: "compressed data goes on after the end of the deflate stream", detail: None });
+        }
+        Ok(())
+    }
+}
+
+// whether `r` has nothing more to read
+fn at_end<T:Reader>(r: &mut T) -> IoResult<bool> {
+    let mut buf = [0u8, ..1];
+    loop {
+        match r.read(buf.as_mut_slice()) {
+            Ok(0) => {}
+            Ok(_) => return Ok(false),
+            Err(ref e) if e.kind == EndOfFile => return Ok(true),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+impl<'a, R:Reader> Reader for EntryData<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        match *self {
+            StoredData(ref mut r) => r.read(buf),
+            DeflatedData(ref mut r) => r.read(buf),
+            Bzip2Data(ref mut r) => r.read(buf),
+        }
+    }
+}
+
+/// A Reader over the decompressed contents of an entry, returned by `ZipReader::read_file` and
+/// `ZipReader::read_file_encrypted`.
+///
+/// The CRC32 is checked as the data goes by: once all of the entry has been read, the next
+/// read fails with an InvalidInput error instead of EndOfFile if the CRC32 doesn't match, or if
+/// the data goes on past its recorded size (or the compressed data past the end of the deflate
+/// stream).
+/// AE-2 entries have no CRC32; their authentication code is checked instead.
+pub struct ZipEntryReader<'a, R:'a> {
+    // expecting the CRC32 and size from the central directory, unless there is no CRC32
+    data: CrcReader<EntryData<'a, R>>,
+    // uncompressed bytes left to read
+    remaining: u64,
+    crc_mismatch: bool,
+}
+
+impl<'a, R:Reader> Reader for ZipEntryReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        if self.remaining == 0 {
+            if self.data.check().is_err() {
+                self.crc_mismatch = true;
+                return Err(IoError { kind: InvalidInput, desc: "CRC mismatch", detail: None });
+            }
+            try!(self.data.get_mut().check_end());
+            return Err(io::standard_error(EndOfFile));
+        }
+
+        // never read past the size recorded in the header
+        let len = cmp::min(buf.len() as u64, self.remaining) as uint;
+        let buf = buf.slice_to_mut(len);
+        let n = match self.data.read(buf) {
+            Ok(n) => n,
+            // the data ended before the size recorded in the header
+            Err(ref e) if e.kind == EndOfFile => {
+                return Err(IoError { kind: InvalidInput, desc: "entry data is truncated", detail: None });
+            }
+            Err(e) => return Err(e),
+        };
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+fn le_u16(buf: &[u8]) -> u16 {
+    (buf[0] as u16) | (buf[1] as u16 << 8)
+}
+
+fn le_u32(buf: &[u8]) -> u32 {
+    (le_u16(buf) as u32) | (le_u16(buf.slice_from(2)) as u32 << 16)
+}
+
+// Finds the End of Central Directory record, looking backwards from the end of the file, and
+// returns its offset and the number of bytes after its comment. The record is 22 bytes plus a
+// comment of at most 65535 bytes, so only that much of the end of the file needs to be
+// searched. The signature bytes can also show up in the comment (or in a stored inner
+// archive), so a candidate only counts if its central directory ends before the record, and
+// one whose comment reaches exactly to the end of the file is preferred. Failing that, the
+// last candidate whose comment fits is used: some tools append data (like signatures) after
+// the archive.
+fn find_end_record<R:Reader+Seek>(r: &mut R) -> Result<Option<(u64, u64)>, ZipError> {
+    let fixed_size = 22u64;
+    try_io!(r.seek(0, SeekEnd));
+    let file_size = try_io!(r.tell());
+    if file_size < fixed_size {
+        return Ok(None);
+    }
+
+    let search_start = if file_size > fixed_size + 65535 { file_size - (fixed_size + 65535) } else { 0 };
+    try_io!(format::seek_to(r, search_start));
+    let tail = try_io!(r.read_exact((file_size - search_start) as uint));
+
+    let mut fallback = None;
+    let mut i = tail.len() - fixed_size as uint;
+    loop {
+        let record = tail.slice_from(i);
+        if le_u32(record) == format::EOCDR_SIGNATURE {
+            let offset = search_start + i as u64;
+            let central_directory_size = le_u32(record.slice_from(12)) as u64;
+            let central_directory_offset = le_u32(record.slice_from(16)) as u64;
+            let comment_length = le_u16(record.slice_from(20)) as uint;
+            // saturated values are in the ZIP64 record and can't be checked here
+            let zip64 = central_directory_size == format::ZIP64_SENTINEL_U32 as u64 ||
+                        central_directory_offset == format::ZIP64_SENTINEL_U32 as u64;
+            let record_size = fixed_size as uint + comment_length;
+            if record_size <= record.len() &&
+               (zip64 || central_directory_offset + central_directory_size <= offset) {
+                let trailing = (record.len() - record_size) as u64;
+                if trailing == 0 {
+                    return Ok(Some((offset, 0)));
+                }
+                if fallback.is_none() {
+                    fallback = Some((offset, trailing));
+                }
+            }
+        }
+        if i == 0 {
+            return Ok(fallback);
+        }
+        i -= 1;
+    }
+}
+
+fn glob_matches(pattern: &[u8], name: &[u8]) -> bool {
+    if pattern.is_empty() {
+        return name.is_empty();
+    }
+    if pattern.starts_with(b"**") {
+        let rest = pattern.slice_from(2);
+        // `**/` can also match no directory at all
+        if rest.starts_with(b"/") && glob_matches(rest.slice_from(1), name) {
+            return true;
+        }
+        return range(0, name.len() + 1).any(|i| glob_matches(rest, name.slice_from(i)));
+    }
+    match pattern[0] {
+        b'*' => {
+            for i in range(0, name.len() + 1) {
+                if glob_matches(pattern.slice_from(1), name.slice_from(i)) {
+                    return true;
+                }
+                if i < name.len() && name[i] == b'/' {
+                    break;
+                }
+            }
+            false
+        }
+        b'?' => !name.is_empty() && name[0] != b'/' && glob_matches(pattern.slice_from(1), name.slice_from(1)),
+        c => !name.is_empty() && name[0] == c && glob_matches(pattern.slice_from(1), name.slice_from(1)),
+    }
+}
+
+// the key names are compared by in case-insensitive lookups
+fn fold_name(name: &[u8]) -> Vec<u8> {
+    name.iter().map(|&b| {
+        match b {
+            b'\\' => b'/',
+            b'A'...b'Z' => b - b'A' + b'a',
+            _ => b,
+        }
+    }).collect()
+}
+
+// Whether a symlink with the given path components (relative to the extraction directory)
+// pointing to `target` stays inside the extraction directory.
+//
+// Counting components only works for those that are real directories. The directories the
+// link is in are (nothing is extracted through a symlink), so `..` may climb out of them, but
+// any other component may be, or later become, another symlink: `..` after it would climb out
+// of wherever that one points, so it is refused.
+fn symlink_stays_inside(link: &[&[u8]], target: &[u8]) -> bool {
+    if target.is_empty() || target[0] == b'/' || target.contains(&0) {
+        return false;
+    }
+    // the link itself is at depth link.len(), its target is relative to its directory
+    let mut depth = link.len() - 1;
+    let mut descended = false;
+    for component in target.split(|&b| b == b'/') {
+        if component == b".." {
+            if depth == 0 || descended {
+                return false;
+            }
+            depth -= 1;
+        } else if !component.is_empty() && component != b"." {
+            depth += 1;
+            descended = true;
+        }
+    }
+    true
+}
+
+// Finds the first occurrence of a 4-byte signature at or after `from`, returning its offset.
+fn find_signature<R:Reader+Seek>(r: &mut R, from: u64, signature: u32) -> Result<Option<u64>, ZipError> {
+    try_io!(format::seek_to(r, from));
+    let mut input = BufferedReader::new(r.by_ref());
+    let mut window = 0u32;
+    let mut offset = from;
+    loop {
+        let byte = match input.read_byte() {
+            Ok(byte) => byte,
+            Err(ref e) if e.kind == EndOfFile => return Ok(None),
+            Err(e) => return Err(error::SomeIoError(e)),
+        };
+        offset += 1;
+        // little-endian: the first byte read ends up lowest
+        window = (window >> 8) | (byte as u32 << 24);
+        if offset - from >= 4 && window == signature {
+            return Ok(Some(offset - 4));
+        }
+    }
+}
+
+// Reads the ZIP64 end of central directory record, if the locator is right before the end of
+// central directory record at `end_record_offset`.
+// Returns the ZIP64 end record if there is one, with the size of any data prepended to the
+// archive (as for self-extracting archives), which the offset in the locator doesn't count.
+fn read_zip64_end_record<R:Reader+Seek>(r: &mut R, end_record_offset: u64)
+                                        -> Result<Option<(format::Zip64EndOfCentralDirectoryRecord, u64)>, ZipError> {
+    let locator_size = format::ZIP64_EOCDL_SIZE as u64;
+    if end_record_offset < locator_size {
+        return Ok(None);
+    }
+    try_io!(format::seek_to(r, end_record_offset - locator_size));
+    let locator = match format::Zip64EndOfCentralDirectoryLocator::read(r) {
+        Ok(locator) => locator,
+        Err(error::InvalidSignature(..)) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let locator_offset = end_record_offset - locator_size;
+    if locator.zip64_end_record_offset > locator_offset {
+        return Err(error::SomeIoError(IoError {
+            kind: InvalidInput,
+            desc: "ZIP64 end of central directory record is after its locator",
+            detail: Some(format!("{}", locator.zip64_end_record_offset)),
+        }));
+    }
+    // with prepended data, the record isn't where the locator says; it is then looked for
+    // right before the locator, where it is unless it has an extensible data sector
+    let record_offset = if try!(has_signature(r, locator.zip64_end_record_offset, format::ZIP64_EOCDR_SIGNATURE)) {
+        locator.zip64_end_record_offset
+    } else if locator_offset >= locator.zip64_end_record_offset + ZIP64_EOCDR_FIXED_SIZE &&
+              try!(has_signature(r, locator_offset - ZIP64_EOCDR_FIXED_SIZE, format::ZIP64_EOCDR_SIGNATURE)) {
+        locator_offset - ZIP64_EOCDR_FIXED_SIZE
+    } else {
+        return Err(error::SomeIoError(IoError {
+            kind: InvalidInput,
+            desc: "ZIP64 end of central directory record not found",
+            detail: Some(format!("{}", locator.zip64_end_record_offset)),
+        }));
+    };
+    try_io!(format::seek_to(r, record_offset));
+    let available = locator_offset - record_offset;
+    let record = try!(format::Zip64EndOfCentralDirectoryRecord::read(r, available));
+    Ok(Some((record, record_offset - locator.zip64_end_record_offset)))
+}
+
+// the size of a ZIP64 end of central directory record without extensible data
+static ZIP64_EOCDR_FIXED_SIZE: u64 = 56;
+
+fn has_signature<R:Reader+Seek>(r: &mut R, offset: u64, signature: u32) -> Result<bool, ZipError> {
+    try_io!(format::seek_to(r, offset));
+    match r.read_le_u32() {
+        Ok(magic) => Ok(magic == signature),
+        Err(ref e) if e.kind == EndOfFile => Ok(false),
+        Err(e) => Err(error::SomeIoError(e)),
+    }
+}
+
+// copies the rest of an entry to `writer`, turning a CRC mismatch into CrcError. `progress`
+// is given the number of bytes written so far after each chunk.
+fn copy_entry<'a, R:Reader, T:Writer>(entry: &mut ZipEntryReader<'a, R>, writer: &mut T,
+                                      progress: |u64|) -> Result<(), ZipError> {
+    let mut buf = [0u8, ..COPY_BUFFER_SIZE];
+    let mut done = 0u64;
+    loop {
+        let n = match entry.read(buf.as_mut_slice()) {
+            Ok(n) => n,
+            Err(ref e) if e.kind == EndOfFile => return Ok(()),
+            Err(_) if entry.crc_mismatch => return Err(error::CrcError),
+            Err(e) => return Err(error::IoErrorWhile(e, "reading the data of an entry")),
+        };
+        try_io!(writer.write(buf.slice_to(n)), "writing the data of an entry");
+        done += n as u64;
+        progress(done);
+    }
+}
+
+// the rest of an entry, for `read` and `read_encrypted`
+fn read_whole_entry<'a, R:Reader>(f: &FileInfo, entry: &mut ZipEntryReader<'a, R>) -> Result<Vec<u8>, ZipError> {
+    // the size comes from the archive, so the buffer grows as the data arrives instead
+    let mut result = MemWriter::with_capacity(cmp::min(f.uncompressed_size, COPY_BUFFER_SIZE as u64) as uint);
+    try!(copy_entry(entry, &mut result, |_| {}).map_err(|e| entry_error(f, e)));
+    Ok(result.unwrap())
+}
+
+// The size of the data of an entry with the traditional encryption, whose compressed size
+// includes the encryption header.
+fn data_size_after_encryption_header(f: &FileInfo) -> Result<u64, ZipError> {
+    match f.compressed_size.checked_sub(&(format::ENCRYPTION_HEADER_SIZE as u64)) {
+        Some(size) => Ok(size),
+        None => Err(error::SomeIoError(IoError {
+            kind: InvalidInput,
+            desc: "encrypted entry is too short for its encryption header",
+            detail: None,
+        })),
+    }
+}
+
+// Says which entry an error is about, unless it already does.
+fn entry_error(f: &FileInfo, e: ZipError) -> ZipError {
+    match e {
+        e @ error::EntryError(..) => e,
+        e => error::EntryError(f.name.clone(), f.local_file_header_offset, box e),
+    }
+}
+
+// Which field of a local file header disagrees with the central directory, if any. The CRC32
+// and sizes are only compared when the local header has them, that is without a data
+// descriptor or ZIP64 extra field (saturated sizes); some streaming writers also leave them
+// zeroed without setting the data descriptor flag.
+fn local_header_mismatch(h: &format::LocalFileHeader, f: &FileInfo) -> Option<&'static str> {
+    if h.file_name.as_bytes() != f.raw_name.as_bytes() {
+        return Some("file name");
+    }
+    if h.compression_method != f.raw_compression_method {
+        return Some("compression method");
+    }
+    if h.has_data_descriptor() || h.has_zip64_extra_field() {
+        return None;
+    }
+    if h.crc32 == 0 && h.compressed_size == 0 && h.uncompressed_size == 0 {
+        return None;
+    }
+    if h.crc32 != f.crc32 {
+        Some("CRC32")
+    } else if h.compressed_size as u64 != f.compressed_size {
+        Some("compressed size")
+    } else if h.uncompressed_size as u64 != f.uncompressed_size {
+        Some("uncompressed size")
+    } else {
+        None
     }
 }
 
@@ -49,113 +594,2489 @@ impl ZipReader<File> {
     pub fn open(path: &Path) -> Result<ZipReader<File>, ZipError> {
         ZipReader::new(try_io!(File::open(path)))
     }
+
+    /// Opens the archive file again, reusing the parsed central directory and the options,
+    /// for reading different entries at the same time (see `extract_all_parallel`).
+    pub fn try_clone(&self) -> Result<ZipReader<File>, ZipError> {
+        let file = try_io!(File::open(self.reader.path()));
+        Ok(self.with_reader(file))
+    }
+
+    /// Like `extract_all`, but files are extracted by `threads` tasks, each with its own file
+    /// handle. Directories are created first and symlinks extracted last, on the calling task,
+    /// so the tasks never race to create the same directory. Entries with the same name go to
+    /// the same task, in archive order, so the last one wins as with `extract_all`. A failing
+    /// entry doesn't stop the others: every failure is in the report.
+    pub fn extract_all_parallel(&self, dest: &Path, threads: uint) -> Result<ExtractReport, ZipError> {
+        let threads = cmp::max(threads, 1);
+        let mut main = try!(self.try_clone());
+        // positions in `self.entries.files` of the entries to extract
+        let all_files = self.entries.files.as_slice();
+        let positions: Vec<uint> = range(0, all_files.len())
+                                       .filter(|&i| !(self.skip_macos_metadata && all_files[i].is_macos_metadata()))
+                                       .collect();
+        let files: Vec<&FileInfo> = positions.iter().map(|&i| &all_files[i]).collect();
+        let mut report = ExtractReport { extracted: 0, failures: Vec::new() };
+        let mut directories = Vec::new();
+
+        // directories, and the parents of files that have no entry of their own
+        for &f in files.iter().filter(|f| f.is_dir()) {
+            match main.extract_entry_to(f, dest, |_, _, _| {}) {
+                Ok(ExtractedDirectory(path)) => {
+                    directories.push((f, path));
+                    report.extracted += 1;
+                }
+                Ok(_) => {}
+                Err(e) => report.failures.push((f.name.clone(), entry_error(f, e))),
+            }
+        }
+        for &f in files.iter().filter(|f| !f.is_dir()) {
+            match self.relative_path(f) {
+                // failures show up again when extracting the file
+                Ok(relative) => { let _ = fs::mkdir_recursive(&dest.join(relative.dir_path()), io::USER_RWX); }
+                Err(_) => {}
+            }
+        }
+
+        // each task gets the indices in `files` and positions of its entries; entries that would
+        // be extracted to the same path (even on a case-insensitive file system) go to the same one
+        let (tx, rx) = comm::channel();
+        let mut expected = Vec::new();
+        let mut shards = Vec::from_fn(threads, |_| Vec::new());
+        for (i, &f) in files.iter().enumerate() {
+            if !f.is_dir() && !f.is_symlink() {
+                expected.push(i);
+                let shard = hash::hash(&fold_name(self.effective_name(f).as_bytes())) % threads as u64;
+                shards.as_mut_slice()[shard as uint].push((i, positions[i]));
+            }
+        }
+        // every file handle is opened before any task starts, so failing here leaves nothing running
+        let mut readers = Vec::with_capacity(threads);
+        for _ in range(0, threads) {
+            readers.push(try!(self.try_clone()));
+        }
+        for (reader, shard) in readers.into_iter().zip(shards.into_iter()) {
+            let mut reader = reader;
+            let dest = dest.clone();
+            let tx = tx.clone();
+            task::spawn(proc() {
+                let entries = reader.entries.clone();
+                for &(i, position) in shard.iter() {
+                    let f = &entries.files[position];
+                    let result = match reader.extract_entry_to(f, &dest, |_, _, _| {}) {
+                        Ok(ExtractedFile(path)) => reader.restore_metadata(f, &path, false),
+                        Ok(_) => Ok(()),
+                        Err(e) => Err(e),
+                    };
+                    tx.send((i, result));
+                }
+            });
+        }
+        drop(tx);
+
+        // the channel closes once every task is done, even if one of them failed
+        let mut done = Vec::from_elem(files.len(), false);
+        for (i, result) in rx.iter() {
+            done.as_mut_slice()[i] = true;
+            match result {
+                Ok(()) => report.extracted += 1,
+                Err(e) => report.failures.push((files[i].name.clone(), entry_error(files[i], e))),
+            }
+        }
+        for &i in expected.iter() {
+            if !done[i] {
+                let e = error::SomeIoError(IoError { kind: io::OtherIoError, desc: "extraction task failed", detail: None });
+                report.failures.push((files[i].name.clone(), entry_error(files[i], e)));
+            }
+        }
+
+        for &f in files.iter().filter(|f| !f.is_dir() && f.is_symlink()) {
+            let result = match main.extract_entry_to(f, dest, |_, _, _| {}) {
+                Ok(ExtractedFile(path)) => main.restore_metadata(f, &path, false).map(|_| 1u),
+                Ok(SkippedSymlink(_)) => Ok(0),
+                Ok(_) => Ok(1),
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(n) => report.extracted += n,
+                Err(e) => report.failures.push((f.name.clone(), entry_error(f, e))),
+            }
+        }
+        // directories come last, see `extract_all`
+        for &(f, ref path) in directories.iter() {
+            match main.restore_metadata(f, path, true) {
+                Ok(()) => {}
+                Err(e) => report.failures.push((f.name.clone(), entry_error(f, e))),
+            }
+        }
+        Ok(report)
+    }
+}
+
+impl ZipReader<MemReader> {
+    /// Reads an archive held in memory, such as one read out of another archive.
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<ZipReader<MemReader>, ZipError> {
+        ZipReader::new(MemReader::new(bytes))
+    }
+}
+
+impl<'a> ZipReader<BufReader<'a>> {
+    /// Like `from_bytes`, but borrows the archive instead of taking it.
+    pub fn from_slice(bytes: &'a [u8]) -> Result<ZipReader<BufReader<'a>>, ZipError> {
+        ZipReader::new(BufReader::new(bytes))
+    }
+}
+
+impl ZipReader<SplitReader<File>> {
+    /// Opens a split archive from the paths of its parts, in order (.z01, .z02, ..., .zip).
+    pub fn open_split(paths: &[Path]) -> Result<ZipReader<SplitReader<File>>, ZipError> {
+        ZipReader::new_split(try_io!(SplitReader::open(paths)))
+    }
+}
+
+impl<R:Reader+Seek> ZipReader<SplitReader<R>> {
+    /// Reads a split archive, whose parts are given in order. Entries may straddle parts.
+    /// Fails with MissingParts if the archive says it has a different number of parts.
+    pub fn new_split(reader: SplitReader<R>) -> Result<ZipReader<SplitReader<R>>, ZipError> {
+        let mut disk_starts = Vec::with_capacity(reader.part_count());
+        for disk_number in range(0, reader.part_count() as u32) {
+            disk_starts.push(reader.part_start(disk_number).unwrap());
+        }
+        let mut zip_reader = ZipReader::with_defaults(reader);
+        zip_reader.disk_starts = disk_starts;
+        try!(zip_reader.load());
+        Ok(zip_reader)
+    }
 }
 
 impl<R:Reader+Seek> ZipReader<R> {
     pub fn new(reader: R) -> Result<ZipReader<R>, ZipError> {
-        // find the End of Central Directory record, looking backwards from the end of the file
-        let mut r = reader;
-        try_io!(r.seek(0, SeekEnd));
-        let file_size = try_io!(r.tell());
-        let mut end_record_offset : Option<u64> = None;
-        for i in range_inclusive(4, file_size) {
-            let offset = file_size - i;
-            try_io!(r.seek(offset as i64, SeekSet));
-
-            let sig = try_io!(r.read_le_u32());
-
-            // TODO: check for false positives here
-            if sig == format::EOCDR_SIGNATURE {
-                end_record_offset = Some(offset);
+        ZipReader::new_with_mode(reader, format::Lenient)
+    }
+
+    /// Like `new`, with headers that violate the specification in common ways accepted (the
+    /// default, see `warnings`) or refused with IrregularHeader, for the central directory when
+    /// opening the archive and for local file headers when reading entries.
+    pub fn new_with_mode(reader: R, mode: format::ParseMode) -> Result<ZipReader<R>, ZipError> {
+        let mut zip_reader = ZipReader::with_defaults(reader);
+        zip_reader.parse_mode = mode;
+        try!(zip_reader.load());
+        Ok(zip_reader)
+    }
+
+    /// Like `new`, but if the central directory is missing or broken (as in a truncated
+    /// download), rebuilds the list of entries by scanning the local file headers from the
+    /// start of the file. Entries found this way have `FileInfo::recovered` set, and lack what
+    /// only the central directory has (comments, attributes); a truncated last entry is left
+    /// out. The archive then has no central directory for `files_raw` and `central_directory`.
+    pub fn open_with_recovery(reader: R) -> Result<ZipReader<R>, ZipError> {
+        let mut zip_reader = ZipReader::with_defaults(reader);
+        match zip_reader.load() {
+            Ok(()) => return Ok(zip_reader),
+            Err(_) => {}
+        }
+        zip_reader.end_record = format::EndOfCentralDirectoryRecord::new();
+        zip_reader.zip64_end_record = None;
+        zip_reader.entry_count = 0;
+        zip_reader.central_directory_offset = 0;
+        zip_reader.base_offset = 0;
+        zip_reader.digital_signature = None;
+        zip_reader.archive_extra_data = None;
+        zip_reader.warnings.clear();
+        let files = try!(zip_reader.scan_local_headers());
+        if files.is_empty() {
+            return Err(error::NotAZipFile);
+        }
+        zip_reader.entries.make_unique().files = files;
+        zip_reader.build_name_index();
+        Ok(zip_reader)
+    }
+
+    // a ZipReader over `reader` sharing everything else with this one
+    fn with_reader<S>(&self, reader: S) -> ZipReader<S> {
+        ZipReader {
+            reader: reader,
+            end_record: self.end_record.clone(),
+            zip64_end_record: self.zip64_end_record.clone(),
+            entry_count: self.entry_count,
+            central_directory_offset: self.central_directory_offset,
+            base_offset: self.base_offset,
+            disk_starts: self.disk_starts.clone(),
+            entries: self.entries.clone(),
+            restore_times: self.restore_times,
+            permission_mask: self.permission_mask,
+            symlink_policy: self.symlink_policy.clone(),
+            check_local_headers: self.check_local_headers,
+            skip_macos_metadata: self.skip_macos_metadata,
+            trailing_garbage_len: self.trailing_garbage_len,
+            normalize_names: self.normalize_names,
+            digital_signature: self.digital_signature.clone(),
+            archive_extra_data: self.archive_extra_data.clone(),
+            parse_mode: self.parse_mode.clone(),
+            warnings: self.warnings.clone(),
+        }
+    }
+
+    fn with_defaults(reader: R) -> ZipReader<R> {
+        ZipReader {
+            reader: reader,
+            end_record: format::EndOfCentralDirectoryRecord::new(),
+            zip64_end_record: None,
+            entry_count: 0,
+            central_directory_offset: 0,
+            base_offset: 0,
+            disk_starts: Vec::new(),
+            entries: Arc::new(Entries {
+                files: Vec::new(),
+                name_index: HashMap::new(),
+                folded_name_index: HashMap::new(),
+            }),
+            restore_times: true,
+            permission_mask: 0o777,
+            symlink_policy: MaterializeSymlinks,
+            check_local_headers: true,
+            skip_macos_metadata: false,
+            trailing_garbage_len: 0,
+            normalize_names: true,
+            digital_signature: None,
+            archive_extra_data: None,
+            parse_mode: format::Lenient,
+            warnings: Vec::new(),
+        }
+    }
+
+    // finds the end of central directory record, and loads the central directory it points to
+    fn load(&mut self) -> Result<(), ZipError> {
+        let offset = match try!(find_end_record(&mut self.reader)
+                                     .map_err(|e| e.during("looking for the end of central directory record"))) {
+            Some((offset, trailing_garbage_len)) => {
+                self.trailing_garbage_len = trailing_garbage_len;
+                offset
+            }
+            None => return Err(error::NotAZipFile),
+        };
+        try_io!(format::seek_to(&mut self.reader, offset), "reading the end of central directory record");
+        let e = try!(format::EndOfCentralDirectoryRecord::read(&mut self.reader)
+                     .map_err(|e| e.during("reading the end of central directory record")));
+        if !self.disk_starts.is_empty() && e.disk_number as uint + 1 != self.disk_starts.len() {
+            return Err(error::MissingParts(e.disk_number as u32 + 1));
+        }
+        if self.disk_starts.is_empty() && e.disk_number != 0 {
+            return Err(error::FeatureNotSupported("multi-disk archives, unless opened with ZipReader::new_split"));
+        }
+        // split archives aren't supported with ZIP64, since the locator holds an offset too
+        let (zip64, zip64_base_offset) = match try!(read_zip64_end_record(&mut self.reader, offset)
+                         .map_err(|e| e.during("reading the ZIP64 end of central directory record"))) {
+            Some((e64, base_offset)) => (Some(e64), base_offset),
+            None => (None, 0),
+        };
+        self.end_record = e;
+        let (central_directory_disk, central_directory_offset) = match zip64 {
+            Some(ref e64) => {
+                self.entry_count = e64.total_entry_count;
+                (e64.disk_number_with_start_of_central_directory, e64.central_directory_offset)
+            }
+            None => {
+                let e = &self.end_record;
+                self.entry_count = e.total_entry_count as u64;
+                (e.disk_number_with_start_of_central_directory as u32, e.central_directory_offset as u64)
+            }
+        };
+        let central_directory_start = try_io!(format::offset_after(try!(self.disk_start(central_directory_disk)),
+                                                                   central_directory_offset));
+
+        // the central directory ends right before the end record, so anything before where it
+        // is supposed to start was prepended; with ZIP64, the same goes for the ZIP64 end record
+        if zip64.is_some() {
+            self.base_offset = zip64_base_offset;
+        } else {
+            let central_directory_end = try_io!(format::offset_after(central_directory_start,
+                                                                     self.end_record.central_directory_size as u64));
+            if central_directory_end <= offset {
+                self.base_offset = offset - central_directory_end;
+            }
+        }
+        self.central_directory_offset = try_io!(format::offset_after(self.base_offset, central_directory_start));
+        self.zip64_end_record = zip64;
+        try!(self.load_central_directory());
+        self.archive_extra_data = self.find_archive_extra_data();
+        Ok(())
+    }
+
+    // The archive extra data record has to be looked for right after the data of the last
+    // entry, since nothing points to it. Anything unexpected there means there is none.
+    fn find_archive_extra_data(&mut self) -> Option<format::ArchiveExtraDataRecord> {
+        let last = match self.entries.files.iter().max_by(|f| f.local_file_header_offset) {
+            Some(f) => f.clone(),
+            None => return None,
+        };
+        let data_end = {
+            if format::seek_to(&mut self.reader, last.local_file_header_offset).is_err() {
+                return None;
+            }
+            let h = match format::LocalFileHeader::read(&mut self.reader) {
+                Ok(h) => h,
+                Err(_) => return None,
+            };
+            let data_end = match format::offset_after(last.local_file_header_offset, h.total_size())
+                                     .and_then(|data_start| format::offset_after(data_start, last.compressed_size)) {
+                Ok(data_end) => data_end,
+                Err(_) => return None,
+            };
+            if !h.has_data_descriptor() {
+                data_end
+            } else {
+                if format::seek_to(&mut self.reader, data_end).is_err() {
+                    return None;
+                }
+                match format::DataDescriptor::read_after_data(&mut self.reader, h.has_zip64_extra_field(),
+                                                               last.crc32, last.compressed_size) {
+                    Ok(dd) => match format::offset_after(data_end, dd.total_size()) {
+                        Ok(end) => end,
+                        Err(_) => return None,
+                    },
+                    Err(_) => return None,
+                }
+            }
+        };
+        if data_end >= self.central_directory_offset || format::seek_to(&mut self.reader, data_end).is_err() {
+            return None;
+        }
+        match format::ArchiveExtraDataRecord::read(&mut self.reader, self.central_directory_offset - data_end) {
+            Ok(record) => Some(record),
+            Err(_) => None,
+        }
+    }
+
+    // where the part with the given disk number starts in the reader
+    fn disk_start(&self, disk_number: u32) -> Result<u64, ZipError> {
+        if self.disk_starts.is_empty() {
+            return Ok(0);
+        }
+        match self.disk_starts.as_slice().get(disk_number as uint) {
+            Some(&start) => Ok(start),
+            None => Err(error::MissingParts(self.end_record.disk_number as u32 + 1)),
+        }
+    }
+
+    // where the local file header of an entry is in the reader
+    fn local_header_position(&self, h: &format::CentralDirectoryHeader) -> Result<u64, ZipError> {
+        let zip64 = try!(h.zip64_extended_information());
+        let disk_start = try!(self.disk_start(zip64.disk_number_start));
+        Ok(try_io!(format::offset_after(self.base_offset + disk_start, zip64.relative_offset_of_local_header)))
+    }
+
+    // Rebuilds the entries from the local file headers, in file order. Entries with a data
+    // descriptor don't record their size up front, so their end is found by looking for a
+    // data descriptor whose compressed size matches the distance from the start of the data
+    // (which only works for descriptors with the optional signature, as nearly all have; the
+    // other entries are left out with a warning, and the scan goes on inside their data).
+    fn scan_local_headers(&mut self) -> Result<Vec<FileInfo>, ZipError> {
+        try_io!(self.reader.seek(0, SeekEnd));
+        let file_size = try_io!(self.reader.tell());
+        let mut files = Vec::new();
+        let mut offset = 0u64;
+        loop {
+            let start = match try!(find_signature(&mut self.reader, offset, format::LFH_SIGNATURE)) {
+                Some(start) => start,
+                None => break,
+            };
+            try_io!(format::seek_to(&mut self.reader, start));
+            let h = match format::LocalFileHeader::read(&mut self.reader) {
+                Ok(h) => h,
+                // a truncated header, or signature bytes that happen to be in some data
+                Err(_) => { offset = start + 1; continue; }
+            };
+            let data_start = try_io!(format::offset_after(start, h.total_size()));
+
+            let (crc, compressed_size, uncompressed_size, data_end) = if h.has_data_descriptor() {
+                let mut found = None;
+                let mut search_from = data_start;
+                loop {
+                    let dd_offset = match try!(find_signature(&mut self.reader, search_from, format::DD_SIGNATURE)) {
+                        Some(dd_offset) => dd_offset,
+                        None => break,
+                    };
+                    try_io!(format::seek_to(&mut self.reader, dd_offset));
+                    match format::DataDescriptor::read(&mut self.reader, h.has_zip64_extra_field()) {
+                        Ok(ref dd) if dd.compressed_size == dd_offset - data_start => {
+                            let dd_end = try_io!(format::offset_after(dd_offset, dd.total_size()));
+                            found = Some((dd.crc32, dd.compressed_size, dd.uncompressed_size, dd_end));
+                            break;
+                        }
+                        _ => search_from = dd_offset + 1,
+                    }
+                }
+                match found {
+                    Some(sizes) => sizes,
+                    // the descriptor was cut off (or has no signature): the data can't be delimited
+                    None => {
+                        self.warnings.push(format::ParseWarning {
+                            entry: h.file_name.clone(),
+                            irregularity: format::UndelimitedData,
+                        });
+                        offset = start + 4;
+                        continue;
+                    }
+                }
+            } else {
+                let (compressed_size, uncompressed_size) =
+                    match try!(extra::find(h.extra_field.as_slice(), format::ZIP64_EXTRA_FIELD_ID)) {
+                        // the local copy always has both sizes
+                        Some(data) if data.len() >= 16 => {
+                            let mut r = BufReader::new(data);
+                            let uncompressed_size = try_io!(r.read_le_u64());
+                            (try_io!(r.read_le_u64()), uncompressed_size)
+                        }
+                        _ => (h.compressed_size as u64, h.uncompressed_size as u64),
+                    };
+                let data_end = try_io!(format::offset_after(data_start, compressed_size));
+                (h.crc32, compressed_size, uncompressed_size, data_end)
+            };
+            if data_end > file_size {
                 break;
             }
+            // each entry has to move the scan forward, whatever its sizes say
+            if data_end <= start {
+                return Err(error::SomeIoError(IoError {
+                    kind: InvalidInput,
+                    desc: "entry ends before its local file header",
+                    detail: None,
+                }));
+            }
+            files.push(FileInfo::from_lfh(&h, start, crc, compressed_size, uncompressed_size));
+            offset = data_end;
+        }
+        Ok(files)
+    }
+
+    // How many headers to reserve room for. The entry count comes from the archive, so it is
+    // capped by how many headers the central directory has room for.
+    fn header_capacity(&self) -> uint {
+        cmp::min(self.entry_count, self.central_directory_size() / format::CDH_FIXED_SIZE) as uint
+    }
+
+    fn load_central_directory(&mut self) -> Result<(), ZipError> {
+        try_io!(format::seek_to(&mut self.reader, self.central_directory_offset), "reading the central directory");
+        let mut headers = Vec::with_capacity(self.header_capacity());
+        self.warnings.clear();
+        for _ in range(0, self.entry_count) {
+            let h = format::CentralDirectoryHeader::read_with_mode(&mut self.reader, self.parse_mode.clone(),
+                                                                  &mut self.warnings);
+            headers.push(try!(h.map_err(|e| e.during("reading the central directory"))));
+        }
+        let mut files = Vec::with_capacity(headers.len());
+        for h in headers.iter() {
+            let mut f = try!(FileInfo::from_cdh(h));
+            f.local_file_header_offset = try!(self.local_header_position(h));
+            files.push(f);
+        }
+        self.entries.make_unique().files = files;
+        // the reader is right after the last header, where the digital signature would be
+        self.digital_signature = match format::CentralDirectoryDigitalSignature::read(&mut self.reader) {
+            Ok(signature) => Some(signature),
+            Err(error::InvalidSignature(..)) => None,
+            Err(e) => return Err(e),
+        };
+        self.build_name_index();
+        Ok(())
+    }
+
+    /// Sets whether `info`, `info_all` and extraction use the names of entries made on MS-DOS
+    /// or Windows with backslashes turned into slashes (the default, see
+    /// `FileInfo::normalized_name`), or the names as they are. The names `files` returns are
+    /// never changed.
+    pub fn set_normalize_names(&mut self, normalize_names: bool) {
+        self.normalize_names = normalize_names;
+        self.build_name_index();
+    }
+
+    // the name of an entry for lookups and extraction
+    fn effective_name(&self, f: &FileInfo) -> MaybeUTF8 {
+        if self.normalize_names { f.normalized_name() } else { f.name.clone() }
+    }
+
+    // the path an entry is extracted to, relative to the destination; every extraction gets it
+    // from here, so names are checked in one place. Once names are normalized, a backslash left
+    // in one is part of a file name, except on Windows where it can't be.
+    fn relative_path(&self, f: &FileInfo) -> Result<Path, ZipError> {
+        self.effective_name(f).to_safe_path(cfg!(windows) || !self.normalize_names)
+    }
 
+    fn build_name_index(&mut self) {
+        let mut name_index = HashMap::new();
+        let mut folded_name_index = HashMap::new();
+        for (i, f) in self.entries.files.iter().enumerate() {
+            let name = self.effective_name(f);
+            if name_index.contains_key(&name) {
+                name_index.get_mut(&name).push(i);
+            } else {
+                name_index.insert(name, vec![i]);
+            }
+            let folded = fold_name(f.name.as_bytes());
+            if folded_name_index.contains_key(&folded) {
+                folded_name_index.get_mut(&folded).push(i);
+            } else {
+                folded_name_index.insert(folded, vec![i]);
+            }
         }
+        let entries = self.entries.make_unique();
+        entries.name_index = name_index;
+        entries.folded_name_index = folded_name_index;
+    }
+
+    /// Returns the underlying reader, at an unspecified position.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    pub fn get_ref<'a>(&'a self) -> &'a R {
+        &self.reader
+    }
+
+    /// Gives access to the underlying reader. Moving it around is harmless, since every read
+    /// seeks first; changing what it holds only affects reads, not the central directory
+    /// parsed when the archive was opened.
+    pub fn get_mut<'a>(&'a mut self) -> &'a mut R {
+        &mut self.reader
+    }
 
-        match end_record_offset {
-            Some(offset) => {
-                try_io!(r.seek(offset as i64, SeekSet));
-                let e = try!(format::EndOfCentralDirectoryRecord::read(&mut r));
-                Ok(ZipReader {reader: r, end_record: e})
-            },
-            None => Err(error::NotAZipFile)
+    /// Sets whether entry names come from the Unicode path extra field when there is a valid
+    /// one (the default), or are always the names stored in the headers, which is what
+    /// `FileInfo::name` and `info` then use.
+    pub fn set_use_unicode_path_field(&mut self, use_unicode_path_field: bool) {
+        for f in self.entries.make_unique().files.iter_mut() {
+            f.name = match f.unicode_path_name() {
+                Some(name) if use_unicode_path_field => MaybeUTF8::from_str(name),
+                _ => f.raw_name.clone(),
+            };
         }
+        self.build_name_index();
     }
 
+    /// Iterates over the central directory by reading it again from the underlying reader,
+    /// instead of using the copy parsed when the archive was opened.
     pub fn files_raw<'a>(&'a mut self) -> Files<'a, R> {
-        let cdr_offset = self.end_record.central_directory_offset;
+        let cdr_offset = self.central_directory_offset;
         Files {
             zip_reader: self,
             current_entry: 0,
-            current_offset: cdr_offset as u64
+            current_offset: cdr_offset,
+            failed: false,
         }
     }
 
-    pub fn files<'a>(&'a mut self) -> iter::Map<Result<FileInfo, ZipError>, FileInfo,
-                                                Files<'a, R>> {
-        self.files_raw().map(|fileinfo_or_err| fileinfo_or_err.unwrap())
+    /// Like `files_raw`, but collects the entries, stopping at the first error.
+    pub fn try_files(&mut self) -> Result<Vec<FileInfo>, ZipError> {
+        let mut files = Vec::with_capacity(self.header_capacity());
+        for f in self.files_raw() {
+            files.push(try!(f));
+        }
+        Ok(files)
     }
 
-    pub fn file_names<'a>(&'a mut self) -> iter::Map<Result<FileInfo, ZipError>, MaybeUTF8,
-                                                     Files<'a, R>> {
-        self.files_raw().map(|fileinfo_or_err| fileinfo_or_err.unwrap().name)
+    /// The end of central directory record, as stored. Its fields may be saturated, with the
+    /// real values in the ZIP64 record; the accessors below take care of that.
+    pub fn end_record<'a>(&'a self) -> &'a format::EndOfCentralDirectoryRecord {
+        &self.end_record
     }
 
-    pub fn info<T:BytesContainer>(&mut self, name: T) -> Result<FileInfo, ZipError> {
-        for i in self.files() {
-            if i.name.equiv(&name) {
-                return Ok(i);
-            }
+    pub fn zip64_end_record<'a>(&'a self) -> Option<&'a format::Zip64EndOfCentralDirectoryRecord> {
+        self.zip64_end_record.as_ref()
+    }
+
+    /// The number of the disk (part) holding the end of central directory record, which is
+    /// the last one.
+    pub fn disk_number(&self) -> u32 {
+        match self.zip64_end_record {
+            Some(ref e64) => e64.disk_number,
+            None => self.end_record.disk_number as u32,
         }
-        Err(error::FileNotFoundInArchive)
     }
 
-    // TODO: Create a Reader for the cases when you don't want to decompress the whole file
-    pub fn read(&mut self, f: &FileInfo) -> Result<Vec<u8>, ZipError> {
-        try_io!(self.reader.seek(f.local_file_header_offset as i64, SeekSet));
-        let h = try!(format::LocalFileHeader::read(&mut self.reader));
-        let file_offset = f.local_file_header_offset as i64 + h.total_size() as i64;
-
-        let result =
-            match CompressionMethod::from_u16(h.compression_method) {
-                fileinfo::Store => self.read_stored_file(file_offset, h.uncompressed_size),
-                fileinfo::Deflate => self.read_deflated_file(file_offset, h.compressed_size, h.uncompressed_size),
-                _ => panic!()
-            };
-        let result = try_io!(result);
+    /// The number of the disk where the central directory starts.
+    pub fn central_directory_disk(&self) -> u32 {
+        match self.zip64_end_record {
+            Some(ref e64) => e64.disk_number_with_start_of_central_directory,
+            None => self.end_record.disk_number_with_start_of_central_directory as u32,
+        }
+    }
 
-        // Check the CRC32 of the result against the one stored in the header
-        let crc = crc32::crc32(result.as_slice());
+    /// Where the central directory starts in the underlying reader. This is the offset stored
+    /// in the archive, plus `base_offset` and the start of its part for split archives.
+    pub fn central_directory_offset(&self) -> u64 {
+        self.central_directory_offset
+    }
 
-        if crc == h.crc32 { Ok(result) }
-        else { Err(error::CrcError) }
+    pub fn central_directory_size(&self) -> u64 {
+        match self.zip64_end_record {
+            Some(ref e64) => e64.central_directory_size,
+            None => self.end_record.central_directory_size as u64,
+        }
     }
 
-    fn read_stored_file(&mut self, pos: i64, uncompressed_size: u32) -> IoResult<Vec<u8>> {
-        try!(self.reader.seek(pos, SeekSet));
-        self.reader.read_exact(uncompressed_size as uint)
+    /// The central directory digital signature record, as stored. Its data isn't checked.
+    pub fn digital_signature<'a>(&'a self) -> Option<&'a format::CentralDirectoryDigitalSignature> {
+        self.digital_signature.as_ref()
     }
 
-    fn read_deflated_file(&mut self, pos: i64, compressed_size: u32, uncompressed_size: u32) -> IoResult<Vec<u8>> {
-        try!(self.reader.seek(pos, SeekSet));
-        let compressed_bytes = try!(self.reader.read_exact(compressed_size as uint));
-        let uncompressed_bytes = match flate::inflate_bytes(compressed_bytes.as_slice()) {
-            Some(bytes) => bytes,
-            None => return Err(IoError { kind: InvalidInput, desc: "decompression failure", detail: None })
-        };
-        assert!(uncompressed_bytes.len() as u32 == uncompressed_size);
-        // FIXME try not to copy the buffer, or switch to the incremental fashion
-        Ok(uncompressed_bytes.as_slice().to_vec())
+    /// The irregularities found in lenient mode: in the central directory when the archive was
+    /// opened, then in the local file headers of the entries read since. Each is only listed
+    /// once, however many times its entry is read.
+    pub fn warnings<'a>(&'a self) -> &'a [format::ParseWarning] {
+        self.warnings.as_slice()
     }
 
-    // when we make read return a Reader, we will be able to loop here and copy
-    // blocks of a fixed size from Reader to Writer
-    pub fn extract<T:Writer>(&mut self, f: &FileInfo, writer: &mut T) -> Result<(), ZipError> {
-        match self.read(f) {
-            Ok(bytes) => { try_io!(writer.write(bytes.as_slice())); Ok(()) },
-            Err(x) => Err(x)
+    // reads the local file header at the current position, as the parse mode says
+    fn read_local_header(&mut self) -> Result<format::LocalFileHeader, ZipError> {
+        let mut warnings = Vec::new();
+        let h = try!(format::LocalFileHeader::read_with_mode(&mut self.reader, self.parse_mode.clone(), &mut warnings)
+                     .map_err(|e| e.during("reading a local file header")));
+        for warning in warnings.into_iter() {
+            if !self.warnings.contains(&warning) {
+                self.warnings.push(warning);
+            }
         }
+        Ok(h)
     }
 
-}
+    /// The archive extra data record right before the central directory, as stored.
+    pub fn archive_extra_data<'a>(&'a self) -> Option<&'a format::ArchiveExtraDataRecord> {
+        self.archive_extra_data.as_ref()
+    }
+
+    /// Whether the archive says it is split into several parts.
+    pub fn is_multi_disk(&self) -> bool {
+        self.disk_number() != 0 || self.central_directory_disk() != 0
+    }
+
+    /// The number of bytes after the end of the archive (its end of central directory record
+    /// and comment), which are ignored. Tools that need the file to be exactly an archive, like
+    /// signature checkers, can refuse files where this isn't 0.
+    pub fn trailing_garbage_len(&self) -> u64 {
+        self.trailing_garbage_len
+    }
+
+    /// The size of the data before the archive itself, such as the executable stub of a
+    /// self-extracting archive; 0 for plain archives. `FileInfo::local_file_header_offset` is
+    /// counted from the start of the reader, so it includes this.
+    pub fn base_offset(&self) -> u64 {
+        self.base_offset
+    }
+
+    /// The number of entries in the archive.
+    pub fn len(&self) -> uint {
+        self.entries.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.files.is_empty()
+    }
+
+    /// Iterates over the entries of the archive. Since the central directory is parsed when the
+    /// archive is opened, a corrupt one is reported by `ZipReader::new` and this never fails.
+    /// The iterator knows how many entries are left, and can go backwards.
+    pub fn files<'a>(&'a self) -> slice::Items<'a, FileInfo> {
+        self.entries.files.iter()
+    }
+
+    /// Returns the entries under `prefix`, a directory, with or without the trailing slash:
+    /// `assets` selects `assets/a.png` but not `assets2/b.png`. An empty prefix, the root,
+    /// selects every entry.
+    pub fn files_with_prefix<'a, T:BytesContainer>(&'a self, prefix: T) -> Vec<&'a FileInfo> {
+        let prefix = prefix.container_as_bytes();
+        self.entries.files.iter().filter(|f| {
+            prefix.is_empty() || f.name.starts_with(prefix) &&
+                (f.name.len() == prefix.len() || prefix.ends_with(b"/") || f.name.as_bytes()[prefix.len()] == b'/')
+        }).collect()
+    }
+
+    /// Returns the entries whose name matches a glob pattern, where `?` matches any character
+    /// but `/`, `*` any sequence of them, and `**` any sequence of characters including `/`
+    /// (so `assets/**/*.png` matches PNG files at any depth under `assets`).
+    pub fn files_matching<'a, T:BytesContainer>(&'a self, pattern: T) -> Vec<&'a FileInfo> {
+        let pattern = pattern.container_as_bytes();
+        self.entries.files.iter().filter(|f| glob_matches(pattern, f.name.as_bytes())).collect()
+    }
+
+    /// The entries that aren't macOS Finder metadata (see `FileInfo::is_macos_metadata`).
+    pub fn files_without_macos_metadata<'a>(&'a self) -> Vec<&'a FileInfo> {
+        self.entries.files.iter().filter(|f| !f.is_macos_metadata()).collect()
+    }
+
+    /// A line of statistics for each entry, as listed by `unzip -v`.
+    pub fn list(&self) -> Vec<EntryStat> {
+        self.entries.files.iter().map(|f| EntryStat::from_info(f)).collect()
+    }
+
+    /// The number of entries and their total sizes.
+    pub fn stats(&self) -> ArchiveStats {
+        ArchiveStats::from_entries(self.entries.files.iter())
+    }
+
+    pub fn file_names<'a>(&'a self) -> iter::Map<&'a FileInfo, &'a MaybeUTF8,
+                                                 slice::Items<'a, FileInfo>> {
+        self.entries.files.iter().map(|f| &f.name)
+    }
+
+    /// Returns the archive comment, as stored in the end of central directory record. It has no
+    /// encoding flag, so it is kept as raw bytes (often CP437); see MaybeUTF8 for conversions.
+    pub fn comment<'a>(&'a self) -> &'a MaybeUTF8 {
+        &self.end_record.comment
+    }
+
+    /// Reads every central directory header as stored in the archive, without turning them
+    /// into FileInfo. Useful for tools that need to see the raw headers, or to preserve fields
+    /// FileInfo leaves out.
+    pub fn central_directory(&mut self) -> Result<Vec<format::CentralDirectoryHeader>, ZipError> {
+        try_io!(format::seek_to(&mut self.reader, self.central_directory_offset));
+        let mut headers = Vec::with_capacity(self.header_capacity());
+        for _ in range(0, self.entry_count) {
+            headers.push(try!(format::CentralDirectoryHeader::read(&mut self.reader)));
+        }
+        Ok(headers)
+    }
+
+    /// Reads the local file header of an entry and its data exactly as stored in the
+    /// archive, without decompressing or checking it.
+    pub fn read_raw_entry(&mut self, h: &format::CentralDirectoryHeader)
+                          -> Result<(format::LocalFileHeader, Vec<u8>), ZipError> {
+        let zip64 = try!(h.zip64_extended_information());
+        let position = try!(self.local_header_position(h));
+        try_io!(format::seek_to(&mut self.reader, position));
+        let lfh = try!(format::LocalFileHeader::read(&mut self.reader));
+        let data = try!(self.read_data(zip64.compressed_size));
+        Ok((lfh, data))
+    }
+
+    // Reads `len` bytes of entry data from the current position. The size comes from the
+    // archive, so the buffer grows as the data arrives instead of being allocated up front, and
+    // data that ends early is an error.
+    fn read_data(&mut self, len: u64) -> Result<Vec<u8>, ZipError> {
+        if len > uint::MAX as u64 {
+            return Err(error::SomeIoError(IoError {
+                kind: InvalidInput,
+                desc: "entry data is too large to be held in memory",
+                detail: Some(format!("{} bytes", len)),
+            }));
+        }
+        let data = try_io!(LimitReader::new(self.reader.by_ref(), len as uint).read_to_end());
+        if (data.len() as u64) < len {
+            return Err(error::SomeIoError(IoError { kind: InvalidInput, desc: "entry data is truncated", detail: None }));
+        }
+        Ok(data)
+    }
+
+    /// Reads the data of an entry without decompressing, decrypting or checking it, for
+    /// repacking or inspecting an archive.
+    pub fn read_raw(&mut self, f: &FileInfo) -> Result<RawEntry, ZipError> {
+        try_io!(format::seek_to(&mut self.reader, f.local_file_header_offset));
+        let h = try!(self.read_local_header());
+        try!(self.check_local_header(&h, f));
+        let mut data_size = f.compressed_size;
+        let encryption_header = match f.encryption_check_byte {
+            Some(_) => {
+                data_size = try!(data_size_after_encryption_header(f));
+                Some(try!(format::EncryptionHeader::read(&mut self.reader)))
+            }
+            None => None,
+        };
+        let data = try!(self.read_data(data_size));
+        Ok(RawEntry {
+            compression_method: h.compression_method,
+            crc32: f.crc32,
+            uncompressed_size: f.uncompressed_size,
+            encryption_header: encryption_header,
+            data: data,
+        })
+    }
+
+    /// Looks up an entry by name, without touching the underlying reader. If several entries
+    /// share a name, the last one wins, as with most unzip implementations.
+    pub fn info<T:BytesContainer>(&self, name: T) -> Result<FileInfo, ZipError> {
+        match self.entries.name_index.find_equiv(&MaybeUTF8Slice::from_bytes(name.container_as_bytes())) {
+            Some(indices) => Ok(self.entries.files[*indices.last().unwrap()].clone()),
+            None => Err(error::FileNotFoundInArchive)
+        }
+    }
+
+    /// Returns every entry named `name`, in archive order.
+    pub fn info_all<T:BytesContainer>(&self, name: T) -> Vec<FileInfo> {
+        match self.entries.name_index.find_equiv(&MaybeUTF8Slice::from_bytes(name.container_as_bytes())) {
+            Some(indices) => indices.iter().map(|&i| self.entries.files[i].clone()).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Whether several entries share a name. Tools disagree on which one counts, which can be
+    /// used to show different contents to different tools, so scanners may want to flag this.
+    pub fn has_duplicates(&self) -> bool {
+        self.entries.name_index.len() < self.entries.files.len()
+    }
+
+    /// The names shared by several entries, in the order of their first entry.
+    pub fn duplicate_names(&self) -> Vec<MaybeUTF8> {
+        let mut names = Vec::new();
+        for (i, f) in self.entries.files.iter().enumerate() {
+            let name = self.effective_name(f);
+            match self.entries.name_index.find_equiv(&name.as_slice()) {
+                Some(indices) if indices.len() > 1 && indices[0] == i => names.push(name),
+                _ => {}
+            }
+        }
+        names
+    }
+
+    /// Like `info`, but ignores ASCII case and treats backslashes as slashes, for archives made
+    /// on case-insensitive file systems or by tools writing Windows separators. If several
+    /// entries match, the last one wins.
+    pub fn info_ignore_case<T:BytesContainer>(&self, name: T) -> Result<FileInfo, ZipError> {
+        match self.entries.folded_name_index.find(&fold_name(name.container_as_bytes())) {
+            Some(indices) => Ok(self.entries.files[*indices.last().unwrap()].clone()),
+            None => Err(error::FileNotFoundInArchive)
+        }
+    }
+
+    /// Returns every entry matching `name` the way `info_ignore_case` does, in archive order.
+    pub fn infos_ignore_case<T:BytesContainer>(&self, name: T) -> Vec<FileInfo> {
+        match self.entries.folded_name_index.find(&fold_name(name.container_as_bytes())) {
+            Some(indices) => indices.iter().map(|&i| self.entries.files[i].clone()).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Sets whether reading an entry fails with HeaderMismatch when its local file header
+    /// disagrees with the central directory about its name, compression method, CRC32 or
+    /// sizes (the default). Such disagreements are used to hide content from tools that only
+    /// look at one of the two; when this is off, the central directory is trusted.
+    pub fn set_check_local_headers(&mut self, check_local_headers: bool) {
+        self.check_local_headers = check_local_headers;
+    }
+
+    fn check_local_header(&self, h: &format::LocalFileHeader, f: &FileInfo) -> Result<(), ZipError> {
+        if !self.check_local_headers {
+            return Ok(());
+        }
+        match local_header_mismatch(h, f) {
+            Some(field) => Err(error::HeaderMismatch(field)),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns a Reader that decompresses the entry incrementally, for entries too large to be
+    /// held in memory at once.
+    pub fn read_file<'a>(&'a mut self, f: &FileInfo) -> Result<ZipEntryReader<'a, R>, ZipError> {
+        self.open_entry(f, None).map_err(|e| entry_error(f, e))
+    }
+
+    /// Like `read_file`, but decrypts the entry with `password` if it is encrypted. Fails with
+    /// an EntryError wrapping InvalidPassword if the password is wrong (see
+    /// `ZipError::innermost`).
+    pub fn read_file_encrypted<'a>(&'a mut self, f: &FileInfo, password: &[u8])
+                                   -> Result<ZipEntryReader<'a, R>, ZipError> {
+        self.open_entry(f, Some(password)).map_err(|e| entry_error(f, e))
+    }
+
+    /// Like `read`, but decrypts the entry with `password` if it is encrypted. Fails with an
+    /// EntryError wrapping InvalidPassword if the password is wrong.
+    pub fn read_encrypted(&mut self, f: &FileInfo, password: &[u8]) -> Result<Vec<u8>, ZipError> {
+        let mut entry = try!(self.read_file_encrypted(f, password));
+        read_whole_entry(f, &mut entry)
+    }
+
+    fn open_entry<'a>(&'a mut self, f: &FileInfo, password: Option<&[u8]>)
+                      -> Result<ZipEntryReader<'a, R>, ZipError> {
+        try_io!(format::seek_to(&mut self.reader, f.local_file_header_offset));
+        let h = try!(self.read_local_header());
+        try!(h.check_supported());
+        try!(self.check_local_header(&h, f));
+        let (crc, compressed_size, uncompressed_size) = (f.crc32, f.compressed_size, f.uncompressed_size);
+
+        // AES-encrypted entries record the real compression method in their extra field
+        let aes_field = if f.raw_compression_method == aes::AES_COMPRESSION_METHOD {
+            match try!(extra::find(h.extra_field.as_slice(), aes::AES_EXTRA_FIELD_ID)) {
+                Some(data) => Some(try!(AesExtraField::read(&mut BufReader::new(data)))),
+                None => return Err(error::UnsupportedCompressionMethod(f.raw_compression_method)),
+            }
+        } else {
+            None
+        };
+        let compression_method = match aes_field {
+            Some(ref field) => field.compression_method,
+            None => f.raw_compression_method,
+        };
+
+        // the reader now sits at the start of the data, or of the encryption header
+        let source = if h.is_encrypted() {
+            let password = match password {
+                Some(password) => password,
+                None => return Err(error::EncryptedFileUnsupported),
+            };
+            match (&aes_field, f.encryption_check_byte) {
+                (&Some(ref field), _) => {
+                    let compressed = LimitReader::new(self.reader.by_ref(), compressed_size as uint);
+                    AesSource(try!(AesReader::new(compressed, password, field.strength, compressed_size)))
+                }
+                (&None, Some(check_byte)) => {
+                    let data_size = try!(data_size_after_encryption_header(f));
+                    let header = try!(format::EncryptionHeader::read(&mut self.reader));
+                    let compressed = LimitReader::new(self.reader.by_ref(), data_size as uint);
+                    match ZipCryptoReader::new(compressed, &header, password, check_byte) {
+                        Some(r) => ZipCryptoSource(r),
+                        None => return Err(error::InvalidPassword),
+                    }
+                }
+                (&None, None) => return Err(error::EncryptedFileUnsupported),
+            }
+        } else {
+            let compressed = LimitReader::new(self.reader.by_ref(), compressed_size as uint);
+            PlainSource(compressed)
+        };
+
+        let data = match CompressionMethod::from_u16(compression_method) {
+            fileinfo::Store => StoredData(source),
+            fileinfo::Deflate => DeflatedData(InflateReader::new(BufferedReader::new(source))),
+            fileinfo::Bzip2 if bunzip2::SUPPORTED => Bzip2Data(Bunzip2Reader::new(source)),
+            _ => return Err(error::UnsupportedCompressionMethod(compression_method))
+        };
+        let mut data = CrcReader::new(data);
+        // AE-2 leaves the CRC32 out, relying on the authentication code
+        match aes_field {
+            Some(ref field) if field.vendor_version == 2 => {}
+            _ => data.expect(crc, uncompressed_size),
+        }
+        Ok(ZipEntryReader {
+            data: data,
+            remaining: uncompressed_size,
+            crc_mismatch: false,
+        })
+    }
+
+    /// Reads the whole decompressed contents of an entry, like `read_file` followed by
+    /// `read_to_end`, with the same checks.
+    pub fn read(&mut self, f: &FileInfo) -> Result<Vec<u8>, ZipError> {
+        let mut entry = try!(self.read_file(f));
+        read_whole_entry(f, &mut entry)
+    }
+
+    /// Reads a text entry, like `read`. Fails with NonUTF8Field if it isn't UTF-8.
+    pub fn read_to_string(&mut self, f: &FileInfo) -> Result<String, ZipError> {
+        match String::from_utf8(try!(self.read(f))) {
+            Ok(s) => Ok(s),
+            Err(_) => Err(error::NonUTF8Field),
+        }
+    }
+
+    /// Like `read_to_string`, but replaces invalid UTF-8 sequences with U+FFFD.
+    pub fn read_to_string_lossy(&mut self, f: &FileInfo) -> Result<String, ZipError> {
+        let data = try!(self.read(f));
+        Ok(String::from_utf8_lossy(data.as_slice()).into_string())
+    }
+
+    /// Sets whether `extract_to` and `extract_all` give the extracted files the modification
+    /// time recorded in the archive (the default), or leave them with the current time.
+    pub fn set_restore_times(&mut self, restore_times: bool) {
+        self.restore_times = restore_times;
+    }
+
+    /// Sets the permission bits that extracted files may keep, on Unix; like a umask, but
+    /// listing the allowed bits. The default, 0o777, leaves out setuid, setgid and sticky bits.
+    pub fn set_permission_mask(&mut self, permission_mask: u32) {
+        self.permission_mask = permission_mask;
+    }
+
+    // sets the modification time and permissions of an extracted file or directory
+    fn restore_metadata(&self, f: &FileInfo, path: &Path, is_dir: bool) -> Result<(), ZipError> {
+        try!(self.restore_permissions(f, path, is_dir));
+        if self.restore_times {
+            let millis = cmp::max(f.modified_unix_time(), 0) as u64 * 1000;
+            try_io!(fs::change_file_times(path, millis, millis));
+        }
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    fn restore_permissions(&self, f: &FileInfo, path: &Path, is_dir: bool) -> Result<(), ZipError> {
+        let mode = match f.unix_mode() {
+            Some(mode) => mode & 0o7777,
+            None => {
+                let mode = if is_dir { 0o755 } else { 0o644 };
+                if f.is_readonly() { mode & !0o222 } else { mode }
+            }
+        };
+        try_io!(fs::chmod(path, io::FilePermission::from_bits_truncate(mode & self.permission_mask)));
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn restore_permissions(&self, _f: &FileInfo, _path: &Path, _is_dir: bool) -> Result<(), ZipError> {
+        Ok(())
+    }
+
+    /// Sets whether `extract_all` and `extract_all_parallel` leave out the metadata the macOS
+    /// Finder adds to archives (see `FileInfo::is_macos_metadata`); off by default.
+    pub fn set_skip_macos_metadata(&mut self, skip_macos_metadata: bool) {
+        self.skip_macos_metadata = skip_macos_metadata;
+    }
+
+    /// Sets what `extract_to` and `extract_all` do with symlink entries (MaterializeSymlinks by
+    /// default).
+    pub fn set_symlink_policy(&mut self, symlink_policy: SymlinkPolicy) {
+        self.symlink_policy = symlink_policy;
+    }
+
+    // extracts an entry into `dest_dir`, without restoring its metadata
+    // `progress` is called after each chunk of a file is written, see `extract_with_progress`
+    fn extract_entry_to(&mut self, f: &FileInfo, dest_dir: &Path, progress: |&FileInfo, u64, u64|)
+                        -> Result<Extracted, ZipError> {
+        let relative = try!(self.relative_path(f));
+        let components: Vec<&[u8]> = relative.components().collect();
+        let mut path = dest_dir.clone();
+        for (i, component) in components.iter().enumerate() {
+            path.push(*component);
+            // never write through a symlink extracted earlier, which could lead anywhere; one
+            // in place of the entry itself is replaced
+            match fs::lstat(&path) {
+                Ok(ref stat) if stat.kind == io::TypeSymlink => {
+                    if i + 1 < components.len() {
+                        return Err(error::UnsafeFileName(f.name.clone()));
+                    }
+                    try_io!(fs::unlink(&path));
+                }
+                _ => {}
+            }
+        }
+
+        if f.is_dir() {
+            try_io!(fs::mkdir_recursive(&path, io::USER_RWX));
+            return Ok(ExtractedDirectory(path));
+        }
+        if f.is_symlink() {
+            match self.symlink_policy {
+                SkipSymlinks => return Ok(SkippedSymlink(path)),
+                CreateSymlinks if cfg!(unix) => {
+                    let target = try!(self.read_symlink_target(f));
+                    if !symlink_stays_inside(components.as_slice(), target.as_slice()) {
+                        return Err(error::UnsafeFileName(f.name.clone()));
+                    }
+                    try_io!(fs::mkdir_recursive(&path.dir_path(), io::USER_RWX));
+                    try_io!(fs::symlink(&Path::new(target), &path));
+                    return Ok(ExtractedSymlink(path));
+                }
+                // the link target becomes the contents of a regular file
+                _ => {}
+            }
+        }
+        try_io!(fs::mkdir_recursive(&path.dir_path(), io::USER_RWX));
+        let mut file = try_io!(File::create(&path));
+        try!(self.extract_with_progress(f, &mut file, progress));
+        Ok(ExtractedFile(path))
+    }
+
+    fn read_symlink_target(&mut self, f: &FileInfo) -> Result<Vec<u8>, ZipError> {
+        let mut entry = try!(self.read_file(f));
+        let mut target = MemWriter::new();
+        try!(copy_entry(&mut entry, &mut target, |_| {}));
+        Ok(target.unwrap())
+    }
+
+    /// Extracts an entry into `dest_dir`, creating the directories leading to it, and returns
+    /// its path. Names that could point outside of `dest_dir` (absolute, with a drive prefix,
+    /// with `..` components, or through a symlink) fail with UnsafeFileName, and so do symlinks
+    /// pointing outside of it. The modification time and (on Unix) permissions recorded in the
+    /// archive are restored. Symlinks are handled according to `set_symlink_policy`; when they
+    /// are skipped, nothing is written but their path is still returned.
+    pub fn extract_to(&mut self, f: &FileInfo, dest_dir: &Path) -> Result<Path, ZipError> {
+        match try!(self.extract_entry_to(f, dest_dir, |_, _, _| {})) {
+            ExtractedFile(path) => {
+                try!(self.restore_metadata(f, &path, false));
+                Ok(path)
+            }
+            ExtractedDirectory(path) => {
+                try!(self.restore_metadata(f, &path, true));
+                Ok(path)
+            }
+            // setting the metadata of a symlink would change its target instead
+            ExtractedSymlink(path) | SkippedSymlink(path) => Ok(path),
+        }
+    }
+
+    /// Extracts every entry into `dest` like `extract_to`, creating directories along the way
+    /// (even those without an entry of their own), and returns the number of entries extracted.
+    /// The first failure stops the extraction, with an EntryError naming the entry.
+    pub fn extract_all(&mut self, dest: &Path) -> Result<uint, ZipError> {
+        self.extract_all_with_progress(dest, |_, _, _, _| {})
+    }
+
+    /// Like `extract_all`, calling `progress` with the entry being extracted, the number of its
+    /// bytes written so far, its size, and the number of entries extracted before it. It is
+    /// called after each chunk of a file is written, and once for each entry when it is done
+    /// (with the count of entries updated); directories and skipped symlinks, which have nothing
+    /// written, are then reported with 0 bytes out of 0.
+    ///
+    /// `progress` runs on the calling task, so if it fails, the failure unwinds through this
+    /// call and takes the ZipReader with it, like any other failure of the task. The entries
+    /// extracted so far are left in place, and the one being written may be incomplete; run
+    /// the extraction in its own task to survive a failing callback.
+    pub fn extract_all_with_progress(&mut self, dest: &Path, progress: |&FileInfo, u64, u64, uint|)
+                                     -> Result<uint, ZipError> {
+        let skip_macos_metadata = self.skip_macos_metadata;
+        let files: Vec<FileInfo> = self.entries.files.iter()
+                                             .filter(|f| !(skip_macos_metadata && f.is_macos_metadata()))
+                                             .map(|f| f.clone()).collect();
+        let mut directories = Vec::new();
+        let mut count = 0u;
+        for f in files.iter() {
+            // the number of entries extracted, and of bytes written
+            let result = match self.extract_entry_to(f, dest, |f, done, total| progress(f, done, total, count)) {
+                Ok(ExtractedFile(path)) => self.restore_metadata(f, &path, false).map(|_| (1u, f.uncompressed_size)),
+                Ok(ExtractedDirectory(path)) => {
+                    directories.push((f, path));
+                    Ok((1, 0))
+                }
+                Ok(ExtractedSymlink(_)) => Ok((1, f.uncompressed_size)),
+                Ok(SkippedSymlink(_)) => Ok((0, 0)),
+                Err(e) => Err(e),
+            };
+            let written = match result {
+                Ok((n, written)) => {
+                    count += n;
+                    written
+                }
+                Err(e) => return Err(entry_error(f, e)),
+            };
+            progress(f, written, written, count);
+        }
+
+        // directories come last, since extracting their contents changes their modification
+        // time, and they may be read-only
+        for &(f, ref path) in directories.iter() {
+            match self.restore_metadata(f, path, true) {
+                Ok(()) => {}
+                Err(e) => return Err(entry_error(f, e)),
+            }
+        }
+        Ok(count)
+    }
+
+    /// Tests every entry like `unzip -t`: its local file header must agree with the central
+    /// directory, its data must decompress to the recorded size and CRC32, and its data
+    /// descriptor (if any) must match. Problems are collected instead of stopping at the first
+    /// one; only failing to read the central directory fails the whole call. Encrypted entries
+    /// can't be tested without a password, and are reported with an EntryError wrapping
+    /// EncryptedFileUnsupported.
+    pub fn verify(&mut self) -> Result<VerifyReport, ZipError> {
+        let files = self.entries.files.clone();
+        let mut report = VerifyReport { ok: 0, failures: Vec::new() };
+        for f in files.iter() {
+            match self.verify_entry(f) {
+                Ok(()) => report.ok += 1,
+                Err(e) => report.failures.push((f.name.clone(), entry_error(f, e))),
+            }
+        }
+        Ok(report)
+    }
+
+    fn verify_entry(&mut self, f: &FileInfo) -> Result<(), ZipError> {
+        try_io!(format::seek_to(&mut self.reader, f.local_file_header_offset));
+        let h = try!(self.read_local_header());
+        match local_header_mismatch(&h, f) {
+            Some(field) => return Err(error::HeaderMismatch(field)),
+            None => {}
+        }
+        {
+            let mut entry = try!(self.read_file(f));
+            try!(copy_entry(&mut entry, &mut NullWriter, |_| {}));
+        }
+
+        if h.has_data_descriptor() {
+            let data_start = try_io!(format::offset_after(f.local_file_header_offset, h.total_size()));
+            let data_end = try_io!(format::offset_after(data_start, f.compressed_size));
+            try_io!(format::seek_to(&mut self.reader, data_end));
+            let dd = try!(format::DataDescriptor::read(&mut self.reader, h.has_zip64_extra_field()));
+            if dd.crc32 != f.crc32 || dd.compressed_size != f.compressed_size ||
+               dd.uncompressed_size != f.uncompressed_size {
+                return Err(error::SomeIoError(IoError {
+                    kind: InvalidInput,
+                    desc: "data descriptor doesn't match the central directory",
+                    detail: None,
+                }));
+            }
+        }
+        Ok(())
+    }
+
+    /// Decompresses an entry to `writer`, a chunk at a time, so memory use doesn't depend on
+    /// the size of the entry. The CRC32 and size are checked once all of it has been written.
+    pub fn extract<T:Writer>(&mut self, f: &FileInfo, writer: &mut T) -> Result<(), ZipError> {
+        self.extract_with_progress(f, writer, |_, _, _| {})
+    }
+
+    /// Like `extract`, calling `progress` after each chunk is written with the entry, the number
+    /// of bytes written so far and the size of the entry. `progress` runs on the calling task:
+    /// if it fails, the failure unwinds through this call like any other failure of the task,
+    /// and `writer` is left with part of the entry.
+    pub fn extract_with_progress<T:Writer>(&mut self, f: &FileInfo, writer: &mut T,
+                                           progress: |&FileInfo, u64, u64|) -> Result<(), ZipError> {
+        let mut entry = try!(self.read_file(f));
+        copy_entry(&mut entry, writer, |done| progress(f, done, f.uncompressed_size))
+            .map_err(|e| entry_error(f, e))
+    }
 
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::cmp;
+    use std::io;
+    use std::slice;
+    use std::io::{File, MemWriter, MemReader, TempDir, IoError, IoResult, EndOfFile};
+    use std::io::{Seek, SeekStyle, SeekSet, SeekCur, SeekEnd};
+    use flate;
+    use crc32;
+    use error;
+    use format;
+    use maybe_utf8::MaybeUTF8;
+    use writer::{ZipWriter, FileOptions};
+    use fileinfo;
+    use fileinfo::FileInfo;
+    use super::{ZipReader, CreateSymlinks, symlink_stays_inside};
+
+    fn archive(names: &[&str]) -> ZipReader<MemReader> {
+        let mut w = ZipWriter::new(MemWriter::new());
+        for name in names.iter() {
+            w.add_file(*name, &FileOptions::new(), name.as_bytes()).unwrap();
+        }
+        ZipReader::from_bytes(w.finish().unwrap().unwrap()).unwrap()
+    }
+
+    fn names(files: Vec<&FileInfo>) -> Vec<String> {
+        files.iter().map(|f| f.name.to_string()).collect()
+    }
+
+    // an archive whose central directory is located through ZIP64 records, after `prefix`
+    fn zip64_archive(prefix: &[u8]) -> Vec<u8> {
+        let mut w = ZipWriter::new(MemWriter::new());
+        w.add_file("a.txt", &FileOptions::new(), b"hello").unwrap();
+        let plain = w.finish().unwrap().unwrap();
+        let end = ZipReader::from_bytes(plain.clone()).unwrap().end_record().clone();
+        let archive_end = plain.len() - 22;
+
+        let mut out = MemWriter::new();
+        out.write(prefix).unwrap();
+        out.write(plain.slice_to(archive_end)).unwrap();
+        let mut e64 = format::Zip64EndOfCentralDirectoryRecord::new();
+        e64.version_made_by = 45;
+        e64.version_needed_to_extract = 45;
+        e64.entry_count_this_disk = end.entry_count_this_disk as u64;
+        e64.total_entry_count = end.total_entry_count as u64;
+        e64.central_directory_size = end.central_directory_size as u64;
+        e64.central_directory_offset = end.central_directory_offset as u64;
+        e64.write(&mut out).unwrap();
+        let locator = format::Zip64EndOfCentralDirectoryLocator {
+            disk_number_with_zip64_end_record: 0,
+            zip64_end_record_offset: archive_end as u64,
+            total_disk_count: 1,
+        };
+        locator.write(&mut out).unwrap();
+        let mut e = format::EndOfCentralDirectoryRecord::new();
+        e.entry_count_this_disk = 0xFFFF;
+        e.total_entry_count = 0xFFFF;
+        e.central_directory_size = 0xFFFFFFFF;
+        e.central_directory_offset = 0xFFFFFFFF;
+        e.write(&mut out).unwrap();
+        out.unwrap()
+    }
+
+    #[test]
+    fn prepended_data() {
+        // an archive appended to a stub as is, its offsets still counting from its own start
+        let mut w = ZipWriter::new(MemWriter::new());
+        w.add_file("a.txt", &FileOptions::new(), b"hello").unwrap();
+        w.add_file("b.txt", &FileOptions::new(), b"world").unwrap();
+        let archive = w.finish().unwrap().unwrap();
+        for stub in [b"#!/bin/sh\nexit 0\n".to_vec(), Vec::from_fn(1024, |i| (i * 7) as u8)].iter() {
+            let mut bytes = stub.clone();
+            bytes.push_all(archive.as_slice());
+            let mut zip = ZipReader::from_bytes(bytes).unwrap();
+            assert_eq!(zip.base_offset(), stub.len() as u64);
+            let f = zip.info("a.txt").unwrap();
+            assert_eq!(f.local_file_header_offset, stub.len() as u64);
+            assert!(zip.read(&f).unwrap().as_slice() == b"hello");
+            let f = zip.info("b.txt").unwrap();
+            assert!(zip.read(&f).unwrap().as_slice() == b"world");
+            assert_eq!(zip.verify().unwrap().ok, 2);
+        }
+    }
+
+    #[test]
+    fn zip64_with_prepended_data() {
+        for prefix in [b"", b"#!/bin/sh\nexit 0\n"].iter() {
+            let mut zip = ZipReader::from_bytes(zip64_archive(*prefix)).unwrap();
+            assert!(zip.zip64_end_record().is_some());
+            assert_eq!(zip.base_offset(), prefix.len() as u64);
+            let f = zip.info("a.txt").unwrap();
+            assert!(zip.read(&f).unwrap().as_slice() == b"hello");
+        }
+    }
+
+    #[test]
+    fn entry_count() {
+        let mut zip = archive(&[]);
+        assert_eq!(zip.len(), 0);
+        assert!(zip.is_empty());
+        assert_eq!(zip.files_raw().size_hint(), (0, Some(0)));
+
+        let names: Vec<String> = range(0u, 1000).map(|i| format!("{}.txt", i)).collect();
+        let names: Vec<&str> = names.iter().map(|s| s.as_slice()).collect();
+        let mut zip = archive(names.as_slice());
+        assert_eq!(zip.len(), 1000);
+        assert!(!zip.is_empty());
+        assert_eq!(zip.files_raw().size_hint(), (1000, Some(1000)));
+        assert_eq!(zip.files().count(), 1000);
+
+        // from the ZIP64 record
+        let zip = ZipReader::from_bytes(zip64_archive(b"")).unwrap();
+        assert_eq!(zip.len(), 1);
+    }
+
+    #[test]
+    fn nested_archives() {
+        let mut w = ZipWriter::new(MemWriter::new());
+        w.add_file("inner.txt", &FileOptions::new(), b"deep inside").unwrap();
+        let inner = w.finish().unwrap().unwrap();
+        let mut w = ZipWriter::new(MemWriter::new());
+        w.add_file("inner.zip", &FileOptions::new(), inner.as_slice()).unwrap();
+        let outer = w.finish().unwrap().unwrap();
+
+        // both levels in memory: the outer archive borrowed, the inner one owned
+        let mut outer = ZipReader::from_slice(outer.as_slice()).unwrap();
+        let f = outer.info("inner.zip").unwrap();
+        let bytes = outer.read(&f).unwrap();
+        assert!(bytes == inner);
+        let mut zip = ZipReader::from_bytes(bytes).unwrap();
+        let f = zip.info("inner.txt").unwrap();
+        assert!(zip.read(&f).unwrap().as_slice() == b"deep inside");
+    }
+
+    #[test]
+    fn inner_reader() {
+        let mut zip = archive(&["a.txt", "b.txt"]);
+        let len = zip.get_ref().get_ref().len();
+        // reads seek first, so moving the reader behind its back is harmless
+        zip.get_mut().seek(0, SeekEnd).unwrap();
+        let f = zip.info("b.txt").unwrap();
+        assert!(zip.read(&f).unwrap().as_slice() == b"b.txt");
+
+        let mut reader = zip.into_inner();
+        assert_eq!(reader.get_ref().len(), len);
+        reader.seek(0, SeekSet).unwrap();
+        let mut zip = ZipReader::new(reader).unwrap();
+        let f = zip.info("a.txt").unwrap();
+        assert!(zip.read(&f).unwrap().as_slice() == b"a.txt");
+    }
+
+    #[test]
+    fn archive_comment() {
+        let zip = archive(&["a.txt"]);
+        assert!(zip.comment().as_bytes().is_empty());
+
+        // not UTF-8: kept as it is, and decoded as CP437 on demand
+        let mut w = ZipWriter::new(MemWriter::new());
+        w.add_file("a.txt", &FileOptions::new(), b"hello").unwrap();
+        w.set_comment(b"build 42 \xa9 ACME").unwrap();
+        let zip = ZipReader::from_bytes(w.finish().unwrap().unwrap()).unwrap();
+        assert!(zip.comment().as_bytes() == b"build 42 \xa9 ACME");
+        assert!(zip.comment().as_str().is_none());
+        assert_eq!(zip.comment().to_string_cp437(), "build 42 \u2310 ACME".to_string());
+    }
+
+    // an archive after `prefix` zeros, which are made up when read instead of stored
+    struct Sparse {
+        prefix: u64,
+        data: Vec<u8>,
+        pos: u64,
+    }
+
+    impl Reader for Sparse {
+        fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+            let len = self.prefix + self.data.len() as u64;
+            if self.pos >= len {
+                return Err(io::standard_error(EndOfFile));
+            }
+            let n = cmp::min(buf.len() as u64, len - self.pos) as uint;
+            for (i, b) in buf.slice_to_mut(n).iter_mut().enumerate() {
+                let at = self.pos + i as u64;
+                *b = if at < self.prefix { 0 } else { self.data[(at - self.prefix) as uint] };
+            }
+            self.pos += n as u64;
+            Ok(n)
+        }
+    }
+
+    impl Seek for Sparse {
+        fn tell(&self) -> IoResult<u64> {
+            Ok(self.pos)
+        }
+
+        fn seek(&mut self, pos: i64, style: SeekStyle) -> IoResult<()> {
+            self.pos = match style {
+                SeekSet => pos,
+                SeekCur => self.pos as i64 + pos,
+                SeekEnd => (self.prefix + self.data.len() as u64) as i64 + pos,
+            } as u64;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn offsets_past_2_and_4_gib() {
+        let mut w = ZipWriter::new(MemWriter::new());
+        w.add_file("a.txt", &FileOptions::new(), b"hello").unwrap();
+        w.add_file("b.txt", &FileOptions::new(), b"world").unwrap();
+        let bytes = w.finish().unwrap().unwrap();
+
+        for &prefix in [0x8000_0003u64, 0xFFFF_FFF0, 0x1_0000_0005].iter() {
+            let mut zip = ZipReader::new(Sparse { prefix: prefix, data: bytes.clone(), pos: 0 }).unwrap();
+            assert_eq!(zip.base_offset(), prefix);
+            let b = zip.info("b.txt").unwrap();
+            assert!(b.local_file_header_offset > prefix);
+            assert!(zip.read(&b).unwrap().as_slice() == b"world");
+            assert_eq!(zip.try_files().unwrap().len(), 2);
+            assert!(zip.verify().unwrap().is_ok());
+        }
+
+        // past what Seek can take, or what a u64 can hold
+        let mut r = MemReader::new(Vec::new());
+        assert!(format::seek_to(&mut r, 1 << 63).is_err());
+        assert!(format::offset_after(0xFFFF_FFFF_FFFF_FFF0, 0x10).is_err());
+        assert_eq!(format::offset_after(0xFFFF_FFFF, 0x10).unwrap(), 0x1_0000_000F);
+    }
+
+    #[test]
+    fn end_record_metadata() {
+        for prefix in [b"".as_slice(), b"#!/bin/sh\nexit 0\n".as_slice()].iter() {
+            let mut w = ZipWriter::new(MemWriter::new());
+            w.add_file("a.txt", &FileOptions::new(), b"hello").unwrap();
+            w.add_file("b.txt", &FileOptions::new(), b"world").unwrap();
+            let mut bytes = prefix.to_vec();
+            bytes.push_all(w.finish().unwrap().unwrap().as_slice());
+            let zip = ZipReader::from_bytes(bytes.clone()).unwrap();
+
+            // the first central directory header, then the end record right after the last one
+            let start = zip.central_directory_offset() as uint;
+            assert!(bytes.slice(start, start + 4) == [0x50, 0x4b, 0x01, 0x02].as_slice());
+            assert_eq!(zip.central_directory_size(), 2 * (46 + 5));
+            let end = start + zip.central_directory_size() as uint;
+            assert!(bytes.slice(end, end + 4) == [0x50, 0x4b, 0x05, 0x06].as_slice());
+            assert_eq!((zip.disk_number(), zip.central_directory_disk()), (0, 0));
+            assert!(!zip.is_multi_disk());
+        }
+
+        // the saturated fields come from the ZIP64 record
+        let bytes = zip64_archive(b"");
+        let zip = ZipReader::from_bytes(bytes.clone()).unwrap();
+        assert_eq!(zip.end_record().central_directory_size, 0xFFFFFFFF);
+        assert_eq!(zip.central_directory_size(), 46 + 5);
+        let start = zip.central_directory_offset() as uint;
+        assert!(bytes.slice(start, start + 4) == [0x50, 0x4b, 0x01, 0x02].as_slice());
+    }
+
+    #[test]
+    fn archive_extra_data() {
+        assert!(archive(&["a.txt"]).archive_extra_data().is_none());
+
+        for &data_descriptors in [false, true].iter() {
+            let mut w = ZipWriter::new(MemWriter::new());
+            w.set_data_descriptors(data_descriptors);
+            w.add_file("a.txt", &FileOptions::new(), b"hello").unwrap();
+            w.add_file("b.txt", &FileOptions::new(), b"world").unwrap();
+            w.set_archive_extra_data(Some(format::ArchiveExtraDataRecord::new(b"extra".to_vec())));
+            let bytes = w.finish().unwrap().unwrap();
+
+            let mut zip = ZipReader::from_bytes(bytes.clone()).unwrap();
+            // right before the central directory, which the offsets still point to
+            let start = zip.central_directory_offset() as uint;
+            assert!(bytes.slice(start - 13, start) == b"PK\x06\x08\x05\x00\x00\x00extra");
+            assert!(zip.archive_extra_data().unwrap().data.as_slice() == b"extra");
+            assert_eq!(zip.base_offset(), 0);
+            let f = zip.info("b.txt").unwrap();
+            assert!(zip.read(&f).unwrap().as_slice() == b"world");
+            assert!(zip.verify().unwrap().is_ok());
+        }
+    }
+
+    #[test]
+    fn digital_signature() {
+        assert!(archive(&["a.txt"]).digital_signature().is_none());
+
+        let mut w = ZipWriter::new(MemWriter::new());
+        w.add_file("a.txt", &FileOptions::new(), b"hello").unwrap();
+        w.set_digital_signature(Some(format::CentralDirectoryDigitalSignature::new(b"not really signed".to_vec())));
+        let bytes = w.finish().unwrap().unwrap();
+        // between the central directory and the end record: signature, size, data
+        let end = bytes.len() - 22;
+        assert!(bytes.slice(end - 23, end - 17) == [0x50, 0x4b, 0x05, 0x05, 17, 0].as_slice());
+
+        let mut zip = ZipReader::from_bytes(bytes).unwrap();
+        assert!(zip.digital_signature().unwrap().data.as_slice() == b"not really signed");
+        assert_eq!(zip.base_offset(), 0);
+        let f = zip.info("a.txt").unwrap();
+        assert!(zip.read(&f).unwrap().as_slice() == b"hello");
+    }
+
+    #[test]
+    fn trailing_garbage() {
+        for comment in [b"".as_slice(), b"with a comment".as_slice()].iter() {
+            let mut w = ZipWriter::new(MemWriter::new());
+            w.add_file("a.txt", &FileOptions::new(), b"hello").unwrap();
+            w.set_comment(*comment).unwrap();
+            let mut bytes = w.finish().unwrap().unwrap();
+            // such as a signature appended to the file
+            bytes.push_all(Vec::from_fn(64, |i| (i * 7) as u8).as_slice());
+
+            let mut zip = ZipReader::from_bytes(bytes).unwrap();
+            assert_eq!(zip.trailing_garbage_len(), 64);
+            assert!(zip.comment().as_bytes() == *comment);
+            let f = zip.info("a.txt").unwrap();
+            assert!(zip.read(&f).unwrap().as_slice() == b"hello");
+            assert!(zip.verify().unwrap().is_ok());
+        }
+    }
+
+    #[test]
+    fn end_record_signature_in_the_comment() {
+        // the signature alone, then a whole record that doesn't reach the end of the comment; one
+        // that did would be indistinguishable from the real thing
+        let mut fake = vec![0x50, 0x4b, 0x05, 0x06];
+        fake.push_all([0u8, ..18].as_slice());
+        fake.push_all(b" and more");
+        for comment in [b"before PK\x05\x06 after".to_vec(), fake].iter() {
+            let mut w = ZipWriter::new(MemWriter::new());
+            w.add_file("a.txt", &FileOptions::new(), b"hello").unwrap();
+            w.set_comment(comment.as_slice()).unwrap();
+            let mut zip = ZipReader::from_bytes(w.finish().unwrap().unwrap()).unwrap();
+            assert!(zip.comment().as_bytes() == comment.as_slice());
+            assert_eq!(zip.trailing_garbage_len(), 0);
+            let f = zip.info("a.txt").unwrap();
+            assert!(zip.read(&f).unwrap().as_slice() == b"hello");
+        }
+    }
+
+    #[test]
+    fn local_header_warnings() {
+        let mut w = ZipWriter::new(MemWriter::new());
+        w.add_file("a.txt", &FileOptions::new(), b"hello").unwrap();
+        let mut bytes = w.finish().unwrap().unwrap();
+        // the version needed to extract of the local file header
+        bytes.as_mut_slice()[4] = 0;
+        bytes.as_mut_slice()[5] = 0;
+        let mut zip = ZipReader::from_bytes(bytes).unwrap();
+        assert!(zip.warnings().is_empty());
+        let f = zip.info("a.txt").unwrap();
+        zip.read(&f).unwrap();
+        zip.read(&f).unwrap();
+        assert_eq!(zip.warnings().len(), 1);
+        assert!(zip.warnings()[0].entry == f.raw_name);
+        assert!(zip.warnings()[0].irregularity == format::ZeroVersionNeeded);
+    }
+
+    #[test]
+    fn backslash_names() {
+        let mut w = ZipWriter::new(MemWriter::new());
+        // made on MS-DOS, the default, and on Unix where a backslash is just a character
+        w.add_file("dir\\a.txt", &FileOptions::new(), b"dos").unwrap();
+        w.add_file("b\\c.txt", &FileOptions::new().made_by(format::Unix, 30), b"unix").unwrap();
+        let mut zip = ZipReader::from_bytes(w.finish().unwrap().unwrap()).unwrap();
+
+        assert_eq!(names(zip.files().collect()), vec!["dir\\a.txt".to_string(), "b\\c.txt".to_string()]);
+        let f = zip.info("dir/a.txt").unwrap();
+        assert!(f.normalized_name() == "dir/a.txt");
+        assert!(zip.info("dir\\a.txt").is_err());
+        let f = zip.info("b\\c.txt").unwrap();
+        assert!(f.normalized_name() == "b\\c.txt");
+        assert!(zip.info("b/c.txt").is_err());
+
+        let dir = TempDir::new("zip-test").unwrap();
+        assert_eq!(zip.extract_all(dir.path()).unwrap(), 2);
+        assert!(File::open(&dir.path().join("dir/a.txt")).read_to_end().unwrap().as_slice() == b"dos");
+        if cfg!(unix) {
+            assert!(File::open(&dir.path().join("b\\c.txt")).read_to_end().unwrap().as_slice() == b"unix");
+        }
+
+        zip.set_normalize_names(false);
+        assert!(zip.info("dir\\a.txt").is_ok());
+        assert!(zip.info("dir/a.txt").is_err());
+    }
+
+    #[test]
+    fn parse_modes() {
+        let mut w = ZipWriter::new(MemWriter::new());
+        w.add_file("a.txt", &FileOptions::new(), b"hello").unwrap();
+        let plain = w.finish().unwrap().unwrap();
+        let cd = ZipReader::from_bytes(plain.clone()).unwrap().end_record().central_directory_offset as uint;
+        assert!(ZipReader::new_with_mode(MemReader::new(plain.clone()), format::Strict).is_ok());
+
+        // (position in the central directory header, bytes written there, what is wrong)
+        for &(at, patch, irregularity) in [(6u, [0u8, 0].as_slice(), format::ZeroVersionNeeded),
+                                           (12, [0, 0, 0, 0].as_slice(), format::ZeroDateTime),
+                                           (8, [0x40, 0].as_slice(), format::StrongEncryptionWithoutEncryption)].iter() {
+            let mut bytes = plain.clone();
+            slice::bytes::copy_memory(bytes.as_mut_slice().slice_mut(cd + at, cd + at + patch.len()), patch);
+
+            let zip = ZipReader::from_bytes(bytes.clone()).unwrap();
+            assert_eq!(zip.warnings().len(), 1);
+            assert!(zip.warnings()[0].entry == "a.txt");
+            assert!(zip.warnings()[0].irregularity == irregularity);
+
+            match ZipReader::new_with_mode(MemReader::new(bytes), format::Strict).err().unwrap() {
+                error::IrregularHeader(ref name, ref found) => assert!(*name == "a.txt" && *found == irregularity),
+                e => panic!("unexpected error {}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn files_with_prefix() {
+        let zip = archive(&["assets/", "assets/a.png", "assets2/b.png", "c.txt"]);
+        assert_eq!(names(zip.files_with_prefix("assets")), vec!["assets/".to_string(), "assets/a.png".to_string()]);
+        assert_eq!(names(zip.files_with_prefix("assets/")), names(zip.files_with_prefix("assets")));
+        assert_eq!(zip.files_with_prefix("").len(), 4);
+        assert!(zip.files_with_prefix("c.txt/").is_empty());
+    }
+
+    #[test]
+    fn files_raw_length() {
+        let mut zip = archive(&["a.txt", "b.txt", "c.txt"]);
+        let mut files = zip.files_raw();
+        assert_eq!(files.size_hint(), (3, Some(3)));
+        assert!(files.next().unwrap().unwrap().name == "a.txt");
+        assert_eq!(files.len(), 2);
+        assert_eq!(files.size_hint(), (2, Some(2)));
+        assert_eq!(files.count(), 2);
+    }
+
+    #[test]
+    fn corrupt_central_directory_read_again() {
+        let mut zip = archive(&["a.txt", "b.txt"]);
+        // break the signature of the second central directory header, after the archive was
+        // opened: only the entries read again from the reader see it
+        let mut bytes = zip.get_ref().get_ref().to_vec();
+        let second_header = zip.end_record().central_directory_offset as uint + 46 + 5;
+        bytes[second_header] = b'X';
+        *zip.get_mut() = MemReader::new(bytes);
+
+        {
+            let mut files = zip.files_raw();
+            assert!(files.next().unwrap().unwrap().name == "a.txt");
+            match files.next() {
+                Some(Err(error::InvalidSignature(..))) => {}
+                _ => panic!("expected an invalid signature"),
+            }
+            assert!(files.next().is_none());
+        }
+        assert!(zip.try_files().is_err());
+
+        assert_eq!(names(zip.files().collect()), vec!["a.txt".to_string(), "b.txt".to_string()]);
+        assert_eq!(zip.file_names().map(|n| n.to_string()).collect::<Vec<String>>(),
+                   vec!["a.txt".to_string(), "b.txt".to_string()]);
+        assert!(zip.info("b.txt").is_ok());
+    }
+
+    #[test]
+    fn files_matching() {
+        let zip = archive(&["a.png", "assets/b.png", "assets/icons/c.png", "assets/icons/d.svg", "e.txt"]);
+        let matching = |pattern: &str| {
+            let files = zip.files_matching(pattern);
+            names(files)
+        };
+        // `*` and `?` stay within a directory
+        assert_eq!(matching("*.png"), vec!["a.png".to_string()]);
+        assert_eq!(matching("assets/*.png"), vec!["assets/b.png".to_string()]);
+        assert_eq!(matching("?.txt"), vec!["e.txt".to_string()]);
+        assert!(matching("assets?b.png").is_empty());
+        // `**` crosses them, and `**/` may stand for no directory at all
+        assert_eq!(matching("assets/**.png"), vec!["assets/b.png".to_string(), "assets/icons/c.png".to_string()]);
+        assert_eq!(matching("**/*.png"), vec!["a.png".to_string(), "assets/b.png".to_string(), "assets/icons/c.png".to_string()]);
+        assert_eq!(matching("assets/**/c.*"), vec!["assets/icons/c.png".to_string()]);
+        assert_eq!(matching("**").len(), 5);
+        assert!(matching("*.PNG").is_empty());
+    }
+
+    #[test]
+    fn symlink_targets_inside() {
+        assert!(symlink_stays_inside(&[b"l"], b"a/b"));
+        assert!(symlink_stays_inside(&[b"a", b"l"], b".."));
+        assert!(symlink_stays_inside(&[b"a", b"b", b"l"], b"../../c"));
+        assert!(symlink_stays_inside(&[b"a", b"l"], b"./../c/./d"));
+    }
+
+    #[test]
+    fn symlink_targets_outside() {
+        assert!(!symlink_stays_inside(&[b"l"], b".."));
+        assert!(!symlink_stays_inside(&[b"l"], b"/etc/passwd"));
+        assert!(!symlink_stays_inside(&[b"a", b"l"], b"../../etc/passwd"));
+        assert!(!symlink_stays_inside(&[b"l"], b""));
+        assert!(!symlink_stays_inside(&[b"l"], b"a\0b"));
+    }
+
+    #[test]
+    fn chained_symlinks() {
+        // a/l1 -> .. is fine on its own, but l2 -> a/l1/.. would resolve to the parent of the
+        // extraction directory through it
+        assert!(symlink_stays_inside(&[b"a", b"l1"], b".."));
+        assert!(!symlink_stays_inside(&[b"l2"], b"a/l1/.."));
+        // the same when the link in the middle comes later in the archive
+        assert!(!symlink_stays_inside(&[b"l2"], b"l1/.."));
+        assert!(!symlink_stays_inside(&[b"a", b"l2"], b"../b/.."));
+    }
+
+    #[test]
+    fn info_takes_str_bytes_and_names() {
+        let zip = archive(&["dir/a.txt", "b.txt"]);
+        let f = zip.info("dir/a.txt").unwrap();
+        assert!(f.name == "dir/a.txt");
+        assert!(zip.info(b"dir/a.txt").unwrap().name == "dir/a.txt");
+        assert!(zip.info(f.name.clone()).unwrap().name == b"dir/a.txt".as_slice());
+        assert!(zip.info("dir/a").is_err());
+    }
+
+    #[test]
+    fn lookup_by_name() {
+        let names: Vec<String> = range(0u, 300).map(|i| format!("dir{}/file{}.txt", i % 7, i)).collect();
+        let zip = archive(names.iter().map(|name| name.as_slice()).collect::<Vec<&str>>().as_slice());
+        for (i, name) in names.iter().enumerate() {
+            let f = zip.info(name.as_slice()).unwrap();
+            assert!(f.name == name.as_slice());
+            assert_eq!(f.local_file_header_offset, zip.files().nth(i).unwrap().local_file_header_offset);
+        }
+        assert_eq!(zip.info("dir0/file1.txt").err().unwrap(), error::FileNotFoundInArchive);
+        assert_eq!(zip.info("").err().unwrap(), error::FileNotFoundInArchive);
+        assert!(zip.info_all("missing").is_empty());
+    }
+
+    #[test]
+    fn lookup_ignoring_case() {
+        let mut zip = archive(&["Docs\\ReadMe.TXT", "a.txt", "A.TXT", "b.txt"]);
+        assert!(zip.info("docs/readme.txt").is_err());
+        let f = zip.info_ignore_case("docs/readme.txt").unwrap();
+        assert!(zip.read(&f).unwrap().as_slice() == b"Docs\\ReadMe.TXT");
+        let g = zip.info_ignore_case("DOCS\\README.txt").unwrap();
+        assert_eq!(g.local_file_header_offset, f.local_file_header_offset);
+        // several matches: the last one wins, and all of them are listed in archive order
+        let f = zip.info_ignore_case("a.txt").unwrap();
+        assert!(zip.read(&f).unwrap().as_slice() == b"A.TXT");
+        assert_eq!(names(zip.infos_ignore_case("A.txt").iter().collect()), vec!["a.txt".to_string(), "A.TXT".to_string()]);
+        assert!(zip.info_ignore_case("c.txt").is_err());
+        assert!(zip.infos_ignore_case("b.txt ").is_empty());
+    }
+
+    #[test]
+    fn lookup_with_duplicates() {
+        let mut w = ZipWriter::new(MemWriter::new());
+        w.add_file("a.txt", &FileOptions::new(), b"first").unwrap();
+        w.add_file("b.txt", &FileOptions::new(), b"other").unwrap();
+        w.add_file("a.txt", &FileOptions::new(), b"second").unwrap();
+        let mut zip = ZipReader::from_bytes(w.finish().unwrap().unwrap()).unwrap();
+        assert!(zip.has_duplicates());
+        assert!(zip.duplicate_names() == vec![MaybeUTF8::from_str("a.txt".to_string())]);
+
+        // the last one wins, and `info_all` has them all in archive order
+        let f = zip.info("a.txt").unwrap();
+        assert!(zip.read(&f).unwrap().as_slice() == b"second");
+        let all = zip.info_all("a.txt");
+        assert_eq!(all.len(), 2);
+        assert!(zip.read(&all[0]).unwrap().as_slice() == b"first");
+        assert_eq!(all[1].local_file_header_offset, f.local_file_header_offset);
+        assert_eq!(zip.info_all("b.txt").len(), 1);
+
+        assert!(!archive(&["a.txt", "b.txt"]).has_duplicates());
+    }
+
+    #[test]
+    fn entry_errors_name_the_entry() {
+        let mut w = ZipWriter::new(MemWriter::new());
+        w.add_file("plain.txt", &FileOptions::new(), b"hello").unwrap();
+        w.add_file("secret.txt", &FileOptions::new().password(Some(b"pass")), b"hidden").unwrap();
+        let mut zip = ZipReader::from_bytes(w.finish().unwrap().unwrap()).unwrap();
+        let f = zip.info("secret.txt").unwrap();
+
+        let e = zip.read(&f).unwrap_err();
+        assert_eq!(*e.innermost(), error::EncryptedFileUnsupported);
+        assert!(format!("{}", e).as_slice().starts_with("\"secret.txt\" at offset "));
+        match zip.read_encrypted(&f, b"wrong").unwrap_err().innermost() {
+            // the check byte lets 1 wrong password in 256 through, which then fails the CRC32
+            &error::InvalidPassword | &error::CrcError => {}
+            e => panic!("unexpected error {}", e),
+        }
+        assert!(zip.read_encrypted(&f, b"pass").unwrap().as_slice() == b"hidden");
+
+        let report = zip.verify().unwrap();
+        assert_eq!(report.ok, 1);
+        let (ref name, ref e) = report.failures[0];
+        assert!(*name == "secret.txt");
+        assert_eq!(*e.innermost(), error::EncryptedFileUnsupported);
+        assert_eq!(*error::CrcError.innermost(), error::CrcError);
+    }
+
+    #[test]
+    fn verify_reports_the_corrupted_entry() {
+        let stored = FileOptions { compression_method: fileinfo::Store, ..FileOptions::new() };
+        let mut w = ZipWriter::new(MemWriter::new());
+        for name in ["a.txt", "b.txt", "c.txt"].iter() {
+            w.add_file(*name, &stored, name.as_bytes()).unwrap();
+        }
+        let mut bytes = w.finish().unwrap().unwrap();
+        let offset = ZipReader::from_bytes(bytes.clone()).unwrap().info("b.txt").unwrap().local_file_header_offset;
+        // the first byte of its data, after the 30-byte header and the name
+        bytes.as_mut_slice()[offset as uint + 30 + 5] ^= 1;
+
+        let mut zip = ZipReader::from_bytes(bytes).unwrap();
+        let report = zip.verify().unwrap();
+        assert_eq!(report.ok, 2);
+        assert_eq!(report.failures.len(), 1);
+        let (ref name, ref e) = report.failures[0];
+        assert!(*name == "b.txt");
+        assert_eq!(*e.innermost(), error::CrcError);
+    }
+
+    #[test]
+    fn text_entries() {
+        let stored = FileOptions { compression_method: fileinfo::Store, ..FileOptions::new() };
+        let mut w = ZipWriter::new(MemWriter::new());
+        w.add_file("utf8.txt", &FileOptions::new(), "café".as_bytes()).unwrap();
+        w.add_file("latin1.txt", &stored, b"caf\xe9").unwrap();
+        let mut bytes = w.finish().unwrap().unwrap();
+        let mut zip = ZipReader::from_bytes(bytes.clone()).unwrap();
+
+        let f = zip.info("utf8.txt").unwrap();
+        assert_eq!(zip.read_to_string(&f).unwrap(), "café".to_string());
+        assert_eq!(zip.read_to_string_lossy(&f).unwrap(), "café".to_string());
+        let f = zip.info("latin1.txt").unwrap();
+        assert_eq!(zip.read_to_string(&f).err().unwrap(), error::NonUTF8Field);
+        assert_eq!(zip.read_to_string_lossy(&f).unwrap(), "caf\ufffd".to_string());
+
+        // the CRC32 is checked either way, after the 30-byte header and the name
+        bytes.as_mut_slice()[f.local_file_header_offset as uint + 30 + 10] = b'C';
+        let mut zip = ZipReader::from_bytes(bytes).unwrap();
+        assert_eq!(*zip.read_to_string(&f).unwrap_err().innermost(), error::CrcError);
+        assert_eq!(*zip.read_to_string_lossy(&f).unwrap_err().innermost(), error::CrcError);
+    }
+
+    #[test]
+    fn encryption_header() {
+        for &data_descriptors in [false, true].iter() {
+            let mut w = ZipWriter::new(MemWriter::new());
+            w.set_data_descriptors(data_descriptors);
+            w.add_file("plain.txt", &FileOptions::new(), b"hello").unwrap();
+            let options = FileOptions { last_modified_datetime: format::MsdosDateTime::new(2014, 10, 3, 12, 30, 44),
+                                        ..FileOptions::new() };
+            w.add_file("secret.txt", &options.password(Some(b"pass")), b"hidden").unwrap();
+            let mut zip = ZipReader::from_bytes(w.finish().unwrap().unwrap()).unwrap();
+
+            // listed like any other entry, with the byte the password is checked against
+            assert_eq!(zip.files().count(), 2);
+            let plain = zip.info("plain.txt").unwrap();
+            assert!(!plain.is_encrypted);
+            assert_eq!(plain.encryption_check_byte, None);
+            let f = zip.info("secret.txt").unwrap();
+            assert!(f.is_encrypted);
+            let expected = if data_descriptors {
+                (format::MsdosDateTime::new(2014, 10, 3, 12, 30, 44).dos_time() >> 8) as u8
+            } else {
+                (crc32::crc32(b"hidden") >> 24) as u8
+            };
+            assert_eq!(f.encryption_check_byte, Some(expected));
+
+            // the 12 bytes of the encryption header count in the compressed size
+            let raw = zip.read_raw(&f).unwrap();
+            assert!(raw.encryption_header.is_some());
+            assert_eq!(raw.data.len() as u64 + 12, f.compressed_size);
+            assert!(zip.read_raw(&plain).unwrap().encryption_header.is_none());
+            assert!(zip.read_encrypted(&f, b"pass").unwrap().as_slice() == b"hidden");
+        }
+    }
+
+    #[test]
+    fn unsupported_flags() {
+        let stored = FileOptions { compression_method: fileinfo::Store, ..FileOptions::new() };
+        let mut w = ZipWriter::new(MemWriter::new());
+        w.add_file("a.txt", &stored, b"first").unwrap();
+        w.add_file("b.txt", &stored, b"second").unwrap();
+        let plain = w.finish().unwrap().unwrap();
+        let cd = ZipReader::from_bytes(plain.clone()).unwrap().end_record().central_directory_offset as uint;
+
+        // strong encryption (bit 6) and masked local headers (bit 13), set for the first entry
+        // in both headers: the archive opens, but that entry can't be read
+        for &(byte, bit, feature) in [(0u, 0x40u8, "strong encryption (general purpose flag bit 6)"),
+                                      (1u, 0x20u8, "masked local headers (general purpose flag bit 13)")].iter() {
+            let mut bytes = plain.clone();
+            bytes.as_mut_slice()[6 + byte] |= bit;
+            bytes.as_mut_slice()[cd + 8 + byte] |= bit;
+            let mut zip = ZipReader::from_bytes(bytes).unwrap();
+            let a = zip.info("a.txt").unwrap();
+            assert_eq!(*zip.read(&a).unwrap_err().innermost(), error::FeatureNotSupported(feature));
+            let b = zip.info("b.txt").unwrap();
+            assert!(zip.read(&b).unwrap().as_slice() == b"second");
+        }
+    }
+
+    #[test]
+    fn extraction_refuses_unsafe_names() {
+        let dir = TempDir::new("zip-test").unwrap();
+        let dest = dir.path().join("dest");
+        for name in ["../evil.txt", "a/../../evil.txt", "/tmp/evil.txt", "C:..\\..\\evil.txt"].iter() {
+            let mut zip = archive(&[*name]);
+            let f = zip.info(*name).unwrap();
+            // the name in the error is the normalized one
+            match *zip.extract_to(&f, &dest).unwrap_err().innermost() {
+                error::UnsafeFileName(_) => {}
+                ref e => panic!("unexpected error {}", e),
+            }
+        }
+        assert!(!dir.path().join("evil.txt").exists());
+
+        let mut zip = archive(&["a//b.txt", "..%2fc.txt"]);
+        assert_eq!(zip.extract_all(&dest).unwrap(), 2);
+        assert!(File::open(&dest.join("a/b.txt")).read_to_end().unwrap().as_slice() == b"a//b.txt");
+        assert!(dest.join("..%2fc.txt").exists());
+    }
+
+    #[test]
+    fn extraction_progress() {
+        let big = Vec::from_fn(200000, |i| (i * i % 251) as u8);
+        let mut w = ZipWriter::new(MemWriter::new());
+        w.add_file("big.bin", &FileOptions::new(), big.as_slice()).unwrap();
+        w.add_file("dir/", &FileOptions::new(), b"").unwrap();
+        w.add_file("dir/small.txt", &FileOptions::new(), b"small").unwrap();
+        let mut zip = ZipReader::from_bytes(w.finish().unwrap().unwrap()).unwrap();
+
+        // several chunks, counting up to the size of the entry
+        let f = zip.info("big.bin").unwrap();
+        let mut calls = Vec::new();
+        zip.extract_with_progress(&f, &mut MemWriter::new(), |_, done, total| calls.push((done, total))).unwrap();
+        assert!(calls.len() > 1);
+        assert!(calls.windows(2).all(|w| { let ((before, _), (after, _)) = (w[0], w[1]); before < after }));
+        assert_eq!(*calls.last().unwrap(), (200000, 200000));
+
+        // the bytes of the last call for each entry add up to the uncompressed sizes
+        let dir = TempDir::new("zip-test").unwrap();
+        let mut last: Vec<(String, u64, uint)> = Vec::new();
+        let count = zip.extract_all_with_progress(dir.path(), |f, done, total, count| {
+            assert!(done <= total);
+            let name = f.name.to_string();
+            let same_entry = last.last().map_or(false, |&(ref previous, _, _)| *previous == name);
+            if same_entry {
+                last.pop();
+            }
+            last.push((name, done, count));
+        }).unwrap();
+        assert_eq!(count, 3);
+        assert_eq!(last.iter().fold(0, |sum, &(_, done, _)| sum + done), 200000 + 5);
+        assert_eq!(last.iter().map(|&(_, _, count)| count).collect::<Vec<uint>>(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn parallel_extraction() {
+        let stored = FileOptions { compression_method: fileinfo::Store, ..FileOptions::new() };
+        let mut w = ZipWriter::new(MemWriter::new());
+        w.add_file("dir/", &FileOptions::new(), b"").unwrap();
+        for i in range(0u, 40) {
+            let name = format!("dir/{}.txt", i);
+            w.add_file(name.as_slice(), &stored, name.as_bytes()).unwrap();
+        }
+        let mut bytes = w.finish().unwrap().unwrap();
+        // a CRC mismatch in one entry, the first byte of its data
+        let offset = ZipReader::from_bytes(bytes.clone()).unwrap().info("dir/17.txt").unwrap().local_file_header_offset;
+        bytes.as_mut_slice()[offset as uint + 30 + 10] ^= 1;
+
+        let dir = TempDir::new("zip-test").unwrap();
+        let path = dir.path().join("a.zip");
+        File::create(&path).write(bytes.as_slice()).unwrap();
+        let zip = ZipReader::open(&path).unwrap();
+        let dest = dir.path().join("dest");
+        let report = zip.extract_all_parallel(&dest, 4).unwrap();
+
+        // the failure doesn't stop the task that hit it
+        assert_eq!(report.extracted, 40);
+        assert_eq!(report.failures.len(), 1);
+        let (ref name, ref e) = report.failures[0];
+        assert!(*name == "dir/17.txt");
+        assert_eq!(*e.innermost(), error::CrcError);
+        for i in range(0u, 40).filter(|&i| i != 17) {
+            let name = format!("dir/{}.txt", i);
+            assert!(File::open(&dest.join(name.as_slice())).read_to_end().unwrap() == name.into_bytes());
+        }
+    }
+
+    #[test]
+    fn macos_metadata() {
+        // as the Finder compresses a folder
+        let mut w = ZipWriter::new(MemWriter::new());
+        for &(name, data) in [("photos/", ""), ("photos/a.jpg", "jpeg"), ("photos/.DS_Store", "store"),
+                              ("__MACOSX/", ""), ("__MACOSX/photos/", ""),
+                              ("__MACOSX/photos/._a.jpg", "fork")].iter() {
+            w.add_file(name, &FileOptions::new(), data.as_bytes()).unwrap();
+        }
+        let mut zip = ZipReader::from_bytes(w.finish().unwrap().unwrap()).unwrap();
+        assert_eq!(zip.files().count(), 6);
+        assert_eq!(names(zip.files_without_macos_metadata()), vec!["photos/".to_string(), "photos/a.jpg".to_string()]);
+
+        let dir = TempDir::new("zip-test").unwrap();
+        assert_eq!(zip.extract_all(&dir.path().join("all")).unwrap(), 6);
+        assert!(dir.path().join("all/__MACOSX/photos/._a.jpg").exists());
+        zip.set_skip_macos_metadata(true);
+        assert_eq!(zip.extract_all(&dir.path().join("filtered")).unwrap(), 2);
+        assert!(dir.path().join("filtered/photos/a.jpg").exists());
+        assert!(!dir.path().join("filtered/photos/.DS_Store").exists());
+        assert!(!dir.path().join("filtered/__MACOSX").exists());
+        // listing is left alone
+        assert_eq!(zip.files().count(), 6);
+    }
+
+    #[test]
+    fn extract_all_tree() {
+        let mut w = ZipWriter::new(MemWriter::new());
+        w.add_file("empty/", &FileOptions::new(), b"").unwrap();
+        w.add_file("a.txt", &FileOptions::new(), b"first").unwrap();
+        w.add_file("dir/", &FileOptions::new(), b"").unwrap();
+        w.add_file("dir/b.txt", &FileOptions::new(), b"second").unwrap();
+        // no entries for its directories
+        w.add_file("deep/er/c.txt", &FileOptions::new(), b"third").unwrap();
+        let mut zip = ZipReader::from_bytes(w.finish().unwrap().unwrap()).unwrap();
+
+        let dir = TempDir::new("zip-test").unwrap();
+        let dest = dir.path().join("dest");
+        assert_eq!(zip.extract_all(&dest).unwrap(), 5);
+        assert!(dest.join("empty").is_dir());
+        assert!(dest.join("deep/er").is_dir());
+        for &(name, contents) in [("a.txt", "first"), ("dir/b.txt", "second"), ("deep/er/c.txt", "third")].iter() {
+            assert!(File::open(&dest.join(name)).read_to_end().unwrap().as_slice() == contents.as_bytes());
+        }
+
+        // a file where a directory should be: the failure names the entry
+        let mut zip = archive(&["x", "x/y.txt"]);
+        let dest = dir.path().join("conflict");
+        match zip.extract_all(&dest).unwrap_err() {
+            error::EntryError(ref name, _, _) => assert!(*name == "x/y.txt"),
+            e => panic!("unexpected error {}", e),
+        }
+    }
+
+    // an archive with one entry "a", whose data and recorded CRC32 and size are given
+    fn handmade(compression_method: u16, data: &[u8], crc: u32, size: u32) -> Vec<u8> {
+        let mut h = format::CentralDirectoryHeader::new();
+        h.version_needed_to_extract = 20;
+        h.compression_method = compression_method;
+        h.crc32 = crc;
+        h.compressed_size = data.len() as u32;
+        h.uncompressed_size = size;
+        h.file_name = MaybeUTF8::from_str("a".to_string());
+        let mut w = MemWriter::new();
+        format::LocalFileHeader::from_info(&FileInfo::from_cdh(&h).unwrap()).unwrap().write(&mut w).unwrap();
+        w.write(data).unwrap();
+        let central_directory_offset = w.get_ref().len();
+        h.write(&mut w).unwrap();
+        let mut e = format::EndOfCentralDirectoryRecord::new();
+        e.entry_count_this_disk = 1;
+        e.total_entry_count = 1;
+        e.central_directory_size = (w.get_ref().len() - central_directory_offset) as u32;
+        e.central_directory_offset = central_directory_offset as u32;
+        e.write(&mut w).unwrap();
+        w.unwrap()
+    }
+
+    // reads the entry a few bytes at a time, until it fails
+    fn read_error(archive: Vec<u8>) -> IoError {
+        let mut zip = ZipReader::from_bytes(archive).unwrap();
+        let f = zip.info("a").unwrap();
+        let mut entry = zip.read_file(&f).unwrap();
+        let mut buf = [0u8, ..3];
+        loop {
+            match entry.read(buf.as_mut_slice()) {
+                Ok(_) => {}
+                Err(e) => return e,
+            }
+        }
+    }
+
+    #[test]
+    fn entry_data_ends_where_recorded() {
+        let hello = flate::deflate_bytes(b"hello").unwrap();
+        assert_eq!(read_error(handmade(8, hello.as_slice(), crc32::crc32(b"hello"), 5)).kind, EndOfFile);
+
+        // the stored data goes on past its uncompressed size
+        assert_eq!(read_error(handmade(0, b"hello", crc32::crc32(b"hell"), 4)).desc,
+                   "entry data is longer than its recorded size");
+
+        // junk after the end of the deflate stream
+        let mut junk = hello.as_slice().to_vec();
+        junk.push_all(b"junk");
+        assert_eq!(read_error(handmade(8, junk.as_slice(), crc32::crc32(b"hello"), 5)).desc,
+                   "compressed data goes on after the end of the deflate stream");
+    }
+
+    #[test]
+    fn read_checks_like_read_file() {
+        let read = |archive: Vec<u8>| {
+            let mut zip = ZipReader::from_bytes(archive).unwrap();
+            let f = zip.info("a").unwrap();
+            zip.read(&f)
+        };
+        let hello = flate::deflate_bytes(b"hello").unwrap();
+        assert!(read(handmade(8, hello.as_slice(), crc32::crc32(b"hello"), 5)).unwrap().as_slice() == b"hello");
+        assert!(read(handmade(8, hello.as_slice(), crc32::crc32(b"hellO"), 5)).unwrap_err().innermost() == &error::CrcError);
+
+        let e = read(handmade(0, b"hello", crc32::crc32(b"hell"), 4)).unwrap_err();
+        assert_eq!(e.io_error().unwrap().desc, "entry data is longer than its recorded size");
+        let mut junk = hello.as_slice().to_vec();
+        junk.push_all(b"junk");
+        let e = read(handmade(8, junk.as_slice(), crc32::crc32(b"hello"), 5)).unwrap_err();
+        assert_eq!(e.io_error().unwrap().desc, "compressed data goes on after the end of the deflate stream");
+    }
+
+    // "hello", compressed with bzip2
+    static BZIP2_HELLO: &'static [u8] = &[
+        0x42, 0x5a, 0x68, 0x39, 0x31, 0x41, 0x59, 0x26, 0x53, 0x59, 0x19, 0x31, 0x65, 0x3d, 0x00, 0x00,
+        0x00, 0x81, 0x00, 0x02, 0x44, 0xa0, 0x00, 0x21, 0x9a, 0x68, 0x33, 0x4d, 0x07, 0x33, 0x8b, 0xb9,
+        0x22, 0x9c, 0x28, 0x48, 0x0c, 0x98, 0xb2, 0x9e, 0x80,
+    ];
+
+    #[cfg(feature = "bzip2")]
+    #[test]
+    fn bzip2() {
+        let mut zip = ZipReader::from_bytes(handmade(12, BZIP2_HELLO, crc32::crc32(b"hello"), 5)).unwrap();
+        let f = zip.info("a").unwrap();
+        assert!(zip.read(&f).unwrap().as_slice() == b"hello");
+        assert_eq!(read_error(handmade(12, BZIP2_HELLO, crc32::crc32(b"hello"), 5)).kind, EndOfFile);
+
+        // a stream cut short, claiming far more data than it has
+        let cut = BZIP2_HELLO.slice_to(BZIP2_HELLO.len() - 10);
+        let mut zip = ZipReader::from_bytes(handmade(12, cut, crc32::crc32(b"hello"), 0xFFFFFFF0)).unwrap();
+        let f = zip.info("a").unwrap();
+        assert!(zip.read(&f).is_err());
+    }
+
+    #[cfg(not(feature = "bzip2"))]
+    #[test]
+    fn bzip2_without_the_feature() {
+        let mut zip = ZipReader::from_bytes(handmade(12, BZIP2_HELLO, crc32::crc32(b"hello"), 5)).unwrap();
+        let f = zip.info("a").unwrap();
+        assert!(*zip.read(&f).unwrap_err().innermost() == error::UnsupportedCompressionMethod(12));
+    }
+
+    #[test]
+    fn unknown_compression_method() {
+        // LZMA, which is never supported: the entry is listed, and reading it is an error
+        let mut zip = ZipReader::from_bytes(handmade(14, b"not really LZMA", crc32::crc32(b"hello"), 5)).unwrap();
+        let f = zip.info("a").unwrap();
+        assert_eq!(f.raw_compression_method, 14);
+        assert!(*zip.read(&f).unwrap_err().innermost() == error::UnsupportedCompressionMethod(14));
+        let report = zip.verify().unwrap();
+        assert_eq!(report.failures.len(), 1);
+    }
+
+    #[test]
+    fn sizes_from_the_central_directory() {
+        let data = Vec::from_fn(1000, |i| (i % 7) as u8);
+        // with data descriptors, the local header has zeros and bit 3
+        let mut w = ZipWriter::new(MemWriter::new());
+        w.set_data_descriptors(true);
+        w.add_file("a", &FileOptions::new(), data.as_slice()).unwrap();
+        let bytes = w.finish().unwrap().unwrap();
+        assert!(bytes.slice(14, 26) == [0u8, ..12].as_slice());
+        let mut zip = ZipReader::from_bytes(bytes).unwrap();
+        let f = zip.info("a").unwrap();
+        assert!(zip.read(&f).unwrap() == data);
+        assert!(zip.verify().unwrap().is_ok());
+
+        // and without bit 3, as some streaming writers leave it
+        let mut w = ZipWriter::new(MemWriter::new());
+        w.add_file("a", &FileOptions::new(), data.as_slice()).unwrap();
+        let mut bytes = w.finish().unwrap().unwrap();
+        for b in bytes.as_mut_slice().slice_mut(14, 26).iter_mut() {
+            *b = 0;
+        }
+        let mut zip = ZipReader::from_bytes(bytes).unwrap();
+        let f = zip.info("a").unwrap();
+        assert!(zip.read(&f).unwrap() == data);
+    }
+
+    #[test]
+    fn huge_recorded_size() {
+        // nothing is allocated for the 4 GiB the header claims, the data just ends early
+        let mut zip = ZipReader::from_bytes(handmade(0, b"hello", crc32::crc32(b"hello"), 0xFFFFFFF0)).unwrap();
+        let f = zip.info("a").unwrap();
+        let e = zip.read_encrypted(&f, b"password").unwrap_err();
+        assert_eq!(e.io_error().unwrap().desc, "entry data is truncated");
+    }
+
+    // an archive from `handmade`, with both headers claiming a compressed size of `size`
+    fn claim_compressed_size(archive: Vec<u8>, size: u32) -> Vec<u8> {
+        let mut archive = archive;
+        let end_record = archive.len() - 22;
+        let central_directory_offset = range(0u, 4).fold(0u, |offset, i| offset | (archive[end_record + 16 + i] as uint) << (8 * i));
+        for &at in [18u, central_directory_offset + 20].iter() {
+            for i in range(0u, 4) {
+                archive.as_mut_slice()[at + i] = (size >> (8 * i)) as u8;
+            }
+        }
+        archive
+    }
+
+    #[test]
+    fn raw_data_claiming_too_much() {
+        let archive = handmade(0, b"hello", crc32::crc32(b"hello"), 5);
+        let mut zip = ZipReader::from_bytes(archive.clone()).unwrap();
+        let f = zip.info("a").unwrap();
+        assert!(zip.read_raw(&f).unwrap().data.as_slice() == b"hello");
+
+        let mut zip = ZipReader::from_bytes(claim_compressed_size(archive, 0xFFFFFFF0)).unwrap();
+        let f = zip.info("a").unwrap();
+        let e = zip.read_raw(&f).err().unwrap();
+        assert_eq!(e.io_error().unwrap().desc, "entry data is truncated");
+        let h = zip.central_directory().unwrap()[0].clone();
+        let e = zip.read_raw_entry(&h).err().unwrap();
+        assert_eq!(e.io_error().unwrap().desc, "entry data is truncated");
+    }
+
+    #[test]
+    fn extract_all_refuses_escaping_names() {
+        let dir = TempDir::new("zip-test").unwrap();
+        let dest = dir.path().join("dest");
+        for name in ["../../etc/passwd", "C:\\evil.txt", "..\\evil.txt"].iter() {
+            let mut zip = archive(&["ok.txt", *name]);
+            let e = zip.extract_all(&dest).unwrap_err();
+            match e {
+                error::EntryError(ref entry, _, _) => assert!(*entry == *name),
+                ref e => panic!("unexpected error {}", e),
+            }
+            match *e.innermost() {
+                error::UnsafeFileName(_) => {}
+                ref e => panic!("unexpected error {}", e),
+            }
+        }
+        assert!(dest.join("ok.txt").exists());
+        assert!(!dir.path().join("evil.txt").exists());
+    }
+
+    #[test]
+    fn extraction_restores_modification_times() {
+        use std::io::fs;
+
+        // 1999-12-31 23:59:58, 946684798 seconds after the epoch in UTC
+        let options = FileOptions { last_modified_datetime: format::MsdosDateTime::new(1999, 12, 31, 23, 59, 58),
+                                    ..FileOptions::new() };
+        let mut w = ZipWriter::new(MemWriter::new());
+        w.add_file("a.txt", &options, b"hello").unwrap();
+        let mut zip = ZipReader::from_bytes(w.finish().unwrap().unwrap()).unwrap();
+        let f = zip.info("a.txt").unwrap();
+
+        let dir = TempDir::new("zip-test").unwrap();
+        let path = zip.extract_to(&f, dir.path()).unwrap();
+        let modified = fs::stat(&path).unwrap().modified;
+        assert_eq!(modified, f.modified_unix_time() as u64 * 1000);
+        // the MS-DOS time is local time, which is within a day of UTC
+        assert!(modified / 1000 > 946684798 - 86400 && modified / 1000 < 946684798 + 86400);
+
+        zip.set_restore_times(false);
+        let path = zip.extract_to(&f, &dir.path().join("now")).unwrap();
+        assert!(fs::stat(&path).unwrap().modified / 1000 > 946684798 + 86400);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn extraction_restores_permissions() {
+        use std::io::fs;
+
+        let mut w = ZipWriter::new(MemWriter::new());
+        w.add_file("run.sh", &FileOptions::new().unix_mode(0o100755), b"#!/bin/sh\n").unwrap();
+        w.add_file("setuid", &FileOptions::new().unix_mode(0o104755), b"").unwrap();
+        w.add_file("plain.txt", &FileOptions::new(), b"").unwrap();
+        let mut zip = ZipReader::from_bytes(w.finish().unwrap().unwrap()).unwrap();
+
+        let dir = TempDir::new("zip-test").unwrap();
+        // the setuid bit is masked out, and an entry without a Unix mode gets the default
+        for &(name, mode) in [("run.sh", 0o755u32), ("setuid", 0o755), ("plain.txt", 0o644)].iter() {
+            let f = zip.info(name).unwrap();
+            let path = zip.extract_to(&f, dir.path()).unwrap();
+            assert_eq!(fs::stat(&path).unwrap().perm.bits(), mode);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_stay_inside() {
+        use std::io::fs;
+        use std::io;
+
+        let link = FileOptions::new().unix_mode(0o120777);
+        let mut w = ZipWriter::new(MemWriter::new());
+        w.add_file("a/l1", &link, b"..").unwrap();
+        w.add_file("l2", &link, b"a/l1/..").unwrap();
+        w.add_file("l3", &link, b"../outside").unwrap();
+        w.add_file("a/l1/x.txt", &FileOptions::new(), b"through the link").unwrap();
+        let mut zip = ZipReader::from_bytes(w.finish().unwrap().unwrap()).unwrap();
+        zip.set_symlink_policy(CreateSymlinks);
+
+        let dir = TempDir::new("zip-test").unwrap();
+        let dest = dir.path().join("dest");
+        let f = zip.info("a/l1").unwrap();
+        let path = zip.extract_to(&f, &dest).unwrap();
+        assert_eq!(fs::lstat(&path).unwrap().kind, io::TypeSymlink);
+        for name in ["l2", "l3", "a/l1/x.txt"].iter() {
+            let f = zip.info(*name).unwrap();
+            match *zip.extract_to(&f, &dest).unwrap_err().innermost() {
+                error::UnsafeFileName(_) => {}
+                ref e => panic!("unexpected error {}", e),
+            }
+        }
+        for name in ["l2", "l3", "x.txt"].iter() {
+            assert!(fs::lstat(&dest.join(*name)).is_err());
+        }
+    }
+
+    // an archive with its last 100 bytes (part of the central directory) cut off
+    fn truncated(w: ZipWriter<MemWriter>) -> Vec<u8> {
+        let bytes = w.finish().unwrap().unwrap();
+        bytes.slice_to(bytes.len() - 100).to_vec()
+    }
+
+    fn recovered_contents(zip: &mut ZipReader<MemReader>) -> Vec<(String, Vec<u8>)> {
+        let files: Vec<FileInfo> = zip.files().map(|f| f.clone()).collect();
+        files.iter().map(|f| {
+            assert!(f.recovered);
+            (f.name.to_string(), zip.read(f).unwrap())
+        }).collect()
+    }
+
+    #[test]
+    fn recovery() {
+        for &data_descriptors in [false, true].iter() {
+            let mut w = ZipWriter::new(MemWriter::new());
+            w.set_data_descriptors(data_descriptors);
+            for name in ["a.txt", "b.txt", "c.txt"].iter() {
+                w.add_file(*name, &FileOptions::new(), name.as_bytes()).unwrap();
+            }
+            let bytes = truncated(w);
+            assert!(ZipReader::from_bytes(bytes.clone()).is_err());
+            let mut zip = ZipReader::open_with_recovery(MemReader::new(bytes)).unwrap();
+            assert_eq!(recovered_contents(&mut zip),
+                       vec![("a.txt".to_string(), b"a.txt".to_vec()), ("b.txt".to_string(), b"b.txt".to_vec()),
+                            ("c.txt".to_string(), b"c.txt".to_vec())]);
+        }
+    }
+
+    #[test]
+    fn recovery_without_data_descriptor_signature() {
+        // the first entry can't be delimited, the scan goes on to the next one
+        let mut w = ZipWriter::new(MemWriter::new());
+        w.set_data_descriptors(true);
+        w.set_data_descriptor_signature(false);
+        w.add_file("a.txt", &FileOptions::new(), b"first").unwrap();
+        w.set_data_descriptors(false);
+        for name in ["b.txt", "c.txt"].iter() {
+            w.add_file(*name, &FileOptions::new(), name.as_bytes()).unwrap();
+        }
+        let mut zip = ZipReader::open_with_recovery(MemReader::new(truncated(w))).unwrap();
+        assert_eq!(recovered_contents(&mut zip),
+                   vec![("b.txt".to_string(), b"b.txt".to_vec()), ("c.txt".to_string(), b"c.txt".to_vec())]);
+        assert_eq!(zip.warnings().len(), 1);
+        assert!(zip.warnings()[0].entry == "a.txt");
+        assert_eq!(zip.warnings()[0].irregularity, format::UndelimitedData);
+    }
+
+    #[test]
+    fn recovery_with_wrapping_size() {
+        // a local header whose ZIP64 extra field says the data reaches past 2^64
+        let mut w = MemWriter::new();
+        w.write_le_u32(format::LFH_SIGNATURE).unwrap();
+        for &field in [45u16, 0, 0, 0, 0x21].iter() {
+            w.write_le_u16(field).unwrap();
+        }
+        for &field in [0u32, 0xFFFFFFFF, 0xFFFFFFFF].iter() {
+            w.write_le_u32(field).unwrap();
+        }
+        w.write_le_u16(1).unwrap();
+        w.write_le_u16(20).unwrap();
+        w.write(b"a").unwrap();
+        w.write_le_u16(format::ZIP64_EXTRA_FIELD_ID).unwrap();
+        w.write_le_u16(16).unwrap();
+        w.write_le_u64(0).unwrap();
+        w.write_le_u64(0xFFFFFFFFFFFFFFF0).unwrap();
+        w.write([0u8, ..64].as_slice()).unwrap();
+        assert!(ZipReader::open_with_recovery(MemReader::new(w.unwrap())).is_err());
+    }
+
+    // a Reader whose reads fail when they start within `bad`
+    struct Failing {
+        inner: MemReader,
+        bad: (u64, u64),
+    }
+
+    impl Reader for Failing {
+        fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+            let pos = self.inner.tell().unwrap();
+            let (start, end) = self.bad;
+            if start <= pos && pos < end {
+                return Err(IoError { kind: io::OtherIoError, desc: "bad sector", detail: None });
+            }
+            self.inner.read(buf)
+        }
+    }
+
+    impl Seek for Failing {
+        fn tell(&self) -> IoResult<u64> {
+            self.inner.tell()
+        }
+
+        fn seek(&mut self, pos: i64, style: SeekStyle) -> IoResult<()> {
+            self.inner.seek(pos, style)
+        }
+    }
+
+    #[test]
+    fn io_errors_say_what_was_being_done() {
+        let stored = FileOptions { compression_method: fileinfo::Store, ..FileOptions::new() };
+        let mut w = ZipWriter::new(MemWriter::new());
+        w.add_file("a.txt", &stored, b"hello").unwrap();
+        w.add_file("b.txt", &stored, b"world").unwrap();
+        let bytes = w.finish().unwrap().unwrap();
+        let healthy = ZipReader::from_bytes(bytes.clone()).unwrap();
+        let (a, b) = (healthy.info("a.txt").unwrap(), healthy.info("b.txt").unwrap());
+        let cd = healthy.central_directory_offset;
+        let eocd = bytes.len() as u64 - 22;
+        let failing = |bad: (u64, u64)| Failing { inner: MemReader::new(bytes.clone()), bad: bad };
+
+        // opening: the end record, then the first central directory header
+        let e = ZipReader::new(failing((eocd, eocd + 22))).err().unwrap();
+        assert_eq!(e, error::IoErrorWhile(IoError { kind: io::OtherIoError, desc: "bad sector", detail: None },
+                                          "reading the end of central directory record"));
+        assert_eq!(format!("{}", e).as_slice(), "error while reading the end of central directory record: bad sector");
+        let e = ZipReader::new(failing((cd, cd + 46))).err().unwrap();
+        assert!(format!("{}", e).as_slice().contains("while reading the central directory"));
+        assert_eq!(e.io_error().unwrap().kind, io::OtherIoError);
+
+        // reading an entry: its local header, then its data, named along with what was done
+        let data_start = a.local_file_header_offset + 30 + 5;
+        let mut zip = ZipReader::new(failing((b.local_file_header_offset, b.local_file_header_offset + 35))).unwrap();
+        let e = zip.extract(&b, &mut MemWriter::new()).err().unwrap();
+        let message = format!("{}", e);
+        assert!(message.as_slice().starts_with("\"b.txt\" at offset "));
+        assert!(message.as_slice().contains("error while reading a local file header: bad sector"));
+        assert_eq!(zip.read(&a).unwrap().as_slice(), b"hello");
+
+        let mut zip = ZipReader::new(failing((data_start, data_start + 5))).unwrap();
+        let e = zip.extract(&a, &mut MemWriter::new()).err().unwrap();
+        match *e.innermost() {
+            error::IoErrorWhile(ref err, context) => {
+                assert_eq!(err.kind, io::OtherIoError);
+                assert_eq!(context, "reading the data of an entry");
+            }
+            ref e => panic!("unexpected error {}", e),
+        }
+        assert!(format!("{}", e).as_slice().contains("\"a.txt\""));
+        assert_eq!(zip.read(&b).unwrap().as_slice(), b"world");
+    }
+}
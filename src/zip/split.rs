@@ -0,0 +1,100 @@
+//! Reading archives split into several parts (.z01, .z02, ..., .zip), like the ones
+//! `SplitZipWriter` or `zip -s` make.
+
+use std::cmp;
+use std::io::{File, Reader, Seek, SeekSet, SeekCur, SeekEnd, SeekStyle};
+use std::io::{IoResult, IoError, InvalidInput, EndOfFile};
+use std::io;
+
+/// Presents the parts of a split archive, in order, as one Reader. Seeking works in the
+/// concatenation of the parts, and a read never goes past the end of the part it starts in.
+pub struct SplitReader<R> {
+    parts: Vec<R>,
+    // position of each part in the concatenation
+    part_starts: Vec<u64>,
+    total_size: u64,
+    pos: u64,
+}
+
+impl SplitReader<File> {
+    /// Opens the parts at `paths`, which must be in order, the .zip part last.
+    pub fn open(paths: &[Path]) -> IoResult<SplitReader<File>> {
+        let mut parts = Vec::with_capacity(paths.len());
+        for path in paths.iter() {
+            parts.push(try!(File::open(path)));
+        }
+        SplitReader::new(parts)
+    }
+}
+
+impl<R:Reader+Seek> SplitReader<R> {
+    pub fn new(parts: Vec<R>) -> IoResult<SplitReader<R>> {
+        let mut parts = parts;
+        let mut part_starts = Vec::with_capacity(parts.len());
+        let mut total_size = 0u64;
+        for part in parts.iter_mut() {
+            part_starts.push(total_size);
+            try!(part.seek(0, SeekEnd));
+            total_size += try!(part.tell());
+        }
+        Ok(SplitReader { parts: parts, part_starts: part_starts, total_size: total_size, pos: 0 })
+    }
+
+    pub fn part_count(&self) -> uint {
+        self.parts.len()
+    }
+
+    /// Where the part with the given disk number starts in the concatenation, if there is one.
+    pub fn part_start(&self, disk_number: u32) -> Option<u64> {
+        self.part_starts.as_slice().get(disk_number as uint).map(|&start| start)
+    }
+
+    pub fn unwrap(self) -> Vec<R> {
+        self.parts
+    }
+
+    // the part holding `pos`, which must be before the end
+    fn part_at(&self, pos: u64) -> uint {
+        let mut i = self.part_starts.len() - 1;
+        while self.part_starts[i] > pos {
+            i -= 1;
+        }
+        i
+    }
+}
+
+impl<R:Reader+Seek> Reader for SplitReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        if self.pos >= self.total_size {
+            return Err(io::standard_error(EndOfFile));
+        }
+        let i = self.part_at(self.pos);
+        let part_end = if i + 1 < self.part_starts.len() { self.part_starts[i + 1] } else { self.total_size };
+        let len = cmp::min(buf.len() as u64, part_end - self.pos) as uint;
+        let offset = self.pos - self.part_starts[i];
+        let part = &mut self.parts.as_mut_slice()[i];
+        try!(part.seek(offset as i64, SeekSet));
+        let n = try!(part.read(buf.slice_to_mut(len)));
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R:Reader+Seek> Seek for SplitReader<R> {
+    fn tell(&self) -> IoResult<u64> {
+        Ok(self.pos)
+    }
+
+    fn seek(&mut self, pos: i64, style: SeekStyle) -> IoResult<()> {
+        let base = match style {
+            SeekSet => 0,
+            SeekCur => self.pos as i64,
+            SeekEnd => self.total_size as i64,
+        };
+        if base + pos < 0 {
+            return Err(IoError { kind: InvalidInput, desc: "seek to a negative position", detail: None });
+        }
+        self.pos = (base + pos) as u64;
+        Ok(())
+    }
+}
@@ -0,0 +1,433 @@
+//! Reading archives front to back, from input that can't seek (a pipe, a socket).
+//!
+//! Archives are meant to be read from the central directory at their end, so a few things are
+//! lost when reading them as a stream:
+//!
+//! * entry metadata comes from the local file headers only: comments, attributes (and so
+//!   `is_symlink`, `unix_mode`) and the version made by are missing, and
+//!   `local_file_header_offset` is meaningless;
+//! * entries deleted from the central directory, but whose data was left in place, show up;
+//! * entries with a data descriptor only have their CRC32 and sizes once all of their data has
+//!   been read, and they can only be delimited when they are deflated (the end of the deflate
+//!   stream marks the end of the data); stored ones with a data descriptor fail with
+//!   FeatureNotSupported.
+
+use std::mem;
+use std::uint;
+use std::io::{Reader, BufferedReader};
+use std::io::{IoResult, IoError, InvalidInput, EndOfFile};
+use std::io::util::LimitReader;
+use std::io;
+use error;
+use error::ZipResult;
+use crc32;
+use format;
+use format::extra;
+use fileinfo;
+use fileinfo::FileInfo;
+use inflate::InflateReader;
+
+// counts the bytes read through it, which gives the offsets of the headers for errors
+struct CountingReader<R> {
+    inner: R,
+    count: u64,
+}
+
+impl<R:Reader> Reader for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        let n = try!(self.inner.read(buf));
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+// the underlying reader, in a shape depending on where we are in the archive
+enum Input<R> {
+    // at a header
+    Headers(CountingReader<BufferedReader<R>>),
+    // in the data of a stored entry
+    StoredInput(LimitReader<CountingReader<BufferedReader<R>>>),
+    // in the data of a deflated entry
+    DeflatedInput(InflateReader<LimitReader<CountingReader<BufferedReader<R>>>>),
+    // in the data of an entry we can't decompress, which can only be skipped
+    SkippedInput(LimitReader<CountingReader<BufferedReader<R>>>),
+    // only while switching between the others
+    NoInput,
+}
+
+struct CurrentEntry {
+    info: FileInfo,
+    has_data_descriptor: bool,
+    zip64: bool,
+    // of the uncompressed data read so far
+    crc32: u32,
+    uncompressed_size: u64,
+}
+
+/// Reads the entries of an archive in the order they are stored, without seeking. See the
+/// module documentation for what this can't do.
+///
+/// `next_entry` moves on to the next entry, whose data can then be read from the
+/// ZipStreamReader itself. The CRC32 and sizes of the data are checked at its end against the
+/// data descriptor, or the local file header for entries without one, like with
+/// `ZipEntryReader`. Data that isn't read is skipped by the next call to `next_entry`.
+pub struct ZipStreamReader<R> {
+    input: Input<R>,
+    current: Option<CurrentEntry>,
+    // the central directory was reached
+    finished: bool,
+}
+
+fn invalid(desc: &'static str) -> IoError {
+    IoError { kind: InvalidInput, desc: desc, detail: None }
+}
+
+impl<R:Reader> ZipStreamReader<R> {
+    pub fn new(reader: R) -> ZipStreamReader<R> {
+        ZipStreamReader {
+            input: Headers(CountingReader { inner: BufferedReader::new(reader), count: 0 }),
+            current: None,
+            finished: false,
+        }
+    }
+
+    /// Skips what is left of the current entry and reads the next local file header. Returns
+    /// None once the central directory is reached.
+    pub fn next_entry(&mut self) -> ZipResult<Option<FileInfo>> {
+        try!(self.skip_entry());
+        if self.finished {
+            return Ok(None);
+        }
+        let mut input = match mem::replace(&mut self.input, NoInput) {
+            Headers(input) => input,
+            _ => unreachable!(),
+        };
+
+        let mut offset = input.count;
+        let mut magic = try_io!(input.read_le_u32());
+        // split archives start with the spanning signature
+        if magic == format::DD_SIGNATURE {
+            offset = input.count;
+            magic = try_io!(input.read_le_u32());
+        }
+        if magic == format::CDH_SIGNATURE || magic == format::EOCDR_SIGNATURE {
+            self.finished = true;
+            self.input = Headers(input);
+            return Ok(None);
+        }
+        if magic != format::LFH_SIGNATURE {
+            return Err(error::InvalidSignature(format::LFH_SIGNATURE, magic, offset));
+        }
+        let h = try!(format::LocalFileHeader::read_after_signature(&mut input));
+
+        let (compressed_size, uncompressed_size) =
+            match try!(extra::find(h.extra_field.as_slice(), format::ZIP64_EXTRA_FIELD_ID)) {
+                // the local copy always has both sizes
+                Some(data) if data.len() >= 16 => {
+                    let mut r = io::BufReader::new(data);
+                    let uncompressed_size = try_io!(r.read_le_u64());
+                    (try_io!(r.read_le_u64()), uncompressed_size)
+                }
+                _ => (h.compressed_size as u64, h.uncompressed_size as u64),
+            };
+        let method = fileinfo::CompressionMethod::from_u16(h.compression_method);
+        let readable = !h.is_encrypted() && h.check_supported().is_ok() &&
+                       (method == fileinfo::Store || method == fileinfo::Deflate);
+
+        self.input = if h.has_data_descriptor() {
+            // the data can only be delimited by decompressing it
+            if !readable || method != fileinfo::Deflate {
+                return Err(error::FeatureNotSupported("entries with a data descriptor that aren't plainly deflated, in a stream"));
+            }
+            DeflatedInput(InflateReader::new(LimitReader::new(input, uint::MAX)))
+        } else {
+            let compressed = LimitReader::new(input, compressed_size as uint);
+            if !readable {
+                SkippedInput(compressed)
+            } else if method == fileinfo::Store {
+                StoredInput(compressed)
+            } else {
+                DeflatedInput(InflateReader::new(compressed))
+            }
+        };
+        let info = FileInfo::from_lfh(&h, 0, h.crc32, compressed_size, uncompressed_size);
+        self.current = Some(CurrentEntry {
+            info: info.clone(),
+            has_data_descriptor: h.has_data_descriptor(),
+            zip64: h.has_zip64_extra_field(),
+            crc32: 0,
+            uncompressed_size: 0,
+        });
+        Ok(Some(info))
+    }
+
+    /// The current entry, with its CRC32 and sizes from the data descriptor once all of its
+    /// data has been read.
+    pub fn current_entry(&self) -> Option<&FileInfo> {
+        self.current.as_ref().map(|entry| &entry.info)
+    }
+
+    // reads through the rest of the current entry
+    fn skip_entry(&mut self) -> ZipResult<()> {
+        let mut buf = [0u8, ..8192];
+        loop {
+            let skipped = match self.input {
+                Headers(_) => return Ok(()),
+                NoInput => return Err(error::SomeIoError(invalid("the stream can't be read past a broken entry"))),
+                SkippedInput(ref mut r) => Some(r.read(buf.as_mut_slice())),
+                StoredInput(_) | DeflatedInput(_) => None,
+            };
+            let result = match skipped {
+                Some(result) => result,
+                None => self.read(buf.as_mut_slice()),
+            };
+            match result {
+                Ok(_) => {}
+                Err(ref e) if e.kind == EndOfFile => {
+                    match mem::replace(&mut self.input, NoInput) {
+                        SkippedInput(r) => {
+                            if r.limit() > 0 {
+                                return Err(error::SomeIoError(invalid("entry data is truncated")));
+                            }
+                            self.input = Headers(r.unwrap());
+                        }
+                        input => self.input = input,
+                    }
+                }
+                // the data could be delimited and only failed its checks, which doesn't matter
+                // when skipping it
+                Err(_) if match self.input { Headers(_) => true, _ => false } => return Ok(()),
+                Err(e) => return Err(error::SomeIoError(e)),
+            }
+        }
+    }
+
+    // called at the end of the data: reads the data descriptor if there is one, then checks the
+    // compressed size, CRC32 and size of what was read against those recorded (in the data
+    // descriptor, or else the local file header), and gets ready for the next header
+    fn finish_entry(&mut self) -> IoResult<()> {
+        let mut limited = match mem::replace(&mut self.input, NoInput) {
+            StoredInput(r) => r,
+            DeflatedInput(r) => r.unwrap(),
+            _ => unreachable!(),
+        };
+        let entry = self.current.as_mut().unwrap();
+        let compressed_size = if entry.has_data_descriptor {
+            (uint::MAX - limited.limit()) as u64
+        } else {
+            entry.info.compressed_size - limited.limit() as u64
+        };
+        if !entry.has_data_descriptor {
+            // data left after the end of a deflate stream is read through, so that the next
+            // header can still be found
+            let mut buf = [0u8, ..512];
+            loop {
+                match limited.read(buf.as_mut_slice()) {
+                    Ok(_) => {}
+                    Err(ref e) if e.kind == EndOfFile => break,
+                    Err(e) => return Err(e),
+                }
+            }
+            if limited.limit() > 0 {
+                return Err(invalid("entry data is truncated"));
+            }
+        }
+        let mut input = limited.unwrap();
+
+        if entry.has_data_descriptor {
+            let dd = match format::DataDescriptor::read_after_data(&mut input, entry.zip64, entry.crc32,
+                                                                   compressed_size) {
+                Ok(dd) => dd,
+                Err(error::CrcError) => {
+                    // the descriptor was read whole, so the next header can still be found
+                    self.input = Headers(input);
+                    return Err(IoError { kind: InvalidInput, desc: "CRC mismatch", detail: None });
+                }
+                Err(error::SomeIoError(e)) => return Err(e),
+                Err(_) => return Err(invalid("invalid data descriptor")),
+            };
+            entry.info.crc32 = dd.crc32;
+            entry.info.compressed_size = dd.compressed_size;
+            entry.info.uncompressed_size = dd.uncompressed_size;
+        }
+        self.input = Headers(input);
+
+        if compressed_size != entry.info.compressed_size {
+            return Err(invalid("compressed data goes on after the end of the deflate stream"));
+        }
+        if entry.uncompressed_size != entry.info.uncompressed_size {
+            return Err(invalid("entry data doesn't have the recorded size"));
+        }
+        if entry.crc32 != entry.info.crc32 {
+            return Err(IoError { kind: InvalidInput, desc: "CRC mismatch", detail: None });
+        }
+        Err(io::standard_error(EndOfFile))
+    }
+}
+
+impl<R:Reader> Reader for ZipStreamReader<R> {
+    /// Reads the data of the current entry.
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        let result = match self.input {
+            StoredInput(ref mut r) => r.read(buf),
+            DeflatedInput(ref mut r) => r.read(buf),
+            SkippedInput(_) => return Err(invalid("the entry is encrypted or compressed with an unsupported method")),
+            Headers(_) | NoInput => return Err(io::standard_error(EndOfFile)),
+        };
+        match result {
+            Ok(n) => {
+                let entry = self.current.as_mut().unwrap();
+                entry.crc32 = crc32::update(entry.crc32, buf.slice_to(n));
+                entry.uncompressed_size += n as u64;
+                if !entry.has_data_descriptor && entry.uncompressed_size > entry.info.uncompressed_size {
+                    return Err(invalid("entry data is longer than its recorded size"));
+                }
+                Ok(n)
+            }
+            Err(ref e) if e.kind == EndOfFile => self.finish_entry(),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{MemWriter, MemReader, IoResult};
+    use error;
+    use fileinfo;
+    use writer::{ZipWriter, FileOptions};
+    use super::ZipStreamReader;
+
+    // hides that the input could seek, like a pipe
+    struct Pipe(MemReader);
+
+    impl Reader for Pipe {
+        fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+            let Pipe(ref mut r) = *self;
+            r.read(buf)
+        }
+    }
+
+    fn stream(archive: Vec<u8>) -> ZipStreamReader<Pipe> {
+        ZipStreamReader::new(Pipe(MemReader::new(archive)))
+    }
+
+    fn archive(data_descriptors: bool) -> Vec<u8> {
+        let mut w = ZipWriter::new(MemWriter::new());
+        w.set_data_descriptors(data_descriptors);
+        w.add_file("a.txt", &FileOptions::new(), b"hello hello hello").unwrap();
+        w.add_file("b.txt", &FileOptions::new(), b"world").unwrap();
+        w.finish().unwrap().unwrap()
+    }
+
+    // the data of each entry, or the error reading it
+    fn read_all(archive: Vec<u8>) -> Vec<IoResult<Vec<u8>>> {
+        let mut r = stream(archive);
+        let mut results = Vec::new();
+        loop {
+            match r.next_entry().unwrap() {
+                Some(_) => results.push(r.read_to_end()),
+                None => return results,
+            }
+        }
+    }
+
+    fn error(result: &IoResult<Vec<u8>>) -> &'static str {
+        result.as_ref().unwrap_err().desc
+    }
+
+    #[test]
+    fn data_descriptors() {
+        for &data_descriptors in [false, true].iter() {
+            let results = read_all(archive(data_descriptors));
+            assert_eq!(results.len(), 2);
+            assert!(results[0].as_ref().unwrap().as_slice() == b"hello hello hello");
+            assert!(results[1].as_ref().unwrap().as_slice() == b"world");
+        }
+    }
+
+    #[test]
+    fn data_descriptor_disagrees() {
+        let bytes = archive(true);
+        // the first data descriptor, which starts with its signature
+        let dd = bytes.as_slice().windows(4).position(|w| w == b"PK\x07\x08").unwrap();
+
+        // CRC32 and uncompressed size: the next entry can still be read
+        for &(field, desc) in [(4u, "CRC mismatch"), (12, "entry data doesn't have the recorded size")].iter() {
+            let mut corrupt = bytes.clone();
+            corrupt.as_mut_slice()[dd + field] ^= 1;
+            let results = read_all(corrupt);
+            assert_eq!(error(&results[0]), desc);
+            assert!(results[1].as_ref().unwrap().as_slice() == b"world");
+        }
+
+        // compressed size: where the entry ends is in doubt
+        let mut corrupt = bytes.clone();
+        corrupt.as_mut_slice()[dd + 8] ^= 1;
+        let mut r = stream(corrupt);
+        r.next_entry().unwrap();
+        assert_eq!(r.read_to_end().unwrap_err().desc, "data descriptor doesn't match the entry data");
+    }
+
+    #[test]
+    fn junk_after_deflate_stream() {
+        // four bytes inserted after the data of the first entry, and counted in its compressed size
+        let bytes = archive(false);
+        let compressed_size = bytes[18] as uint | (bytes[19] as uint << 8);
+        let extra_field_length = bytes[28] as uint | (bytes[29] as uint << 8);
+        let data_end = 30 + "a.txt".len() + extra_field_length + compressed_size;
+        let mut corrupt = bytes.slice_to(data_end).to_vec();
+        corrupt.push_all(b"junk");
+        corrupt.push_all(bytes.slice_from(data_end));
+        corrupt.as_mut_slice()[18] += 4;
+
+        let results = read_all(corrupt);
+        assert_eq!(error(&results[0]), "compressed data goes on after the end of the deflate stream");
+        assert!(results[1].as_ref().unwrap().as_slice() == b"world");
+    }
+
+    #[test]
+    fn entries_in_order() {
+        let stored = FileOptions { compression_method: fileinfo::Store, ..FileOptions::new() };
+        let mut w = ZipWriter::new(MemWriter::new());
+        w.add_file("stored.txt", &stored, b"stored").unwrap();
+        w.add_file("deflated.txt", &FileOptions::new(), b"deflated deflated").unwrap();
+        w.add_file("unread.txt", &FileOptions::new(), b"skipped").unwrap();
+        w.add_file("last.txt", &FileOptions::new(), b"last").unwrap();
+        let mut r = stream(w.finish().unwrap().unwrap());
+
+        let f = r.next_entry().unwrap().unwrap();
+        assert!(f.name == "stored.txt" && f.uncompressed_size == 6);
+        assert!(r.read_to_end().unwrap().as_slice() == b"stored");
+        assert!(r.next_entry().unwrap().unwrap().name == "deflated.txt");
+        assert!(r.read_to_end().unwrap().as_slice() == b"deflated deflated");
+        assert!(r.next_entry().unwrap().unwrap().name == "unread.txt");
+        assert!(r.next_entry().unwrap().unwrap().name == "last.txt");
+        assert!(r.read_to_end().unwrap().as_slice() == b"last");
+        assert!(r.next_entry().unwrap().is_none());
+        assert!(r.next_entry().unwrap().is_none());
+    }
+
+    #[test]
+    fn sizes_from_data_descriptors() {
+        let mut r = stream(archive(true));
+        let f = r.next_entry().unwrap().unwrap();
+        assert_eq!(f.uncompressed_size, 0);
+        r.read_to_end().unwrap();
+        let f = r.current_entry().unwrap();
+        assert_eq!(f.uncompressed_size, 17);
+        assert!(f.compressed_size > 0);
+    }
+
+    #[test]
+    fn stored_with_data_descriptor() {
+        // nothing delimits the data
+        let mut w = ZipWriter::new(MemWriter::new());
+        w.set_data_descriptors(true);
+        w.add_file("a.txt", &FileOptions { compression_method: fileinfo::Store, ..FileOptions::new() }, b"a").unwrap();
+        match stream(w.finish().unwrap().unwrap()).next_entry() {
+            Err(error::FeatureNotSupported(_)) => {}
+            _ => panic!("a stored entry with a data descriptor was read"),
+        }
+    }
+}
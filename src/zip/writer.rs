@@ -0,0 +1,1395 @@
+use std::cmp;
+use std::mem;
+use std::io::{Reader, Writer, Seek, MemWriter};
+use std::io::{IoResult, IoError, InvalidInput, EndOfFile};
+use std::path::BytesContainer;
+use error;
+use error::ZipResult;
+use maybe_utf8::MaybeUTF8;
+use crc32;
+use crc32::CrcWriter;
+use format;
+use format::{MsdosDateTime, HostSystem};
+use format::extra;
+use format::extra::{UnixOwner, NtfsTimestamps};
+use fileinfo;
+use fileinfo::{CompressionMethod, FileInfo};
+use reader::ZipReader;
+use zipcrypto;
+use zipcrypto::ZipCryptoKeys;
+use aes;
+use aes::{AesStrength, AesExtraField, AesEncryptor};
+use deflate::Deflater;
+
+/// Per-entry settings used when adding a file to a `ZipWriter`.
+#[deriving(Clone)]
+pub struct FileOptions {
+    pub compression_method: CompressionMethod,
+    pub last_modified_datetime: MsdosDateTime,
+    /// Encrypts the entry with this password, using the traditional PKWARE encryption
+    /// (which is weak) unless `aes_strength` is set. A `ZipWriter` gives entries with the
+    /// traditional encryption a data descriptor, so that they can be encrypted as they are
+    /// written.
+    pub password: Option<Vec<u8>>,
+    /// Uses WinZip AES (AE-2) encryption with the given key size when a password is set.
+    /// Needs the `aes` feature.
+    pub aes_strength: Option<AesStrength>,
+    /// The host system and specification version the entry says it was made with; see
+    /// `made_by`.
+    pub version_made_by: u16,
+    /// Host-dependent, see `unix_mode` and `msdos_attributes`.
+    pub external_file_attributes: u32,
+    /// Whether the entry is marked as text; when None, `ZipWriter::set_detect_text` decides.
+    pub text: Option<bool>,
+    /// The Unix user and group IDs of the owner, stored in an Info-ZIP Unix owner extra field.
+    pub owner: Option<(u64, u64)>,
+    /// Precise timestamps, stored in an NTFS extra field.
+    pub ntfs_timestamps: Option<NtfsTimestamps>,
+}
+
+impl FileOptions {
+    /// Deflate-compressed, unencrypted entry stamped with the DOS epoch (1980-01-01 00:00:00).
+    pub fn new() -> FileOptions {
+        FileOptions {
+            compression_method: fileinfo::Deflate,
+            last_modified_datetime: MsdosDateTime::new(1980, 1, 1, 0, 0, 0),
+            password: None,
+            aes_strength: None,
+            version_made_by: format::version_made_by(format::MsDos, 20),
+            external_file_attributes: 0,
+            text: None,
+            owner: None,
+            ntfs_timestamps: None,
+        }
+    }
+
+    /// Sets or clears the password the entry is encrypted with.
+    pub fn password(mut self, password: Option<&[u8]>) -> FileOptions {
+        self.password = password.map(|p| p.to_vec());
+        self
+    }
+
+    /// Selects WinZip AES encryption (or the traditional one, with None) for password-protected entries.
+    pub fn aes_strength(mut self, strength: Option<AesStrength>) -> FileOptions {
+        self.aes_strength = strength;
+        self
+    }
+
+    /// Sets the host system and specification version (major * 10 + minor) of "version made
+    /// by". Readers use the host system to interpret the attributes: MS-DOS (the default) for
+    /// plain files, Unix to store a Unix mode.
+    pub fn made_by(mut self, host: HostSystem, spec_version: u8) -> FileOptions {
+        self.version_made_by = format::version_made_by(host, spec_version);
+        self
+    }
+
+    /// Stores a Unix mode (file type and permission bits) for the entry, which also makes
+    /// Unix its host system.
+    pub fn unix_mode(mut self, mode: u32) -> FileOptions {
+        let spec_version = self.version_made_by as u8;
+        self.version_made_by = format::version_made_by(format::Unix, spec_version);
+        self.external_file_attributes = format::unix_external_attributes(mode);
+        self
+    }
+
+    /// Sets the MS-DOS attributes of the entry (a combination of the `format::MSDOS_*`
+    /// constants), replacing any Unix mode.
+    pub fn msdos_attributes(mut self, attributes: u8) -> FileOptions {
+        self.external_file_attributes = format::msdos_external_attributes(attributes);
+        self
+    }
+
+    /// Marks the entry as text or binary, whatever its contents.
+    pub fn text(mut self, text: bool) -> FileOptions {
+        self.text = Some(text);
+        self
+    }
+
+    /// Records the Unix user and group IDs of the owner of the entry.
+    pub fn owner(mut self, uid: u64, gid: u64) -> FileOptions {
+        self.owner = Some((uid, gid));
+        self
+    }
+
+    /// Records precise modification, access and creation times (as FILETIMEs, see
+    /// `format::extra::unix_to_filetime`) for the entry.
+    pub fn ntfs_timestamps(mut self, timestamps: NtfsTimestamps) -> FileOptions {
+        self.ntfs_timestamps = Some(timestamps);
+        self
+    }
+
+    fn uses_aes(&self) -> bool {
+        self.password.is_some() && self.aes_strength.is_some()
+    }
+}
+
+static COPY_BUFFER_SIZE: uint = 64 * 1024;
+// the smallest part of a split archive, which must hold an end of central directory record,
+// and the signature that starts its first part
+static MIN_PART_SIZE: u64 = 22;
+static SPANNING_SIGNATURE_SIZE: u64 = 4;
+// how much of a deflated entry is kept in memory to store it instead if deflating doesn't pay
+static STORE_FALLBACK_LIMIT: u64 = 1024 * 1024;
+// how much of an entry is looked at to tell whether it is text
+static TEXT_SAMPLE_SIZE: uint = 4 * 1024;
+
+// Sizes, offsets and counts must fit their header fields, since ZIP64 isn't written. The
+// all-ones value of a field means that the real one is in a ZIP64 record, so it can't be
+// written as is either.
+fn fit_u32(value: u64, field: &'static str) -> ZipResult<u32> {
+    if value >= 0xFFFFFFFF { Err(error::Zip64Required(field)) } else { Ok(value as u32) }
+}
+
+fn fit_u16(value: uint, field: &'static str) -> ZipResult<u16> {
+    if value >= 0xFFFF { Err(error::Zip64Required(field)) } else { Ok(value as u16) }
+}
+
+// builds the headers of a new entry; crc32, sizes and offsets are left for the caller to fill in
+fn entry_headers<T:BytesContainer>(name: T, options: &FileOptions)
+                                   -> ZipResult<(format::LocalFileHeader, format::CentralDirectoryHeader)> {
+    match options.compression_method {
+        fileinfo::Store | fileinfo::Deflate => {}
+        // only decompression is supported
+        fileinfo::Bzip2 | fileinfo::Unknown => return Err(unsupported_compression(options.compression_method)),
+    }
+    // checked here rather than when the data is encrypted, so that nothing is written
+    if options.uses_aes() && !aes::SUPPORTED {
+        return Err(error::FeatureNotSupported("AES encryption (build with the `aes` feature)"));
+    }
+    let file_name = match name.container_as_str() {
+        Some(s) => MaybeUTF8::from_str(s.to_string()),
+        None => MaybeUTF8::from_bytes(name.container_as_bytes().to_vec()),
+    };
+    let mut compression_method = options.compression_method as u16;
+    let mut extra_field = MemWriter::new();
+    match options.aes_strength {
+        Some(strength) if options.uses_aes() => {
+            // the real compression method moves to the AES extra field
+            let aes_field = AesExtraField {
+                vendor_version: 2,
+                strength: strength,
+                compression_method: compression_method,
+            };
+            try!(aes_field.write(&mut extra_field));
+            compression_method = aes::AES_COMPRESSION_METHOD;
+        }
+        _ => {}
+    }
+    match options.owner {
+        Some((uid, gid)) => {
+            let owner = UnixOwner { uid: uid, gid: gid }.serialize();
+            let record = try!(extra::serialize(&[(extra::UNIX_OWNER_ID, owner.as_slice())]));
+            try_io!(extra_field.write(record.as_slice()));
+        }
+        None => {}
+    }
+    match options.ntfs_timestamps {
+        Some(ref timestamps) => {
+            let record = try!(extra::serialize(&[(extra::NTFS_ID, timestamps.serialize().as_slice())]));
+            try_io!(extra_field.write(record.as_slice()));
+        }
+        None => {}
+    }
+
+    let info = FileInfo {
+        name: file_name.clone(),
+        raw_name: file_name,
+        comment: MaybeUTF8::new(),
+        compression_method: options.compression_method,
+        raw_compression_method: compression_method,
+        // the deflate implementation only has its default level
+        deflate_option: if options.compression_method == fileinfo::Deflate { Some(format::Normal) } else { None },
+        last_modified_datetime: options.last_modified_datetime.to_tuple(),
+        crc32: 0,
+        compressed_size: 0,
+        uncompressed_size: 0,
+        is_encrypted: options.password.is_some(),
+        // the data descriptor flag is up to the caller
+        encryption_check_byte: None,
+        extra_field: extra_field.unwrap(),
+        version_made_by: options.version_made_by,
+        // the local file header has no internal attributes
+        internal_file_attributes: if options.text == Some(true) { 1 } else { 0 },
+        external_file_attributes: options.external_file_attributes,
+        local_file_header_offset: 0,
+        recovered: false,
+    };
+    let h = try!(format::LocalFileHeader::from_info(&info));
+    let cdh = try!(format::CentralDirectoryHeader::from_info(&info, 0, 0));
+    Ok((h, cdh))
+}
+
+fn compress(method: CompressionMethod, data: &[u8]) -> ZipResult<Vec<u8>> {
+    match method {
+        fileinfo::Store => Ok(data.to_vec()),
+        // the same encoder as for entries written a chunk at a time, so both give the same bytes
+        fileinfo::Deflate => {
+            let mut deflater = Deflater::new();
+            let mut compressed = Vec::new();
+            deflater.write(data, &mut compressed);
+            deflater.finish(&mut compressed);
+            Ok(compressed)
+        }
+        fileinfo::Bzip2 | fileinfo::Unknown => Err(unsupported_compression(method)),
+    }
+}
+
+fn unsupported_compression(method: CompressionMethod) -> error::ZipError {
+    match method {
+        fileinfo::Unknown => error::FeatureNotSupported("compression with an unknown method"),
+        method => error::UnsupportedCompressionMethod(method as u16),
+    }
+}
+
+// deflates `data`, unless that would make it bigger (as with already compressed data), in which
+// case it is stored
+fn deflate_or_store(data: &[u8]) -> ZipResult<(CompressionMethod, Vec<u8>)> {
+    let compressed_bytes = try!(compress(fileinfo::Deflate, data));
+    if compressed_bytes.len() >= data.len() {
+        Ok((fileinfo::Store, data.to_vec()))
+    } else {
+        Ok((fileinfo::Deflate, compressed_bytes))
+    }
+}
+
+// encrypts the compressed data of an entry if a password is given. `check_byte` ends the
+// traditional encryption header: the high byte of the CRC32, or of the DOS time for entries with a
+// data descriptor, since that's what readers check the password against.
+fn encrypt(options: &FileOptions, check_byte: u8, compressed_bytes: Vec<u8>) -> ZipResult<Vec<u8>> {
+    match (&options.password, options.aes_strength) {
+        (&Some(ref password), Some(strength)) =>
+            aes::encrypt_entry(password.as_slice(), strength, compressed_bytes.as_slice()),
+        (&Some(ref password), None) =>
+            Ok(zipcrypto::encrypt_entry(password.as_slice(), check_byte, compressed_bytes.as_slice())),
+        (&None, _) => Ok(compressed_bytes),
+    }
+}
+
+// Whether the start of an entry looks like text, as Info-ZIP guesses it: no NUL or other
+// control characters besides whitespace, form feed, ^Z and escape. Bytes above 127 are
+// accepted, so UTF-8 (with or without a BOM) and 8-bit code pages pass.
+fn looks_like_text(sample: &[u8]) -> bool {
+    !sample.is_empty() && sample.iter().all(|&b| {
+        b >= 32 || b == b'\t' || b == b'\n' || b == b'\r' || b == 12 || b == 26 || b == 27
+    })
+}
+
+// the CRC32 to record in the headers; AE-2 leaves it out, since the authentication code covers it
+fn stored_crc32(options: &FileOptions, crc: u32) -> u32 {
+    if options.uses_aes() { 0 } else { crc }
+}
+
+// writes out what an entry has produced so far, unless its compressed size would outgrow the
+// headers
+fn write_output<W:Writer>(writer: &mut W, output: &mut Vec<u8>, compressed_size: &mut u64,
+                          offset: &mut u64) -> ZipResult<()> {
+    try!(fit_u32(*compressed_size + output.len() as u64, "compressed size of an entry"));
+    try_io!(writer.write(output.as_slice()));
+    *compressed_size += output.len() as u64;
+    *offset += output.len() as u64;
+    output.clear();
+    Ok(())
+}
+
+enum Encryption {
+    Unencrypted,
+    ZipCryptoEncrypted(ZipCryptoKeys),
+    AesEncrypted(AesEncryptor),
+}
+
+// compresses and encrypts the data of an entry as it comes, collecting the result in `output`
+struct EntryEncoder {
+    deflater: Option<Deflater>,
+    encryption: Encryption,
+    output: Vec<u8>,
+}
+
+impl EntryEncoder {
+    // the output starts with the encryption header, if any; see `encrypt` for `check_byte`
+    fn new(options: &FileOptions, check_byte: u8) -> ZipResult<EntryEncoder> {
+        let (encryption, output) = match (&options.password, options.aes_strength) {
+            (&Some(ref password), Some(strength)) => {
+                let (encryptor, prefix) = try!(AesEncryptor::new(password.as_slice(), strength));
+                (AesEncrypted(encryptor), prefix)
+            }
+            (&Some(ref password), None) => {
+                let (keys, header) = zipcrypto::start_entry(password.as_slice(), check_byte);
+                (ZipCryptoEncrypted(keys), header)
+            }
+            (&None, _) => (Unencrypted, Vec::new()),
+        };
+        let deflater = match options.compression_method {
+            fileinfo::Deflate => Some(Deflater::new()),
+            _ => None,
+        };
+        Ok(EntryEncoder { deflater: deflater, encryption: encryption, output: output })
+    }
+
+    fn encrypt_from(&mut self, start: uint) {
+        let data = self.output.slice_from_mut(start);
+        match self.encryption {
+            Unencrypted => {}
+            ZipCryptoEncrypted(ref mut keys) => keys.encrypt(data),
+            AesEncrypted(ref mut encryptor) => encryptor.encrypt(data),
+        }
+    }
+
+    // ends the compressed data and appends the authentication code of AES entries
+    fn finish(&mut self) {
+        let start = self.output.len();
+        match self.deflater {
+            Some(ref mut deflater) => deflater.finish(&mut self.output),
+            None => {}
+        }
+        self.encrypt_from(start);
+        match self.encryption {
+            AesEncrypted(ref mut encryptor) => self.output.push_all(encryptor.finish().as_slice()),
+            _ => {}
+        }
+    }
+}
+
+impl Writer for EntryEncoder {
+    fn write(&mut self, buf: &[u8]) -> IoResult<()> {
+        let start = self.output.len();
+        match self.deflater {
+            Some(ref mut deflater) => deflater.write(buf, &mut self.output),
+            None => self.output.push_all(buf),
+        }
+        self.encrypt_from(start);
+        Ok(())
+    }
+}
+
+// where the uncompressed data of an entry goes, which gives its CRC32 and size
+enum EntryData {
+    // stored and unencrypted entries are written straight to the archive, whose CrcWriter is
+    // reset at the start of their data
+    Direct,
+    // the start of a deflated entry that may still be stored instead; past
+    // STORE_FALLBACK_LIMIT, it is encoded like the others
+    Buffered(CrcWriter<MemWriter>),
+    Encoded(CrcWriter<EntryEncoder>),
+}
+
+impl EntryData {
+    // the CRC32 and size of the uncompressed data written so far
+    fn crc_and_size<W:Writer>(&self, archive: &CrcWriter<W>) -> (u32, u64) {
+        match *self {
+            Direct => (archive.crc(), archive.count()),
+            Buffered(ref data) => (data.crc(), data.count()),
+            Encoded(ref data) => (data.crc(), data.count()),
+        }
+    }
+}
+
+// bookkeeping for the entry currently being written
+struct PendingEntry {
+    header: format::CentralDirectoryHeader,
+    // position of the local file header in the underlying stream, for patching it afterwards
+    header_position: u64,
+    compressed_size: u64,
+    options: FileOptions,
+    data: EntryData,
+    // the first bytes of the entry, when it is to be checked for text
+    text_sample: Option<Vec<u8>>,
+}
+
+pub struct ZipWriter<W> {
+    // the CRC32 and count cover the data of the current entry when it is written directly
+    writer: CrcWriter<W>,
+    files: Vec<format::CentralDirectoryHeader>,
+    current: Option<PendingEntry>,
+    // offset of the next byte to be written, counting any prepended data
+    offset: u64,
+    comment: Vec<u8>,
+    digital_signature: Option<format::CentralDirectoryDigitalSignature>,
+    archive_extra_data: Option<format::ArchiveExtraDataRecord>,
+    data_descriptors: bool,
+    data_descriptor_signature: bool,
+    store_fallback: bool,
+    detect_text: bool,
+}
+
+/// A handle to the entry being written. Bytes written to it are CRC'd, compressed and
+/// encrypted on their way to the archive; the entry is finalized by `finish()` or when the handle is
+/// dropped.
+pub struct EntryWriter<'a, W:'a> {
+    zip_writer: &'a mut ZipWriter<W>,
+}
+
+impl<W:Writer+Seek> ZipWriter<W> {
+    pub fn new(writer: W) -> ZipWriter<W> {
+        ZipWriter::with_prepended_data(writer, 0)
+    }
+
+    /// Creates a ZipWriter for an archive that will end up preceded by `initial_offset` bytes
+    /// of other data, e.g. the stub of a self-extracting archive. The writer may already have
+    /// those bytes in it, or they may be glued in front of the output later.
+    ///
+    /// Every local header offset and the central directory offset are counted from the start
+    /// of the whole file, so readers that locate the archive from its end keep working. Keep
+    /// in mind that this also applies to any alignment the caller cares about: the position of
+    /// entry data in the file includes the prepended bytes.
+    pub fn with_prepended_data(writer: W, initial_offset: u64) -> ZipWriter<W> {
+        ZipWriter {
+            writer: CrcWriter::new(writer),
+            files: Vec::new(),
+            current: None,
+            offset: initial_offset,
+            comment: Vec::new(),
+            digital_signature: None,
+            archive_extra_data: None,
+            data_descriptors: false,
+            data_descriptor_signature: true,
+            store_fallback: true,
+            detect_text: false,
+        }
+    }
+
+    /// Creates a ZipWriter that first writes `preamble` (e.g. an executable stub) to `writer`.
+    pub fn with_preamble(writer: W, preamble: &[u8]) -> ZipResult<ZipWriter<W>> {
+        let mut writer = writer;
+        try_io!(writer.write(preamble));
+        Ok(ZipWriter::with_prepended_data(writer, preamble.len() as u64))
+    }
+
+    /// Sets the archive comment, written in the end of central directory record. Fails with
+    /// TooLongField if it is longer than 65535 bytes.
+    pub fn set_comment<T:BytesContainer>(&mut self, comment: T) -> ZipResult<()> {
+        let comment = comment.container_as_bytes();
+        if comment.len() > 0xFFFF {
+            return Err(error::TooLongField);
+        }
+        self.comment = comment.to_vec();
+        Ok(())
+    }
+
+    /// Sets the central directory digital signature record, written after the central
+    /// directory. Its data is written as given; signing the archive is up to the caller.
+    pub fn set_digital_signature(&mut self, signature: Option<format::CentralDirectoryDigitalSignature>) {
+        self.digital_signature = signature;
+    }
+
+    /// Sets the archive extra data record, written between the data of the last entry and the
+    /// central directory.
+    pub fn set_archive_extra_data(&mut self, record: Option<format::ArchiveExtraDataRecord>) {
+        self.archive_extra_data = record;
+    }
+
+    /// When enabled, entries are finalized by writing a data descriptor (general purpose bit 3)
+    /// after their data instead of seeking back to fill in the local file header. Off by default.
+    pub fn set_data_descriptors(&mut self, enabled: bool) {
+        self.data_descriptors = enabled;
+    }
+
+    /// Whether data descriptors start with their optional 0x08074b50 signature. Info-ZIP writes
+    /// it and it is on by default, but some consumers don't expect it.
+    pub fn set_data_descriptor_signature(&mut self, present: bool) {
+        self.data_descriptor_signature = present;
+    }
+
+    /// When enabled (the default), deflated entries that would end up bigger than their contents
+    /// are stored instead. Switching the method needs seeking back to the local header, so
+    /// entries with data descriptors and encrypted entries are always kept deflated. So are
+    /// entries over 1 MiB, since deciding means keeping the entry in memory until it is closed;
+    /// their incompressible parts are stored within the deflate stream, which only costs a few
+    /// bytes per 32 KiB.
+    pub fn set_store_fallback(&mut self, enabled: bool) {
+        self.store_fallback = enabled;
+    }
+
+    /// When enabled, entries whose options don't say whether they are text are marked as text
+    /// if their first few KiB look like it, as Info-ZIP does. Off by default.
+    pub fn set_detect_text(&mut self, enabled: bool) {
+        self.detect_text = enabled;
+    }
+
+    /// Starts a new entry and returns a Writer for its contents.
+    ///
+    /// Only one entry can be open at a time. The returned handle borrows the ZipWriter, and
+    /// if a previous entry was never finalized (its handle was leaked) it is finalized here
+    /// before the new one is started.
+    ///
+    /// Fails with Zip64Required if the archive already has 65534 entries or is 4 GiB long. If
+    /// the contents of the entry reach 4 GiB, the write fails with an InvalidInput error and
+    /// the entry is dropped: it is left out of the central directory, and the rest of the
+    /// archive can still be written. What was already written of it stays in the output,
+    /// unreferenced, since it can't be taken back from a stream.
+    pub fn start_entry<'a, T:BytesContainer>(&'a mut self, name: T, options: &FileOptions)
+                                             -> ZipResult<EntryWriter<'a, W>> {
+        try!(self.finish_entry());
+        try!(fit_u16(self.files.len() + 1, "number of entries"));
+
+        let (mut h, mut cdh) = try!(entry_headers(name, options));
+        cdh.relative_offset_of_local_header = try!(fit_u32(self.offset, "offset of a local file header"));
+        // the traditional encryption header depends on the CRC32 unless there is a data
+        // descriptor, and the CRC32 is only known at the end
+        let zipcrypto = options.password.is_some() && !options.uses_aes();
+        let has_data_descriptor = self.data_descriptors || zipcrypto;
+        if has_data_descriptor {
+            h.general_purpose_bit_flag.set_data_descriptor(true);
+            cdh.general_purpose_bit_flag.set_data_descriptor(true);
+        }
+
+        let data = if options.password.is_none() && options.compression_method != fileinfo::Deflate {
+            Direct
+        } else if options.password.is_none() && self.store_fallback && !has_data_descriptor {
+            Buffered(CrcWriter::new(MemWriter::new()))
+        } else {
+            let check_byte = format::encryption_check_byte(true, 0, &options.last_modified_datetime);
+            Encoded(CrcWriter::new(try!(EntryEncoder::new(options, check_byte))))
+        };
+
+        // crc32 and sizes are zero for now, and get patched or put in a data descriptor when
+        // the entry is finalized
+        let header_position = try_io!(self.writer.get_mut().tell());
+        try!(h.write(&mut self.writer));
+        self.writer.reset();
+
+        self.offset += h.total_size();
+        self.current = Some(PendingEntry {
+            header: cdh,
+            header_position: header_position,
+            compressed_size: 0,
+            options: options.clone(),
+            data: data,
+            text_sample: if self.detect_text && options.text.is_none() { Some(Vec::new()) } else { None },
+        });
+        Ok(EntryWriter { zip_writer: self })
+    }
+
+    /// Adds an entry whose whole contents are already in memory.
+    pub fn add_file<T:BytesContainer>(&mut self, name: T, options: &FileOptions,
+                                      data: &[u8]) -> ZipResult<()> {
+        let mut entry = try!(self.start_entry(name, options));
+        try_io!(entry.write(data));
+        entry.finish()
+    }
+
+    /// Adds an entry with everything `reader` yields until EOF, read in fixed-size chunks, and
+    /// returns the number of (uncompressed) bytes written.
+    pub fn copy_from_reader<T:BytesContainer, R:Reader>(&mut self, name: T, options: &FileOptions,
+                                                        reader: &mut R) -> ZipResult<u64> {
+        let mut entry = try!(self.start_entry(name, options));
+        let mut buf = [0u8, ..COPY_BUFFER_SIZE];
+        let mut total = 0u64;
+        loop {
+            // a reader that keeps returning nothing fails with NoProgress instead of spinning
+            let n = match reader.read_at_least(1, buf.as_mut_slice()) {
+                Ok(n) => n,
+                Err(ref e) if e.kind == EndOfFile => break,
+                Err(e) => return Err(error::SomeIoError(e)),
+            };
+            try_io!(entry.write(buf.slice_to(n)));
+            total += n as u64;
+        }
+        try!(entry.finish());
+        Ok(total)
+    }
+
+    /// Copies an entry from another archive without recompressing it. The compression method,
+    /// timestamps, attributes, extra fields and comment of the entry are kept as they are.
+    /// Its data descriptor is dropped, except for encrypted entries, where the password is
+    /// checked differently without it.
+    pub fn copy_raw_entry<R:Reader+Seek>(&mut self, reader: &mut ZipReader<R>,
+                                         h: &format::CentralDirectoryHeader) -> ZipResult<()> {
+        try!(self.finish_entry());
+
+        let (mut lfh, data) = try!(reader.read_raw_entry(h));
+        // the data descriptor is rewritten for encrypted entries, whose password check byte
+        // depends on whether there is one; for the others the real values go straight into the
+        // headers
+        let data_descriptor = if lfh.is_encrypted() && lfh.has_data_descriptor() {
+            let zip64 = try!(h.zip64_extended_information());
+            Some(format::DataDescriptor {
+                signature_present: self.data_descriptor_signature,
+                zip64: lfh.has_zip64_extra_field(),
+                crc32: h.crc32,
+                compressed_size: zip64.compressed_size,
+                uncompressed_size: zip64.uncompressed_size,
+            })
+        } else {
+            lfh.general_purpose_bit_flag.set_data_descriptor(false);
+            lfh.crc32 = h.crc32;
+            lfh.compressed_size = h.compressed_size;
+            lfh.uncompressed_size = h.uncompressed_size;
+            None
+        };
+
+        let mut cdh = h.clone();
+        cdh.general_purpose_bit_flag.set_data_descriptor(data_descriptor.is_some());
+        cdh.disk_number_start = 0;
+        try!(fit_u16(self.files.len() + 1, "number of entries"));
+        cdh.relative_offset_of_local_header = try!(fit_u32(self.offset, "offset of a local file header"));
+
+        try!(lfh.write(&mut self.writer));
+        try_io!(self.writer.write(data.as_slice()));
+        self.offset += lfh.total_size() + data.len() as u64;
+        match data_descriptor {
+            Some(ref dd) => {
+                try!(dd.write(&mut self.writer));
+                self.offset += dd.total_size();
+            }
+            None => {}
+        }
+        self.files.push(cdh);
+        Ok(())
+    }
+
+    /// Finalizes any open entry, writes the central directory and returns the underlying writer.
+    pub fn finish(mut self) -> ZipResult<W> {
+        try!(self.finish_entry());
+
+        match self.archive_extra_data {
+            Some(ref record) => {
+                try!(record.write(&mut self.writer));
+                self.offset += record.total_size();
+            }
+            None => {}
+        }
+        let central_directory_offset = self.offset;
+        try!(fit_u32(central_directory_offset, "central directory offset"));
+        for h in self.files.iter() {
+            try!(h.write(&mut self.writer));
+            self.offset += h.total_size();
+        }
+        // the signature counts as part of the central directory
+        match self.digital_signature {
+            Some(ref signature) => {
+                try!(signature.write(&mut self.writer));
+                self.offset += signature.total_size();
+            }
+            None => {}
+        }
+
+        let mut e = format::EndOfCentralDirectoryRecord::new();
+        e.entry_count_this_disk = try!(fit_u16(self.files.len(), "number of entries"));
+        e.total_entry_count = e.entry_count_this_disk;
+        e.central_directory_size = try!(fit_u32(self.offset - central_directory_offset, "central directory size"));
+        e.central_directory_offset = central_directory_offset as u32;
+        e.comment = MaybeUTF8::from_bytes(self.comment.clone());
+        try!(e.write(&mut self.writer));
+        try_io!(self.writer.flush());
+
+        Ok(self.writer.into_inner())
+    }
+
+    fn write_entry_data(&mut self, buf: &[u8]) -> IoResult<()> {
+        match self.append_entry_data(buf) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                // an entry too large for its headers is dropped, see `start_entry`
+                match e {
+                    error::Zip64Required(_) => self.current = None,
+                    _ => {}
+                }
+                Err(e.to_io_error())
+            }
+        }
+    }
+
+    fn append_entry_data(&mut self, buf: &[u8]) -> ZipResult<()> {
+        let entry = match self.current {
+            Some(ref mut entry) => entry,
+            None => return Err(error::SomeIoError(IoError {
+                kind: InvalidInput,
+                desc: "the entry is already closed",
+                detail: None,
+            }))
+        };
+        // checked before anything is written, so a stored entry never goes past the limit
+        let (_, size) = entry.data.crc_and_size(&self.writer);
+        try!(fit_u32(size + buf.len() as u64, "uncompressed size of an entry"));
+        match entry.text_sample {
+            Some(ref mut sample) if sample.len() < TEXT_SAMPLE_SIZE => {
+                let n = cmp::min(buf.len(), TEXT_SAMPLE_SIZE - sample.len());
+                sample.push_all(buf.slice_to(n));
+            }
+            _ => {}
+        }
+
+        let switch_to_encoded = match entry.data {
+            Direct => {
+                try_io!(self.writer.write(buf));
+                entry.compressed_size += buf.len() as u64;
+                self.offset += buf.len() as u64;
+                false
+            }
+            Buffered(ref mut data) => {
+                try_io!(data.write(buf));
+                data.count() > STORE_FALLBACK_LIMIT
+            }
+            Encoded(ref mut data) => {
+                try_io!(data.write(buf));
+                try!(write_output(self.writer.get_mut(), &mut data.get_mut().output,
+                                  &mut entry.compressed_size, &mut self.offset));
+                false
+            }
+        };
+        if switch_to_encoded {
+            let buffered = match mem::replace(&mut entry.data, Direct) {
+                Buffered(data) => data.into_inner().unwrap(),
+                _ => unreachable!(),
+            };
+            let mut data = CrcWriter::new(try!(EntryEncoder::new(&entry.options, 0)));
+            try_io!(data.write(buffered.as_slice()));
+            try!(write_output(self.writer.get_mut(), &mut data.get_mut().output,
+                              &mut entry.compressed_size, &mut self.offset));
+            entry.data = Encoded(data);
+        }
+        Ok(())
+    }
+
+    fn finish_entry(&mut self) -> ZipResult<()> {
+        let mut entry = match self.current.take() {
+            Some(entry) => entry,
+            None => return Ok(())
+        };
+
+        let has_data_descriptor = entry.header.has_data_descriptor();
+        let (crc, uncompressed_size) = entry.data.crc_and_size(&self.writer);
+        match mem::replace(&mut entry.data, Direct) {
+            Direct => {}
+            Buffered(data) => {
+                let (method, mut compressed_bytes) = try!(deflate_or_store(data.get_ref().get_ref()));
+                if method != entry.options.compression_method {
+                    entry.header.compression_method = method as u16;
+                    entry.header.version_needed_to_extract = 10;
+                }
+                try!(write_output(self.writer.get_mut(), &mut compressed_bytes,
+                                  &mut entry.compressed_size, &mut self.offset));
+            }
+            Encoded(data) => {
+                let mut encoder = data.into_inner();
+                encoder.finish();
+                try!(write_output(self.writer.get_mut(), &mut encoder.output,
+                                  &mut entry.compressed_size, &mut self.offset));
+            }
+        }
+
+        match entry.text_sample {
+            Some(ref sample) if looks_like_text(sample.as_slice()) => entry.header.internal_file_attributes |= 1,
+            _ => {}
+        }
+
+        entry.header.crc32 = stored_crc32(&entry.options, crc);
+        entry.header.compressed_size = try!(fit_u32(entry.compressed_size, "compressed size of an entry"));
+        entry.header.uncompressed_size = try!(fit_u32(uncompressed_size, "uncompressed size of an entry"));
+
+        if has_data_descriptor {
+            let dd = format::DataDescriptor {
+                signature_present: self.data_descriptor_signature,
+                zip64: false,
+                crc32: entry.header.crc32,
+                compressed_size: entry.compressed_size,
+                uncompressed_size: uncompressed_size,
+            };
+            try!(dd.write(&mut self.writer));
+            self.offset += dd.total_size();
+        } else {
+            // seek back and fill in the local file header, from the version needed to extract
+            // (at byte 4) to the sizes, since the compression method may have changed too
+            let end_position = try_io!(self.writer.get_mut().tell());
+            try_io!(format::seek_to(self.writer.get_mut(), entry.header_position + 4));
+            try_io!(self.writer.write_le_u16(entry.header.version_needed_to_extract));
+            try_io!(self.writer.write_le_u16(entry.header.general_purpose_bit_flag.bits()));
+            try_io!(self.writer.write_le_u16(entry.header.compression_method));
+            try_io!(entry.header.last_modified_datetime.write(&mut self.writer));
+            try_io!(self.writer.write_le_u32(entry.header.crc32));
+            try_io!(self.writer.write_le_u32(entry.header.compressed_size));
+            try_io!(self.writer.write_le_u32(entry.header.uncompressed_size));
+            try_io!(format::seek_to(self.writer.get_mut(), end_position));
+        }
+
+        self.files.push(entry.header);
+        Ok(())
+    }
+}
+
+impl<'a, W:Writer+Seek> EntryWriter<'a, W> {
+    /// Finalizes the entry, reporting any error that dropping the handle would swallow.
+    pub fn finish(self) -> ZipResult<()> {
+        self.zip_writer.finish_entry()
+    }
+}
+
+impl<'a, W:Writer+Seek> Writer for EntryWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> IoResult<()> {
+        self.zip_writer.write_entry_data(buf)
+    }
+}
+
+#[unsafe_destructor]
+impl<'a, W:Writer+Seek> Drop for EntryWriter<'a, W> {
+    fn drop(&mut self) {
+        // errors can't be reported from here; use finish() to see them
+        let _ = self.zip_writer.finish_entry();
+    }
+}
+
+/// Writes an archive split into parts of at most `part_size` bytes, for media or upload size
+/// limits.
+///
+/// The parts are produced by a callback that is given the disk number (starting at 0) of each
+/// new part. They are conventionally named .z01, .z02, ... with the last one, which holds the end
+/// of central directory record, named .zip; since the last part is only known once `finish()`
+/// returns, renaming it is left to the caller.
+///
+/// Local file headers are never split across parts unless they are larger than a part. The
+/// first part is kept in memory until the archive outgrows it, since split archives start with
+/// a spanning signature that an archive fitting in one part must not have.
+pub struct SplitZipWriter<'a, W> {
+    new_part: |u16|: 'a -> IoResult<W>,
+    part_size: u64,
+    writer: W,
+    // the data of the first part, while it is unknown whether there will be others
+    first_part: Option<MemWriter>,
+    disk_number: u16,
+    // bytes written to the current part
+    part_offset: u64,
+    files: Vec<format::CentralDirectoryHeader>,
+    comment: Vec<u8>,
+    store_fallback: bool,
+}
+
+impl<'a, W:Writer> SplitZipWriter<'a, W> {
+    /// Fails with an InvalidInput error if `part_size` is below 22 bytes, too small for the end
+    /// of central directory record. APPNOTE.TXT asks for parts of at least 64 KiB, which some
+    /// tools expect; smaller ones are for tests and for callers with their own limits.
+    pub fn new(part_size: u64, new_part: |u16|: 'a -> IoResult<W>) -> ZipResult<SplitZipWriter<'a, W>> {
+        if part_size < MIN_PART_SIZE {
+            return Err(error::SomeIoError(IoError {
+                kind: InvalidInput,
+                desc: "part size too small for a split archive",
+                detail: Some(format!("{} bytes, the minimum is {}", part_size, MIN_PART_SIZE)),
+            }));
+        }
+        let mut new_part = new_part;
+        let writer = try_io!(new_part(0));
+        Ok(SplitZipWriter {
+            new_part: new_part,
+            part_size: part_size,
+            writer: writer,
+            first_part: Some(MemWriter::new()),
+            disk_number: 0,
+            // room for the spanning signature, written when a second part is started
+            part_offset: SPANNING_SIGNATURE_SIZE,
+            files: Vec::new(),
+            comment: Vec::new(),
+            store_fallback: true,
+        })
+    }
+
+    /// Sets the archive comment, written in the end of central directory record. Fails with
+    /// TooLongField if it is longer than 65535 bytes, and with an InvalidInput error if the
+    /// record wouldn't fit in a part with it.
+    pub fn set_comment<T:BytesContainer>(&mut self, comment: T) -> ZipResult<()> {
+        let comment = comment.container_as_bytes();
+        if comment.len() > 0xFFFF {
+            return Err(error::TooLongField);
+        }
+        if MIN_PART_SIZE + comment.len() as u64 > self.part_size {
+            return Err(error::SomeIoError(IoError {
+                kind: InvalidInput,
+                desc: "comment too long for the part size",
+                detail: Some(format!("{} bytes, the parts are {}", comment.len(), self.part_size)),
+            }));
+        }
+        self.comment = comment.to_vec();
+        Ok(())
+    }
+
+    /// When enabled (the default), deflated entries that would end up bigger than their contents
+    /// are stored instead.
+    pub fn set_store_fallback(&mut self, enabled: bool) {
+        self.store_fallback = enabled;
+    }
+
+    /// Adds an entry whose whole contents are already in memory.
+    pub fn add_file<T:BytesContainer>(&mut self, name: T, options: &FileOptions,
+                                      data: &[u8]) -> ZipResult<()> {
+        let crc = crc32::crc32(data);
+        let (method, compressed_bytes) =
+            if options.compression_method == fileinfo::Deflate && self.store_fallback {
+                try!(deflate_or_store(data))
+            } else {
+                (options.compression_method, try!(compress(options.compression_method, data)))
+            };
+        let mut options = options.clone();
+        options.compression_method = method;
+        let compressed_bytes = try!(encrypt(&options, (crc >> 24) as u8, compressed_bytes));
+
+        let (mut h, mut cdh) = try!(entry_headers(name, &options));
+        try!(fit_u16(self.files.len() + 1, "number of entries"));
+        h.crc32 = stored_crc32(&options, crc);
+        h.compressed_size = try!(fit_u32(compressed_bytes.len() as u64, "compressed size of an entry"));
+        h.uncompressed_size = try!(fit_u32(data.len() as u64, "uncompressed size of an entry"));
+
+        let mut header = MemWriter::new();
+        try!(h.write(&mut header));
+        try!(self.keep_together(header.get_ref().len() as u64));
+
+        cdh.crc32 = h.crc32;
+        cdh.compressed_size = h.compressed_size;
+        cdh.uncompressed_size = h.uncompressed_size;
+        cdh.disk_number_start = self.disk_number;
+        cdh.relative_offset_of_local_header = try!(fit_u32(self.part_offset, "offset of a local file header"));
+
+        try!(self.write_bytes(header.get_ref()));
+        try!(self.write_bytes(compressed_bytes.as_slice()));
+        self.files.push(cdh);
+        Ok(())
+    }
+
+    /// Writes the central directory and returns the writer of the last part.
+    pub fn finish(mut self) -> ZipResult<W> {
+        let mut files = mem::replace(&mut self.files, Vec::new());
+
+        // the end of central directory record always sits whole on the last disk
+        let end_record_size = 22 + self.comment.len() as u64;
+        // an archive that fits in its first part is a plain one, without the spanning signature
+        if self.first_part.is_some() {
+            let rest = files.iter().fold(end_record_size, |size, h| size + h.total_size());
+            if self.part_offset + rest <= self.part_size {
+                let part = self.first_part.take().unwrap();
+                try_io!(self.writer.write(part.get_ref()));
+                self.part_offset -= SPANNING_SIGNATURE_SIZE;
+                for h in files.iter_mut() {
+                    h.relative_offset_of_local_header -= SPANNING_SIGNATURE_SIZE as u32;
+                }
+            }
+        }
+
+        let mut central_directory_start = None;
+        let mut central_directory_size = 0u64;
+        // the last disk the central directory touches, and how many headers it has there
+        let mut entries_disk = self.disk_number;
+        let mut entries_on_disk = 0u16;
+        for h in files.iter() {
+            let mut header = MemWriter::new();
+            try!(h.write(&mut header));
+            try!(self.keep_together(header.get_ref().len() as u64));
+            if central_directory_start.is_none() {
+                central_directory_start = Some((self.disk_number, self.part_offset));
+            }
+            if entries_disk != self.disk_number {
+                entries_disk = self.disk_number;
+                entries_on_disk = 0;
+            }
+            entries_on_disk += 1;
+            try!(self.write_bytes(header.get_ref()));
+            central_directory_size += header.get_ref().len() as u64;
+        }
+
+        try!(self.keep_together(end_record_size));
+        let (start_disk, start_offset) = match central_directory_start {
+            Some(start) => start,
+            None => (self.disk_number, self.part_offset),
+        };
+
+        let mut e = format::EndOfCentralDirectoryRecord::new();
+        e.disk_number = self.disk_number;
+        e.disk_number_with_start_of_central_directory = start_disk;
+        e.entry_count_this_disk = if entries_disk == self.disk_number { entries_on_disk } else { 0 };
+        e.total_entry_count = try!(fit_u16(files.len(), "number of entries"));
+        e.central_directory_size = try!(fit_u32(central_directory_size, "central directory size"));
+        e.central_directory_offset = try!(fit_u32(start_offset, "central directory offset"));
+        e.comment = MaybeUTF8::from_bytes(self.comment.clone());
+
+        let mut end_record = MemWriter::new();
+        try!(e.write(&mut end_record));
+        try!(self.write_bytes(end_record.get_ref()));
+        try_io!(self.writer.flush());
+
+        Ok(self.writer)
+    }
+
+    // moves on to the next part if `len` bytes wouldn't fit in the current one but would in a new one
+    fn keep_together(&mut self, len: u64) -> ZipResult<()> {
+        if self.part_offset > 0 && self.part_offset + len > self.part_size && len <= self.part_size {
+            try!(self.next_part());
+        }
+        Ok(())
+    }
+
+    fn write_bytes(&mut self, buf: &[u8]) -> ZipResult<()> {
+        let mut buf = buf;
+        while !buf.is_empty() {
+            if self.part_offset >= self.part_size {
+                try!(self.next_part());
+            }
+            let n = cmp::min(buf.len() as u64, self.part_size - self.part_offset) as uint;
+            match self.first_part {
+                Some(ref mut part) => try_io!(part.write(buf.slice_to(n))),
+                None => try_io!(self.writer.write(buf.slice_to(n))),
+            }
+            self.part_offset += n as u64;
+            buf = buf.slice_from(n);
+        }
+        Ok(())
+    }
+
+    fn next_part(&mut self) -> ZipResult<()> {
+        let disk_number = match self.disk_number.checked_add(&1) {
+            Some(n) => n,
+            None => return Err(error::SomeIoError(IoError {
+                kind: InvalidInput,
+                desc: "too many parts for a split archive",
+                detail: None,
+            }))
+        };
+        match self.first_part.take() {
+            Some(part) => {
+                try_io!(self.writer.write_le_u32(format::DD_SIGNATURE));
+                try_io!(self.writer.write(part.get_ref()));
+            }
+            None => {}
+        }
+        try_io!(self.writer.flush());
+        self.writer = try_io!((self.new_part)(disk_number));
+        self.disk_number = disk_number;
+        self.part_offset = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cmp;
+    use std::slice;
+    use std::io::{File, MemWriter, TempDir, IoResult, SeekStyle, SeekSet, SeekCur, SeekEnd, InvalidInput, EndOfFile};
+    use std::io;
+    use error;
+    use fileinfo;
+    use reader::ZipReader;
+    use super::{ZipWriter, SplitZipWriter, FileOptions, looks_like_text};
+
+    // a Reader giving out at most 7 bytes of `data` per read
+    struct Trickle<'a> {
+        data: &'a [u8],
+    }
+
+    impl<'a> Reader for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+            if self.data.is_empty() {
+                return Err(io::standard_error(EndOfFile));
+            }
+            let n = cmp::min(cmp::min(buf.len(), 7), self.data.len());
+            slice::bytes::copy_memory(buf, self.data.slice_to(n));
+            self.data = self.data.slice_from(n);
+            Ok(n)
+        }
+    }
+
+    // a Writer that only keeps track of its length, for archives too large to hold
+    struct Sink {
+        pos: u64,
+        len: u64,
+    }
+
+    impl Writer for Sink {
+        fn write(&mut self, buf: &[u8]) -> IoResult<()> {
+            self.pos += buf.len() as u64;
+            self.len = cmp::max(self.len, self.pos);
+            Ok(())
+        }
+    }
+
+    impl Seek for Sink {
+        fn tell(&self) -> IoResult<u64> {
+            Ok(self.pos)
+        }
+
+        fn seek(&mut self, pos: i64, style: SeekStyle) -> IoResult<()> {
+            self.pos = match style {
+                SeekSet => pos,
+                SeekCur => self.pos as i64 + pos,
+                SeekEnd => self.len as i64 + pos,
+            } as u64;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn archive_past_4_gib() {
+        let stored = FileOptions { compression_method: fileinfo::Store, ..FileOptions::new() };
+        let mut w = ZipWriter::with_prepended_data(Sink { pos: 0, len: 0 }, 0xFFFFFF00);
+        w.add_file("a.bin", &stored, [0u8, ..0x100].as_slice()).unwrap();
+        let len = w.writer.get_ref().len;
+        // the next local header would start past 4 GiB, and nothing of it is written
+        assert_eq!(w.start_entry("b.bin", &stored).err().unwrap(), error::Zip64Required("offset of a local file header"));
+        assert_eq!(w.writer.get_ref().len, len);
+        assert_eq!(w.finish().err().unwrap(), error::Zip64Required("central directory offset"));
+    }
+
+    #[test]
+    fn entry_past_4_gib() {
+        let mut w = ZipWriter::new(MemWriter::new());
+        w.set_data_descriptors(true);
+        w.add_file("a.txt", &FileOptions::new(), b"hello").unwrap();
+        {
+            let mut entry = w.start_entry("b.bin", &FileOptions::new()).unwrap();
+            // as if nearly 4 GiB of compressed data had gone by
+            entry.zip_writer.current.as_mut().unwrap().compressed_size = 0xFFFFFFFF - 10;
+            assert_eq!(entry.write(Vec::from_elem(100000, 0u8).as_slice()).unwrap_err().kind, InvalidInput);
+            assert!(entry.finish().is_ok());
+        }
+        w.add_file("c.txt", &FileOptions::new(), b"world").unwrap();
+        let archive = w.finish().unwrap().unwrap();
+        // the local header of the dropped entry is still there
+        assert!(archive.as_slice().windows(5).any(|name| name == b"b.bin"));
+
+        let mut zip = ZipReader::from_bytes(archive).unwrap();
+        assert_eq!(zip.len(), 2);
+        assert!(zip.info("b.bin").is_err());
+        let f = zip.info("c.txt").unwrap();
+        assert!(zip.read(&f).unwrap().as_slice() == b"world");
+    }
+
+    #[test]
+    fn split_part_size_too_small() {
+        for &size in [0u64, 1, 21].iter() {
+            assert!(SplitZipWriter::new(size, |_| Ok(MemWriter::new())).is_err());
+        }
+        let mut w = SplitZipWriter::new(22, |_| Ok(MemWriter::new())).unwrap();
+        assert!(w.set_comment(b"x").is_err());
+        assert!(w.finish().unwrap().unwrap().len() == 22);
+    }
+
+    #[test]
+    fn split_archive_in_several_parts() {
+        let dir = TempDir::new("zip-test").unwrap();
+        let part_path = |n: u16| dir.path().join(format!("part{}", n));
+        let contents = Vec::from_fn(5, |i| Vec::from_elem(40 + 30 * i, b'a' + i as u8));
+        {
+            let mut w = SplitZipWriter::new(100, |n| File::create(&part_path(n))).unwrap();
+            w.set_comment(b"split").unwrap();
+            for (i, data) in contents.iter().enumerate() {
+                let name = format!("{}.txt", i);
+                w.add_file(name.as_slice(), &FileOptions::new(), data.as_slice()).unwrap();
+            }
+            w.finish().unwrap();
+        }
+
+        let mut paths = Vec::new();
+        while part_path(paths.len() as u16).exists() {
+            paths.push(part_path(paths.len() as u16));
+        }
+        assert!(paths.len() >= 3);
+        for path in paths.iter() {
+            assert!(path.stat().unwrap().size <= 100);
+        }
+        let mut zip = ZipReader::open_split(paths.as_slice()).unwrap();
+        assert!(zip.comment().as_bytes() == b"split");
+        assert_eq!(zip.len(), contents.len());
+        for (i, data) in contents.iter().enumerate() {
+            let f = zip.info(format!("{}.txt", i).as_slice()).unwrap();
+            assert!(zip.read(&f).unwrap() == *data);
+        }
+    }
+
+    #[test]
+    fn split_archive_in_one_part() {
+        let mut parts = 0u;
+        let archive = {
+            let mut w = SplitZipWriter::new(64 * 1024, |_| { parts += 1; Ok(MemWriter::new()) }).unwrap();
+            w.add_file("a.txt", &FileOptions::new(), b"hello").unwrap();
+            w.finish().unwrap().unwrap()
+        };
+        assert_eq!(parts, 1);
+        // a plain archive, starting with the local file header of its first entry
+        assert!(archive.slice_to(4) == [0x50, 0x4b, 0x03, 0x04].as_slice());
+        let mut zip = ZipReader::from_bytes(archive).unwrap();
+        let f = zip.info("a.txt").unwrap();
+        assert_eq!(f.local_file_header_offset, 0);
+        assert!(zip.read(&f).unwrap().as_slice() == b"hello");
+    }
+
+    #[cfg(not(feature = "aes"))]
+    #[test]
+    fn aes_without_the_feature() {
+        use aes;
+
+        let mut w = ZipWriter::new(MemWriter::new());
+        let options = FileOptions::new().password(Some(b"secret")).aes_strength(Some(aes::Aes256));
+        assert!(w.start_entry("a.txt", &options).is_err());
+        // nothing of the entry was written
+        assert!(w.finish().unwrap().unwrap().len() == 22);
+    }
+
+    #[test]
+    fn text_detection() {
+        assert!(looks_like_text(b"plain ASCII,\r\n\tand a form feed\x0c"));
+        assert!(looks_like_text("\ufeffUTF-8 with a BOM: caf\u00e9".as_bytes()));
+        assert!(looks_like_text([0x82u8, 0xe9, b'\n'].as_slice()));
+        assert!(!looks_like_text(b"\x7fELF\x02\x01\x01\x00"));
+        assert!(!looks_like_text(b"text with a bell\x07"));
+        assert!(!looks_like_text(b""));
+    }
+
+    #[test]
+    fn small_writes() {
+        let data = Vec::from_fn(100000, |i| b"abcabcabd"[i % 9] + (i / 1000) as u8);
+        let whole = |store_fallback: bool, data_descriptors: bool| {
+            let mut w = ZipWriter::new(MemWriter::new());
+            w.set_store_fallback(store_fallback);
+            w.set_data_descriptors(data_descriptors);
+            w.add_file("a.txt", &FileOptions::new(), data.as_slice()).unwrap();
+            w.finish().unwrap().unwrap()
+        };
+        for &(store_fallback, data_descriptors) in [(true, false), (false, false), (false, true)].iter() {
+            let mut w = ZipWriter::new(MemWriter::new());
+            w.set_store_fallback(store_fallback);
+            w.set_data_descriptors(data_descriptors);
+            {
+                let mut entry = w.start_entry("a.txt", &FileOptions::new()).unwrap();
+                for chunk in data.as_slice().chunks(7) {
+                    entry.write(chunk).unwrap();
+                }
+                entry.finish().unwrap();
+            }
+            assert!(w.finish().unwrap().unwrap() == whole(store_fallback, data_descriptors));
+        }
+
+        // the split writer compresses the same way
+        let split = {
+            let mut w = SplitZipWriter::new(1024 * 1024, |_| Ok(MemWriter::new())).unwrap();
+            w.add_file("a.txt", &FileOptions::new(), data.as_slice()).unwrap();
+            w.finish().unwrap().unwrap()
+        };
+        let mut split = ZipReader::from_bytes(split).unwrap();
+        let mut plain = ZipReader::from_bytes(whole(true, false)).unwrap();
+        let (f, g) = (split.info("a.txt").unwrap(), plain.info("a.txt").unwrap());
+        assert!(split.read_raw(&f).unwrap().data == plain.read_raw(&g).unwrap().data);
+    }
+
+    #[test]
+    fn copy_from_a_slow_reader() {
+        let data = Vec::from_fn(10000, |i| (i % 13) as u8);
+        let mut w = ZipWriter::new(MemWriter::new());
+        assert_eq!(w.copy_from_reader("a.bin", &FileOptions::new(), &mut Trickle { data: data.as_slice() }).unwrap(),
+                   data.len() as u64);
+        assert_eq!(w.copy_from_reader("empty", &FileOptions::new(), &mut Trickle { data: b"" }).unwrap(), 0);
+        let mut zip = ZipReader::from_bytes(w.finish().unwrap().unwrap()).unwrap();
+        let f = zip.info("a.bin").unwrap();
+        assert!(zip.read(&f).unwrap() == data);
+        let f = zip.info("empty").unwrap();
+        assert!(zip.read(&f).unwrap().is_empty());
+    }
+
+    #[test]
+    fn store_fallback() {
+        // a linear congruential generator, so the test doesn't depend on the system RNG
+        let mut x = 12345u32;
+        let random = Vec::from_fn(100000, |_| { x = x * 1103515245 + 12345; (x >> 16) as u8 });
+        let text = Vec::from_fn(100000, |i| b"all work and no play makes jack a dull boy\n"[i % 43]);
+
+        let mut w = ZipWriter::new(MemWriter::new());
+        w.add_file("random.bin", &FileOptions::new(), random.as_slice()).unwrap();
+        w.add_file("text.txt", &FileOptions::new(), text.as_slice()).unwrap();
+        let mut zip = ZipReader::from_bytes(w.finish().unwrap().unwrap()).unwrap();
+        let f = zip.info("random.bin").unwrap();
+        assert_eq!(f.compression_method, fileinfo::Store);
+        assert_eq!(f.compressed_size, random.len() as u64);
+        assert!(zip.read(&f).unwrap() == random);
+        let f = zip.info("text.txt").unwrap();
+        assert_eq!(f.compression_method, fileinfo::Deflate);
+        assert!(f.compressed_size < text.len() as u64 / 10);
+        assert!(zip.read(&f).unwrap() == text);
+
+        // without the fallback, random data is deflated all the same
+        let mut w = ZipWriter::new(MemWriter::new());
+        w.set_store_fallback(false);
+        w.add_file("random.bin", &FileOptions::new(), random.as_slice()).unwrap();
+        let zip = ZipReader::from_bytes(w.finish().unwrap().unwrap()).unwrap();
+        assert_eq!(zip.info("random.bin").unwrap().compression_method, fileinfo::Deflate);
+    }
+
+    #[test]
+    fn prepended_data() {
+        let stub = Vec::from_fn(1024, |i| (i * 7) as u8);
+        let write = |w: &mut ZipWriter<MemWriter>| {
+            w.add_file("a.txt", &FileOptions::new(), b"hello").unwrap();
+            w.add_file("b.txt", &FileOptions::new(), b"world").unwrap();
+        };
+
+        // the stub written by the ZipWriter, then glued on afterwards
+        let mut w = ZipWriter::with_preamble(MemWriter::new(), stub.as_slice()).unwrap();
+        write(&mut w);
+        let with_preamble = w.finish().unwrap().unwrap();
+        let mut w = ZipWriter::with_prepended_data(MemWriter::new(), stub.len() as u64);
+        write(&mut w);
+        let mut glued = stub.clone();
+        glued.push_all(w.finish().unwrap().unwrap().as_slice());
+        assert!(glued == with_preamble);
+        assert!(with_preamble.slice_to(stub.len()) == stub.as_slice());
+
+        // the offsets count from the start of the file, so there is no base offset to make up for
+        let mut zip = ZipReader::from_bytes(with_preamble).unwrap();
+        assert_eq!(zip.base_offset(), 0);
+        let f = zip.info("a.txt").unwrap();
+        assert_eq!(f.local_file_header_offset, stub.len() as u64);
+        assert!(zip.read(&f).unwrap().as_slice() == b"hello");
+        let f = zip.info("b.txt").unwrap();
+        assert!(zip.read(&f).unwrap().as_slice() == b"world");
+    }
+
+    #[test]
+    fn data_descriptor_signature() {
+        for &signature in [true, false].iter() {
+            let mut w = ZipWriter::new(MemWriter::new());
+            w.set_data_descriptors(true);
+            w.set_data_descriptor_signature(signature);
+            w.add_file("a.txt", &FileOptions::new(), b"hello hello hello").unwrap();
+            w.add_file("b.txt", &FileOptions::new(), b"world").unwrap();
+            let archive = w.finish().unwrap().unwrap();
+
+            let mut zip = ZipReader::from_bytes(archive.clone()).unwrap();
+            let f = zip.info("a.txt").unwrap();
+            // the data descriptor is right after the data, before the next local header
+            let end = f.local_file_header_offset as uint + 30 + 5 + f.compressed_size as uint;
+            let has_signature = archive.slice_from(end).starts_with(&[0x50, 0x4b, 0x07, 0x08]);
+            assert_eq!(has_signature, signature);
+            let descriptor_size = if signature { 16 } else { 12 };
+            assert_eq!(zip.info("b.txt").unwrap().local_file_header_offset, (end + descriptor_size) as u64);
+
+            assert!(zip.read(&f).unwrap().as_slice() == b"hello hello hello");
+            let f = zip.info("b.txt").unwrap();
+            assert!(zip.read(&f).unwrap().as_slice() == b"world");
+            assert_eq!(zip.verify().unwrap().ok, 2);
+        }
+    }
+
+    #[test]
+    fn text_attribute() {
+        let mut w = ZipWriter::new(MemWriter::new());
+        w.set_detect_text(true);
+        w.add_file("a.txt", &FileOptions::new(), b"hello\n").unwrap();
+        w.add_file("b.bin", &FileOptions::new(), b"\x00\x01\x02").unwrap();
+        w.add_file("c.txt", &FileOptions::new().text(true), b"\x00").unwrap();
+        w.add_file("d.bin", &FileOptions::new().text(false), b"hello\n").unwrap();
+        let zip = ZipReader::from_bytes(w.finish().unwrap().unwrap()).unwrap();
+        let text: Vec<bool> = zip.files().map(|f| f.is_text()).collect();
+        assert_eq!(text, vec![true, false, true, false]);
+    }
+
+    #[test]
+    fn long_names() {
+        let mut w = ZipWriter::new(MemWriter::new());
+        let too_long = Vec::from_elem(70000, b'a');
+        assert!(w.add_file(too_long.as_slice(), &FileOptions::new(), b"hello") == Err(error::TooLongField));
+        assert!(w.set_comment(too_long.as_slice()) == Err(error::TooLongField));
+        // nothing was written
+        assert!(w.finish().unwrap().unwrap().len() == 22);
+
+        let mut w = ZipWriter::new(MemWriter::new());
+        let longest = Vec::from_elem(65535, b'a');
+        w.add_file(longest.as_slice(), &FileOptions::new(), b"hello").unwrap();
+        w.set_comment(longest.as_slice()).unwrap();
+        let mut zip = ZipReader::from_bytes(w.finish().unwrap().unwrap()).unwrap();
+        assert!(zip.comment().as_bytes() == longest.as_slice());
+        let f = zip.info(longest.as_slice()).unwrap();
+        assert!(zip.read(&f).unwrap().as_slice() == b"hello");
+    }
+}
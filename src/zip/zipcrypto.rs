@@ -0,0 +1,166 @@
+//! The traditional PKWARE encryption ("ZipCrypto"), see section 6.1 of APPNOTE.TXT.
+//! It is weak, and only supported for compatibility with existing tools.
+
+use std::io::{Reader, IoResult};
+use std::rand::{task_rng, Rng};
+use crc32;
+use format::{EncryptionHeader, ENCRYPTION_HEADER_SIZE};
+
+pub struct ZipCryptoKeys {
+    key0: u32,
+    key1: u32,
+    key2: u32,
+}
+
+impl ZipCryptoKeys {
+    /// Initializes the keys from a password.
+    pub fn new(password: &[u8]) -> ZipCryptoKeys {
+        let mut keys = ZipCryptoKeys { key0: 0x12345678, key1: 0x23456789, key2: 0x34567890 };
+        for &byte in password.iter() {
+            keys.update(byte);
+        }
+        keys
+    }
+
+    fn update(&mut self, byte: u8) {
+        self.key0 = crc32::update_byte(self.key0, byte);
+        self.key1 = (self.key1 + (self.key0 & 0xff)) * 134775813 + 1;
+        self.key2 = crc32::update_byte(self.key2, (self.key1 >> 24) as u8);
+    }
+
+    fn stream_byte(&self) -> u8 {
+        let temp = (self.key2 | 2) as u16;
+        ((temp * (temp ^ 1)) >> 8) as u8
+    }
+
+    pub fn encrypt_byte(&mut self, plain: u8) -> u8 {
+        let cipher = plain ^ self.stream_byte();
+        self.update(plain);
+        cipher
+    }
+
+    pub fn decrypt_byte(&mut self, cipher: u8) -> u8 {
+        let plain = cipher ^ self.stream_byte();
+        self.update(plain);
+        plain
+    }
+
+    pub fn encrypt(&mut self, buf: &mut [u8]) {
+        for byte in buf.iter_mut() {
+            *byte = self.encrypt_byte(*byte);
+        }
+    }
+
+    pub fn decrypt(&mut self, buf: &mut [u8]) {
+        for byte in buf.iter_mut() {
+            *byte = self.decrypt_byte(*byte);
+        }
+    }
+}
+
+/// A Reader decrypting the data of an encrypted entry.
+pub struct ZipCryptoReader<R> {
+    inner: R,
+    keys: ZipCryptoKeys,
+}
+
+impl<R:Reader> ZipCryptoReader<R> {
+    /// Decrypts the encryption header already read from `inner`. Returns None if the last byte
+    /// of the header isn't `check_byte` (see `format::encryption_check_byte`), that is if the
+    /// password is wrong. A wrong password passes this check once in 256 times; the CRC32
+    /// catches it then.
+    pub fn new(inner: R, header: &EncryptionHeader, password: &[u8],
+               check_byte: u8) -> Option<ZipCryptoReader<R>> {
+        let mut keys = ZipCryptoKeys::new(password);
+        let mut header = header.as_bytes().to_vec();
+        keys.decrypt(header.as_mut_slice());
+        if header[ENCRYPTION_HEADER_SIZE - 1] != check_byte {
+            return None;
+        }
+        Some(ZipCryptoReader { inner: inner, keys: keys })
+    }
+}
+
+impl<R:Reader> Reader for ZipCryptoReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        let n = try!(self.inner.read(buf));
+        self.keys.decrypt(buf.slice_to_mut(n));
+        Ok(n)
+    }
+}
+
+/// Starts encrypting an entry: returns the keys to encrypt its (already compressed) data with,
+/// and the encryption header that precedes it. `check_byte` is the last byte of the header,
+/// used to verify the password when decrypting, see `format::encryption_check_byte`.
+pub fn start_entry(password: &[u8], check_byte: u8) -> (ZipCryptoKeys, Vec<u8>) {
+    let mut header = Vec::from_elem(ENCRYPTION_HEADER_SIZE, 0u8);
+    task_rng().fill_bytes(header.slice_to_mut(ENCRYPTION_HEADER_SIZE - 1));
+    header.as_mut_slice()[ENCRYPTION_HEADER_SIZE - 1] = check_byte;
+
+    let mut keys = ZipCryptoKeys::new(password);
+    keys.encrypt(header.as_mut_slice());
+    (keys, header)
+}
+
+/// Encrypts the (already compressed) data of an entry, prepending the encryption header.
+pub fn encrypt_entry(password: &[u8], check_byte: u8, data: &[u8]) -> Vec<u8> {
+    let (mut keys, mut result) = start_entry(password, check_byte);
+    let start = result.len();
+    result.push_all(data);
+    keys.encrypt(result.slice_from_mut(start));
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{MemWriter, BufReader};
+    use error;
+    use format::EncryptionHeader;
+    use reader::ZipReader;
+    use writer::{ZipWriter, FileOptions};
+    use super::{ZipCryptoReader, encrypt_entry};
+
+    // made by Info-ZIP's zip 3.0 with `zip -X -D -P secret`, holding a.txt stored with a data
+    // descriptor, so the check byte is the high byte of its modification time (0x18)
+    static INFO_ZIP_ARCHIVE: &'static [u8] = &[
+        0x50, 0x4b, 0x03, 0x04, 0x0a, 0x00, 0x09, 0x00, 0x00, 0x00, 0x80, 0x18, 0x22, 0x50, 0x2e, 0x68,
+        0x3f, 0x83, 0x1d, 0x00, 0x00, 0x00, 0x11, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x61, 0x2e,
+        0x74, 0x78, 0x74, 0xa5, 0xd3, 0x43, 0x5a, 0xfb, 0x63, 0x26, 0x08, 0xfb, 0x83, 0x6e, 0x40, 0x1d,
+        0x85, 0x68, 0xe4, 0x88, 0xbb, 0x96, 0x0d, 0x2a, 0x43, 0x01, 0xb6, 0xfd, 0x17, 0x9d, 0x1f, 0xb8,
+        0x50, 0x4b, 0x07, 0x08, 0x2e, 0x68, 0x3f, 0x83, 0x1d, 0x00, 0x00, 0x00, 0x11, 0x00, 0x00, 0x00,
+        0x50, 0x4b, 0x01, 0x02, 0x1e, 0x03, 0x0a, 0x00, 0x09, 0x00, 0x00, 0x00, 0x80, 0x18, 0x22, 0x50,
+        0x2e, 0x68, 0x3f, 0x83, 0x1d, 0x00, 0x00, 0x00, 0x11, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0xa4, 0x81, 0x00, 0x00, 0x00, 0x00, 0x61, 0x2e,
+        0x74, 0x78, 0x74, 0x50, 0x4b, 0x05, 0x06, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x33,
+        0x00, 0x00, 0x00, 0x50, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn info_zip_archive() {
+        let mut zip = ZipReader::from_bytes(INFO_ZIP_ARCHIVE.to_vec()).unwrap();
+        let f = zip.info("a.txt").unwrap();
+        assert!(zip.read_encrypted(&f, b"secret").unwrap().as_slice() == b"hello, zipcrypto\n");
+        // the header decrypted with this password doesn't end in the check byte
+        assert_eq!(*zip.read_encrypted(&f, b"wrong").unwrap_err().innermost(), error::InvalidPassword);
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut w = ZipWriter::new(MemWriter::new());
+        w.add_file("a.txt", &FileOptions::new().password(Some(b"pass")), b"hidden, hidden, hidden").unwrap();
+        let mut zip = ZipReader::from_bytes(w.finish().unwrap().unwrap()).unwrap();
+        let f = zip.info("a.txt").unwrap();
+        assert!(f.is_encrypted);
+        assert!(zip.read_encrypted(&f, b"pass").unwrap().as_slice() == b"hidden, hidden, hidden");
+    }
+
+    #[test]
+    fn check_byte() {
+        let encrypted = encrypt_entry(b"pass", 0xab, b"some data");
+        let header = EncryptionHeader::read(&mut BufReader::new(encrypted.as_slice())).unwrap();
+        assert!(ZipCryptoReader::new(BufReader::new(b""), &header, b"pass", 0xaa).is_none());
+        let data = encrypted.slice_from(12);
+        let mut reader = ZipCryptoReader::new(BufReader::new(data), &header, b"pass", 0xab).unwrap();
+        assert!(reader.read_to_end().unwrap().as_slice() == b"some data");
+    }
+}